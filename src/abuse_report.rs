@@ -0,0 +1,384 @@
+//! Abuse report emails for the sources in a reputation store, one to each
+//! network's RDAP abuse contact, listing its sources with excerpts from the
+//! logs as evidence.
+//!
+//! Reports are written out as drafts to review by default, and only sent
+//! through `sendmail` when asked, optionally confirming each first.
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use structopt::StructOpt;
+use tarssh::event::{Event, Record as LogRecord};
+
+use crate::reputation::{Record, Reputation};
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+const TEMPLATE: &str = "\
+Hello,
+
+The following {count} address(es) on {netname} connected to an SSH tarpit,
+a server that only answers on an address no legitimate user has any reason
+to connect to, and were held there as follows.  Times are UTC.
+
+{sources}
+
+Excerpts from the tarpit's logs:
+
+{evidence}
+
+These hosts are most likely compromised, or being used to scan for hosts to
+compromise.  Please look into it.
+";
+
+#[derive(Debug, StructOpt)]
+pub struct AbuseReportConfig {
+    /// The --reputation-file to report sources from
+    #[structopt(parse(from_os_str))]
+    reputation: PathBuf,
+    /// A tarssh log to take evidence from, as text or JSON events
+    #[structopt(long = "log", parse(from_os_str))]
+    logs: Vec<PathBuf>,
+    /// The address to send reports from
+    #[structopt(long = "from")]
+    from: String,
+    /// Only report sources seen within this long, e.g. 1d
+    #[structopt(
+        long = "since",
+        default_value = "1d",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    since: Duration,
+    /// Only report sources held at least this long altogether, e.g. 10m
+    #[structopt(
+        long = "min-held",
+        default_value = "10m",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    min_held: Duration,
+    /// Most log lines to quote for each source
+    #[structopt(long = "evidence", default_value = "5")]
+    evidence: usize,
+    /// The body of each report, with {count}, {netname}, {sources} and
+    /// {evidence} filled in
+    #[structopt(long = "template", parse(from_os_str))]
+    template: Option<PathBuf>,
+    /// Write each report to a file in this directory, instead of printing it
+    #[structopt(long = "outbox", parse(from_os_str))]
+    outbox: Option<PathBuf>,
+    /// Send each report with this sendmail-compatible command
+    #[structopt(long = "sendmail", parse(from_os_str))]
+    sendmail: Option<PathBuf>,
+    /// Show each report and ask before sending it
+    #[structopt(long = "confirm", requires = "sendmail")]
+    confirm: bool,
+}
+
+/// Whether an abuse contact from RDAP is safe to put in a header and a file
+/// name
+fn valid_contact(contact: &str) -> bool {
+    contact.contains('@')
+        && contact
+            .chars()
+            .all(|c| c.is_ascii_graphic() && !matches!(c, '/' | '\\' | '<' | '>' | ',' | '"'))
+}
+
+/// Sources to report, by abuse contact
+fn sources(
+    records: impl Iterator<Item = (IpAddr, Record)>,
+    now: SystemTime,
+    since: Duration,
+    min_held: Duration,
+) -> BTreeMap<String, Vec<(IpAddr, Record)>> {
+    let cutoff = now
+        .checked_sub(since)
+        .and_then(|cutoff| cutoff.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |cutoff| cutoff.as_secs());
+    let mut contacts: BTreeMap<String, Vec<_>> = BTreeMap::new();
+    for (ip, record) in records {
+        if record.last_seen < cutoff || record.held < min_held {
+            continue;
+        }
+        if let Some(contact) = record.abuse.clone().filter(|c| valid_contact(c)) {
+            contacts.entry(contact).or_default().push((ip, record));
+        }
+    }
+    for sources in contacts.values_mut() {
+        sources.sort_by(|a, b| b.1.held.cmp(&a.1.held).then_with(|| a.0.cmp(&b.0)));
+    }
+    contacts
+}
+
+/// The last `max` log lines about each of `ips`
+fn evidence<B: BufRead>(
+    input: B,
+    ips: &HashSet<IpAddr>,
+    max: usize,
+    excerpts: &mut HashMap<IpAddr, VecDeque<String>>,
+) -> io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        let peer = match LogRecord::parse(&line).map(|record| record.event) {
+            Some(Event::Connect { peer, .. }) | Some(Event::Disconnect { peer, .. }) => peer,
+            _ => continue,
+        };
+        if ips.contains(&peer.ip()) && max > 0 {
+            let lines = excerpts.entry(peer.ip()).or_default();
+            if lines.len() == max {
+                lines.pop_front();
+            }
+            lines.push_back(line.trim_end().to_string());
+        }
+    }
+    Ok(())
+}
+
+/// A time as an RFC 5322 date, e.g. `Tue, 7 Jun 2022 00:00:00 +0000`
+fn rfc5322(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let rfc3339 = humantime::format_rfc3339_seconds(time).to_string();
+    let field = |range: std::ops::Range<usize>| rfc3339[range].parse::<usize>().unwrap_or(0);
+    format!(
+        "{}, {} {} {} {} +0000",
+        DAYS[(secs / 86400 % 7) as usize],
+        field(8..10),
+        MONTHS[field(5..7).saturating_sub(1) % 12],
+        &rfc3339[..4],
+        &rfc3339[11..19]
+    )
+}
+
+fn unix_time(secs: u64) -> humantime::Rfc3339Timestamp {
+    humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// A report to `contact` about `sources`
+fn message(
+    from: &str,
+    contact: &str,
+    sources: &[(IpAddr, Record)],
+    excerpts: &HashMap<IpAddr, VecDeque<String>>,
+    template: &str,
+    now: SystemTime,
+) -> String {
+    let mut netnames: Vec<&str> = sources
+        .iter()
+        .filter_map(|(_, record)| record.netname.as_deref())
+        .collect();
+    netnames.sort_unstable();
+    netnames.dedup();
+    let netname = match netnames.len() {
+        0 => "your network".to_string(),
+        _ => netnames.join(", "),
+    };
+
+    let table: Vec<String> = sources
+        .iter()
+        .map(|(ip, record)| {
+            format!(
+                "  {}  first seen {}, last seen {}, {} connections held for {}",
+                ip,
+                unix_time(record.first_seen),
+                unix_time(record.last_seen),
+                record.connections,
+                humantime::format_duration(Duration::from_secs(record.held.as_secs()))
+            )
+        })
+        .collect();
+    let quoted: Vec<String> = sources
+        .iter()
+        .filter_map(|(ip, _)| excerpts.get(ip))
+        .flatten()
+        .map(|line| format!("  {}", line))
+        .collect();
+    let quoted = match quoted.len() {
+        0 => "  (none available)".to_string(),
+        _ => quoted.join("\n"),
+    };
+
+    let body = template
+        .replace("{count}", &sources.len().to_string())
+        .replace("{netname}", &netname)
+        .replace("{sources}", &table.join("\n"))
+        .replace("{evidence}", &quoted);
+    format!(
+        "From: {}\nTo: {}\nSubject: SSH scanning from {} ({} address{})\nDate: {}\n\
+         MIME-Version: 1.0\nContent-Type: text/plain; charset=utf-8\n\
+         Content-Transfer-Encoding: 8bit\nAuto-Submitted: auto-generated\n\n{}",
+        from,
+        contact,
+        netname,
+        sources.len(),
+        if sources.len() == 1 { "" } else { "es" },
+        rfc5322(now),
+        body
+    )
+}
+
+fn send(sendmail: &PathBuf, message: &str) -> io::Result<()> {
+    let mut child = Command::new(sendmail)
+        .args(["-t", "-i"])
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(message.as_bytes())?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "{} exited with {}",
+            sendmail.display(),
+            status
+        )));
+    }
+    Ok(())
+}
+
+fn confirm(message: &str) -> io::Result<bool> {
+    print!("{}\nSend? [y/N] ", message);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+pub fn run(config: AbuseReportConfig) -> io::Result<()> {
+    let open = |path: &PathBuf| {
+        File::open(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    };
+    // Existence is checked first, since a missing store would open as empty
+    open(&config.reputation)?;
+    let store = Reputation::open(&config.reputation, Duration::ZERO)?;
+    let template = match &config.template {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?,
+        None => TEMPLATE.to_string(),
+    };
+
+    let now = SystemTime::now();
+    let contacts = sources(
+        store.records().map(|(ip, record)| (*ip, record.clone())),
+        now,
+        config.since,
+        config.min_held,
+    );
+    let ips: HashSet<IpAddr> = contacts.values().flatten().map(|(ip, _)| *ip).collect();
+    let mut excerpts = HashMap::new();
+    for path in &config.logs {
+        evidence(
+            BufReader::new(open(path)?),
+            &ips,
+            config.evidence,
+            &mut excerpts,
+        )?;
+    }
+
+    if let Some(outbox) = &config.outbox {
+        std::fs::create_dir_all(outbox)?;
+    }
+    let (mut sent, mut skipped) = (0, 0);
+    for (contact, sources) in &contacts {
+        let message = message(&config.from, contact, sources, &excerpts, &template, now);
+        if let Some(outbox) = &config.outbox {
+            std::fs::write(outbox.join(format!("{}.eml", contact)), &message)?;
+        }
+        match &config.sendmail {
+            Some(_) if config.confirm && !confirm(&message)? => skipped += 1,
+            Some(sendmail) => {
+                send(sendmail, &message)?;
+                sent += 1;
+            }
+            None if config.outbox.is_none() => println!("{}", message),
+            None => (),
+        }
+    }
+    eprintln!(
+        "abuse-report, contacts: {}, sources: {}, sent: {}, skipped: {}",
+        contacts.len(),
+        ips.len(),
+        sent,
+        skipped
+    );
+    Ok(())
+}
+
+#[test]
+fn test_abuse_report() {
+    let now = UNIX_EPOCH + Duration::from_secs(1_654_560_000);
+    let record = |held: u64, netname: &str, abuse: &str| Record {
+        first_seen: 1_654_473_600,
+        last_seen: 1_654_559_000,
+        connections: 3,
+        held: Duration::from_secs(held),
+        last_action: "timeout",
+        last_connect: Duration::ZERO,
+        intervals: 0,
+        interval_min: Duration::ZERO,
+        interval_total: Duration::ZERO,
+        netname: Some(netname.to_string()),
+        abuse: Some(abuse.to_string()),
+    };
+    let a: IpAddr = [192, 0, 2, 1].into();
+    let b: IpAddr = [192, 0, 2, 2].into();
+    let records = vec![
+        (a, record(3600, "TEST-NET-1", "abuse@example.net")),
+        (b, record(7200, "TEST-NET-1", "abuse@example.net")),
+        (
+            [192, 0, 2, 3].into(),
+            record(60, "TEST-NET-1", "abuse@example.net"),
+        ),
+        (
+            [198, 51, 100, 1].into(),
+            record(3600, "EVIL", "x\nBcc: y@z"),
+        ),
+    ];
+    let contacts = sources(
+        records.into_iter(),
+        now,
+        Duration::from_secs(86400),
+        Duration::from_secs(600),
+    );
+    assert_eq!(contacts.len(), 1);
+    let sources = &contacts["abuse@example.net"];
+    assert_eq!(sources.iter().map(|s| s.0).collect::<Vec<_>>(), vec![b, a]);
+
+    let log = "\
+[2022-06-06T23:00:00.000Z INFO  tarssh] connect, peer: 192.0.2.1:40000, clients: 1
+[2022-06-06T23:00:01.000Z INFO  tarssh] connect, peer: 203.0.113.9:40000, clients: 2
+[2022-06-06T23:30:00.000Z INFO  tarssh] disconnect, peer: 192.0.2.1:40000, duration: 1800.00s, bytes: 1, error: \"Broken pipe\", clients: 1
+";
+    let mut excerpts = HashMap::new();
+    let ips = [a, b].iter().copied().collect();
+    evidence(log.as_bytes(), &ips, 1, &mut excerpts).unwrap();
+
+    let message = message(
+        "tarpit@example.org",
+        "abuse@example.net",
+        sources,
+        &excerpts,
+        "{count} on {netname}:\n{sources}\n{evidence}\n",
+        now,
+    );
+    assert_eq!(
+        message,
+        "From: tarpit@example.org\nTo: abuse@example.net\n\
+         Subject: SSH scanning from TEST-NET-1 (2 addresses)\n\
+         Date: Tue, 7 Jun 2022 00:00:00 +0000\nMIME-Version: 1.0\n\
+         Content-Type: text/plain; charset=utf-8\nContent-Transfer-Encoding: 8bit\n\
+         Auto-Submitted: auto-generated\n\n\
+         2 on TEST-NET-1:\n  \
+         192.0.2.2  first seen 2022-06-06T00:00:00Z, last seen 2022-06-06T23:43:20Z, 3 connections held for 2h\n  \
+         192.0.2.1  first seen 2022-06-06T00:00:00Z, last seen 2022-06-06T23:43:20Z, 3 connections held for 1h\n  \
+         [2022-06-06T23:30:00.000Z INFO  tarssh] disconnect, peer: 192.0.2.1:40000, duration: 1800.00s, bytes: 1, error: \"Broken pipe\", clients: 1\n"
+    );
+}
@@ -0,0 +1,247 @@
+//! Reporting tarpitted peers to AbuseIPDB.
+//!
+//! Peers held for long enough are queued as they disconnect, and sent as a
+//! single CSV batch to the bulk-report API on each interval, so even a busy
+//! tarpit makes only a few requests a day.  A peer reconnecting before its
+//! batch is sent adds to the same report, rather than making another.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+
+use log::{info, warn};
+
+use crate::http::HttpUrl;
+
+/// Most peers to queue between batches, the most the API takes in one
+const QUEUE_MAX: usize = 10000;
+
+/// How long to wait for the API to take a batch
+const POST_TIMEOUT: Duration = Duration::from_secs(60);
+
+const BOUNDARY: &str = "tarssh-abuseipdb-report";
+
+/// Parse the bulk-report API's URL, which must be https://, since every
+/// request carries the API key
+pub fn parse_url(s: &str) -> Result<HttpUrl, String> {
+    let url: HttpUrl = s.parse()?;
+    if !url.is_tls() {
+        return Err("the API key can't be sent over plain http://, use https://".to_string());
+    }
+    Ok(url)
+}
+
+/// Parse comma-separated AbuseIPDB category numbers, e.g. 18,22
+pub fn parse_categories(s: &str) -> Result<String, String> {
+    for category in s.split(',') {
+        match category.trim().parse::<u8>() {
+            Ok(1..=23) => (),
+            _ => return Err(format!("invalid category {:?}, expected 1 to 23", category)),
+        }
+    }
+    Ok(s.split(',').map(str::trim).collect::<Vec<_>>().join(","))
+}
+
+/// What's known of a peer waiting to be reported
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Pending {
+    connections: u32,
+    held: Duration,
+    last: SystemTime,
+}
+
+/// Whether AbuseIPDB would take reports of `ip`, which it won't for private,
+/// loopback and other addresses that aren't routed on the internet
+fn reportable(ip: IpAddr) -> bool {
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        v4 => v4,
+    };
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                || v4.is_multicast()
+                // Shared address space, for carrier-grade NAT
+                || (a == 100 && b & 0xc0 == 64))
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80
+                || first == 0x2001 && v6.segments()[1] == 0xdb8)
+        }
+    }
+}
+
+pub struct AbuseIpDb {
+    url: HttpUrl,
+    key: String,
+    /// Comma-separated category numbers, e.g. 18,22 for brute-force and SSH
+    categories: String,
+    /// Shortest hold worth reporting
+    min_held: Duration,
+    queue: BTreeMap<IpAddr, Pending>,
+    dropped: u64,
+    reported: u64,
+}
+
+impl AbuseIpDb {
+    pub fn new(url: HttpUrl, key: String, categories: String, min_held: Duration) -> Self {
+        Self {
+            url,
+            key,
+            categories,
+            min_held,
+            queue: BTreeMap::new(),
+            dropped: 0,
+            reported: 0,
+        }
+    }
+
+    /// Queue a peer that's disconnected after being held for `held`, if that's
+    /// long enough to report
+    pub fn disconnected(&mut self, ip: IpAddr, held: Duration, now: SystemTime) {
+        if held < self.min_held || !reportable(ip) {
+            return;
+        }
+        if self.queue.len() >= QUEUE_MAX && !self.queue.contains_key(&ip) {
+            self.dropped += 1;
+            return;
+        }
+        let pending = self.queue.entry(ip).or_insert(Pending {
+            connections: 0,
+            held: Duration::ZERO,
+            last: now,
+        });
+        pending.connections += 1;
+        pending.held += held;
+        pending.last = now;
+    }
+
+    /// The queued reports as a bulk-report CSV, emptying the queue
+    fn batch(&mut self) -> String {
+        let mut csv = "IP,Categories,ReportDate,Comment\r\n".to_string();
+        for (ip, pending) in std::mem::take(&mut self.queue) {
+            let held = humantime::format_duration(Duration::from_secs(pending.held.as_secs()));
+            let comment = match pending.connections {
+                1 => format!("SSH tarpit: held for {}", held),
+                n => format!("SSH tarpit: {} connections held for {}", n, held),
+            };
+            csv.push_str(&format!(
+                "{},\"{}\",{},\"{}\"\r\n",
+                ip,
+                self.categories,
+                humantime::format_rfc3339_seconds(pending.last),
+                comment.replace('"', "\"\"")
+            ));
+        }
+        csv
+    }
+
+    /// Send everything queued in the background, if anything is
+    pub fn flush(&mut self) {
+        let count = self.queue.len();
+        if count == 0 {
+            return;
+        }
+        self.reported += count as u64;
+        let csv = self.batch();
+        let body = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"csv\"; filename=\"report.csv\"\r\n\
+             Content-Type: text/csv\r\n\r\n{csv}\r\n--{b}--\r\n",
+            b = BOUNDARY,
+            csv = csv
+        );
+        let url = self.url.clone();
+        let key = self.key.clone();
+
+        tokio::spawn(async move {
+            let content_type = format!("multipart/form-data; boundary={}", BOUNDARY);
+            let headers = [
+                ("Key", key.as_str()),
+                ("Accept", "application/json"),
+                ("Content-Type", content_type.as_str()),
+            ];
+            match url.post(&headers, body.as_bytes(), POST_TIMEOUT).await {
+                Ok(_) => info!("abuseipdb, reports: {}", count),
+                Err(err) => warn!("abuseipdb, reports: {}, error: {}", count, err),
+            }
+        });
+    }
+}
+
+/// Peers queued, sent and dropped for want of room
+impl fmt::Display for AbuseIpDb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "queued: {}, reported: {}, dropped: {}",
+            self.queue.len(),
+            self.reported,
+            self.dropped
+        )
+    }
+}
+
+#[test]
+fn test_abuseipdb() {
+    let mut abuse = AbuseIpDb::new(
+        "http://abuseipdb-proxy/api/v2/bulk-report".parse().unwrap(),
+        "key".to_string(),
+        "18,22".to_string(),
+        Duration::from_secs(60),
+    );
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_654_560_000);
+    let peer: IpAddr = [203, 0, 113, 7].into();
+    let public: IpAddr = [198, 51, 100, 1].into();
+    let held = Duration::from_secs(3600);
+
+    assert!(!reportable(peer));
+    assert!(!reportable("::ffff:10.0.0.1".parse().unwrap()));
+    assert!(!reportable([100, 64, 0, 1].into()));
+    assert!(reportable([100, 128, 0, 1].into()));
+    assert!(reportable("2a00:1450::1".parse().unwrap()));
+
+    abuse.disconnected(peer, held, time);
+    abuse.disconnected([8, 8, 8, 8].into(), Duration::from_secs(5), time);
+    assert!(abuse.queue.is_empty());
+
+    // Documentation ranges aren't reportable, so queue directly
+    let pending = Pending {
+        connections: 1,
+        held,
+        last: time,
+    };
+    abuse.queue.insert(public, pending);
+    abuse.disconnected([9, 9, 9, 9].into(), held, time);
+    abuse.disconnected([9, 9, 9, 9].into(), held, time + held);
+    assert_eq!(
+        abuse.batch(),
+        "IP,Categories,ReportDate,Comment\r\n\
+         9.9.9.9,\"18,22\",2022-06-07T01:00:00Z,\"SSH tarpit: 2 connections held for 2h\"\r\n\
+         198.51.100.1,\"18,22\",2022-06-07T00:00:00Z,\"SSH tarpit: held for 1h\"\r\n"
+    );
+    assert!(abuse.queue.is_empty());
+
+    assert_eq!(parse_categories("18, 22").unwrap(), "18,22");
+    assert!(parse_categories("18,ssh").is_err());
+}
+
+#[test]
+fn test_parse_url() {
+    assert!(parse_url("http://api.abuseipdb.com/api/v2/bulk-report").is_err());
+    assert_eq!(
+        parse_url("https://api.abuseipdb.com/api/v2/bulk-report").is_ok(),
+        cfg!(feature = "tls")
+    );
+}
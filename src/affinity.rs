@@ -0,0 +1,80 @@
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// Most CPUs we'll accept in a list
+const MAX_CPUS: usize = 1024;
+
+/// A list of CPUs, written as e.g. `0,2-3`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuList(Vec<usize>);
+
+impl FromStr for CpuList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut cpus = vec![];
+
+        for part in s.split(',') {
+            let (first, last) = part.split_once('-').unwrap_or((part, part));
+            let parse = |n: &str| {
+                n.trim()
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n < MAX_CPUS)
+                    .ok_or_else(|| format!("invalid CPU {:?}, expected 0 to {}", n, MAX_CPUS - 1))
+            };
+            let (first, last) = (parse(first)?, parse(last)?);
+            if first > last {
+                return Err(format!("invalid CPU range {:?}", part));
+            }
+            cpus.extend(first..=last);
+        }
+
+        cpus.sort_unstable();
+        cpus.dedup();
+        Ok(Self(cpus))
+    }
+}
+
+impl fmt::Display for CpuList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cpus: Vec<String> = self.0.iter().map(|cpu| cpu.to_string()).collect();
+        f.write_str(&cpus.join(","))
+    }
+}
+
+/// Pin the calling thread, and any threads it starts later, to these CPUs
+#[cfg(target_os = "linux")]
+pub fn pin(cpus: &CpuList) -> io::Result<()> {
+    // SAFETY: cpu_set_t is plain data, and CPU_SET is bounds checked against it
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for cpu in &cpus.0 {
+            libc::CPU_SET(*cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin(_cpus: &CpuList) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "CPU affinity is not supported on this platform",
+    ))
+}
+
+#[test]
+fn test_cpu_list() {
+    let cpus: CpuList = "3,0-1, 1".parse().unwrap();
+    assert_eq!(cpus, CpuList(vec![0, 1, 3]));
+    assert_eq!(cpus.to_string(), "0,1,3");
+
+    assert!("2-1".parse::<CpuList>().is_err());
+    assert!("1024".parse::<CpuList>().is_err());
+    assert!("a".parse::<CpuList>().is_err());
+}
@@ -0,0 +1,499 @@
+//! A minimal AgentX (RFC 2741) subagent, exposing tarssh's counters to an
+//! SNMP master agent such as Net-SNMP's snmpd.
+
+use std::convert::TryInto;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// How long to wait before reconnecting to the master agent
+const RETRY: Duration = Duration::from_secs(30);
+
+/// Largest PDU payload we'll accept from the master agent
+const MAX_PAYLOAD: usize = 64 * 1024;
+
+const HEADER_LEN: usize = 20;
+const VERSION: u8 = 1;
+
+const FLAG_NON_DEFAULT_CONTEXT: u8 = 0x08;
+const FLAG_NETWORK_BYTE_ORDER: u8 = 0x10;
+
+const PDU_OPEN: u8 = 1;
+const PDU_CLOSE: u8 = 2;
+const PDU_REGISTER: u8 = 3;
+const PDU_GET: u8 = 5;
+const PDU_GET_NEXT: u8 = 6;
+const PDU_GET_BULK: u8 = 7;
+const PDU_TEST_SET: u8 = 8;
+const PDU_RESPONSE: u8 = 18;
+
+const ERROR_NOT_WRITABLE: u16 = 17;
+
+/// Counters shared with the tarpit's main loop
+#[derive(Debug, Default)]
+pub struct Counters {
+    pub clients: AtomicU64,
+    pub connects: AtomicU64,
+    pub bytes: AtomicU64,
+}
+
+/// An SNMP object identifier, e.g. `1.3.6.1.4.1.8072.9999.9999`
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Oid(Vec<u32>);
+
+impl FromStr for Oid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ids = s
+            .trim_start_matches('.')
+            .split('.')
+            .map(|id| id.parse())
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|_| format!("invalid OID {:?}", s))?;
+        if ids.len() < 2 || ids.len() > 128 {
+            return Err(format!("invalid OID {:?}", s));
+        }
+        Ok(Oid(ids))
+    }
+}
+
+impl fmt::Display for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ids: Vec<_> = self.0.iter().map(u32::to_string).collect();
+        f.write_str(&ids.join("."))
+    }
+}
+
+impl Oid {
+    fn child(&self, ids: &[u32]) -> Oid {
+        Oid(self.0.iter().chain(ids).copied().collect())
+    }
+}
+
+/// Where the master agent listens: a Unix socket path, or `tcp:host:port`
+#[derive(Debug, Clone)]
+pub enum AgentxTarget {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+impl FromStr for AgentxTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(addr) = s.strip_prefix("tcp:") {
+            Ok(AgentxTarget::Tcp(addr.to_string()))
+        } else if s.starts_with('/') {
+            Ok(AgentxTarget::Unix(s.into()))
+        } else {
+            Err("expected a socket path or tcp:host:port".to_string())
+        }
+    }
+}
+
+impl fmt::Display for AgentxTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AgentxTarget::Unix(path) => write!(f, "{}", path.display()),
+            AgentxTarget::Tcp(addr) => write!(f, "tcp:{}", addr),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    Gauge32(u32),
+    TimeTicks(u32),
+    Counter64(u64),
+    NoSuchObject,
+    EndOfMibView,
+}
+
+impl Value {
+    fn put(&self, out: &mut Vec<u8>, name: &Oid) {
+        let kind: u16 = match self {
+            Value::Gauge32(_) => 66,
+            Value::TimeTicks(_) => 67,
+            Value::Counter64(_) => 70,
+            Value::NoSuchObject => 128,
+            Value::EndOfMibView => 130,
+        };
+        out.extend_from_slice(&kind.to_be_bytes());
+        out.extend_from_slice(&[0, 0]);
+        put_oid(out, name, false);
+        match self {
+            Value::Gauge32(v) | Value::TimeTicks(v) => out.extend_from_slice(&v.to_be_bytes()),
+            Value::Counter64(v) => out.extend_from_slice(&v.to_be_bytes()),
+            Value::NoSuchObject | Value::EndOfMibView => (),
+        }
+    }
+}
+
+/// The objects we serve, in OID order
+fn objects(base: &Oid, counters: &Counters, startup: Instant) -> Vec<(Oid, Value)> {
+    let uptime = (startup.elapsed().as_millis() / 10).min(u32::MAX as u128) as u32;
+    let clients = counters.clients.load(Ordering::Relaxed);
+    vec![
+        (
+            base.child(&[1, 0]),
+            Value::Gauge32(clients.min(u32::MAX as u64) as u32),
+        ),
+        (
+            base.child(&[2, 0]),
+            Value::Counter64(counters.connects.load(Ordering::Relaxed)),
+        ),
+        (
+            base.child(&[3, 0]),
+            Value::Counter64(counters.bytes.load(Ordering::Relaxed)),
+        ),
+        (base.child(&[4, 0]), Value::TimeTicks(uptime)),
+    ]
+}
+
+fn put_oid(out: &mut Vec<u8>, oid: &Oid, include: bool) {
+    out.extend_from_slice(&[oid.0.len() as u8, 0, include as u8, 0]);
+    for id in &oid.0 {
+        out.extend_from_slice(&id.to_be_bytes());
+    }
+}
+
+fn put_octets(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(data);
+    out.resize(out.len() + (4 - data.len() % 4) % 4, 0);
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads the fields of a PDU payload in the byte order the sender chose
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < n {
+            return Err(invalid("truncated PDU"));
+        }
+        let (head, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let b = self.take(2)?.try_into().unwrap();
+        Ok(if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let b = self.take(4)?.try_into().unwrap();
+        Ok(if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+
+    fn octets(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        let data = self.take(len)?;
+        self.take((4 - len % 4) % 4)?;
+        Ok(data)
+    }
+
+    /// An OID and its include flag, expanding the `1.3.6.1.<prefix>` shorthand
+    fn oid(&mut self) -> io::Result<(Oid, bool)> {
+        let header = self.take(4)?;
+        let (count, prefix, include) = (header[0], header[1], header[2] != 0);
+        let mut ids = Vec::with_capacity(count as usize + 5);
+        if prefix != 0 {
+            ids.extend_from_slice(&[1, 3, 6, 1, prefix as u32]);
+        }
+        for _ in 0..count {
+            ids.push(self.u32()?);
+        }
+        Ok((Oid(ids), include))
+    }
+
+    fn search_range(&mut self) -> io::Result<(Oid, bool, Oid)> {
+        let (start, include) = self.oid()?;
+        let (end, _) = self.oid()?;
+        Ok((start, include, end))
+    }
+}
+
+struct Pdu {
+    kind: u8,
+    flags: u8,
+    session: u32,
+    transaction: u32,
+    packet: u32,
+    payload: Vec<u8>,
+}
+
+impl Pdu {
+    fn reader(&self) -> Reader<'_> {
+        let mut reader = Reader {
+            data: &self.payload,
+            big_endian: self.flags & FLAG_NETWORK_BYTE_ORDER != 0,
+        };
+        // We only serve the default context, but skip past any other
+        if self.flags & FLAG_NON_DEFAULT_CONTEXT != 0 {
+            let _ = reader.octets();
+        }
+        reader
+    }
+}
+
+fn encode(kind: u8, session: u32, transaction: u32, packet: u32, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_LEN + payload.len());
+    out.extend_from_slice(&[VERSION, kind, FLAG_NETWORK_BYTE_ORDER, 0]);
+    for field in [session, transaction, packet, payload.len() as u32] {
+        out.extend_from_slice(&field.to_be_bytes());
+    }
+    out.extend_from_slice(payload);
+    out
+}
+
+async fn read_pdu<S: AsyncRead + Unpin>(sock: &mut S) -> io::Result<Pdu> {
+    let mut header = [0; HEADER_LEN];
+    sock.read_exact(&mut header).await?;
+    if header[0] != VERSION {
+        return Err(invalid("unsupported AgentX version"));
+    }
+
+    let big_endian = header[2] & FLAG_NETWORK_BYTE_ORDER != 0;
+    let mut reader = Reader {
+        data: &header[4..],
+        big_endian,
+    };
+    let (session, transaction, packet) = (reader.u32()?, reader.u32()?, reader.u32()?);
+    let len = reader.u32()? as usize;
+    if len > MAX_PAYLOAD {
+        return Err(invalid("oversized PDU"));
+    }
+
+    let mut payload = vec![0; len];
+    sock.read_exact(&mut payload).await?;
+    Ok(Pdu {
+        kind: header[1],
+        flags: header[2],
+        session,
+        transaction,
+        packet,
+        payload,
+    })
+}
+
+/// The first object in a search range, or `EndOfMibView` named after its start
+fn next(objects: &[(Oid, Value)], start: &Oid, include: bool, end: &Oid) -> (Oid, Value) {
+    objects
+        .iter()
+        .find(|(oid, _)| {
+            (oid > start || (include && oid == start)) && (end.0.is_empty() || oid < end)
+        })
+        .cloned()
+        .unwrap_or_else(|| (start.clone(), Value::EndOfMibView))
+}
+
+/// Answer a request from the master agent, returning the response payload
+fn respond(pdu: &Pdu, objects: &[(Oid, Value)]) -> io::Result<Vec<u8>> {
+    let mut reader = pdu.reader();
+    let mut error = 0u16;
+    let mut varbinds = vec![];
+
+    match pdu.kind {
+        PDU_GET => {
+            while !reader.data.is_empty() {
+                let (name, _, _) = reader.search_range()?;
+                let value = objects
+                    .iter()
+                    .find(|(oid, _)| *oid == name)
+                    .map_or(Value::NoSuchObject, |(_, value)| *value);
+                varbinds.push((name, value));
+            }
+        }
+        PDU_GET_NEXT => {
+            while !reader.data.is_empty() {
+                let (start, include, end) = reader.search_range()?;
+                varbinds.push(next(objects, &start, include, &end));
+            }
+        }
+        PDU_GET_BULK => {
+            let non_repeaters = reader.u16()? as usize;
+            let max_repetitions = reader.u16()?;
+            let mut ranges = vec![];
+            while !reader.data.is_empty() {
+                ranges.push(reader.search_range()?);
+            }
+
+            for (start, include, end) in ranges.iter().take(non_repeaters) {
+                varbinds.push(next(objects, start, *include, end));
+            }
+            let mut repeaters: Vec<_> = ranges.into_iter().skip(non_repeaters).collect();
+            for _ in 0..max_repetitions {
+                if repeaters.is_empty() {
+                    break;
+                }
+                let mut done = true;
+                for (start, include, end) in repeaters.iter_mut() {
+                    let (oid, value) = next(objects, start, *include, end);
+                    done &= value == Value::EndOfMibView;
+                    *start = oid.clone();
+                    *include = false;
+                    varbinds.push((oid, value));
+                }
+                if done {
+                    break;
+                }
+            }
+        }
+        // Everything we serve is read-only
+        PDU_TEST_SET => error = ERROR_NOT_WRITABLE,
+        _ => (),
+    }
+
+    let mut payload = vec![0; 4];
+    payload.extend_from_slice(&error.to_be_bytes());
+    payload.extend_from_slice(&[0, 0]);
+    for (name, value) in varbinds {
+        value.put(&mut payload, &name);
+    }
+    Ok(payload)
+}
+
+trait Socket: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Socket for T {}
+
+async fn connect(target: &AgentxTarget) -> io::Result<Box<dyn Socket>> {
+    match target {
+        AgentxTarget::Tcp(addr) => Ok(Box::new(tokio::net::TcpStream::connect(addr).await?)),
+        #[cfg(unix)]
+        AgentxTarget::Unix(path) => Ok(Box::new(tokio::net::UnixStream::connect(path).await?)),
+        #[cfg(not(unix))]
+        AgentxTarget::Unix(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Unix sockets are not supported on this platform",
+        )),
+    }
+}
+
+/// Send a session PDU and wait for its response, returning the session ID
+async fn request<S: Socket>(
+    sock: &mut S,
+    kind: u8,
+    session: u32,
+    packet: u32,
+    payload: &[u8],
+) -> io::Result<u32> {
+    sock.write_all(&encode(kind, session, 0, packet, payload))
+        .await?;
+    let response = read_pdu(sock).await?;
+    let mut reader = response.reader();
+    reader.u32()?;
+    match reader.u16()? {
+        0 => Ok(response.session),
+        error => Err(io::Error::other(format!("master agent error {}", error))),
+    }
+}
+
+async fn session(
+    target: &AgentxTarget,
+    base: &Oid,
+    counters: &Counters,
+    startup: Instant,
+) -> io::Result<()> {
+    let mut sock = connect(target).await?;
+
+    let mut open = vec![0; 4];
+    put_oid(&mut open, &Oid::default(), false);
+    put_octets(&mut open, b"tarssh");
+    let session_id = request(&mut sock, PDU_OPEN, 0, 1, &open).await?;
+
+    // Default timeout and priority, registering the whole subtree
+    let mut register = vec![0, 127, 0, 0];
+    put_oid(&mut register, base, false);
+    request(&mut sock, PDU_REGISTER, session_id, 2, &register).await?;
+    info!("agentx, target: {}, oid: {}", target, base);
+
+    loop {
+        let pdu = read_pdu(&mut sock).await?;
+        if pdu.kind == PDU_CLOSE {
+            return Ok(());
+        }
+        let payload = respond(&pdu, &objects(base, counters, startup))?;
+        let response = encode(
+            PDU_RESPONSE,
+            pdu.session,
+            pdu.transaction,
+            pdu.packet,
+            &payload,
+        );
+        sock.write_all(&response).await?;
+    }
+}
+
+/// Serve `counters` under `base` to the master agent at `target`, reconnecting
+/// whenever the session ends
+pub fn spawn(target: AgentxTarget, base: Oid, counters: Arc<Counters>, startup: Instant) {
+    tokio::spawn(async move {
+        loop {
+            match session(&target, &base, &counters, startup).await {
+                Ok(()) => info!("agentx, target: {}, closed: true", target),
+                Err(err) => warn!("agentx, target: {}, error: {}", target, err),
+            }
+            tokio::time::sleep(RETRY).await;
+        }
+    });
+}
+
+#[test]
+fn test_agentx_get_next() {
+    let base: Oid = ".1.3.6.1.4.1.8072.9999.9999".parse().unwrap();
+    let counters = Counters::default();
+    counters.connects.store(5, Ordering::Relaxed);
+    let objects = objects(&base, &counters, Instant::now());
+
+    // A GetNext from the subtree itself, with the prefix shorthand
+    let mut payload = vec![5, 4, 0, 0];
+    for id in [1, 8072, 9999, 9999, 1] {
+        payload.extend_from_slice(&(id as u32).to_le_bytes());
+    }
+    payload.extend_from_slice(&[0, 0, 0, 0]);
+    let pdu = Pdu {
+        kind: PDU_GET_NEXT,
+        flags: 0,
+        session: 1,
+        transaction: 1,
+        packet: 1,
+        payload,
+    };
+
+    let response = respond(&pdu, &objects).unwrap();
+    let mut expected = vec![0; 8];
+    Value::Gauge32(0).put(&mut expected, &base.child(&[1, 0]));
+    assert_eq!(response, expected);
+
+    let (oid, value) = next(&objects, &base.child(&[1, 0]), false, &Oid::default());
+    assert_eq!((oid, value), (base.child(&[2, 0]), Value::Counter64(5)));
+    let last = base.child(&[4, 0]);
+    assert_eq!(
+        next(&objects, &last, false, &Oid::default()),
+        (last, Value::EndOfMibView)
+    );
+}
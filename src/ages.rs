@@ -0,0 +1,73 @@
+//! Counting connected clients by how long they've been held, a quick measure
+//! of how well the tarpit's doing without listing every connection.
+
+use std::fmt;
+use std::iter::FromIterator;
+use std::time::Duration;
+
+/// Upper bounds of each bucket but the last, with its name
+const BUCKETS: [(Duration, &str); 4] = [
+    (Duration::from_secs(60), "under_1m"),
+    (Duration::from_secs(600), "under_10m"),
+    (Duration::from_secs(3600), "under_1h"),
+    (Duration::from_secs(86400), "under_1d"),
+];
+
+const OVER: &str = "over_1d";
+
+/// Connected clients in each age bucket
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Ages([usize; BUCKETS.len() + 1]);
+
+impl Ages {
+    pub fn add(&mut self, age: Duration) {
+        let bucket = BUCKETS
+            .iter()
+            .position(|(limit, _)| age < *limit)
+            .unwrap_or(BUCKETS.len());
+        self.0[bucket] += 1;
+    }
+
+    /// Each bucket's name and count, youngest first
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, usize)> + '_ {
+        BUCKETS
+            .iter()
+            .map(|(_, name)| *name)
+            .chain(Some(OVER))
+            .zip(self.0.iter().copied())
+    }
+}
+
+impl FromIterator<Duration> for Ages {
+    fn from_iter<I: IntoIterator<Item = Duration>>(ages: I) -> Self {
+        let mut buckets = Self::default();
+        for age in ages {
+            buckets.add(age);
+        }
+        buckets
+    }
+}
+
+impl fmt::Display for Ages {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, count)) in self.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}: {}", name, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_ages() {
+    let ages: Ages = [5, 59, 60, 3599, 86400, 100_000]
+        .iter()
+        .map(|secs| Duration::from_secs(*secs))
+        .collect();
+    assert_eq!(
+        ages.to_string(),
+        "under_1m: 2, under_10m: 1, under_1h: 1, under_1d: 0, over_1d: 2"
+    );
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+use log::warn;
+
+/// How long before a peer seen again is logged again, so it can be banned
+/// again once an earlier ban's expired
+const REPEAT: Duration = Duration::from_secs(600);
+
+/// Most peers to remember before sweeping out those due to be logged again
+const TRACKED: usize = 65536;
+
+/// A log of peers caught in the tarpit, for fail2ban and the like to ban from
+/// other services, with a filter for it in `extra/fail2ban`.
+///
+/// Each peer is logged as it connects, and again if it's still connecting
+/// after 10 minutes, one line each in a format that won't change:
+///
+/// ```txt
+/// 2022-06-07T00:00:00Z tarssh: tarpitted 192.0.2.1 port 61986
+/// ```
+pub struct BanLog {
+    file: Option<File>,
+    logged: HashMap<IpAddr, Instant>,
+}
+
+fn line(peer: SocketAddr, time: SystemTime) -> String {
+    format!(
+        "{} tarssh: tarpitted {} port {}",
+        humantime::format_rfc3339_seconds(time),
+        peer.ip(),
+        peer.port()
+    )
+}
+
+impl BanLog {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self {
+            file: Some(OpenOptions::new().create(true).append(true).open(path)?),
+            logged: HashMap::new(),
+        })
+    }
+
+    /// Log a peer that's just connected, unless it was logged recently
+    pub fn connected(&mut self, peer: SocketAddr, now: Instant, time: SystemTime) {
+        let ip = match peer.ip() {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(peer.ip(), IpAddr::V4),
+            v4 => v4,
+        };
+        match self.logged.get(&ip) {
+            Some(logged) if now.saturating_duration_since(*logged) < REPEAT => return,
+            _ => (),
+        }
+        if self.logged.len() >= TRACKED {
+            self.logged
+                .retain(|_, logged| now.saturating_duration_since(*logged) < REPEAT);
+        }
+        self.logged.insert(ip, now);
+
+        if let Some(file) = self.file.as_mut() {
+            if let Err(err) = writeln!(file, "{}", line(SocketAddr::new(ip, peer.port()), time)) {
+                warn!("ban_log, error: {}", err);
+                self.file = None;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_ban_log() {
+    let path = std::env::temp_dir().join(format!("tarssh-ban-log-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let mut log = BanLog::open(&path).unwrap();
+    let now = Instant::now();
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_654_560_000);
+    log.connected("192.0.2.1:61986".parse().unwrap(), now, time);
+    log.connected("[::ffff:192.0.2.1]:61987".parse().unwrap(), now, time);
+    log.connected("[2001:db8::1]:22".parse().unwrap(), now, time);
+    log.connected(
+        "192.0.2.1:61988".parse().unwrap(),
+        now + REPEAT,
+        time + REPEAT,
+    );
+    drop(log);
+
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "2022-06-07T00:00:00Z tarssh: tarpitted 192.0.2.1 port 61986\n\
+         2022-06-07T00:00:00Z tarssh: tarpitted 2001:db8::1 port 22\n\
+         2022-06-07T00:10:00Z tarssh: tarpitted 192.0.2.1 port 61988\n"
+    );
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,441 @@
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::chatter::Chatter;
+use crate::markov::Markov;
+use crate::template::Template;
+
+static SSH: &[u8] = b"My name is Yon Yonson,\r\n\
+    I live in Wisconsin.\r\n\
+    I work in a lumber yard there.\r\n\
+    The people I meet as\r\n\
+    I walk down the street,\r\n\
+    They say \"Hello!\"\r\n\
+    I say \"Hello!\"\r\n\
+    They say \"What's your name.\"\r\n\
+    I say: ";
+
+/// A MySQL protocol 10 handshake claiming to be a maximum-length packet
+static MYSQL: &[u8] = b"\xff\xff\xff\x00\x0a5.7.33-0ubuntu0.18.04.1-log\x00\
+    \x2a\x1b\x00\x00\x3f\x4e\x16\x58\x31\x5d\x2d\x6a\x00\xff\xf7\x08\x02\x00\xff\x81\x15";
+
+/// An X.224 Connection Confirm in a maximum-length TPKT
+static RDP: &[u8] = b"\x03\x00\xff\xff\x0e\xd0\x00\x00\x12\x34\x00\x02\x1f\x08\x00";
+
+/// A VNC greeting, followed by a list of as many security types as allowed
+static VNC: &[u8] = b"RFB 003.008\n\xff";
+
+static SIP: &[u8] = b"SIP/2.0 100 Trying\r\n";
+
+/// The first line of an SMTP greeting, with more to follow
+static SMTP: &[u8] = b"220-mail.example.com ESMTP Postfix (Debian/GNU)\r\n";
+
+/// Continuation lines for an SMTP greeting that never ends
+static SMTP_LINES: &[u8] = b"220-Please wait while we check your connection\r\n";
+
+/// Headers for a SIP response that never ends
+static SIP_HEADERS: &[u8] = b"Via: SIP/2.0/TCP 10.0.0.1:5060;branch=z9hG4bK776asdhds\r\n\
+    Server: Asterisk PBX 16.2.1~dfsg-1+deb10u2\r\n\
+    Allow: INVITE, ACK, CANCEL, OPTIONS, BYE, REFER, SUBSCRIBE, NOTIFY, INFO, PUBLISH, MESSAGE\r\n\
+    Supported: replaces, timer\r\n\
+    X-Trace: 8c8ab1e2-6bb8-4c39-a1d4-59be11c1a4f3\r\n";
+
+/// How much of a banner to send per write
+#[derive(Debug, Clone, Copy)]
+pub enum Chunk {
+    /// Up to and including the next newline
+    Line,
+    /// A fixed number of bytes
+    Bytes(usize),
+}
+
+/// Built-in banners for commonly scanned services
+#[derive(Debug, Clone, Copy)]
+pub enum Preset {
+    Ssh,
+    Mysql,
+    Rdp,
+    Vnc,
+    Sip,
+    Smtp,
+    Openssh,
+    Random,
+    Junk,
+    Keepalive,
+    Template,
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ssh" => Ok(Preset::Ssh),
+            "mysql" => Ok(Preset::Mysql),
+            "rdp" => Ok(Preset::Rdp),
+            "vnc" => Ok(Preset::Vnc),
+            "sip" => Ok(Preset::Sip),
+            "smtp" => Ok(Preset::Smtp),
+            "openssh" => Ok(Preset::Openssh),
+            "random" => Ok(Preset::Random),
+            "junk" => Ok(Preset::Junk),
+            "keepalive" => Ok(Preset::Keepalive),
+            "template" => Ok(Preset::Template),
+            _ => Err(format!(
+                "unknown banner {:?}, expected one of ssh, mysql, rdp, vnc, sip, smtp, openssh, random, junk, keepalive, template",
+                s
+            )),
+        }
+    }
+}
+
+impl Preset {
+    /// The name the preset is given by, as `--banner` takes it
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Ssh => "ssh",
+            Preset::Mysql => "mysql",
+            Preset::Rdp => "rdp",
+            Preset::Vnc => "vnc",
+            Preset::Sip => "sip",
+            Preset::Smtp => "smtp",
+            Preset::Openssh => "openssh",
+            Preset::Random => "random",
+            Preset::Junk => "junk",
+            Preset::Keepalive => "keepalive",
+            Preset::Template => "template",
+        }
+    }
+}
+
+/// Longest chunk of junk to send per write
+const JUNK_MAX: usize = 16;
+
+/// Longest line the random banner may generate, excluding CRLF
+pub const RANDOM_LINE_LIMIT: usize = 253;
+
+/// Characters the random banner draws from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Charset {
+    /// Printable ASCII, including space
+    Printable,
+    /// ASCII letters and digits
+    Alphanumeric,
+    /// Any byte but NUL, CR and LF
+    Binary,
+}
+
+impl FromStr for Charset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "printable" => Ok(Charset::Printable),
+            "alnum" => Ok(Charset::Alphanumeric),
+            "binary" => Ok(Charset::Binary),
+            _ => Err(format!(
+                "unknown charset {:?}, expected one of printable, alnum, binary",
+                s
+            )),
+        }
+    }
+}
+
+impl Charset {
+    pub(crate) fn sample<R: Rng>(self, rng: &mut R) -> u8 {
+        match self {
+            Charset::Printable => rng.gen_range(b' '..=b'~'),
+            Charset::Alphanumeric => rng.sample(rand::distributions::Alphanumeric),
+            Charset::Binary => loop {
+                match rng.gen() {
+                    b'\0' | b'\r' | b'\n' => continue,
+                    b => break b,
+                }
+            },
+        }
+    }
+}
+
+/// Shape of the lines generated by the random banner
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RandomLines {
+    /// Shortest line, excluding CRLF
+    pub min: usize,
+    /// Longest line, excluding CRLF
+    pub max: usize,
+    pub charset: Charset,
+}
+
+impl Default for RandomLines {
+    fn default() -> Self {
+        Self {
+            min: 1,
+            max: 30,
+            charset: Charset::Printable,
+        }
+    }
+}
+
+/// What to send a client.
+#[derive(Debug, Clone)]
+pub enum Banner {
+    /// A prefix sent once, followed by filler repeated forever, split into
+    /// chunks sent one per write.
+    Fixed {
+        prefix: Cow<'static, [u8]>,
+        filler: Cow<'static, [u8]>,
+        chunk: Chunk,
+    },
+    /// Random lines, one per write
+    Random(RandomLines),
+    /// Plausible server chatter, one line per write
+    Chatter(Chatter),
+    /// Pseudo-English generated from a corpus, one line per write
+    Markov(Arc<Markov>),
+    /// Random bytes that are never valid UTF-8, never contain CR or LF, and so
+    /// never form a line
+    Junk,
+    /// A greeting, then lines as a template describes, one per write
+    Template(Arc<Template>),
+}
+
+impl From<Preset> for Banner {
+    fn from(preset: Preset) -> Self {
+        let (prefix, filler, chunk): (&[u8], &[u8], _) = match preset {
+            Preset::Ssh => (b"", SSH, Chunk::Line),
+            Preset::Mysql => (MYSQL, b"\0", Chunk::Bytes(1)),
+            Preset::Rdp => (RDP, b"\0", Chunk::Bytes(1)),
+            Preset::Vnc => (VNC, b"\x02", Chunk::Bytes(1)),
+            Preset::Sip => (SIP, SIP_HEADERS, Chunk::Line),
+            Preset::Smtp => (SMTP, SMTP_LINES, Chunk::Line),
+            // The least we can send that's still a valid pre-banner line
+            Preset::Keepalive => (b"", b"\r\n", Chunk::Line),
+            Preset::Openssh => return Banner::Chatter(Chatter::default()),
+            Preset::Random => return Banner::Random(RandomLines::default()),
+            Preset::Junk => return Banner::Junk,
+            Preset::Template => return Banner::Template(Arc::default()),
+        };
+
+        Banner::Fixed {
+            prefix: prefix.into(),
+            filler: filler.into(),
+            chunk,
+        }
+    }
+}
+
+impl Banner {
+    /// Send each line of `text` in turn, forever.
+    ///
+    /// Lines are terminated with CRLF, cut to `RANDOM_LINE_LIMIT`, and kept
+    /// from looking like an SSH version string.
+    pub fn lines(text: &[u8]) -> Result<Self, String> {
+        let mut filler = Vec::with_capacity(text.len());
+        for line in text.split(|b| *b == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let line = &line[..line.len().min(RANDOM_LINE_LIMIT)];
+            if line.starts_with(b"SSH-") {
+                filler.push(b'_');
+                filler.extend_from_slice(&line[1..]);
+            } else {
+                filler.extend_from_slice(line);
+            }
+            filler.extend_from_slice(b"\r\n");
+        }
+        // A trailing newline shouldn't add a blank line
+        if text.ends_with(b"\n") {
+            filler.truncate(filler.len() - 2);
+        }
+        if filler.iter().all(|b| b.is_ascii_whitespace()) {
+            return Err("banner is empty".to_string());
+        }
+
+        Ok(Banner::Fixed {
+            prefix: Cow::Borrowed(b""),
+            filler: filler.into(),
+            chunk: Chunk::Line,
+        })
+    }
+
+    /// Send `text` exactly as given, a line at a time, forever.
+    ///
+    /// Every line must end in CRLF, and none may start with `SSH-`, which
+    /// would end the pre-banner.
+    pub fn verbatim(text: &[u8]) -> Result<Self, String> {
+        if text.is_empty() {
+            return Err("banner is empty".to_string());
+        }
+        for (i, line) in text.split_inclusive(|b| *b == b'\n').enumerate() {
+            if !line.ends_with(b"\r\n") {
+                return Err(format!("line {} doesn't end in CRLF", i + 1));
+            }
+            if line.starts_with(b"SSH-") {
+                return Err(format!("line {} starts with SSH-", i + 1));
+            }
+        }
+
+        Ok(Banner::Fixed {
+            prefix: Cow::Borrowed(b""),
+            filler: text.to_vec().into(),
+            chunk: Chunk::Line,
+        })
+    }
+
+    /// The next chunk to send, given how many bytes have been sent so far.
+    ///
+    /// Generated chunks are written to `buf`.
+    pub fn next<'a, R: Rng>(&'a self, sent: u64, rng: &mut R, buf: &'a mut [u8]) -> &'a [u8] {
+        match self {
+            Banner::Fixed {
+                prefix,
+                filler,
+                chunk,
+            } => {
+                let pos = match sent.checked_sub(prefix.len() as u64) {
+                    None => &prefix[sent as usize..],
+                    Some(n) => &filler[(n % filler.len() as u64) as usize..],
+                };
+
+                let len = match chunk {
+                    Chunk::Line => pos
+                        .iter()
+                        .position(|b| *b == b'\n')
+                        .map_or(pos.len(), |i| i + 1),
+                    Chunk::Bytes(n) => (*n).min(pos.len()),
+                };
+
+                &pos[..len]
+            }
+            Banner::Random(lines) => {
+                let len = rng.gen_range(lines.min..=lines.max).min(buf.len() - 2) + 2;
+                buf[..len - 2]
+                    .iter_mut()
+                    .for_each(|b| *b = lines.charset.sample(rng));
+                // RFC 4253 says only the version string may start with this
+                if buf.starts_with(b"SSH-") {
+                    buf[0] = b'_';
+                }
+                buf[len - 2..len].copy_from_slice(b"\r\n");
+                &buf[..len]
+            }
+            Banner::Chatter(chatter) => {
+                let mut line = String::with_capacity(buf.len());
+                chatter.line(rng, &mut line);
+                // Only ever ASCII, so any length is a character boundary
+                line.truncate(RANDOM_LINE_LIMIT.min(buf.len() - 2));
+                line.push_str("\r\n");
+                buf[..line.len()].copy_from_slice(line.as_bytes());
+                &buf[..line.len()]
+            }
+            Banner::Markov(markov) => {
+                let mut line = String::with_capacity(buf.len());
+                markov.line(rng, RANDOM_LINE_LIMIT.min(buf.len() - 2), &mut line);
+                if line.starts_with("SSH-") {
+                    line.replace_range(..1, "_");
+                }
+                line.push_str("\r\n");
+                buf[..line.len()].copy_from_slice(line.as_bytes());
+                &buf[..line.len()]
+            }
+            Banner::Junk => {
+                let len = rng.gen_range(1..=JUNK_MAX.min(buf.len()));
+                // Bytes from 0xf8 can never appear in UTF-8
+                buf[0] = rng.gen_range(0xf8..=0xff);
+                buf[1..len]
+                    .iter_mut()
+                    .for_each(|b| *b = Charset::Binary.sample(rng));
+                &buf[..len]
+            }
+            Banner::Template(template) => template.next(sent, rng, buf),
+        }
+    }
+}
+
+#[cfg(test)]
+fn next_owned(banner: &Banner, sent: u64) -> Vec<u8> {
+    use rand::SeedableRng;
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    banner.next(sent, &mut rng, &mut [0; 256]).to_vec()
+}
+
+#[test]
+fn test_preset_name() {
+    for name in "ssh mysql rdp vnc sip smtp openssh random junk keepalive template".split(' ') {
+        assert_eq!(name.parse::<Preset>().unwrap().name(), name);
+    }
+}
+
+#[test]
+fn test_banner_next() {
+    let banner = Banner::from(Preset::Ssh);
+    assert_eq!(next_owned(&banner, 0), b"My name is Yon Yonson,\r\n");
+    assert_eq!(next_owned(&banner, 3), b"name is Yon Yonson,\r\n");
+    assert_eq!(next_owned(&banner, SSH.len() as u64 - 2), b": ");
+    assert_eq!(
+        next_owned(&banner, SSH.len() as u64),
+        b"My name is Yon Yonson,\r\n"
+    );
+
+    let banner = Banner::from(Preset::Vnc);
+    assert_eq!(next_owned(&banner, 0), b"R");
+    assert_eq!(next_owned(&banner, VNC.len() as u64 - 1), b"\xff");
+    assert_eq!(next_owned(&banner, VNC.len() as u64 + 100), b"\x02");
+
+    let banner = Banner::from(Preset::Keepalive);
+    assert_eq!(next_owned(&banner, 0), b"\r\n");
+    assert_eq!(next_owned(&banner, 1), b"\n");
+    assert_eq!(next_owned(&banner, 1000), b"\r\n");
+
+    let banner = Banner::lines(b"Welcome\nSSH-2.0-nope\r\n").unwrap();
+    assert_eq!(next_owned(&banner, 0), b"Welcome\r\n");
+    assert_eq!(next_owned(&banner, 9), b"_SH-2.0-nope\r\n");
+    assert_eq!(next_owned(&banner, 23), b"Welcome\r\n");
+    assert!(Banner::lines(b"\n\r\n").is_err());
+
+    let banner = Banner::verbatim(b"Hello\r\nthere\r\n").unwrap();
+    assert_eq!(next_owned(&banner, 0), b"Hello\r\n");
+    assert_eq!(next_owned(&banner, 14), b"Hello\r\n");
+    assert!(Banner::verbatim(b"Hello\r\nthere").is_err());
+    assert!(Banner::verbatim(b"Hello\nthere\r\n").is_err());
+    assert!(Banner::verbatim(b"SSH-2.0-nope\r\n").is_err());
+    assert!(Banner::verbatim(b"").is_err());
+}
+
+#[cfg(test)]
+quickcheck::quickcheck! {
+    fn prop_random_banner(seed: u64, min: u8, max: u8, binary: bool) -> bool {
+        use rand::SeedableRng;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        let mut buf = [0; 256];
+        let lines = RandomLines {
+            min: (min.min(max) as usize).min(RANDOM_LINE_LIMIT),
+            max: (max.max(min) as usize).min(RANDOM_LINE_LIMIT),
+            charset: if binary { Charset::Binary } else { Charset::Printable },
+        };
+        let banner = Banner::Random(lines);
+        let line = banner.next(0, &mut rng, &mut buf);
+        let text = &line[..line.len() - 2];
+        (lines.min..=lines.max).contains(&text.len())
+            && line.ends_with(b"\r\n")
+            && !line.starts_with(b"SSH-")
+            && text.iter().all(|b| !b"\0\r\n".contains(b))
+            && (binary || text.iter().all(|b| (b' '..=b'~').contains(b)))
+    }
+}
+
+#[cfg(test)]
+quickcheck::quickcheck! {
+    fn prop_junk_banner(seed: u64) -> bool {
+        use rand::SeedableRng;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        let mut buf = [0; 256];
+        let junk = Banner::Junk.next(0, &mut rng, &mut buf);
+        !junk.is_empty()
+            && std::str::from_utf8(junk).is_err()
+            && !junk.contains(&b'\r')
+            && !junk.contains(&b'\n')
+    }
+}
@@ -0,0 +1,114 @@
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{info, warn};
+
+use crate::http::HttpUrl;
+use tarssh::banner::Banner;
+
+/// How long a banner fetch may take before falling back to the cache
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A banner fetched over HTTP, with an optional copy on disk for when the
+/// server can't be reached
+#[derive(Debug, Clone)]
+pub struct BannerSource {
+    pub url: HttpUrl,
+    pub cache: Option<PathBuf>,
+}
+
+impl BannerSource {
+    /// Fetch the banner, updating the cache, or fall back to the cache if
+    /// the fetch fails
+    pub async fn load(&self) -> io::Result<Banner> {
+        let fetched = self.url.get(FETCH_TIMEOUT).await.and_then(|text| {
+            let banner = Banner::lines(&text).map_err(invalid)?;
+            Ok((text, banner))
+        });
+
+        match (fetched, &self.cache) {
+            (Ok((text, banner)), cache) => {
+                info!("banner, url: {}, bytes: {}", self.url, text.len());
+                if let Some(cache) = cache {
+                    if let Err(err) = replace(cache, &text) {
+                        warn!("banner, cache: {}, error: {}", cache.display(), err);
+                    }
+                }
+                Ok(banner)
+            }
+            (Err(err), Some(cache)) => {
+                warn!("banner, url: {}, error: {}", self.url, err);
+                let text = std::fs::read(cache)?;
+                info!("banner, cache: {}, bytes: {}", cache.display(), text.len());
+                Banner::lines(&text).map_err(invalid)
+            }
+            (Err(err), None) => Err(err),
+        }
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Write `data` to `path` without ever leaving a partial file behind
+fn replace(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut partial = OsString::from(path.as_os_str());
+    partial.push(".part");
+    std::fs::write(&partial, data)?;
+    std::fs::rename(&partial, path)
+}
+
+#[cfg(test)]
+fn filler(banner: &Banner) -> &[u8] {
+    match banner {
+        Banner::Fixed { filler, .. } => filler,
+        _ => panic!("expected lines, got {:?}", banner),
+    }
+}
+
+#[test]
+fn test_banner_cache() {
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (mut sock, _) = listener.accept().unwrap();
+        let mut request = vec![];
+        let mut buf = [0; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            let n = sock.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[..n]);
+        }
+        sock.write_all(b"HTTP/1.0 200 OK\r\n\r\nhello\nworld\n")
+            .unwrap();
+    });
+
+    let cache = std::env::temp_dir().join(format!("tarssh-banner-cache-{}", std::process::id()));
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let source = BannerSource {
+        url: format!("http://{}/banner.txt", addr).parse().unwrap(),
+        cache: Some(cache.clone()),
+    };
+    let fetched = runtime.block_on(source.load()).unwrap();
+    server.join().unwrap();
+    assert_eq!(filler(&fetched), b"hello\r\nworld\r\n");
+    assert_eq!(std::fs::read(&cache).unwrap(), b"hello\nworld\n");
+
+    // The server's gone, so the cached copy stands in
+    let cached = runtime.block_on(source.load()).unwrap();
+    assert_eq!(filler(&cached), filler(&fetched));
+
+    let uncached = BannerSource {
+        cache: None,
+        ..source
+    };
+    assert!(runtime.block_on(uncached.load()).is_err());
+    std::fs::remove_file(&cache).unwrap();
+}
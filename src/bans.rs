@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Addresses refused at accept, each until an optional expiry.
+///
+/// With a file, bans survive restarts.  It has a line per address:
+/// `<ip> <unix expiry>`, or `-` in place of the expiry for permanent bans.
+#[derive(Default)]
+pub struct BanList {
+    path: Option<PathBuf>,
+    bans: HashMap<IpAddr, Option<SystemTime>>,
+}
+
+fn parse_line(line: &str) -> Option<(IpAddr, Option<SystemTime>)> {
+    let mut fields = line.split_whitespace();
+    let ip = fields.next()?.parse().ok()?;
+    let until = match fields.next()? {
+        "-" => None,
+        secs => Some(UNIX_EPOCH + Duration::from_secs(secs.parse().ok()?)),
+    };
+    Some((ip, until))
+}
+
+impl BanList {
+    /// Load the list at `path`, starting empty if it doesn't exist yet
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut bans = HashMap::new();
+
+        match std::fs::File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    if let Some((ip, until)) = parse_line(&line?) {
+                        bans.insert(ip, until);
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+
+        Ok(Self {
+            path: Some(path),
+            bans,
+        })
+    }
+
+    /// Read the file again, for bans added to it by something else, returning
+    /// whether they differ from those already held
+    pub fn reload(&mut self) -> io::Result<bool> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+        let reloaded = Self::open(path)?;
+        let changed = reloaded.bans != self.bans;
+        *self = reloaded;
+        Ok(changed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.bans.len()
+    }
+
+    /// Ban `ip`, for `ttl` if given or else until unbanned, replacing any
+    /// existing ban
+    pub fn ban(&mut self, ip: IpAddr, ttl: Option<Duration>, now: SystemTime) -> io::Result<()> {
+        self.bans.insert(ip, ttl.map(|ttl| now + ttl));
+        self.save()
+    }
+
+    /// Lift the ban on `ip`, returning whether there was one
+    pub fn unban(&mut self, ip: IpAddr) -> io::Result<bool> {
+        let found = self.bans.remove(&ip).is_some();
+        if found {
+            self.save()?;
+        }
+        Ok(found)
+    }
+
+    pub fn is_banned(&self, ip: IpAddr, now: SystemTime) -> bool {
+        match self.bans.get(&ip) {
+            Some(Some(until)) => *until > now,
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /// Forget bans that have run out, returning how many went
+    pub fn expire(&mut self, now: SystemTime) -> usize {
+        let before = self.bans.len();
+        self.bans
+            .retain(|_, until| until.is_none_or(|until| until > now));
+        before - self.bans.len()
+    }
+
+    /// Current bans with the time each has left, in address order
+    pub fn list(&self, now: SystemTime) -> Vec<(IpAddr, Option<Duration>)> {
+        let mut list: Vec<_> = self
+            .bans
+            .iter()
+            .filter(|(ip, _)| self.is_banned(**ip, now))
+            .map(|(ip, until)| {
+                let left = until.map(|until| until.duration_since(now).unwrap_or_default());
+                (*ip, left)
+            })
+            .collect();
+        list.sort_by_key(|(ip, _)| *ip);
+        list
+    }
+
+    /// Write the list out, if it has a file, replacing it atomically
+    pub fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let mut partial = OsString::from(path.as_os_str());
+        partial.push(".part");
+        let mut out = BufWriter::new(std::fs::File::create(&partial)?);
+        for (ip, until) in &self.bans {
+            match until {
+                Some(until) => {
+                    let secs = until.duration_since(UNIX_EPOCH).unwrap_or_default();
+                    writeln!(out, "{} {}", ip, secs.as_secs())?
+                }
+                None => writeln!(out, "{} -", ip)?,
+            }
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&partial, path)
+    }
+}
+
+#[test]
+fn test_ban_list() {
+    let path = std::env::temp_dir().join(format!("tarssh-bans-{}", std::process::id()));
+    let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let hour = Duration::from_secs(3600);
+    let a: IpAddr = [192, 0, 2, 1].into();
+    let b: IpAddr = "2001:db8::1".parse().unwrap();
+
+    let mut bans = BanList::open(&path).unwrap();
+    bans.ban(a, Some(hour), now).unwrap();
+    bans.ban(b, None, now).unwrap();
+    assert!(bans.is_banned(a, now));
+    assert!(!bans.is_banned(a, now + hour));
+
+    let mut bans = BanList::open(&path).unwrap();
+    assert_eq!(bans.list(now), vec![(a, Some(hour)), (b, None)]);
+    assert_eq!(bans.expire(now + hour), 1);
+    assert!(bans.unban(b).unwrap());
+    assert!(!bans.unban(b).unwrap());
+    assert_eq!(BanList::open(&path).unwrap().len(), 0);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_ban_list_reload() {
+    let path = std::env::temp_dir().join(format!("tarssh-bans-reload-{}", std::process::id()));
+    let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let a: IpAddr = [192, 0, 2, 1].into();
+    let b: IpAddr = [192, 0, 2, 2].into();
+
+    let mut bans = BanList::open(&path).unwrap();
+    bans.ban(a, None, now).unwrap();
+    assert!(!bans.reload().unwrap());
+
+    // Appended by a feed
+    let mut text = std::fs::read_to_string(&path).unwrap();
+    text.push_str("192.0.2.2 -\nnot an address\n");
+    std::fs::write(&path, text).unwrap();
+    assert!(bans.reload().unwrap());
+    assert!(bans.is_banned(a, now));
+    assert!(bans.is_banned(b, now));
+    assert_eq!(bans.len(), 2);
+
+    assert!(!BanList::default().reload().unwrap());
+    std::fs::remove_file(path).unwrap();
+}
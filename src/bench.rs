@@ -0,0 +1,131 @@
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use structopt::StructOpt;
+use tokio::net::TcpStream;
+
+use crate::dnsbl::Action;
+use crate::notify::Notifier;
+use crate::pacing::{Pacing, Schedule, Timeouts};
+use crate::peer_addr::PeerAddr;
+use crate::tap::Tap;
+use crate::Connection;
+use tarssh::banner::{Banner, Preset};
+
+/// Population steps to time write rounds at, as fractions of the total
+const STEPS: usize = 4;
+
+#[derive(Debug, StructOpt)]
+pub struct BenchConfig {
+    /// Loopback connections to open, each needing two file descriptors
+    #[structopt(short = "n", long = "connections", default_value = "1000")]
+    connections: usize,
+    /// Write rounds to time at each population step
+    #[structopt(long = "rounds", default_value = "10")]
+    rounds: u32,
+}
+
+/// Resident memory in bytes, where we know how to find it
+pub fn rss() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * 4096)
+}
+
+pub async fn run(config: BenchConfig) -> io::Result<()> {
+    let addr: SocketAddr = ([127, 0, 0, 1], 0).into();
+    let mut listener = crate::listen_socket(addr, None).await?;
+    let addr = listener.as_ref().local_addr()?;
+
+    // Pretend tarssh has been up a while, so every round is due a write
+    let startup = Instant::now()
+        .checked_sub(Duration::from_secs(1))
+        .unwrap_or_else(Instant::now);
+    let pacing = Pacing {
+        banner: Banner::from(Preset::Ssh),
+        schedule: Schedule::fixed(Duration::from_secs(1)),
+        timeouts: Timeouts {
+            write: Duration::MAX,
+            initial_data: None,
+            lifetime: None,
+            relaxed: None,
+        },
+        ssh_version: None,
+        cork: false,
+        tuned: vec![],
+        banners: vec![],
+        listeners: vec![],
+        listed: Action::Tag,
+        listed_delay: Duration::ZERO,
+    };
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut notifier = Notifier::default();
+
+    let rss_before = rss();
+    let mut clients = Vec::with_capacity(config.connections);
+    let mut connections = Vec::with_capacity(config.connections);
+
+    let start = Instant::now();
+    for _ in 0..config.connections {
+        clients.push(TcpStream::connect(addr).await?);
+        let sock = listener
+            .next()
+            .await
+            .ok_or_else(|| io::Error::other("listener closed"))??;
+        let peer = PeerAddr::from(sock.peer_addr()?);
+        connections.push(Connection::new(sock, peer, startup, Tap::default()));
+    }
+    let admitted = start.elapsed();
+
+    println!("connections: {}", config.connections);
+    println!(
+        "admitted: {:.2?} ({:.0}/s)",
+        admitted,
+        config.connections as f64 / admitted.as_secs_f64()
+    );
+    println!(
+        "connection size: {} bytes",
+        std::mem::size_of::<Connection>()
+    );
+    if let (Some(before), Some(after)) = (rss_before, rss()) {
+        println!(
+            "rss per connection: {} bytes",
+            after.saturating_sub(before) / config.connections.max(1) as u64
+        );
+    }
+
+    println!("\nround latency by population:");
+    for step in 1..=STEPS {
+        let population = config.connections * step / STEPS;
+        let mut bytes = 0;
+        let mut slowest = Duration::ZERO;
+
+        let start = Instant::now();
+        for _ in 0..config.rounds {
+            let round = Instant::now();
+            for connection in &mut connections[..population] {
+                connection.last_write = Default::default();
+                if let Ok(n) = connection.tick(&pacing, startup, &mut rng, &mut notifier) {
+                    bytes += n;
+                }
+            }
+            slowest = slowest.max(round.elapsed());
+        }
+        let mean = start.elapsed() / config.rounds.max(1);
+
+        println!(
+            "  {:>8} conns  mean {:>10.2?}  max {:>10.2?}  {:>8.2?}/conn  {} bytes",
+            population,
+            mean,
+            slowest,
+            mean / population.max(1) as u32,
+            bytes
+        );
+    }
+
+    Ok(())
+}
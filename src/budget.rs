@@ -0,0 +1,109 @@
+use std::fmt;
+
+/// Rough cost of each socket beyond what tarssh allocates for it: the
+/// runtime's registration and the kernel's own bookkeeping
+pub const SOCKET_OVERHEAD: usize = 512;
+
+/// Rough cost of a connection held aside by --require-data, buffer included
+pub const PENDING_COST: usize = SOCKET_OVERHEAD + 512;
+
+/// An estimate of memory used by connections, kept per slot so it can be
+/// refreshed as each slot is serviced rather than by walking every connection.
+///
+/// Accepts pause once the estimate reaches nine tenths of the limit, leaving
+/// room for existing connections to grow.
+pub struct MemoryBudget {
+    limit: usize,
+    slots: Box<[usize]>,
+    accepting: bool,
+}
+
+/// Whether accepting has paused or resumed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shed {
+    Paused { used: usize },
+    Resumed { used: usize },
+}
+
+impl fmt::Display for Shed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shed::Paused { used } => write!(f, "accepting: false, used: {}", used),
+            Shed::Resumed { used } => write!(f, "accepting: true, used: {}", used),
+        }
+    }
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize, slots: usize) -> Self {
+        Self {
+            limit,
+            slots: vec![0; slots].into_boxed_slice(),
+            accepting: true,
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Replace the estimate for a slot after servicing it
+    pub fn set(&mut self, slot: usize, bytes: usize) {
+        self.slots[slot] = bytes;
+    }
+
+    pub fn add(&mut self, slot: usize, bytes: usize) {
+        self.slots[slot] += bytes;
+    }
+
+    pub fn sub(&mut self, slot: usize, bytes: usize) {
+        self.slots[slot] = self.slots[slot].saturating_sub(bytes);
+    }
+
+    pub fn used(&self, pending: usize) -> usize {
+        self.slots.iter().sum::<usize>() + pending * PENDING_COST
+    }
+
+    pub fn accepting(&self) -> bool {
+        self.accepting
+    }
+
+    /// How far usage is over the limit itself
+    pub fn excess(&self, pending: usize) -> usize {
+        self.used(pending).saturating_sub(self.limit)
+    }
+
+    /// Compare usage against the limit, returning any change in whether
+    /// new connections should be accepted
+    pub fn check(&mut self, pending: usize) -> Option<Shed> {
+        let used = self.used(pending);
+        match (self.accepting, used < self.limit / 10 * 9) {
+            (true, false) => {
+                self.accepting = false;
+                Some(Shed::Paused { used })
+            }
+            (false, true) => {
+                self.accepting = true;
+                Some(Shed::Resumed { used })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_memory_budget() {
+    let mut budget = MemoryBudget::new(10_000, 2);
+    budget.add(0, 4000);
+    budget.add(1, 4000);
+    assert_eq!(budget.check(0), None);
+    assert_eq!(budget.excess(2), 48);
+    assert_eq!(budget.check(2), Some(Shed::Paused { used: 10_048 }));
+    assert!(!budget.accepting());
+    assert_eq!(budget.check(2), None);
+
+    budget.set(0, 1000);
+    assert_eq!(budget.check(2), Some(Shed::Resumed { used: 7048 }));
+    budget.sub(1, 5000);
+    assert_eq!(budget.used(0), 1000);
+}
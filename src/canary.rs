@@ -0,0 +1,182 @@
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use regex::bytes::{Regex, RegexBuilder};
+
+/// How much trailing client data to keep so patterns split across reads still match
+const WINDOW: usize = 256;
+
+/// A pattern that raises an alert when it appears in client data.
+///
+/// Written as `str:<text>`, `re:<regex>` or `hex:<bytes>`, e.g.
+/// `str:db01.corp.example`, `re:(?i)passw(or)?d` or `hex:deadbeef`.  Patterns
+/// matching empty input are refused, as are literals longer than `WINDOW`.
+#[derive(Debug)]
+pub struct Canary {
+    spec: String,
+    re: Regex,
+}
+
+impl FromStr for Canary {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (kind, pattern) = spec
+            .split_once(':')
+            .ok_or_else(|| format!("expected str:, re: or hex: prefix in {:?}", spec))?;
+
+        let literal = match kind {
+            "str" => Some(pattern.as_bytes().to_vec()),
+            "hex" => Some(unhex(pattern)?),
+            "re" => None,
+            _ => return Err(format!("unknown canary type {:?}", kind)),
+        };
+        if let Some(literal) = &literal {
+            if literal.len() > WINDOW {
+                return Err(format!(
+                    "canary {:?} is longer than {} bytes, so could be missed when split across reads",
+                    spec, WINDOW
+                ));
+            }
+        }
+
+        let pattern = match literal {
+            Some(literal) => escape(&literal),
+            None => pattern.to_string(),
+        };
+
+        let re = RegexBuilder::new(&pattern)
+            .unicode(false)
+            .build()
+            .map_err(|e| e.to_string())?;
+        if re.is_match(b"") {
+            return Err(format!(
+                "canary {:?} matches empty input, so every client",
+                spec
+            ));
+        }
+
+        Ok(Self {
+            spec: spec.to_string(),
+            re,
+        })
+    }
+}
+
+impl fmt::Display for Canary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.spec)
+    }
+}
+
+fn escape(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("\\x{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Result<Vec<u8>, String> {
+    if s.is_empty() || !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return Err(format!("invalid hex string {:?}", s));
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|e| e.to_string()))
+        .collect()
+}
+
+/// Per-connection canary state, firing each pattern at most once.
+pub struct CanaryWatch {
+    canaries: Rc<[Canary]>,
+    window: Vec<u8>,
+    fired: Vec<bool>,
+}
+
+impl CanaryWatch {
+    pub fn new(canaries: Rc<[Canary]>) -> Self {
+        Self {
+            fired: vec![false; canaries.len()],
+            canaries,
+            window: Vec::with_capacity(WINDOW * 2),
+        }
+    }
+
+    /// Feed client data, returning any canaries seen for the first time
+    pub fn scan(&mut self, data: &[u8]) -> Vec<&Canary> {
+        self.window.extend_from_slice(data);
+
+        let mut hits = vec![];
+        for (canary, fired) in self.canaries.iter().zip(self.fired.iter_mut()) {
+            if !*fired && canary.re.is_match(&self.window) {
+                *fired = true;
+                hits.push(canary);
+            }
+        }
+
+        let excess = self.window.len().saturating_sub(WINDOW);
+        self.window.drain(..excess);
+        hits
+    }
+
+    pub fn memory(&self) -> usize {
+        self.window.capacity() + self.fired.capacity()
+    }
+}
+
+#[cfg(test)]
+quickcheck::quickcheck! {
+    fn prop_canary_split(prefix: Vec<u8>, suffix: Vec<u8>, split: usize) -> bool {
+        let canaries: Rc<[Canary]> = vec!["hex:00ff00ff".parse().unwrap()].into();
+        let mut data = prefix;
+        data.extend_from_slice(&[0, 255, 0, 255]);
+        data.extend_from_slice(&suffix);
+
+        let split = split % (data.len() + 1);
+        let mut watch = CanaryWatch::new(canaries);
+        let hits = watch.scan(&data[..split]).len() + watch.scan(&data[split..]).len();
+        hits == 1
+    }
+}
+
+#[test]
+fn test_canary_parse() {
+    assert!("str:db01".parse::<Canary>().is_ok());
+    assert!("hex:deadbeef".parse::<Canary>().is_ok());
+    assert!("re:(?i)passw(or)?d".parse::<Canary>().is_ok());
+    assert!("db01".parse::<Canary>().is_err());
+    assert!("txt:db01".parse::<Canary>().is_err());
+    assert!("re:(".parse::<Canary>().is_err());
+}
+
+#[test]
+fn test_canary_empty() {
+    assert!("str:".parse::<Canary>().is_err());
+    assert!("hex:".parse::<Canary>().is_err());
+    assert!("re:".parse::<Canary>().is_err());
+    // Just as empty, if less obviously
+    assert!("re:x*".parse::<Canary>().is_err());
+    assert!("re:^".parse::<Canary>().is_err());
+}
+
+#[test]
+fn test_canary_window() {
+    let longest = format!("str:{}", "x".repeat(WINDOW));
+    assert!(longest.parse::<Canary>().is_ok());
+    assert!(format!("{}x", longest).parse::<Canary>().is_err());
+    assert!(format!("hex:{}", "ab".repeat(WINDOW))
+        .parse::<Canary>()
+        .is_ok());
+    assert!(format!("hex:{}", "ab".repeat(WINDOW + 1))
+        .parse::<Canary>()
+        .is_err());
+
+    // The longest literal still matches however it's split across reads
+    let canaries: Rc<[Canary]> = vec![longest.parse().unwrap()].into();
+    let data = format!("..{}..", "x".repeat(WINDOW));
+    for split in 0..data.len() {
+        let mut watch = CanaryWatch::new(canaries.clone());
+        let hits = watch.scan(&data.as_bytes()[..split]).len()
+            + watch.scan(&data.as_bytes()[split..]).len();
+        assert_eq!(hits, 1);
+    }
+}
@@ -0,0 +1,110 @@
+//! A summary of the protections and limits tarssh actually started with, for
+//! auditing a fleet from its logs.
+
+use std::fmt;
+
+/// Optional features compiled into this build
+fn features() -> Vec<&'static str> {
+    let features = [
+        ("sandbox", cfg!(feature = "sandbox")),
+        ("drop_privs", cfg!(feature = "drop_privs")),
+        ("packet_capture", cfg!(feature = "packet_capture")),
+        ("console", cfg!(feature = "console")),
+        ("tls", cfg!(feature = "tls")),
+        ("nightly", cfg!(feature = "nightly")),
+    ];
+    features
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// The soft limit on open file descriptors, if there is one that can be read
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn fd_limit() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: getrlimit only writes to the struct given
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    Some(limit.rlim_cur).filter(|_| limit.rlim_cur != libc::RLIM_INFINITY)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn fd_limit() -> Option<u64> {
+    None
+}
+
+pub struct Capabilities {
+    features: Vec<&'static str>,
+    sandboxed: bool,
+    privdropped: bool,
+    fd_limit: Option<u64>,
+    listeners: Vec<String>,
+    retrying: usize,
+}
+
+impl Capabilities {
+    pub fn new(
+        sandboxed: bool,
+        privdropped: bool,
+        listeners: Vec<String>,
+        retrying: usize,
+    ) -> Self {
+        Self {
+            features: features(),
+            sandboxed,
+            privdropped,
+            fd_limit: fd_limit(),
+            listeners,
+            retrying,
+        }
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "features: \"{}\", sandbox: {}, privdrop: {}, fd_limit: ",
+            self.features.join(" "),
+            self.sandboxed,
+            self.privdropped
+        )?;
+        match self.fd_limit {
+            Some(limit) => write!(f, "{}", limit)?,
+            None => f.write_str("none")?,
+        }
+        // The runtime is single-threaded
+        write!(f, ", workers: 1, listeners: \"")?;
+        for (i, addr) in self.listeners.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{}", addr)?;
+        }
+        write!(f, "\", retrying: {}", self.retrying)
+    }
+}
+
+#[test]
+fn test_capabilities() {
+    let caps = Capabilities {
+        features: vec!["sandbox", "drop_privs"],
+        sandboxed: true,
+        privdropped: false,
+        fd_limit: Some(1024),
+        listeners: vec!["dmz".to_string(), "[::]:22".to_string()],
+        retrying: 1,
+    };
+    assert_eq!(
+        caps.to_string(),
+        "features: \"sandbox drop_privs\", sandbox: true, privdrop: false, fd_limit: 1024, \
+         workers: 1, listeners: \"dmz [::]:22\", retrying: 1"
+    );
+    assert_eq!(features().contains(&"sandbox"), cfg!(feature = "sandbox"));
+}
@@ -0,0 +1,57 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::spool::Spool;
+
+/// Raw bytes sent by a client, written verbatim up to a fixed limit.
+pub struct Capture {
+    out: File,
+    path: PathBuf,
+    remaining: u64,
+}
+
+impl Capture {
+    pub fn create(spool: &Spool, id: u64, limit: u64) -> io::Result<Self> {
+        let (out, path) = spool.create_for(id, "capture")?;
+        Ok(Self {
+            out,
+            path,
+            remaining: limit,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn memory(&self) -> usize {
+        self.path.capacity()
+    }
+
+    /// Append data to the capture, returning false once the limit is reached
+    pub fn received(&mut self, data: &[u8]) -> io::Result<bool> {
+        let n = data.len().min(self.remaining as usize);
+        self.out.write_all(&data[..n])?;
+        self.remaining -= n as u64;
+        Ok(self.remaining > 0)
+    }
+}
+
+#[test]
+fn test_capture_limit() {
+    let path = std::env::temp_dir().join(format!("tarssh-capture-{}", std::process::id()));
+    let spool = Spool::open(&path).unwrap();
+    let mut capture = Capture::create(&spool, 1, 8).unwrap();
+    assert!(capture.received(b"").unwrap());
+    assert!(capture.received(b"abcde").unwrap());
+    // Cut at the limit, mid-write
+    assert!(!capture.received(b"fghij").unwrap());
+    assert!(!capture.received(b"klm").unwrap());
+    assert_eq!(std::fs::read(capture.path()).unwrap(), b"abcdefgh");
+
+    let mut exact = Capture::create(&spool, 2, 4).unwrap();
+    assert!(!exact.received(b"abcd").unwrap());
+    assert_eq!(std::fs::read(exact.path()).unwrap(), b"abcd");
+    std::fs::remove_dir_all(&path).unwrap();
+}
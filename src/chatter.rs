@@ -0,0 +1,216 @@
+//! Plausible chatter from a busy server's SSH daemon: the login banners,
+//! message-of-the-day and system summaries a real host might send, for a
+//! tarpit that's harder to tell from a slow server than a poem is.
+
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+/// Systems the chatter may claim to be, with the OpenSSH each actually ships
+const SYSTEMS: [(&str, &str); 6] = [
+    (
+        "Ubuntu 22.04.4 LTS",
+        "SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.10",
+    ),
+    (
+        "Ubuntu 20.04.6 LTS",
+        "SSH-2.0-OpenSSH_8.2p1 Ubuntu-4ubuntu0.11",
+    ),
+    (
+        "Debian GNU/Linux 12 (bookworm)",
+        "SSH-2.0-OpenSSH_9.2p1 Debian-2+deb12u3",
+    ),
+    (
+        "Debian GNU/Linux 11 (bullseye)",
+        "SSH-2.0-OpenSSH_8.4p1 Debian-5+deb11u3",
+    ),
+    ("Rocky Linux 9.4 (Blue Onyx)", "SSH-2.0-OpenSSH_8.7"),
+    (
+        "FreeBSD 14.1-RELEASE",
+        "SSH-2.0-OpenSSH_9.7 FreeBSD-20240318",
+    ),
+];
+
+const ROLES: [&str; 10] = [
+    "web", "db", "app", "mail", "bastion", "backup", "git", "ci", "vpn", "cache",
+];
+
+const DOMAINS: [&str; 5] = [
+    "prod.example.com",
+    "corp.internal",
+    "dc1.lan",
+    "infra.example.net",
+    "int.example.org",
+];
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// How many kinds of line there are to pick from
+const LINES: u32 = 12;
+
+/// One host's chatter, keeping the same name and system throughout as a real
+/// server would.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chatter {
+    hostname: String,
+    system: usize,
+}
+
+impl Default for Chatter {
+    fn default() -> Self {
+        Self {
+            hostname: "web01.prod.example.com".to_string(),
+            system: 0,
+        }
+    }
+}
+
+impl Chatter {
+    /// A randomly named host running a randomly chosen system
+    pub fn new<R: Rng>(rng: &mut R) -> Self {
+        let role = ROLES.choose(rng).expect("roles");
+        let domain = DOMAINS.choose(rng).expect("domains");
+        Self {
+            hostname: format!("{}{:02}.{}", role, rng.gen_range(1..=24), domain),
+            system: rng.gen_range(0..SYSTEMS.len()),
+        }
+    }
+
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    /// The identification string the host's OpenSSH would send
+    pub fn version(&self) -> &'static str {
+        SYSTEMS[self.system].1
+    }
+
+    /// Write a random line of chatter, without its line ending
+    pub fn line<R: Rng>(&self, rng: &mut R, out: &mut String) {
+        let system = SYSTEMS[self.system].0;
+        let ip = |rng: &mut R| {
+            format!(
+                "10.{}.{}.{}",
+                rng.gen_range(0..=255),
+                rng.gen_range(0..=255),
+                rng.gen_range(1..=254)
+            )
+        };
+        let _ = match rng.gen_range(0..LINES) {
+            0 => write!(out, "Welcome to {} ({})", system, self.hostname),
+            1 => write!(
+                out,
+                "Authorized uses only. All activity may be monitored and reported."
+            ),
+            2 => write!(
+                out,
+                "  System information as of {}",
+                ctime(now() - rng.gen_range(0..600))
+            ),
+            3 => write!(
+                out,
+                "  System load:  {:.2}               Processes:             {}",
+                rng.gen_range(0.0..4.0),
+                rng.gen_range(90..400)
+            ),
+            4 => write!(
+                out,
+                "  Usage of /:   {:.1}% of {}GB   Users logged in:       {}",
+                rng.gen_range(5.0..95.0),
+                [20, 39, 48, 96, 196].choose(rng).expect("sizes"),
+                rng.gen_range(0..4)
+            ),
+            5 => write!(
+                out,
+                "  Memory usage: {}%                IPv4 address for eth0: {}",
+                rng.gen_range(8..90),
+                ip(rng)
+            ),
+            6 => write!(
+                out,
+                "Last login: {} from {}",
+                ctime(now() - rng.gen_range(3600..14 * 86400)),
+                ip(rng)
+            ),
+            7 => write!(
+                out,
+                "{} updates can be applied immediately.",
+                rng.gen_range(0..120)
+            ),
+            8 => write!(out, "*** System restart required ***"),
+            9 => write!(
+                out,
+                "This system is managed by Ansible. Local changes will be overwritten."
+            ),
+            10 => write!(
+                out,
+                "Unauthorized access to {} is prohibited and will be prosecuted.",
+                self.hostname
+            ),
+            // A blank line, as between paragraphs of a message of the day
+            _ => Ok(()),
+        };
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// A Unix time in UTC, formatted like `ctime(3)` without the newline
+fn ctime(secs: u64) -> String {
+    let days = secs / 86400;
+    let time = secs % 86400;
+
+    // Howard Hinnant's civil_from_days
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{} {} {:2} {:02}:{:02}:{:02} {}",
+        DAYS[(days % 7) as usize],
+        MONTHS[month as usize - 1],
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        year
+    )
+}
+
+#[test]
+fn test_chatter() {
+    use rand::SeedableRng;
+
+    assert_eq!(ctime(0), "Thu Jan  1 00:00:00 1970");
+    assert_eq!(ctime(1_709_210_096), "Thu Feb 29 12:34:56 2024");
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let chatter = Chatter::new(&mut rng);
+    assert!(chatter.version().starts_with("SSH-2.0-OpenSSH_"));
+    let mut seen = 0;
+    for _ in 0..200 {
+        let mut line = String::new();
+        chatter.line(&mut rng, &mut line);
+        if line.contains(chatter.hostname()) {
+            seen += 1;
+        }
+        assert!(!line.starts_with("SSH-") && line.is_ascii() && !line.contains('\n'));
+    }
+    assert!(seen > 0);
+}
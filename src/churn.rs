@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+/// How long to count accepts for before comparing against the baseline
+pub const WINDOW: Duration = Duration::from_secs(60);
+
+/// Weight of the newest window in the baseline
+const ALPHA: f64 = 0.1;
+
+/// Windows to learn from before flagging anything
+const WARMUP: u32 = 10;
+
+/// Fewest accepts in a window for its country shares to mean anything
+const MIN_ACCEPTS: u64 = 20;
+
+/// Baseline share below which a country's forgotten, as a fraction
+const MIN_SHARE: f64 = 0.001;
+
+/// A window whose accept count stood out from the baseline
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anomaly {
+    Spike { count: u64, baseline: f64 },
+    Drop { count: u64, baseline: f64 },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (kind, count, baseline) = match self {
+            Anomaly::Spike { count, baseline } => ("spike", count, baseline),
+            Anomaly::Drop { count, baseline } => ("drop", count, baseline),
+        };
+        write!(f, "{} {}/min vs {:.1}/min", kind, count, baseline)
+    }
+}
+
+/// Watches the rate clients are accepted at, flagging windows that stray too
+/// far from an exponentially weighted baseline.
+///
+/// Both a new scanning campaign and a listener that's silently become
+/// unreachable show up as a sudden change in churn.
+#[derive(Debug)]
+pub struct ChurnWatch {
+    /// Standard deviations from the baseline that count as anomalous
+    threshold: f64,
+    count: u64,
+    mean: f64,
+    variance: f64,
+    windows: u32,
+}
+
+impl ChurnWatch {
+    pub fn new(threshold: f64) -> Self {
+        Self {
+            threshold,
+            count: 0,
+            mean: 0.0,
+            variance: 0.0,
+            windows: 0,
+        }
+    }
+
+    pub fn accepted(&mut self) {
+        self.count += 1;
+    }
+
+    /// Close the current window, returning how it compared if it stood out
+    pub fn sample(&mut self) -> Option<Anomaly> {
+        let count = std::mem::take(&mut self.count);
+        let x = count as f64;
+        let baseline = self.mean;
+
+        // Arrivals are roughly Poisson, so don't let a quiet, steady baseline
+        // shrink the deviation to nothing
+        let deviation = self.variance.sqrt().max(baseline.sqrt()).max(1.0);
+        let anomaly = if self.windows < WARMUP {
+            None
+        } else if x > baseline + self.threshold * deviation {
+            Some(Anomaly::Spike { count, baseline })
+        } else if x < baseline - self.threshold * deviation {
+            Some(Anomaly::Drop { count, baseline })
+        } else {
+            None
+        };
+
+        // Anomalies still feed the baseline, so a lasting change becomes the
+        // new normal rather than alerting forever
+        if self.windows == 0 {
+            self.mean = x;
+        } else {
+            let diff = x - self.mean;
+            let increment = ALPHA * diff;
+            self.mean += increment;
+            self.variance = (1.0 - ALPHA) * (self.variance + diff * increment);
+        }
+        self.windows = self.windows.saturating_add(1);
+
+        anomaly
+    }
+}
+
+/// A country whose share of a window's accepts moved away from its baseline
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shift {
+    pub country: String,
+    /// Share of the window's accepts, as a fraction
+    pub share: f64,
+    pub baseline: f64,
+}
+
+impl fmt::Display for Shift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = if self.share > self.baseline {
+            "rise"
+        } else {
+            "fall"
+        };
+        write!(
+            f,
+            "{} {} {:.1}% vs {:.1}%",
+            self.country,
+            kind,
+            self.share * 100.0,
+            self.baseline * 100.0
+        )
+    }
+}
+
+/// Watches each country's share of accepted clients, flagging windows where
+/// one strays too far from its exponentially weighted baseline share.
+///
+/// A country suddenly making up much more of the traffic usually means a new
+/// campaign has started from there; one vanishing, that it's ended.  Clients
+/// with no known country still count towards the total.
+#[derive(Debug)]
+pub struct CountryWatch {
+    /// Change in share, as a fraction, that counts as anomalous
+    threshold: f64,
+    counts: HashMap<String, u64>,
+    total: u64,
+    shares: HashMap<String, f64>,
+    windows: u32,
+}
+
+impl CountryWatch {
+    /// Watch for shares moving by more than `points` percentage points
+    pub fn new(points: f64) -> Self {
+        Self {
+            threshold: points / 100.0,
+            counts: HashMap::new(),
+            total: 0,
+            shares: HashMap::new(),
+            windows: 0,
+        }
+    }
+
+    pub fn accepted(&mut self, country: Option<&str>) {
+        self.total += 1;
+        if let Some(country) = country {
+            match self.counts.get_mut(country) {
+                Some(count) => *count += 1,
+                None => {
+                    self.counts.insert(country.to_string(), 1);
+                }
+            }
+        }
+    }
+
+    /// Close the current window, returning each country whose share stood
+    /// out, most changed first
+    pub fn sample(&mut self) -> Vec<Shift> {
+        let counts = std::mem::take(&mut self.counts);
+        let total = std::mem::take(&mut self.total);
+        // Too few to tell a shift from chance, so neither judged nor learnt
+        if total < MIN_ACCEPTS {
+            return vec![];
+        }
+
+        for country in counts.keys() {
+            if !self.shares.contains_key(country) {
+                self.shares.insert(country.clone(), 0.0);
+            }
+        }
+
+        let mut shifts = vec![];
+        for (country, baseline) in self.shares.iter_mut() {
+            let share = counts.get(country).copied().unwrap_or(0) as f64 / total as f64;
+            if self.windows >= WARMUP && (share - *baseline).abs() > self.threshold {
+                shifts.push(Shift {
+                    country: country.clone(),
+                    share,
+                    baseline: *baseline,
+                });
+            }
+            // The first window sets the baseline outright, and shifts still
+            // feed it so a lasting change becomes the new normal
+            if self.windows == 0 {
+                *baseline = share;
+            } else {
+                *baseline += ALPHA * (share - *baseline);
+            }
+        }
+        self.shares.retain(|_, share| *share >= MIN_SHARE);
+        self.windows = self.windows.saturating_add(1);
+
+        shifts.sort_by(|a, b| {
+            let change = |shift: &Shift| (shift.share - shift.baseline).abs();
+            change(b)
+                .partial_cmp(&change(a))
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.country.cmp(&b.country))
+        });
+        shifts
+    }
+}
+
+#[test]
+fn test_churn_watch() {
+    let mut watch = ChurnWatch::new(4.0);
+    let window = |watch: &mut ChurnWatch, count| {
+        (0..count).for_each(|_| watch.accepted());
+        watch.sample()
+    };
+
+    // Nothing is flagged while warming up, however wild
+    assert_eq!(window(&mut watch, 100), None);
+    for i in 0..30 {
+        assert_eq!(window(&mut watch, 100 + i % 5), None);
+    }
+
+    assert!(matches!(
+        window(&mut watch, 0),
+        Some(Anomaly::Drop { count: 0, .. })
+    ));
+    assert!(matches!(
+        window(&mut watch, 400),
+        Some(Anomaly::Spike { count: 400, .. })
+    ));
+}
+
+#[test]
+fn test_country_watch() {
+    let mut watch = CountryWatch::new(20.0);
+    let window = |watch: &mut CountryWatch, countries: &[(Option<&str>, u64)]| {
+        for (country, count) in countries {
+            (0..*count).for_each(|_| watch.accepted(*country));
+        }
+        watch.sample()
+    };
+
+    // Nothing is flagged while warming up, however wild
+    assert!(window(&mut watch, &[(Some("NL"), 100)]).is_empty());
+    for _ in 0..30 {
+        let steady = [(Some("NL"), 60), (Some("US"), 30), (None, 10)];
+        assert!(window(&mut watch, &steady).is_empty());
+    }
+
+    // Too quiet a window to judge, or to learn from
+    assert!(window(&mut watch, &[(Some("CN"), 19)]).is_empty());
+
+    let shifts = window(
+        &mut watch,
+        &[(Some("NL"), 20), (Some("US"), 30), (Some("CN"), 50)],
+    );
+    assert_eq!(
+        shifts
+            .iter()
+            .map(|s| s.country.as_str())
+            .collect::<Vec<_>>(),
+        vec!["CN", "NL"]
+    );
+    assert_eq!(shifts[0].share, 0.5);
+    assert_eq!(shifts[0].baseline, 0.0);
+    assert_eq!(shifts[0].to_string(), "CN rise 50.0% vs 0.0%");
+    assert!(shifts[1].to_string().starts_with("NL fall 20.0% vs 6"));
+
+    // A lasting change becomes the new normal
+    for _ in 0..50 {
+        window(
+            &mut watch,
+            &[(Some("NL"), 20), (Some("US"), 30), (Some("CN"), 50)],
+        );
+    }
+    let shifts = window(
+        &mut watch,
+        &[(Some("NL"), 20), (Some("US"), 30), (Some("CN"), 50)],
+    );
+    assert!(shifts.is_empty());
+}
@@ -0,0 +1,110 @@
+//! Noticing when the clocks jump, as when a VM is paused or a laptop resumes
+//! from suspend, or NTP steps the wall clock, so the main loop can re-base its
+//! timers rather than treat the gap as time connections were held for.
+
+use std::fmt;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The main loop's expected gap between checks
+const TICK: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Jump {
+    /// The monotonic clock moved on this much further than a tick, so the
+    /// process was paused or starved
+    Gap(Duration),
+    /// The wall clock moved this much further than the monotonic clock, as
+    /// after a suspend on systems where the monotonic clock stops, or an NTP
+    /// step
+    Step { forward: bool, by: Duration },
+}
+
+impl fmt::Display for Jump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = |d: &Duration| humantime::format_duration(Duration::from_secs(d.as_secs()));
+        match self {
+            Jump::Gap(gap) => write!(f, "jump: monotonic, gap: {}", secs(gap)),
+            Jump::Step { forward, by } => write!(
+                f,
+                "jump: wall, offset: {}{}",
+                if *forward { "+" } else { "-" },
+                secs(by)
+            ),
+        }
+    }
+}
+
+pub struct Clock {
+    threshold: Duration,
+    mono: Instant,
+    wall: SystemTime,
+}
+
+impl Clock {
+    pub fn new(threshold: Duration, mono: Instant, wall: SystemTime) -> Self {
+        Self {
+            threshold,
+            mono,
+            wall,
+        }
+    }
+
+    /// Compare both clocks with their readings at the last check, returning
+    /// any jump larger than the threshold
+    pub fn check(&mut self, mono: Instant, wall: SystemTime) -> Option<Jump> {
+        let elapsed = mono.saturating_duration_since(self.mono);
+        let (forward, walked) = match wall.duration_since(self.wall) {
+            Ok(walked) => (true, walked),
+            Err(err) => (false, err.duration()),
+        };
+        self.mono = mono;
+        self.wall = wall;
+
+        if elapsed > self.threshold + TICK {
+            Some(Jump::Gap(elapsed - TICK))
+        } else if forward && walked > elapsed + self.threshold {
+            Some(Jump::Step {
+                forward: true,
+                by: walked - elapsed,
+            })
+        } else if !forward && walked > self.threshold {
+            Some(Jump::Step {
+                forward: false,
+                by: walked + elapsed,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn test_clock() {
+    let mono = Instant::now();
+    let wall = SystemTime::UNIX_EPOCH + Duration::from_secs(1_654_560_000);
+    let secs = Duration::from_secs;
+    let mut clock = Clock::new(secs(30), mono, wall);
+
+    assert_eq!(clock.check(mono + secs(1), wall + secs(1)), None);
+    assert_eq!(clock.check(mono + secs(20), wall + secs(20)), None);
+
+    // A paused VM, whose clocks both carry on
+    let jump = clock.check(mono + secs(620), wall + secs(620));
+    assert_eq!(jump, Some(Jump::Gap(secs(599))));
+    assert_eq!(jump.unwrap().to_string(), "jump: monotonic, gap: 9m 59s");
+
+    // A suspend, where only the wall clock moves
+    let jump = clock.check(mono + secs(621), wall + secs(4221));
+    assert_eq!(jump.unwrap().to_string(), "jump: wall, offset: +1h");
+
+    // An NTP step backwards
+    let jump = clock.check(mono + secs(622), wall + secs(3622));
+    assert_eq!(
+        jump,
+        Some(Jump::Step {
+            forward: false,
+            by: secs(600)
+        })
+    );
+    assert_eq!(clock.check(mono + secs(623), wall + secs(3623)), None);
+}
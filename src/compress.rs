@@ -0,0 +1,100 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{debug, warn};
+
+/// Gzips finished files on a background thread, replacing each with a `.gz`.
+#[derive(Clone)]
+pub struct Compressor {
+    queue: Sender<PathBuf>,
+}
+
+impl Compressor {
+    pub fn start() -> io::Result<Self> {
+        let (queue, paths) = channel::<PathBuf>();
+
+        thread::Builder::new()
+            .name("compress".to_string())
+            .spawn(move || {
+                for path in paths {
+                    match gzip(&path) {
+                        Ok(()) => debug!("compress, path: {}", path.display()),
+                        Err(err) => warn!("compress, path: {}, error: {}", path.display(), err),
+                    }
+                }
+            })?;
+
+        Ok(Self { queue })
+    }
+
+    pub fn compress(&self, path: &Path) {
+        let _ = self.queue.send(path.to_path_buf());
+    }
+}
+
+/// Compress `path` to `path.gz`, only removing the original once the
+/// compressed copy is complete
+fn gzip(path: &Path) -> io::Result<()> {
+    let with_ext = |ext: &str| {
+        let mut name = OsString::from(path.as_os_str());
+        name.push(ext);
+        PathBuf::from(name)
+    };
+    let (partial, done) = (with_ext(".gz.part"), with_ext(".gz"));
+
+    let mut input = BufReader::new(File::open(path)?);
+    let mut output = GzEncoder::new(BufWriter::new(File::create(&partial)?), Compression::best());
+    io::copy(&mut input, &mut output)?;
+    output
+        .finish()?
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+
+    std::fs::rename(&partial, &done)?;
+    std::fs::remove_file(path)
+}
+
+#[test]
+fn test_gzip() {
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    let path = std::env::temp_dir().join(format!("tarssh-gzip-{}", std::process::id()));
+    std::fs::write(&path, b"hello hello hello").unwrap();
+    gzip(&path).unwrap();
+    assert!(!path.exists());
+
+    let gz = PathBuf::from(format!("{}.gz", path.display()));
+    let mut data = vec![];
+    GzDecoder::new(File::open(&gz).unwrap())
+        .read_to_end(&mut data)
+        .unwrap();
+    assert_eq!(data, b"hello hello hello");
+    std::fs::remove_file(gz).unwrap();
+}
+
+#[test]
+fn test_compressor() {
+    let path = std::env::temp_dir().join(format!("tarssh-compressor-{}", std::process::id()));
+    let gz = PathBuf::from(format!("{}.gz", path.display()));
+    std::fs::write(&path, b"hello").unwrap();
+
+    let compressor = Compressor::start().unwrap();
+    compressor.compress(&path);
+    // A file that's already gone is logged and skipped
+    compressor.compress(Path::new("/nonexistent/tarssh-compressor"));
+    assert!((0..500).any(|_| {
+        thread::sleep(std::time::Duration::from_millis(10));
+        gz.exists()
+    }));
+    assert!(!path.exists());
+    assert!(!PathBuf::from(format!("{}.gz.part", path.display())).exists());
+    std::fs::remove_file(gz).unwrap();
+}
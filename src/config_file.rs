@@ -0,0 +1,129 @@
+//! Options read from a TOML file given with `--config`.
+//!
+//! Keys are long option names and values are strings, numbers, `true` for
+//! flags, or arrays for options that may be repeated:
+//!
+//! ```toml
+//! listen = ["0.0.0.0:22", "[::]:22"]
+//! delay = 10
+//! max-clients = 8192
+//! user = "nobody"
+//! disable-log-timestamps = true
+//! verbose = 1
+//!
+//! [template]
+//! greeting = "220 mail.example.com ESMTP\r\n"
+//! ```
+//!
+//! Keys in a section are prefixed with its name, so `greeting` under
+//! `[template]` is `--template-greeting`.
+//!
+//! The file is turned into command line arguments, so it's validated exactly
+//! like the command line, which overrides it option by option.
+
+use std::ffi::OsString;
+use std::path::Path;
+
+use toml::Value;
+
+/// Read the file at `path` as arguments, leaving out options for which
+/// `given` returns true
+pub fn args<F: Fn(&str) -> bool>(path: &Path, given: F) -> Result<Vec<OsString>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    parse(&text, given)
+}
+
+/// Read `text`, as from a file, as arguments, leaving out options for which
+/// `given` returns true
+pub fn parse<F: Fn(&str) -> bool>(text: &str, given: F) -> Result<Vec<OsString>, String> {
+    let table: toml::value::Table = toml::from_str(text).map_err(|e| e.to_string())?;
+    let mut args = vec![];
+    for (key, value) in &table {
+        let section = match value {
+            Value::Table(section) => section,
+            _ => {
+                if !given(key) {
+                    push(&mut args, key, value)?;
+                }
+                continue;
+            }
+        };
+        for (name, value) in section {
+            let key = format!("{}-{}", key, name);
+            if matches!(value, Value::Table(_)) {
+                return Err(format!("{}: sections can't be nested", key));
+            }
+            if !given(&key) {
+                push(&mut args, &key, value)?;
+            }
+        }
+    }
+    Ok(args)
+}
+
+fn push(args: &mut Vec<OsString>, key: &str, value: &Value) -> Result<(), String> {
+    match value {
+        Value::Boolean(true) => args.push(format!("--{}", key).into()),
+        Value::Boolean(false) => (),
+        // Counted flags, the only one being -v
+        Value::Integer(n) if key == "verbose" => {
+            args.extend((0..*n).map(|_| OsString::from("--verbose")));
+        }
+        Value::String(s) => args.push(format!("--{}={}", key, s).into()),
+        Value::Integer(n) => args.push(format!("--{}={}", key, n).into()),
+        Value::Float(f) => args.push(format!("--{}={}", key, f).into()),
+        Value::Array(values) => {
+            for value in values {
+                if matches!(value, Value::Array(_)) {
+                    return Err(format!("{}: arrays can't be nested", key));
+                }
+                push(args, key, value)?;
+            }
+        }
+        Value::Datetime(_) | Value::Table(_) => {
+            return Err(format!(
+                "{}: expected a string, number, boolean or array",
+                key
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_config_args() {
+    let text = r#"
+        listen = ["0.0.0.0:22", "[::]:22"]
+        delay = 10
+        churn-alert = 4.5
+        user = "nobody"
+        disable-log-timestamps = true
+        disable-log-level = false
+        verbose = 2
+    "#;
+    let args = parse(text, |key| key == "delay").unwrap();
+    assert_eq!(
+        args,
+        [
+            "--churn-alert=4.5",
+            "--disable-log-timestamps",
+            "--listen=0.0.0.0:22",
+            "--listen=[::]:22",
+            "--user=nobody",
+            "--verbose",
+            "--verbose",
+        ]
+        .iter()
+        .map(OsString::from)
+        .collect::<Vec<_>>()
+    );
+
+    let text = "[template]\ngreeting = \"hi\"\nlines = \"counter\"\n[privdrop.user]\nname = 1";
+    assert!(parse(text, |_| false).is_err());
+    let args = parse(&text[..text.find("[privdrop").unwrap()], |key| {
+        key == "template-lines"
+    })
+    .unwrap();
+    assert_eq!(args, [OsString::from("--template-greeting=hi")]);
+    assert!(parse("delay = ", |_| false).is_err());
+}
@@ -0,0 +1,59 @@
+//! Runtime diagnostics: tokio-console instrumentation and periodic runtime
+//! metrics, for working out where the time goes at huge connection counts.
+//!
+//! Both need Tokio's unstable APIs, so build with
+//! `RUSTFLAGS="--cfg tokio_unstable"`.
+
+#[cfg(not(tokio_unstable))]
+compile_error!("the console feature needs RUSTFLAGS=\"--cfg tokio_unstable\"");
+
+use std::time::Duration;
+
+use log::info;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Start the console server, listening on 127.0.0.1:6669 unless overridden by
+/// the `TOKIO_CONSOLE_BIND` environment variable.
+///
+/// Only tasks spawned after this are visible to the console.
+pub fn init() -> Result<(), tracing::subscriber::SetGlobalDefaultError> {
+    let subscriber = tracing_subscriber::registry().with(console_subscriber::spawn());
+    tracing::subscriber::set_global_default(subscriber)
+}
+
+/// Log the runtime's metrics every `period`, as totals across its workers
+/// since the previous line.
+pub async fn log_runtime_stats(period: Duration) {
+    let metrics = tokio::runtime::Handle::current().metrics();
+    let workers = metrics.num_workers();
+    let mut interval = tokio::time::interval(period);
+    let mut last_polls = 0;
+    let mut last_busy = Duration::ZERO;
+    let mut last_ready = 0;
+
+    loop {
+        interval.tick().await;
+
+        let polls: u64 = (0..workers).map(|w| metrics.worker_poll_count(w)).sum();
+        let busy: Duration = (0..workers)
+            .map(|w| metrics.worker_total_busy_duration(w))
+            .sum();
+        let ready = metrics.io_driver_ready_count();
+        let (polls_delta, busy_delta) = (polls - last_polls, busy - last_busy);
+        let ready_delta = ready - last_ready;
+        last_polls = polls;
+        last_busy = busy;
+        last_ready = ready;
+
+        let mean_poll = busy_delta.div_f64(polls_delta.max(1) as f64);
+        info!(
+            "runtime, workers: {}, polls: {}, busy: {:.1}%, mean_poll: {:.2?}, queue: {}, io_ready: {}",
+            workers,
+            polls_delta,
+            100.0 * busy_delta.as_secs_f64() / (period.as_secs_f64() * workers as f64),
+            mean_poll,
+            metrics.injection_queue_depth(),
+            ready_delta
+        );
+    }
+}
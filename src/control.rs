@@ -0,0 +1,205 @@
+//! A line-based control socket for adjusting the tarpit while it runs.
+//!
+//! Each line is a command, answered with one or more lines:
+//!
+//! ```txt
+//! ban <ip> [ttl]    ban an address, for e.g. `1h` or else until unbanned
+//! unban <ip>        lift a ban
+//! bans              list bans, with the time each has left
+//! listeners         list labelled listeners, with connections accepted
+//! ```
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use log::info;
+use tokio::sync::oneshot;
+
+use crate::bans::BanList;
+use crate::listeners::Label;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Ban(IpAddr, Option<Duration>),
+    Unban(IpAddr),
+    Bans,
+    Listeners,
+}
+
+/// A command and where to send its reply
+pub type Request = (Command, oneshot::Sender<String>);
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let ip = |s: &str| s.parse::<IpAddr>().map_err(|e| format!("{}: {}", s, e));
+        match words[..] {
+            ["ban", addr] => Ok(Command::Ban(ip(addr)?, None)),
+            ["ban", addr, ttl] => {
+                let ttl = humantime::parse_duration(ttl).map_err(|e| format!("{}: {}", ttl, e))?;
+                Ok(Command::Ban(ip(addr)?, Some(ttl)))
+            }
+            ["unban", addr] => Ok(Command::Unban(ip(addr)?)),
+            ["bans"] => Ok(Command::Bans),
+            ["listeners"] => Ok(Command::Listeners),
+            _ => Err(format!("unknown command: {}", s.trim())),
+        }
+    }
+}
+
+/// The command as it would be written on the control socket
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Ban(ip, Some(ttl)) => {
+                write!(f, "ban {} {}", ip, humantime::format_duration(*ttl))
+            }
+            Command::Ban(ip, None) => write!(f, "ban {}", ip),
+            Command::Unban(ip) => write!(f, "unban {}", ip),
+            Command::Bans => f.write_str("bans"),
+            Command::Listeners => f.write_str("listeners"),
+        }
+    }
+}
+
+impl Command {
+    /// Carry out the command, returning the reply
+    pub fn apply(self, bans: &mut BanList, listeners: &[(&Label, u64)], now: SystemTime) -> String {
+        bans.expire(now);
+        let result = match self {
+            Command::Ban(ip, ttl) => bans.ban(ip, ttl, now).map(|()| {
+                match ttl {
+                    Some(ttl) => info!("ban, ip: {}, ttl: {}", ip, humantime::format_duration(ttl)),
+                    None => info!("ban, ip: {}, ttl: forever", ip),
+                }
+                "ok".to_string()
+            }),
+            Command::Unban(ip) => bans.unban(ip).map(|found| {
+                if found {
+                    info!("unban, ip: {}", ip);
+                    "ok".to_string()
+                } else {
+                    format!("error: {} isn't banned", ip)
+                }
+            }),
+            Command::Bans => Ok(bans
+                .list(now)
+                .iter()
+                .map(|(ip, left)| match left {
+                    Some(left) => {
+                        let left = Duration::from_secs(left.as_secs());
+                        format!("{} {}\n", ip, humantime::format_duration(left))
+                    }
+                    None => format!("{} forever\n", ip),
+                })
+                .collect::<String>()
+                + "ok"),
+            Command::Listeners => Ok(listeners
+                .iter()
+                .map(|(label, connections)| {
+                    format!("{} {} {}\n", label.name, label.addr, connections)
+                })
+                .collect::<String>()
+                + "ok"),
+        };
+        result.unwrap_or_else(|err| format!("error: {}", err))
+    }
+}
+
+/// Listen on a Unix socket at `path`, forwarding commands to `requests`.
+///
+/// A stale socket left behind by an earlier run is replaced.
+#[cfg(unix)]
+pub fn spawn(
+    path: &std::path::Path,
+    requests: tokio::sync::mpsc::UnboundedSender<Request>,
+) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::UnixListener;
+
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+
+    tokio::spawn(async move {
+        loop {
+            let (sock, _) = match listener.accept().await {
+                Ok(client) => client,
+                Err(err) => {
+                    log::warn!("control, error: {}", err);
+                    continue;
+                }
+            };
+            let requests = requests.clone();
+            tokio::spawn(async move {
+                let (read, mut write) = sock.into_split();
+                let mut lines = BufReader::new(read).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let reply = match line.parse::<Command>() {
+                        Ok(command) => {
+                            let (tx, rx) = oneshot::channel();
+                            if requests.send((command, tx)).is_err() {
+                                break;
+                            }
+                            rx.await.unwrap_or_default()
+                        }
+                        Err(err) => format!("error: {}", err),
+                    };
+                    if write
+                        .write_all(format!("{}\n", reply).as_bytes())
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+#[test]
+fn test_command() {
+    let ip: IpAddr = [192, 0, 2, 1].into();
+    assert_eq!("ban 192.0.2.1".parse(), Ok(Command::Ban(ip, None)));
+    assert_eq!(
+        " ban  192.0.2.1 1h ".parse(),
+        Ok(Command::Ban(ip, Some(Duration::from_secs(3600))))
+    );
+    assert_eq!("unban 192.0.2.1".parse(), Ok(Command::Unban(ip)));
+    assert!("ban nonsense".parse::<Command>().is_err());
+    assert!("ban 192.0.2.1 soon".parse::<Command>().is_err());
+    assert!("bans please".parse::<Command>().is_err());
+    assert_eq!("listeners".parse(), Ok(Command::Listeners));
+    assert_eq!(
+        Command::Ban(ip, Some(Duration::from_secs(3600))).to_string(),
+        "ban 192.0.2.1 1h"
+    );
+
+    let now = SystemTime::now();
+    let mut bans = BanList::default();
+    assert_eq!(Command::Ban(ip, None).apply(&mut bans, &[], now), "ok");
+    assert_eq!(
+        Command::Bans.apply(&mut bans, &[], now),
+        "192.0.2.1 forever\nok"
+    );
+    assert_eq!(Command::Unban(ip).apply(&mut bans, &[], now), "ok");
+    assert!(Command::Unban(ip)
+        .apply(&mut bans, &[], now)
+        .starts_with("error"));
+    let label: Label = "dmz=0.0.0.0:22".parse().unwrap();
+    assert_eq!(
+        Command::Listeners.apply(&mut bans, &[(&label, 3)], now),
+        "dmz 0.0.0.0:22 3\nok"
+    );
+}
@@ -0,0 +1,45 @@
+//! Corking connections around each write, so whatever one tick sends leaves
+//! as a single segment rather than being split as it's copied in.
+
+use std::io;
+
+/// Whether this platform can cork sockets
+pub const SUPPORTED: bool = cfg!(target_os = "linux");
+
+/// Hold back partial segments while `corked`, flushing whatever's queued when
+/// cleared
+#[cfg(target_os = "linux")]
+pub fn set<S: std::os::unix::io::AsRawFd>(sock: &S, corked: bool) -> io::Result<()> {
+    let value = libc::c_int::from(corked);
+    // SAFETY: the value is a live c_int of the length given
+    let ret = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_CORK,
+            (&value as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set<S>(_sock: &S, _corked: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "corking is not supported on this platform",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_cork() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let sock = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    set(&sock, true).unwrap();
+    set(&sock, false).unwrap();
+}
@@ -0,0 +1,104 @@
+//! Connect lines held back for a while before they're logged, so they can
+//! carry what's learned about the connection in the meantime.
+//!
+//! Lines are handed back to be logged as they're released, so they come from
+//! the same place as any that weren't held.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Instant;
+
+#[derive(Default)]
+pub struct Deferred {
+    lines: HashMap<SocketAddr, (String, Instant)>,
+    /// Peers in the order they're due, as each is held for the same time
+    due: VecDeque<(Instant, SocketAddr)>,
+}
+
+impl Deferred {
+    /// Hold `line` for `peer` until `until` at the latest
+    pub fn hold(&mut self, peer: SocketAddr, line: String, until: Instant) {
+        self.lines.insert(peer, (line, until));
+        self.due.push_back((until, peer));
+    }
+
+    /// The line held for `peer`, if any, with `extra` appended
+    pub fn release(&mut self, peer: SocketAddr, extra: &dyn Display) -> Option<String> {
+        self.lines
+            .remove(&peer)
+            .map(|(line, _)| format!("{}{}", line, extra))
+    }
+
+    /// Any lines held for connections from `ip`, as they are
+    pub fn release_ip(&mut self, ip: IpAddr) -> Vec<String> {
+        let peers: Vec<_> = self
+            .lines
+            .keys()
+            .filter(|peer| peer.ip() == ip)
+            .copied()
+            .collect();
+        peers
+            .into_iter()
+            .filter_map(|peer| self.release(peer, &""))
+            .collect()
+    }
+
+    /// When the next line is due, if any are held
+    pub fn next_due(&self) -> Option<Instant> {
+        self.due.front().map(|(until, _)| *until)
+    }
+
+    /// The lines due by `now`, with `extra` appended
+    pub fn expire(&mut self, now: Instant, extra: &dyn Display) -> Vec<String> {
+        let mut lines = vec![];
+        while let Some(&(until, peer)) = self.due.front() {
+            if until > now {
+                break;
+            }
+            self.due.pop_front();
+            // Skip peers since released, or held again by a later connection
+            if self.lines.get(&peer).map(|(_, held)| *held) == Some(until) {
+                lines.extend(self.release(peer, extra));
+            }
+        }
+        lines
+    }
+
+    /// Everything still held, as on shutdown
+    pub fn flush(&mut self) -> Vec<String> {
+        self.due.clear();
+        self.lines.drain().map(|(_, (line, _))| line).collect()
+    }
+}
+
+#[test]
+fn test_deferred() {
+    let mut deferred = Deferred::default();
+    let now = Instant::now();
+    let later = now + std::time::Duration::from_secs(1);
+    let a: SocketAddr = "192.0.2.1:1000".parse().unwrap();
+    let b: SocketAddr = "192.0.2.1:1001".parse().unwrap();
+    let c: SocketAddr = "192.0.2.2:1000".parse().unwrap();
+
+    deferred.hold(a, "connect, peer: a".to_string(), now);
+    deferred.hold(b, "connect, peer: b".to_string(), later);
+    deferred.hold(c, "connect, peer: c".to_string(), later);
+    assert_eq!(deferred.next_due(), Some(now));
+
+    assert_eq!(
+        deferred.release(a, &", dnsbl: zen.spamhaus.org").unwrap(),
+        "connect, peer: a, dnsbl: zen.spamhaus.org"
+    );
+    assert_eq!(deferred.release(a, &""), None);
+    assert!(deferred.expire(now, &", enrich: timeout").is_empty());
+    assert_eq!(deferred.next_due(), Some(later));
+
+    assert_eq!(deferred.release_ip(b.ip()), vec!["connect, peer: b"]);
+    assert_eq!(
+        deferred.expire(later, &", enrich: timeout"),
+        vec!["connect, peer: c, enrich: timeout"]
+    );
+    assert!(deferred.lines.is_empty());
+    assert_eq!(deferred.next_due(), None);
+}
@@ -0,0 +1,372 @@
+//! A DNS sinkhole: every name resolves to the tarpit, so malware that looks
+//! up its command and control server before connecting ends up held there,
+//! and the names it asked for are logged.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Instant;
+
+use log::{debug, info};
+use tokio::net::UdpSocket;
+
+use crate::sanitize::Field;
+
+/// TTL of sinkholed answers, short enough that clients will keep asking
+const TTL: u32 = 300;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+
+/// Answers per second each source may have, and how many it may save up
+const SOURCE_RATE: f64 = 5.0;
+const SOURCE_BURST: f64 = 20.0;
+
+/// Answers per second across all sources, however many there are, since
+/// spoofed queries can come from anywhere
+const MAX_RATE: f64 = 256.0;
+
+/// Most sources to track at once
+const MAX_SOURCES: usize = 4096;
+
+/// Response codes
+const NOERROR: u8 = 0;
+const REFUSED: u8 = 5;
+
+/// The addresses every name resolves to
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Sinkhole {
+    pub v4: Option<Ipv4Addr>,
+    pub v6: Option<Ipv6Addr>,
+}
+
+impl Sinkhole {
+    /// The first address of each family in `addrs`
+    pub fn new(addrs: &[IpAddr]) -> Self {
+        let mut sinkhole = Self::default();
+        for addr in addrs {
+            match addr {
+                IpAddr::V4(v4) => sinkhole.v4 = sinkhole.v4.or(Some(*v4)),
+                IpAddr::V6(v6) => sinkhole.v6 = sinkhole.v6.or(Some(*v6)),
+            }
+        }
+        sinkhole
+    }
+
+    fn rdata(&self, qtype: u16) -> Option<(u16, Vec<u8>)> {
+        match qtype {
+            TYPE_A | TYPE_ANY if self.v4.is_some() => Some((TYPE_A, self.v4?.octets().to_vec())),
+            TYPE_AAAA => Some((TYPE_AAAA, self.v6?.octets().to_vec())),
+            TYPE_ANY => Some((TYPE_AAAA, self.v6?.octets().to_vec())),
+            _ => None,
+        }
+    }
+}
+
+/// A token bucket, refilling at a steady rate up to its burst
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(burst: f64, now: Instant) -> Self {
+        Self {
+            tokens: burst,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, rate: f64, burst: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.updated = now;
+    }
+}
+
+/// Limits on how fast we answer, per source and overall, so spoofed queries
+/// can't point a flood of answers at someone else
+#[derive(Debug)]
+struct Throttle {
+    sources: HashMap<IpAddr, Bucket>,
+    global: Bucket,
+}
+
+impl Throttle {
+    fn new(now: Instant) -> Self {
+        Self {
+            sources: HashMap::new(),
+            global: Bucket::new(MAX_RATE, now),
+        }
+    }
+
+    /// Whether to answer a query from `ip`, taking a token if so
+    fn allow(&mut self, ip: IpAddr, now: Instant) -> bool {
+        self.global.refill(MAX_RATE, MAX_RATE, now);
+        if self.global.tokens < 1.0 {
+            return false;
+        }
+
+        if self.sources.len() >= MAX_SOURCES && !self.sources.contains_key(&ip) {
+            // Forget sources that have refilled, as new ones would start full
+            for bucket in self.sources.values_mut() {
+                bucket.refill(SOURCE_RATE, SOURCE_BURST, now);
+            }
+            self.sources
+                .retain(|_, bucket| bucket.tokens < SOURCE_BURST);
+            if self.sources.len() >= MAX_SOURCES {
+                return false;
+            }
+        }
+
+        let source = self
+            .sources
+            .entry(ip)
+            .or_insert_with(|| Bucket::new(SOURCE_BURST, now));
+        source.refill(SOURCE_RATE, SOURCE_BURST, now);
+        if source.tokens < 1.0 {
+            return false;
+        }
+
+        source.tokens -= 1.0;
+        self.global.tokens -= 1.0;
+        true
+    }
+}
+
+/// A standard query for a single name
+#[derive(Debug, PartialEq)]
+struct Query<'a> {
+    name: Vec<u8>,
+    qtype: u16,
+    qclass: u16,
+    /// The header and question, as they'll be echoed back
+    question: &'a [u8],
+}
+
+fn parse(data: &[u8]) -> Option<Query<'_>> {
+    // A query, with a standard opcode, asking one question
+    if data.len() < 12 || data[2] & 0xf8 != 0 || data[4..6] != [0, 1] {
+        return None;
+    }
+
+    let mut name = vec![];
+    let mut pos = 12;
+    loop {
+        let len = *data.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        // Compression has no place in a question
+        if len > 63 || name.len() + len >= 255 {
+            return None;
+        }
+        if !name.is_empty() {
+            name.push(b'.');
+        }
+        name.extend_from_slice(data.get(pos..pos + len)?);
+        pos += len;
+    }
+    let fixed = data.get(pos..pos + 4)?;
+
+    Some(Query {
+        name,
+        qtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+        qclass: u16::from_be_bytes([fixed[2], fixed[3]]),
+        question: &data[..pos + 4],
+    })
+}
+
+fn type_name(qtype: u16) -> String {
+    match qtype {
+        TYPE_A => "A".to_string(),
+        TYPE_AAAA => "AAAA".to_string(),
+        TYPE_ANY => "ANY".to_string(),
+        2 => "NS".to_string(),
+        5 => "CNAME".to_string(),
+        6 => "SOA".to_string(),
+        12 => "PTR".to_string(),
+        15 => "MX".to_string(),
+        16 => "TXT".to_string(),
+        33 => "SRV".to_string(),
+        65 => "HTTPS".to_string(),
+        n => format!("TYPE{}", n),
+    }
+}
+
+/// An authoritative answer pointing the query at the sinkhole.
+///
+/// Anything the query carried after its question, such as EDNS options, is
+/// left out, keeping answers within a few bytes of their queries.
+fn respond(query: &Query<'_>, sinkhole: &Sinkhole) -> Vec<u8> {
+    let mut r = query.question.to_vec();
+    let (rcode, answer) = match query.qclass {
+        CLASS_IN => (NOERROR, sinkhole.rdata(query.qtype)),
+        _ => (REFUSED, None),
+    };
+    r[2] = 0x84 | (r[2] & 0x01); // response, authoritative, keep RD
+    r[3] = 0x80 | rcode; // recursion available
+    r[6..12].fill(0);
+    if let Some((rtype, rdata)) = answer {
+        r[7] = 1;
+        r.extend_from_slice(&[0xc0, 12]); // the name in the question
+        r.extend_from_slice(&rtype.to_be_bytes());
+        r.extend_from_slice(&CLASS_IN.to_be_bytes());
+        r.extend_from_slice(&TTL.to_be_bytes());
+        r.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        r.extend_from_slice(&rdata);
+    }
+    r
+}
+
+pub async fn bind(addr: SocketAddr) -> std::io::Result<UdpSocket> {
+    UdpSocket::bind(addr).await
+}
+
+/// Answer queries on `sock` with the sinkhole, logging each, unless their
+/// source or everyone together is asking too fast.
+pub async fn serve(sock: UdpSocket, sinkhole: Sinkhole) {
+    let mut buf = [0u8; 1500];
+    let mut throttle = Throttle::new(Instant::now());
+
+    loop {
+        let (n, peer) = match sock.recv_from(&mut buf).await {
+            Ok(recv) => recv,
+            Err(err) => {
+                debug!("dns, error: {}", err);
+                continue;
+            }
+        };
+
+        if !throttle.allow(peer.ip(), Instant::now()) {
+            debug!("dns, peer: {}, throttled: true", peer);
+            continue;
+        }
+
+        let query = match parse(&buf[..n]) {
+            Some(query) => query,
+            None => {
+                debug!("dns, peer: {}, error: invalid query", peer);
+                continue;
+            }
+        };
+        info!(
+            "dns, peer: {}, name: {}, type: {}",
+            peer,
+            Field(&query.name),
+            type_name(query.qtype)
+        );
+
+        if let Err(err) = sock.try_send_to(&respond(&query, &sinkhole), peer) {
+            debug!("dns, peer: {}, error: {}", peer, err);
+        }
+    }
+}
+
+#[test]
+fn test_dns_sinkhole() {
+    let sinkhole = Sinkhole::new(&[
+        "192.0.2.1".parse().unwrap(),
+        "2001:db8::1".parse().unwrap(),
+        "192.0.2.2".parse().unwrap(),
+    ]);
+    assert_eq!(sinkhole.v4, Some(Ipv4Addr::new(192, 0, 2, 1)));
+
+    // A recursive query for c2.example A, with an EDNS OPT record
+    let mut data = b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x01\
+        \x02c2\x07example\x00\x00\x01\x00\x01"
+        .to_vec();
+    data.extend_from_slice(b"\x00\x00\x29\x04\xd0\x00\x00\x00\x00\x00\x00");
+    let query = parse(&data).unwrap();
+    assert_eq!(query.name, b"c2.example");
+    assert_eq!(type_name(query.qtype), "A");
+
+    let response = respond(&query, &sinkhole);
+    assert_eq!(
+        &response[..12],
+        b"\x12\x34\x85\x80\x00\x01\x00\x01\x00\x00\x00\x00"
+    );
+    assert_eq!(&response[12..28], &data[12..28]);
+    assert_eq!(
+        &response[28..],
+        b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\xc0\x00\x02\x01"
+    );
+    assert!(response.len() <= data.len() + 5);
+
+    // MX gets no answer, and v6-only sinkholes none for A
+    data[25] = 15;
+    let response = respond(&parse(&data).unwrap(), &sinkhole);
+    assert_eq!(response.len(), 28);
+    data[25] = TYPE_A as u8;
+    let v6 = Sinkhole::new(&["2001:db8::1".parse().unwrap()]);
+    assert_eq!(respond(&parse(&data).unwrap(), &v6).len(), 28);
+
+    // Responses and pointers aren't queries
+    data[2] |= 0x80;
+    assert_eq!(parse(&data), None);
+    assert_eq!(
+        parse(b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\xc0\x0c"),
+        None
+    );
+}
+
+#[test]
+fn test_throttle_source() {
+    let now = Instant::now();
+    let mut throttle = Throttle::new(now);
+    let a: IpAddr = "192.0.2.1".parse().unwrap();
+    let b: IpAddr = "192.0.2.2".parse().unwrap();
+
+    for _ in 0..SOURCE_BURST as usize {
+        assert!(throttle.allow(a, now));
+    }
+    assert!(!throttle.allow(a, now));
+    // Others have their own buckets
+    assert!(throttle.allow(b, now));
+
+    // A second earns a source back SOURCE_RATE answers
+    let later = now + std::time::Duration::from_secs(1);
+    for _ in 0..SOURCE_RATE as usize {
+        assert!(throttle.allow(a, later));
+    }
+    assert!(!throttle.allow(a, later));
+}
+
+#[test]
+fn test_throttle_global() {
+    let now = Instant::now();
+    let mut throttle = Throttle::new(now);
+    let answered = (0..1024u32)
+        .filter(|n| throttle.allow(IpAddr::V4(Ipv4Addr::from(0xc000_0200 + n)), now))
+        .count();
+    assert_eq!(answered, MAX_RATE as usize);
+}
+
+#[test]
+fn test_throttle_max_sources() {
+    let now = Instant::now();
+    let mut throttle = Throttle::new(now);
+    let ip = |n: usize| IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + n as u32));
+
+    // A full table of sources that have used up their answers
+    for n in 0..MAX_SOURCES {
+        throttle.sources.insert(
+            ip(n),
+            Bucket {
+                tokens: 0.0,
+                updated: now,
+            },
+        );
+    }
+    assert!(!throttle.allow(ip(MAX_SOURCES), now));
+    assert!(throttle.allow(ip(0), now + std::time::Duration::from_secs(1)));
+
+    // Once the sources have refilled they're forgotten, making room, except
+    // the one that's since been answered
+    let later = now + std::time::Duration::from_secs_f64(SOURCE_BURST / SOURCE_RATE);
+    assert!(throttle.allow(ip(MAX_SOURCES), later));
+    assert_eq!(throttle.sources.len(), 2);
+}
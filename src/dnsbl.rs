@@ -0,0 +1,257 @@
+//! DNS blocklist lookups for connecting peers, such as against
+//! `zen.spamhaus.org`, so listed hosts can be tagged, slowed further or
+//! closed.
+//!
+//! Lookups run in the background on an enrichment pipeline, so accepts never
+//! wait on them.  A listing
+//! applies to the connection that prompted the lookup once it arrives, and
+//! straight away to later connections while it's cached.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::future::join_all;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::enrich::{self, Limits, Pipeline};
+
+/// What to do with connections from listed hosts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// Just tag them in logs and metrics
+    Tag,
+    /// Tag them and write to them no more often than `--dnsbl-delay`
+    Delay,
+    /// Tag them and close them at their next visit
+    Close,
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tag" => Ok(Action::Tag),
+            "delay" => Ok(Action::Delay),
+            "close" => Ok(Action::Close),
+            _ => Err(format!(
+                "unknown action {:?}, expected one of tag, delay, close",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::Tag => "tag",
+            Action::Delay => "delay",
+            Action::Close => "close",
+        })
+    }
+}
+
+/// Parse a blocklist zone, e.g. zen.spamhaus.org
+pub fn parse_zone(s: &str) -> Result<String, String> {
+    let zone = s.trim_matches('.');
+    if zone.is_empty() || !zone.split('.').all(|label| (1..64).contains(&label.len())) {
+        return Err(format!("invalid zone {:?}", s));
+    }
+    Ok(zone.to_string())
+}
+
+/// A blocklist's answer for a listed host
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listing {
+    pub zone: Arc<str>,
+    /// The return code, e.g. 127.0.0.4 for Spamhaus XBL
+    pub result: Ipv4Addr,
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "zone: {}, result: {}", self.zone, self.result)
+    }
+}
+
+/// The name to look up for `ip` in `zone`: reversed octets for IPv4, and
+/// reversed nibbles for IPv6
+fn query_name(ip: IpAddr, zone: &str) -> String {
+    let mut name = String::new();
+    match ip {
+        IpAddr::V4(v4) => {
+            for octet in v4.octets().iter().rev() {
+                name.push_str(&format!("{}.", octet));
+            }
+        }
+        IpAddr::V6(v6) => {
+            for octet in v6.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", octet & 0xf, octet >> 4));
+            }
+        }
+    }
+    name.push_str(zone);
+    name
+}
+
+/// The listing in a blocklist's answer, if any.
+///
+/// Answers outside 127.0.0.0/8 aren't listings, and 127.255.255.0/24 is how
+/// Spamhaus reports errors such as queries from public resolvers.
+fn listing(zone: &Arc<str>, answer: impl IntoIterator<Item = IpAddr>) -> Option<Listing> {
+    answer.into_iter().find_map(|ip| match ip {
+        IpAddr::V4(v4) if v4.octets()[0] == 127 && v4.octets()[..3] != [127, 255, 255] => {
+            Some(Listing {
+                zone: zone.clone(),
+                result: v4,
+            })
+        }
+        _ => None,
+    })
+}
+
+/// Look `ip` up in each zone at once, returning the first listing, or an error
+/// if any lookup timed out before a listing was found
+async fn lookup(
+    zones: Arc<[Arc<str>]>,
+    ip: IpAddr,
+    timeout: Duration,
+) -> Result<Option<Listing>, String> {
+    let lookups = zones.iter().map(|zone| async move {
+        let name = query_name(ip, zone);
+        match tokio::time::timeout(timeout, tokio::net::lookup_host((name.clone(), 0))).await {
+            Ok(Ok(addrs)) => Ok(listing(zone, addrs.map(|addr| addr.ip()))),
+            // Not found, most likely, which means not listed
+            Ok(Err(_)) => Ok(None),
+            Err(_) => Err(format!("timed out looking up {}", name)),
+        }
+    });
+    let results = join_all(lookups).await;
+    if let Some(listing) = results
+        .iter()
+        .find_map(|r| r.as_ref().ok().cloned().flatten())
+    {
+        return Ok(Some(listing));
+    }
+    results.into_iter().find(Result::is_err).unwrap_or(Ok(None))
+}
+
+/// A finished lookup, as sent back to the main loop
+pub type Resolved = enrich::Resolved<Option<Listing>>;
+
+pub struct Dnsbl {
+    pipeline: Pipeline<Option<Listing>>,
+    listed: u64,
+}
+
+impl Dnsbl {
+    pub fn new(
+        zones: Vec<String>,
+        timeout: Duration,
+        ttl: Duration,
+        limits: Limits,
+    ) -> (Self, UnboundedReceiver<Resolved>) {
+        let zones: Arc<[Arc<str>]> = zones.into_iter().map(Arc::from).collect();
+        let (pipeline, rx) =
+            Pipeline::new(limits, ttl, move |ip| lookup(zones.clone(), ip, timeout));
+        (
+            Self {
+                pipeline,
+                listed: 0,
+            },
+            rx,
+        )
+    }
+
+    /// The cached listing for a peer just accepted into `slot`, or `None` if
+    /// it's not listed or a lookup's needed, in which case one is queued
+    pub fn check(&mut self, ip: IpAddr, slot: usize, now: Instant) -> Option<Listing> {
+        let listing = self.pipeline.check(ip, slot, now).flatten();
+        if listing.is_some() {
+            self.listed += 1;
+        }
+        listing
+    }
+
+    /// Cache a finished lookup, returning the slots of the connections waiting
+    /// on it
+    pub fn resolved(&mut self, resolved: &Resolved, now: Instant) -> Vec<usize> {
+        self.pipeline.resolved(resolved, now)
+    }
+
+    /// Whether a lookup for `ip` is under way
+    pub fn pending(&self, ip: IpAddr) -> bool {
+        self.pipeline.pending(ip)
+    }
+
+    /// Count a connection found listed by a lookup it waited on
+    pub fn listed(&mut self) {
+        self.listed += 1;
+    }
+}
+
+/// Connections from listed hosts, and the pipeline's lookups
+impl fmt::Display for Dnsbl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "listed: {}, {}", self.listed, self.pipeline)
+    }
+}
+
+#[test]
+fn test_dnsbl() {
+    let zone = parse_zone("zen.spamhaus.org.").unwrap();
+    assert_eq!(zone, "zen.spamhaus.org");
+    assert!(parse_zone("bad..zone").is_err());
+
+    assert_eq!(
+        query_name([192, 0, 2, 99].into(), "zen.spamhaus.org"),
+        "99.2.0.192.zen.spamhaus.org"
+    );
+    assert_eq!(
+        query_name("2001:db8::1".parse().unwrap(), "example"),
+        "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.example"
+    );
+
+    let limits = Limits {
+        workers: 1,
+        queue: 1,
+        overflow: enrich::Overflow::DropOldest,
+    };
+    let (mut dnsbl, _rx) = Dnsbl::new(
+        vec![zone],
+        Duration::from_secs(1),
+        Duration::from_secs(60),
+        limits,
+    );
+    let zone: Arc<str> = Arc::from("zen.spamhaus.org");
+    let answer = |ips: &[[u8; 4]]| ips.iter().map(|ip| IpAddr::from(*ip)).collect::<Vec<_>>();
+    assert_eq!(listing(&zone, answer(&[[127, 255, 255, 254]])), None);
+    assert_eq!(listing(&zone, answer(&[[192, 0, 2, 1]])), None);
+    assert_eq!(
+        listing(&zone, answer(&[[127, 0, 0, 4]]))
+            .unwrap()
+            .to_string(),
+        "zone: zen.spamhaus.org, result: 127.0.0.4"
+    );
+
+    let ip = [192, 0, 2, 1].into();
+    let now = Instant::now();
+    let listed = listing(&zone, answer(&[[127, 0, 0, 2]]));
+    assert_eq!(
+        dnsbl.resolved(&(ip, Ok(listed.clone())), now),
+        Vec::<usize>::new()
+    );
+    assert_eq!(dnsbl.check(ip, 0, now), listed);
+    assert!(!dnsbl.pending(ip));
+    assert_eq!(
+        dnsbl.to_string(),
+        "listed: 1, lookups: 0, failed: 0, dropped: 0, cached: 1, queued: 0"
+    );
+
+    assert_eq!("close".parse(), Ok(Action::Close));
+    assert!("drop".parse::<Action>().is_err());
+}
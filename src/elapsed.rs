@@ -3,7 +3,7 @@ use std::time::{Duration, Instant};
 
 /// A tiny type for tracking approximate Durations from a known starting point
 /// Wraps every 13.6 years, precision of 1 decisecond (100ms)
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default)]
 pub struct Elapsed(u32);
 
 impl From<Instant> for Elapsed {
@@ -20,8 +20,10 @@ impl From<Elapsed> for Duration {
 }
 
 impl Elapsed {
+    /// Time since this point, or zero if it's still to come, as it is for a
+    /// connection accepted before a clock jump moved `start` on past it
     pub fn elapsed(&self, start: Instant) -> Duration {
-        start.elapsed() - Duration::from(*self)
+        start.elapsed().saturating_sub(Duration::from(*self))
     }
 }
 
@@ -30,3 +32,21 @@ impl fmt::Debug for Elapsed {
         Duration::from(*self).fmt(f)
     }
 }
+
+#[test]
+fn test_elapsed_past() {
+    let start = Instant::now() - Duration::from_secs(10);
+    let elapsed = Elapsed::from(start);
+    assert!(elapsed.elapsed(start) < Duration::from_secs(1));
+    let earlier = start - Duration::from_secs(5);
+    assert!(elapsed.elapsed(earlier) >= Duration::from_secs(5));
+}
+
+#[test]
+fn test_elapsed_future() {
+    // Accepted 10s into the run, just before a jump moved startup on by 60s
+    let start = Instant::now() - Duration::from_secs(10);
+    let elapsed = Elapsed::from(start);
+    let startup = start + Duration::from_secs(60);
+    assert_eq!(elapsed.elapsed(startup), Duration::ZERO);
+}
@@ -0,0 +1,259 @@
+//! A pipeline for looking peers up in slow external services, such as DNS
+//! blocklists, off the accept and tick paths.
+//!
+//! Each source gets its own queue and pool of workers, so one that's slow or
+//! down can't hold up another, and its own cache.  The queue is bounded: when
+//! it's full, a new lookup either pushes out the oldest waiting, or is dropped
+//! itself, per the overflow policy.  Either way nothing waits for room, and
+//! connections whose lookup is dropped just go without.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Notify;
+
+/// Most results to cache before sweeping out expired ones
+const CACHE_MAX: usize = 65536;
+
+/// What to do with a lookup when the queue's full
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    /// Drop the oldest lookup still queued, most likely for a connection
+    /// that's long gone
+    DropOldest,
+    /// Drop the new lookup
+    DropNewest,
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-oldest" => Ok(Overflow::DropOldest),
+            "drop-newest" => Ok(Overflow::DropNewest),
+            _ => Err(format!(
+                "unknown policy {:?}, expected drop-oldest or drop-newest",
+                s
+            )),
+        }
+    }
+}
+
+/// Limits shared by every source
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Lookups each source runs at once
+    pub workers: usize,
+    /// Lookups each source may have queued
+    pub queue: usize,
+    pub overflow: Overflow,
+}
+
+/// A finished lookup, as sent back to the main loop
+pub type Resolved<T> = (IpAddr, Result<T, String>);
+
+struct Queue {
+    ips: Mutex<VecDeque<IpAddr>>,
+    ready: Notify,
+}
+
+pub struct Pipeline<T> {
+    limits: Limits,
+    ttl: Duration,
+    queue: Arc<Queue>,
+    /// Starts the workers on first use, so a pipeline can be made outside a
+    /// runtime
+    start: Option<Box<dyn FnOnce(Arc<Queue>)>>,
+    cache: HashMap<IpAddr, (Instant, T)>,
+    /// Lookups queued or under way, with the slots of the connections waiting
+    /// on them
+    waiting: HashMap<IpAddr, Vec<usize>>,
+    lookups: u64,
+    failures: u64,
+    dropped: u64,
+}
+
+impl<T: Clone + Send + 'static> Pipeline<T> {
+    /// A pipeline running `lookup` for each peer, caching successful results
+    /// for `ttl`
+    pub fn new<F, Fut>(
+        limits: Limits,
+        ttl: Duration,
+        lookup: F,
+    ) -> (Self, UnboundedReceiver<Resolved<T>>)
+    where
+        F: Fn(IpAddr) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<T, String>> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let lookup = Arc::new(lookup);
+        let workers = limits.workers;
+        let start = move |queue: Arc<Queue>| {
+            for _ in 0..workers {
+                tokio::spawn(work(queue.clone(), lookup.clone(), tx.clone()));
+            }
+        };
+        let pipeline = Self {
+            limits,
+            ttl,
+            queue: Arc::new(Queue {
+                ips: Mutex::new(VecDeque::new()),
+                ready: Notify::new(),
+            }),
+            start: Some(Box::new(start)),
+            cache: HashMap::new(),
+            waiting: HashMap::new(),
+            lookups: 0,
+            failures: 0,
+            dropped: 0,
+        };
+        (pipeline, rx)
+    }
+
+    /// The cached result for a peer just accepted into `slot`, or `None` if a
+    /// lookup's needed, in which case one is queued
+    pub fn check(&mut self, ip: IpAddr, slot: usize, now: Instant) -> Option<T> {
+        match self.cache.get(&ip) {
+            Some((expires, result)) if *expires > now => return Some(result.clone()),
+            _ => (),
+        }
+
+        if let Some(waiting) = self.waiting.get_mut(&ip) {
+            waiting.push(slot);
+            return None;
+        }
+        if !self.enqueue(ip) {
+            return None;
+        }
+        self.waiting.insert(ip, vec![slot]);
+        self.lookups += 1;
+        if let Some(start) = self.start.take() {
+            start(self.queue.clone());
+        }
+        None
+    }
+
+    /// Queue a lookup for `ip`, returning false if it's dropped
+    fn enqueue(&mut self, ip: IpAddr) -> bool {
+        let mut ips = self.queue.ips.lock().unwrap();
+        if ips.len() >= self.limits.queue {
+            self.dropped += 1;
+            match self.limits.overflow {
+                Overflow::DropNewest => return false,
+                Overflow::DropOldest => {
+                    if let Some(oldest) = ips.pop_front() {
+                        self.waiting.remove(&oldest);
+                    }
+                }
+            }
+        }
+        ips.push_back(ip);
+        drop(ips);
+        self.queue.ready.notify_one();
+        true
+    }
+
+    /// Cache a finished lookup, returning the slots of the connections waiting
+    /// on it
+    pub fn resolved(&mut self, resolved: &Resolved<T>, now: Instant) -> Vec<usize> {
+        let (ip, result) = resolved;
+        match result {
+            Ok(result) => {
+                if self.cache.len() >= CACHE_MAX {
+                    self.cache.retain(|_, (expires, _)| *expires > now);
+                }
+                self.cache.insert(*ip, (now + self.ttl, result.clone()));
+            }
+            Err(_) => self.failures += 1,
+        }
+        self.waiting.remove(ip).unwrap_or_default()
+    }
+
+    /// Whether a lookup for `ip` is queued or under way
+    pub fn pending(&self, ip: IpAddr) -> bool {
+        self.waiting.contains_key(&ip)
+    }
+}
+
+async fn work<T, F, Fut>(queue: Arc<Queue>, lookup: Arc<F>, tx: UnboundedSender<Resolved<T>>)
+where
+    F: Fn(IpAddr) -> Fut,
+    Fut: Future<Output = Result<T, String>>,
+{
+    loop {
+        let next = queue.ips.lock().unwrap().pop_front();
+        let ip = match next {
+            Some(ip) => ip,
+            None => {
+                queue.ready.notified().await;
+                continue;
+            }
+        };
+        if tx.send((ip, lookup(ip).await)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Lookups, failures, lookups dropped for want of queue room, results cached,
+/// and lookups queued
+impl<T> fmt::Display for Pipeline<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "lookups: {}, failed: {}, dropped: {}, cached: {}, queued: {}",
+            self.lookups,
+            self.failures,
+            self.dropped,
+            self.cache.len(),
+            self.queue.ips.lock().unwrap().len()
+        )
+    }
+}
+
+#[test]
+fn test_pipeline() {
+    let limits = Limits {
+        workers: 1,
+        queue: 2,
+        overflow: Overflow::DropOldest,
+    };
+    let lookup = |ip: IpAddr| async move { Ok(ip.is_loopback()) };
+    let (mut pipeline, _rx) = Pipeline::new(limits, Duration::from_secs(60), lookup);
+    // Don't start any workers, so lookups stay queued
+    pipeline.start = None;
+
+    let now = Instant::now();
+    let ip = |n: u8| IpAddr::from([192, 0, 2, n]);
+    assert_eq!(pipeline.check(ip(1), 0, now), None);
+    assert_eq!(pipeline.check(ip(1), 1, now), None);
+    assert_eq!(pipeline.check(ip(2), 2, now), None);
+    assert_eq!(pipeline.check(ip(3), 3, now), None);
+    assert!(!pipeline.pending(ip(1)));
+    assert_eq!(
+        pipeline.resolved(&(ip(1), Ok(false)), now),
+        Vec::<usize>::new()
+    );
+    assert_eq!(pipeline.resolved(&(ip(3), Ok(false)), now), vec![3]);
+    assert_eq!(pipeline.check(ip(3), 0, now), Some(false));
+    assert_eq!(
+        pipeline.check(ip(3), 0, now + Duration::from_secs(60)),
+        None
+    );
+    assert_eq!(
+        pipeline.to_string(),
+        "lookups: 4, failed: 0, dropped: 2, cached: 2, queued: 2"
+    );
+
+    pipeline.limits.overflow = Overflow::DropNewest;
+    assert_eq!(pipeline.check(ip(4), 0, now), None);
+    assert!(!pipeline.pending(ip(4)));
+    assert_eq!("drop-newest".parse(), Ok(Overflow::DropNewest));
+}
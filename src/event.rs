@@ -0,0 +1,720 @@
+//! The tarssh event schema, and a parser for its text log format.
+//!
+//! tarssh logs one event per line, as an event name followed by `key: value`
+//! fields, optionally prefixed by `env_logger`'s bracketed header:
+//!
+//! ```txt
+//! [2022-06-07T00:00:00.000Z INFO  tarssh] connect, peer: 127.0.0.1:61986, clients: 1
+//! ```
+//!
+//! [`Record::parse`] turns such lines, or their JSON equivalents, into
+//! [`Record`]s.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// A single tarssh event.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Init {
+        pid: u32,
+        version: String,
+    },
+    Listen {
+        addr: SocketAddr,
+        /// Whether an IPv6 listener also takes IPv4, where that was asked for
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dual: Option<bool>,
+        /// The name given the listener with `--listen-label`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+    },
+    Start {
+        servers: usize,
+        max_clients: u32,
+        #[serde(with = "secs")]
+        delay: Duration,
+        #[serde(with = "secs")]
+        timeout: Duration,
+    },
+    Connect {
+        peer: SocketAddr,
+        clients: usize,
+        /// When `--reputation-file` first saw the source, as RFC 3339
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        first_seen: Option<String>,
+        /// Connections `--reputation-file` has seen from the source,
+        /// including this one
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        times_seen: Option<u64>,
+        /// The label of the listener the connection came in on
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        listener: Option<String>,
+        #[serde(flatten)]
+        location: Location,
+        /// The `--dnsbl` zone listing the source, with `--enrich-wait`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dnsbl: Option<String>,
+        /// The address the zone answered with
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dnsbl_result: Option<String>,
+        /// `timeout` if `--enrich-wait` ran out before lookups finished
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        enrich: Option<String>,
+    },
+    Disconnect {
+        peer: SocketAddr,
+        #[serde(with = "secs")]
+        duration: Duration,
+        bytes: u64,
+        error: String,
+        clients: usize,
+        /// Successful writes, absent from older logs
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        writes: Option<u32>,
+        /// Consecutive writes that failed on a full socket buffer at the end
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        failed: Option<u32>,
+        /// Average time between successful writes
+        #[serde(default, skip_serializing_if = "Option::is_none", with = "opt_secs")]
+        interval: Option<Duration>,
+        /// Bytes still unsent or unacknowledged when the connection closed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        unacked: Option<u64>,
+        #[serde(flatten)]
+        location: Location,
+        /// Tags picked up by the connection, e.g. `canary=str:secret`
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tags: Vec<String>,
+        /// The label of the listener, given only when `short` is
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        listener: Option<String>,
+        /// Whether the connect line was left out, the connection not lasting
+        /// `--log-after`
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        short: bool,
+    },
+    /// A source found on a `--dnsbl` zone, and what was done about it
+    Dnsbl {
+        peer: SocketAddr,
+        zone: String,
+        result: String,
+        action: String,
+    },
+    /// A status report, or shutdown if `shutdown` is set
+    Info {
+        shutdown: bool,
+        pid: u32,
+        signal: String,
+        #[serde(with = "secs")]
+        uptime: Duration,
+        clients: usize,
+        total: u64,
+        bytes: u64,
+    },
+}
+
+/// Where `--geoip` places a peer, as far as its databases say
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    /// ISO 3166 country code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u32>,
+    /// The organisation announcing the ASN
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
+}
+
+impl Location {
+    fn parse(fields: &HashMap<&str, &str>) -> Self {
+        let get = |key: &str| fields.get(key).copied();
+        Self {
+            country: get("country").map(str::to_string),
+            city: get("city").map(unescape),
+            asn: get("asn").and_then(|asn| asn.parse().ok()),
+            org: get("org").map(unescape),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(country) = &self.country {
+            write!(f, ", country: {}", country)?;
+        }
+        if let Some(city) = &self.city {
+            write!(f, ", city: {}", Quoted(city))?;
+        }
+        if let Some(asn) = self.asn {
+            write!(f, ", asn: {}", asn)?;
+        }
+        if let Some(org) = &self.org {
+            write!(f, ", org: {}", Quoted(org))?;
+        }
+        Ok(())
+    }
+}
+
+/// An [`Event`] with the log timestamp it was recorded at, if known.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// An RFC 3339 UTC timestamp, e.g. `2022-06-07T00:00:00.000Z`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl Record {
+    /// Parse a line of text log or JSON, returning `None` if it isn't a
+    /// recognised event.
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.starts_with('{') {
+            return serde_json::from_str(line).ok();
+        }
+
+        let (time, message) = match line.strip_prefix('[') {
+            Some(rest) => {
+                let (header, message) = rest.split_once("] ")?;
+                let time = header
+                    .split_whitespace()
+                    .next()
+                    .filter(|t| t.starts_with(|c: char| c.is_ascii_digit()) && t.contains('T'));
+                (time.map(str::to_string), message)
+            }
+            None => (None, line),
+        };
+
+        Some(Self {
+            time,
+            event: Event::parse(message)?,
+        })
+    }
+
+    /// The hour of day of the timestamp, if any
+    pub fn hour(&self) -> Option<u8> {
+        let time = self.time.as_ref()?;
+        let (_, rest) = time.split_once('T')?;
+        rest.get(..2)?.parse().ok().filter(|h| *h < 24)
+    }
+}
+
+impl Event {
+    /// Parse an event from the message portion of a log line
+    pub fn parse(message: &str) -> Option<Self> {
+        let (name, rest) = message.split_once(", ").unwrap_or((message, ""));
+        let fields = fields(rest);
+        let get = |key: &str| fields.get(key).copied();
+
+        Some(match name {
+            "init" => Event::Init {
+                pid: get("pid")?.parse().ok()?,
+                version: get("version")?.to_string(),
+            },
+            "listen" => Event::Listen {
+                addr: get("addr")?.parse().ok()?,
+                dual: get("dual").and_then(|d| d.parse().ok()),
+                label: get("label").map(str::to_string),
+            },
+            "start" => Event::Start {
+                servers: get("servers")?.parse().ok()?,
+                max_clients: get("max_clients")?.parse().ok()?,
+                delay: parse_duration(get("delay")?)?,
+                timeout: parse_duration(get("timeout")?)?,
+            },
+            "connect" => Event::Connect {
+                peer: get("peer")?.parse().ok()?,
+                clients: get("clients")?.parse().ok()?,
+                first_seen: get("first_seen").map(str::to_string),
+                times_seen: get("times_seen").and_then(|n| n.parse().ok()),
+                listener: get("listener").map(str::to_string),
+                location: Location::parse(&fields),
+                dnsbl: get("dnsbl").map(str::to_string),
+                dnsbl_result: get("dnsbl_result").map(str::to_string),
+                enrich: get("enrich").map(str::to_string),
+            },
+            "disconnect" => Event::Disconnect {
+                peer: get("peer")?.parse().ok()?,
+                duration: parse_duration(get("duration")?)?,
+                bytes: get("bytes")?.parse().ok()?,
+                error: get("error")?.to_string(),
+                clients: get("clients")?.parse().ok()?,
+                writes: get("writes").and_then(|w| w.parse().ok()),
+                failed: get("failed").and_then(|f| f.parse().ok()),
+                interval: get("interval").and_then(parse_duration),
+                unacked: get("unacked").and_then(|u| u.parse().ok()),
+                location: Location::parse(&fields),
+                tags: get("tags")
+                    .map(|tags| tags.split_whitespace().map(str::to_string).collect())
+                    .unwrap_or_default(),
+                listener: get("listener").map(str::to_string),
+                short: get("short") == Some("true"),
+            },
+            "dnsbl" => Event::Dnsbl {
+                peer: get("peer")?.parse().ok()?,
+                zone: get("zone")?.to_string(),
+                result: get("result")?.to_string(),
+                action: get("action")?.to_string(),
+            },
+            "info" | "shutdown" => Event::Info {
+                shutdown: name == "shutdown",
+                pid: get("pid")?.parse().ok()?,
+                signal: get("signal")?.to_string(),
+                uptime: parse_duration(get("uptime")?)?,
+                clients: get("clients")?.parse().ok()?,
+                total: get("total")?.parse().ok()?,
+                bytes: get("bytes")?.parse().ok()?,
+            },
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Init { pid, version } => write!(f, "init, pid: {}, version: {}", pid, version),
+            Event::Listen { addr, dual, label } => {
+                write!(f, "listen, addr: {}", addr)?;
+                if let Some(dual) = dual {
+                    write!(f, ", dual: {}", dual)?;
+                }
+                if let Some(label) = label {
+                    write!(f, ", label: {}", label)?;
+                }
+                Ok(())
+            }
+            Event::Start {
+                servers,
+                max_clients,
+                delay,
+                timeout,
+            } => write!(
+                f,
+                "start, servers: {}, max_clients: {}, delay: {}s, timeout: {}s",
+                servers,
+                max_clients,
+                delay.as_secs(),
+                timeout.as_secs()
+            ),
+            Event::Connect {
+                peer,
+                clients,
+                first_seen,
+                times_seen,
+                listener,
+                location,
+                dnsbl,
+                dnsbl_result,
+                enrich,
+            } => {
+                write!(f, "connect, peer: {}, clients: {}", peer, clients)?;
+                if let (Some(first_seen), Some(times_seen)) = (first_seen, times_seen) {
+                    write!(
+                        f,
+                        ", first_seen: {}, times_seen: {}",
+                        first_seen, times_seen
+                    )?;
+                }
+                if let Some(listener) = listener {
+                    write!(f, ", listener: {}", listener)?;
+                }
+                write!(f, "{}", location)?;
+                if let (Some(dnsbl), Some(dnsbl_result)) = (dnsbl, dnsbl_result) {
+                    write!(f, ", dnsbl: {}, dnsbl_result: {}", dnsbl, dnsbl_result)?;
+                }
+                if let Some(enrich) = enrich {
+                    write!(f, ", enrich: {}", enrich)?;
+                }
+                Ok(())
+            }
+            Event::Disconnect {
+                peer,
+                duration,
+                bytes,
+                error,
+                clients,
+                writes,
+                failed,
+                interval,
+                unacked,
+                location,
+                tags,
+                listener,
+                short,
+            } => {
+                write!(
+                    f,
+                    "disconnect, peer: {}, duration: {:.2?}, bytes: {}, error: \"{}\", clients: {}",
+                    peer, duration, bytes, error, clients
+                )?;
+                if let (Some(writes), Some(failed)) = (writes, failed) {
+                    write!(f, ", writes: {}, failed: {}", writes, failed)?;
+                    match interval {
+                        Some(interval) => write!(f, ", interval: {:.2?}", interval)?,
+                        None => write!(f, ", interval: none")?,
+                    }
+                }
+                if let Some(unacked) = unacked {
+                    write!(f, ", unacked: {}", unacked)?;
+                }
+                write!(f, "{}", location)?;
+                if !tags.is_empty() {
+                    write!(f, ", tags: \"{}\"", tags.join(" "))?;
+                }
+                if let Some(listener) = listener {
+                    write!(f, ", listener: {}", listener)?;
+                }
+                if *short {
+                    write!(f, ", short: true")?;
+                }
+                Ok(())
+            }
+            Event::Dnsbl {
+                peer,
+                zone,
+                result,
+                action,
+            } => write!(
+                f,
+                "dnsbl, peer: {}, zone: {}, result: {}, action: {}",
+                peer, zone, result, action
+            ),
+            Event::Info {
+                shutdown,
+                pid,
+                signal,
+                uptime,
+                clients,
+                total,
+                bytes,
+            } => write!(
+                f,
+                "{}, pid: {}, signal: {}, uptime: {:.2?}, clients: {}, total: {}, bytes: {}",
+                if *shutdown { "shutdown" } else { "info" },
+                pid,
+                signal,
+                uptime,
+                clients,
+                total,
+                bytes
+            ),
+        }
+    }
+}
+
+/// Split `key: value, key: "quoted, value"` into a map
+fn fields(mut s: &str) -> HashMap<&str, &str> {
+    let mut fields = HashMap::new();
+
+    while let Some((key, rest)) = s.split_once(": ") {
+        let (value, rest) = match rest.strip_prefix('"') {
+            Some(quoted) => match closing_quote(quoted) {
+                Some(end) => (&quoted[..end], &quoted[end + 3..]),
+                None => (quoted.strip_suffix('"').unwrap_or(quoted), ""),
+            },
+            None => rest.split_once(", ").unwrap_or((rest, "")),
+        };
+        fields.insert(key, value);
+        s = rest;
+    }
+
+    fields
+}
+
+/// A value in quotes, escaping quotes, backslashes, and control and bidi
+/// characters as the daemon does
+struct Quoted<'a>(&'a str);
+
+impl fmt::Display for Quoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"")?;
+        for c in self.0.chars() {
+            match c {
+                '"' | '\\' => write!(f, "{}", c.escape_default())?,
+                c if c.is_control()
+                    || matches!(
+                        c,
+                        '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}'
+                    ) =>
+                {
+                    write!(f, "{}", c.escape_default())?
+                }
+                c => write!(f, "{}", c)?,
+            }
+        }
+        f.write_str("\"")
+    }
+}
+
+/// Where the `", ` ending a quoted value starts, skipping escaped quotes
+fn closing_quote(quoted: &str) -> Option<usize> {
+    quoted.match_indices("\", ").map(|(i, _)| i).find(|&i| {
+        let backslashes = quoted[..i]
+            .bytes()
+            .rev()
+            .take_while(|b| *b == b'\\')
+            .count();
+        backslashes % 2 == 0
+    })
+}
+
+/// Undo the `escape_default` quoting of a value, e.g. `\"` or `\u{1b}`
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some('0') => unescaped.push('\0'),
+            Some('u') => {
+                let rest = chars.as_str();
+                let code = rest
+                    .strip_prefix('{')
+                    .and_then(|rest| rest.split_once('}'))
+                    .and_then(|(hex, _)| u32::from_str_radix(hex, 16).ok())
+                    .and_then(char::from_u32);
+                match code {
+                    Some(c) => {
+                        unescaped.push(c);
+                        chars = rest[rest.find('}').unwrap_or(0) + 1..].chars();
+                    }
+                    None => unescaped.push_str("\\u"),
+                }
+            }
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Parse a `Duration` formatted with `{:?}`, e.g. `19.80s` or `250.00ms`
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let split = s.find(|c: char| !(c.is_ascii_digit() || c == '.'))?;
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value.parse().ok()?;
+    let scale = match unit {
+        "s" => 1.0,
+        "ms" => 1e-3,
+        "µs" | "us" => 1e-6,
+        "ns" => 1e-9,
+        _ => return None,
+    };
+    Some(Duration::from_secs_f64(value * scale))
+}
+
+/// (De)serialize a `Duration` as fractional seconds
+mod secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_f64(d.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        let secs = f64::deserialize(d)?;
+        Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+    }
+}
+
+/// (De)serialize an optional `Duration` as fractional seconds
+mod opt_secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(d: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+        match d {
+            Some(d) => super::secs::serialize(d, s),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        Option::<f64>::deserialize(d)?
+            .map(|secs| Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+#[test]
+fn test_parse_duration() {
+    assert_eq!(parse_duration("19.80s"), Some(Duration::from_millis(19800)));
+    assert_eq!(parse_duration("250.00ms"), Some(Duration::from_millis(250)));
+    assert_eq!(parse_duration("3.00µs"), Some(Duration::from_micros(3)));
+    assert_eq!(parse_duration("s"), None);
+    assert_eq!(parse_duration("1.0h"), None);
+}
+
+#[test]
+fn test_parse_record() {
+    let line = r#"[2022-06-07T05:00:00.000Z INFO  tarssh] disconnect, peer: [::1]:61986, duration: 19.80s, bytes: 24, error: "Broken pipe, (os error 32)", clients: 1"#;
+    let record = Record::parse(line).unwrap();
+    assert_eq!(record.hour(), Some(5));
+    assert_eq!(
+        record.event,
+        Event::Disconnect {
+            peer: "[::1]:61986".parse().unwrap(),
+            duration: Duration::from_millis(19800),
+            bytes: 24,
+            error: "Broken pipe, (os error 32)".to_string(),
+            clients: 1,
+            writes: None,
+            failed: None,
+            interval: None,
+            unacked: None,
+            location: Location::default(),
+            tags: vec![],
+            listener: None,
+            short: false,
+        }
+    );
+
+    let json = serde_json::to_string(&record).unwrap();
+    assert_eq!(Record::parse(&json), Some(record.clone()));
+    assert_eq!(
+        Record::parse(&format!("[INFO ] {}", record.event))
+            .unwrap()
+            .event,
+        record.event
+    );
+
+    let line = "disconnect, peer: 127.0.0.1:1, duration: 3.00s, bytes: 9, error: \"Timed Out\", clients: 0, writes: 3, failed: 2, interval: 1.00s, unacked: 24, tags: \"canary=str:x version=SSH-2.0-Go\", short: true";
+    let event = Event::parse(line).unwrap();
+    assert_eq!(event.to_string(), line);
+    let json = serde_json::to_string(&event).unwrap();
+    assert_eq!(serde_json::from_str::<Event>(&json).unwrap(), event);
+
+    let line =
+        "connect, peer: 127.0.0.1:1, clients: 2, first_seen: 2022-06-01T00:00:00Z, times_seen: 3";
+    let event = Event::parse(line).unwrap();
+    assert_eq!(event.to_string(), line);
+}
+
+#[test]
+fn test_parse_connect_enriched() {
+    let line = "connect, peer: 127.0.0.1:1, clients: 2, listener: ssh, country: NL, city: \"Den \\\"Haag\\\", Zuid\", asn: 1136, org: \"KPN B.V.\", dnsbl: zen.spamhaus.org, dnsbl_result: 127.0.0.4";
+    let event = Event::parse(line).unwrap();
+    match &event {
+        Event::Connect {
+            listener,
+            location,
+            dnsbl,
+            dnsbl_result,
+            enrich,
+            ..
+        } => {
+            assert_eq!(listener.as_deref(), Some("ssh"));
+            assert_eq!(
+                *location,
+                Location {
+                    country: Some("NL".to_string()),
+                    city: Some("Den \"Haag\", Zuid".to_string()),
+                    asn: Some(1136),
+                    org: Some("KPN B.V.".to_string()),
+                }
+            );
+            assert_eq!(dnsbl.as_deref(), Some("zen.spamhaus.org"));
+            assert_eq!(dnsbl_result.as_deref(), Some("127.0.0.4"));
+            assert_eq!(*enrich, None);
+        }
+        _ => panic!("not a connect: {:?}", event),
+    }
+    assert_eq!(event.to_string(), line);
+    let json = serde_json::to_string(&event).unwrap();
+    assert!(json.contains(r#""asn":1136"#));
+    assert_eq!(serde_json::from_str::<Event>(&json).unwrap(), event);
+
+    let line = "connect, peer: [::1]:2, clients: 1, country: DE, enrich: timeout";
+    let event = Event::parse(line).unwrap();
+    assert_eq!(event.to_string(), line);
+}
+
+#[test]
+fn test_parse_disconnect_enriched() {
+    let line = "disconnect, peer: 127.0.0.1:1, duration: 3.00s, bytes: 9, error: \"Timed Out\", clients: 0, writes: 3, failed: 70000, interval: none, asn: 64512, org: \"Esc\\u{1b}aped\", tags: \"version=SSH-2.0-Go\", listener: alt, short: true";
+    let event = Event::parse(line).unwrap();
+    match &event {
+        Event::Disconnect {
+            failed,
+            location,
+            listener,
+            ..
+        } => {
+            assert_eq!(*failed, Some(70000));
+            assert_eq!(location.asn, Some(64512));
+            assert_eq!(location.org.as_deref(), Some("Esc\u{1b}aped"));
+            assert_eq!(location.country, None);
+            assert_eq!(listener.as_deref(), Some("alt"));
+        }
+        _ => panic!("not a disconnect: {:?}", event),
+    }
+    assert_eq!(event.to_string(), line);
+    let json = serde_json::to_string(&event).unwrap();
+    assert_eq!(serde_json::from_str::<Event>(&json).unwrap(), event);
+}
+
+#[test]
+fn test_parse_listen() {
+    for line in &[
+        "listen, addr: 0.0.0.0:22",
+        "listen, addr: [::]:22, dual: true, label: ssh",
+    ] {
+        let event = Event::parse(line).unwrap();
+        assert_eq!(event.to_string(), *line);
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(serde_json::from_str::<Event>(&json).unwrap(), event);
+    }
+    assert_eq!(
+        Event::parse("listen, addr: 0.0.0.0:2222, systemd: true, label: alt"),
+        Some(Event::Listen {
+            addr: "0.0.0.0:2222".parse().unwrap(),
+            dual: None,
+            label: Some("alt".to_string()),
+        })
+    );
+}
+
+#[test]
+fn test_parse_dnsbl() {
+    let line = "dnsbl, peer: 192.0.2.1:22, zone: zen.spamhaus.org, result: 127.0.0.2, action: ban";
+    let event = Event::parse(line).unwrap();
+    assert_eq!(
+        event,
+        Event::Dnsbl {
+            peer: "192.0.2.1:22".parse().unwrap(),
+            zone: "zen.spamhaus.org".to_string(),
+            result: "127.0.0.2".to_string(),
+            action: "ban".to_string(),
+        }
+    );
+    assert_eq!(event.to_string(), line);
+    let json = serde_json::to_string(&event).unwrap();
+    assert_eq!(serde_json::from_str::<Event>(&json).unwrap(), event);
+}
+
+#[test]
+fn test_unescape() {
+    assert_eq!(unescape(r#"a\"b\\c"#), "a\"b\\c");
+    assert_eq!(unescape(r"\u{1b}[0m\n"), "\u{1b}[0m\n");
+    assert_eq!(unescape(r"\u{zz}"), r"\u{zz}");
+    assert_eq!(unescape("trailing\\"), "trailing\\");
+    assert_eq!(Quoted("a\"\u{202e}").to_string(), r#""a\"\u{202e}""#);
+}
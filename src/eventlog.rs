@@ -0,0 +1,127 @@
+//! Logging to the Windows Event Log.
+
+use std::ffi::OsStr;
+use std::io;
+use std::os::windows::ffi::OsStrExt;
+use std::ptr;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use windows_sys::Win32::Foundation::{ERROR_SUCCESS, HANDLE};
+use windows_sys::Win32::System::EventLog::{
+    RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE,
+    EVENTLOG_WARNING_TYPE,
+};
+use windows_sys::Win32::System::Registry::{
+    RegCloseKey, RegCreateKeyExW, RegDeleteKeyW, RegSetValueExW, HKEY, HKEY_LOCAL_MACHINE,
+    KEY_WRITE, REG_DWORD, REG_OPTION_NON_VOLATILE,
+};
+
+use crate::sanitize::Bounded;
+
+const SOURCE: &str = "tarssh";
+const SOURCE_KEY: &str = r"SYSTEM\CurrentControlSet\Services\EventLog\Application\tarssh";
+
+fn wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
+
+fn check(err: u32) -> io::Result<()> {
+    if err == ERROR_SUCCESS {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(err as i32))
+    }
+}
+
+/// Register tarssh as an Event Log source in the Application log
+pub fn register() -> io::Result<()> {
+    let mut key: HKEY = 0;
+    // SAFETY: all pointers are to live, NUL-terminated buffers or locals
+    unsafe {
+        check(RegCreateKeyExW(
+            HKEY_LOCAL_MACHINE,
+            wide(SOURCE_KEY).as_ptr(),
+            0,
+            ptr::null(),
+            REG_OPTION_NON_VOLATILE,
+            KEY_WRITE,
+            ptr::null(),
+            &mut key,
+            ptr::null_mut(),
+        ))?;
+        let types: u32 =
+            (EVENTLOG_ERROR_TYPE | EVENTLOG_WARNING_TYPE | EVENTLOG_INFORMATION_TYPE) as u32;
+        let result = check(RegSetValueExW(
+            key,
+            wide("TypesSupported").as_ptr(),
+            0,
+            REG_DWORD,
+            &types as *const u32 as *const u8,
+            std::mem::size_of::<u32>() as u32,
+        ));
+        RegCloseKey(key);
+        result
+    }
+}
+
+/// Remove tarssh's Event Log source
+pub fn unregister() -> io::Result<()> {
+    // SAFETY: the key name is a live, NUL-terminated buffer
+    check(unsafe { RegDeleteKeyW(HKEY_LOCAL_MACHINE, wide(SOURCE_KEY).as_ptr()) })
+}
+
+struct EventLog {
+    handle: HANDLE,
+    level: LevelFilter,
+}
+
+impl Log for EventLog {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let kind = match record.level() {
+            Level::Error => EVENTLOG_ERROR_TYPE,
+            Level::Warn => EVENTLOG_WARNING_TYPE,
+            _ => EVENTLOG_INFORMATION_TYPE,
+        };
+        let message = wide(&record.args().to_string());
+        let strings = [message.as_ptr()];
+        // SAFETY: the handle lives as long as the logger, and the single
+        // string is NUL-terminated
+        unsafe {
+            ReportEventW(
+                self.handle,
+                kind,
+                0,
+                1,
+                ptr::null_mut(),
+                1,
+                0,
+                strings.as_ptr(),
+                ptr::null(),
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Send all logging to the Event Log rather than standard error
+pub fn init(level: LevelFilter) -> io::Result<()> {
+    // SAFETY: the source name is a live, NUL-terminated buffer
+    let handle = unsafe { RegisterEventSourceW(ptr::null(), wide(SOURCE).as_ptr()) };
+    if handle == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    log::set_logger(Box::leak(Box::new(Bounded(EventLog { handle, level }))))
+        .map_err(|err| io::Error::new(io::ErrorKind::AlreadyExists, err.to_string()))?;
+    log::set_max_level(level);
+    Ok(())
+}
@@ -0,0 +1,62 @@
+//! Startup failures, each kind with its own exit code so service managers and
+//! scripts can tell them apart.
+
+use log::error;
+
+/// Why tarssh couldn't start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fatal {
+    /// A bad configuration file, or an option this platform can't support
+    Config,
+    /// A listener couldn't be bound
+    Bind,
+    /// Privileges couldn't be dropped
+    #[cfg_attr(not(all(unix, feature = "drop_privs")), allow(dead_code))]
+    Privdrop,
+    /// The sandbox was required but couldn't be entered
+    Sandbox,
+    /// A file or directory couldn't be opened or created
+    Io,
+    /// A remote service couldn't be reached or resolved
+    Remote,
+    /// Anything else
+    Other,
+}
+
+impl Fatal {
+    pub fn code(self) -> i32 {
+        match self {
+            Fatal::Config => exitcode::CONFIG,
+            Fatal::Bind => exitcode::UNAVAILABLE,
+            Fatal::Privdrop => exitcode::NOPERM,
+            Fatal::Sandbox => exitcode::OSERR,
+            Fatal::Io => exitcode::IOERR,
+            Fatal::Remote => exitcode::NOHOST,
+            Fatal::Other => exitcode::SOFTWARE,
+        }
+    }
+
+    /// Log `message` and exit with this kind's code
+    pub fn exit<M: AsRef<str>>(self, message: M) -> ! {
+        error!("{}", message.as_ref());
+        std::process::exit(self.code());
+    }
+}
+
+#[test]
+fn test_fatal_codes() {
+    let kinds = [
+        Fatal::Config,
+        Fatal::Bind,
+        Fatal::Privdrop,
+        Fatal::Sandbox,
+        Fatal::Io,
+        Fatal::Remote,
+        Fatal::Other,
+    ];
+    let mut codes: Vec<i32> = kinds.iter().map(|kind| kind.code()).collect();
+    codes.sort_unstable();
+    codes.dedup();
+    assert_eq!(codes.len(), kinds.len());
+    assert_eq!(Fatal::Config.code(), 78);
+}
@@ -0,0 +1,526 @@
+//! Country, city and autonomous system lookups in MaxMind DBs, such as
+//! GeoLite2-City and GeoLite2-ASN, to add where each peer is to its log lines.
+//!
+//! The database is read whole at startup and searched in memory.  Only as much
+//! of the [MaxMind DB format] as lookups need is understood: the search tree,
+//! and strings, maps, arrays and unsigned integers in the data section.
+//!
+//! [MaxMind DB format]: https://maxmind.github.io/MaxMind-DB/
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::sanitize::Field;
+
+/// Marks the start of the metadata, near the end of the file
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+
+/// How far from the end of the file the metadata may start
+const METADATA_MAX: usize = 128 * 1024;
+
+/// Deepest nesting of maps and arrays to decode
+const DEPTH_MAX: usize = 16;
+
+/// A decoded data section value, with types lookups don't need left opaque
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Uint(u64),
+    Map(Vec<(String, Value)>),
+    Array(Vec<Value>),
+    Other,
+}
+
+impl Value {
+    fn get(&self, path: &[&str]) -> Option<&Value> {
+        path.iter().try_fold(self, |value, key| match value {
+            Value::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        })
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn as_uint(&self) -> Option<u64> {
+        match self {
+            Value::Uint(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+fn be_uint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |n, b| n << 8 | *b as u64)
+}
+
+/// Decode the value at `pos`, with pointers relative to `base`, returning it
+/// and the position after it
+fn decode(buf: &[u8], base: usize, pos: usize, depth: usize) -> Option<(Value, usize)> {
+    if depth > DEPTH_MAX {
+        return None;
+    }
+    let ctrl = *buf.get(pos)?;
+    let mut pos = pos + 1;
+    let mut kind = ctrl >> 5;
+
+    if kind == 1 {
+        let len = ((ctrl >> 3) & 3) as usize + 1;
+        let bytes = buf.get(pos..pos + len)?;
+        let high = (ctrl & 7) as u64;
+        let target = match len {
+            1 => high << 8 | be_uint(bytes),
+            2 => (high << 16 | be_uint(bytes)) + 2048,
+            3 => (high << 24 | be_uint(bytes)) + 526_336,
+            _ => be_uint(bytes),
+        };
+        let (value, _) = decode(buf, base, base + target as usize, depth + 1)?;
+        return Some((value, pos + len));
+    }
+    if kind == 0 {
+        kind = 7 + *buf.get(pos)?;
+        pos += 1;
+    }
+
+    let mut size = (ctrl & 0x1f) as usize;
+    if size >= 29 {
+        let len = size - 28;
+        let extra = be_uint(buf.get(pos..pos + len)?) as usize;
+        size = [29, 285, 65_821][len - 1] + extra;
+        pos += len;
+    }
+
+    match kind {
+        2 => {
+            let bytes = buf.get(pos..pos + size)?;
+            Some((
+                Value::String(String::from_utf8_lossy(bytes).into_owned()),
+                pos + size,
+            ))
+        }
+        5 | 6 | 9 | 10 if size <= 8 => {
+            Some((Value::Uint(be_uint(buf.get(pos..pos + size)?)), pos + size))
+        }
+        7 => {
+            let mut entries = Vec::with_capacity(size.min(64));
+            for _ in 0..size {
+                let (key, next) = decode(buf, base, pos, depth + 1)?;
+                let (value, next) = decode(buf, base, next, depth + 1)?;
+                if let Value::String(key) = key {
+                    entries.push((key, value));
+                }
+                pos = next;
+            }
+            Some((Value::Map(entries), pos))
+        }
+        11 => {
+            let mut items = Vec::with_capacity(size.min(64));
+            for _ in 0..size {
+                let (item, next) = decode(buf, base, pos, depth + 1)?;
+                items.push(item);
+                pos = next;
+            }
+            Some((Value::Array(items), pos))
+        }
+        // Booleans keep their value in the size, with nothing following
+        14 => Some((Value::Other, pos)),
+        _ => {
+            buf.get(pos..pos + size)?;
+            Some((Value::Other, pos + size))
+        }
+    }
+}
+
+/// Where a peer is, as far as the database knows
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Location {
+    /// ISO 3166-1 country code, e.g. `NL`
+    pub country: Option<String>,
+    /// English city name
+    pub city: Option<String>,
+    /// Autonomous system number
+    pub asn: Option<u32>,
+    /// Who the autonomous system belongs to, e.g. `DIGITALOCEAN-ASN`
+    pub organisation: Option<String>,
+}
+
+impl Location {
+    fn from_record(record: &Value) -> Self {
+        let country = record
+            .get(&["country", "iso_code"])
+            .or_else(|| record.get(&["registered_country", "iso_code"]))
+            .and_then(Value::as_str)
+            .filter(|code| !code.is_empty() && code.bytes().all(|b| b.is_ascii_alphanumeric()))
+            .map(str::to_string);
+        let city = record
+            .get(&["city", "names", "en"])
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        Self {
+            country,
+            city,
+            ..Self::default()
+        }
+    }
+}
+
+/// Fields to append to a log line, e.g.
+/// `, country: NL, city: "Amsterdam", asn: 64496, org: "EXAMPLE-AS"`
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(country) = &self.country {
+            write!(f, ", country: {}", country)?;
+        }
+        if let Some(city) = &self.city {
+            write!(f, ", city: {}", Field(city.as_bytes()))?;
+        }
+        if let Some(asn) = self.asn {
+            write!(f, ", asn: {}", asn)?;
+        }
+        if let Some(organisation) = &self.organisation {
+            write!(f, ", org: {}", Field(organisation.as_bytes()))?;
+        }
+        Ok(())
+    }
+}
+
+/// A MaxMind DB, read whole into memory
+struct Mmdb {
+    db: Vec<u8>,
+    node_count: u32,
+    record_size: u16,
+    ip_version: u16,
+    /// Where the data section starts
+    data: usize,
+    /// The node IPv4 lookups start from in an IPv6 tree
+    ipv4_start: u32,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+impl Mmdb {
+    fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_bytes(std::fs::read(path)?)
+    }
+
+    fn from_bytes(db: Vec<u8>) -> io::Result<Self> {
+        let tail = db.len().saturating_sub(METADATA_MAX);
+        let marker = db[tail..]
+            .windows(METADATA_MARKER.len())
+            .rposition(|w| w == METADATA_MARKER)
+            .ok_or_else(|| invalid("not a MaxMind DB"))?;
+        let start = tail + marker + METADATA_MARKER.len();
+        let (metadata, _) = decode(&db, start, start, 0).ok_or_else(|| invalid("bad metadata"))?;
+        let field = |key| metadata.get(&[key]).and_then(Value::as_uint);
+
+        let node_count = field("node_count")
+            .and_then(|n| u32::try_from(n).ok())
+            .ok_or_else(|| invalid("bad node count"))?;
+        let record_size = match field("record_size") {
+            Some(size @ (24 | 28 | 32)) => size as u16,
+            _ => return Err(invalid("unsupported record size")),
+        };
+        let ip_version = match field("ip_version") {
+            Some(version @ (4 | 6)) => version as u16,
+            _ => return Err(invalid("unsupported IP version")),
+        };
+        let tree = node_count as usize * record_size as usize / 4;
+        if tree + 16 > start {
+            return Err(invalid("search tree overruns the file"));
+        }
+
+        let mut mmdb = Self {
+            db,
+            node_count,
+            record_size,
+            ip_version,
+            data: tree + 16,
+            ipv4_start: 0,
+        };
+        if ip_version == 6 {
+            // IPv4 lives at ::/96
+            let mut node = 0;
+            for _ in 0..96 {
+                if node >= node_count {
+                    break;
+                }
+                node = mmdb
+                    .record(node, 0)
+                    .ok_or_else(|| invalid("truncated search tree"))?;
+            }
+            mmdb.ipv4_start = node;
+        }
+        Ok(mmdb)
+    }
+
+    /// The left (0) or right (1) record of a node
+    fn record(&self, node: u32, bit: u8) -> Option<u32> {
+        let size = self.record_size as usize / 4;
+        let offset = node as usize * size;
+        let b = self.db.get(offset..offset + size)?;
+        Some(match (self.record_size, bit) {
+            (24, 0) => be_uint(&b[0..3]) as u32,
+            (24, _) => be_uint(&b[3..6]) as u32,
+            (28, 0) => (b[3] as u32 & 0xf0) << 20 | be_uint(&b[0..3]) as u32,
+            (28, _) => (b[3] as u32 & 0x0f) << 24 | be_uint(&b[4..7]) as u32,
+            (_, 0) => be_uint(&b[0..4]) as u32,
+            (_, _) => be_uint(&b[4..8]) as u32,
+        })
+    }
+
+    /// The data record for the network holding `ip`
+    fn lookup(&self, ip: IpAddr) -> Option<Value> {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            v4 => v4,
+        };
+        let (bits, mut node) = match ip {
+            IpAddr::V4(v4) if self.ip_version == 6 => (u32::from(v4) as u128, self.ipv4_start),
+            IpAddr::V4(v4) => (u32::from(v4) as u128, 0),
+            IpAddr::V6(_) if self.ip_version == 4 => return None,
+            IpAddr::V6(v6) => (u128::from(v6), 0),
+        };
+        let width = if ip.is_ipv4() { 32 } else { 128 };
+
+        for i in (0..width).rev() {
+            if node >= self.node_count {
+                break;
+            }
+            node = self.record(node, (bits >> i & 1) as u8)?;
+        }
+        if node <= self.node_count {
+            return None;
+        }
+        let offset = (node - self.node_count) as usize - 16;
+        let (record, _) = decode(&self.db, self.data, self.data + offset, 0)?;
+        Some(record)
+    }
+}
+
+/// Lookups in a city or country database, an ASN database, or both, with
+/// counts of where connections came from for the info report.
+#[derive(Default)]
+pub struct GeoIp {
+    city: Option<Mmdb>,
+    asn: Option<Mmdb>,
+    countries: HashMap<String, u64>,
+    /// Connections from each autonomous system, with its organisation
+    networks: HashMap<u32, (Option<String>, u64)>,
+}
+
+/// The `k` entries with the highest counts, highest first
+fn top<K: Ord + Copy>(counts: impl Iterator<Item = (K, u64)>, k: usize) -> Vec<(K, u64)> {
+    let mut top: Vec<_> = counts.collect();
+    top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    top.truncate(k);
+    top
+}
+
+impl GeoIp {
+    /// Use a city or country database, such as GeoLite2-City
+    pub fn open_city<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.city = Some(Mmdb::open(path)?);
+        Ok(())
+    }
+
+    /// Use an ASN database, such as GeoLite2-ASN
+    pub fn open_asn<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.asn = Some(Mmdb::open(path)?);
+        Ok(())
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Location {
+        let mut location = self
+            .city
+            .as_ref()
+            .and_then(|db| db.lookup(ip))
+            .map_or_else(Location::default, |record| Location::from_record(&record));
+        if let Some(record) = self.asn.as_ref().and_then(|db| db.lookup(ip)) {
+            location.asn = record
+                .get(&["autonomous_system_number"])
+                .and_then(Value::as_uint)
+                .and_then(|n| u32::try_from(n).ok());
+            location.organisation = record
+                .get(&["autonomous_system_organization"])
+                .and_then(Value::as_str)
+                .map(str::to_string);
+        }
+        location
+    }
+
+    /// Look up a newly connected peer, counting its country and network
+    pub fn connected(&mut self, ip: IpAddr) -> Location {
+        let location = self.lookup(ip);
+        if let Some(country) = &location.country {
+            *self.countries.entry(country.clone()).or_insert(0) += 1;
+        }
+        if let Some(asn) = location.asn {
+            let network = self
+                .networks
+                .entry(asn)
+                .or_insert_with(|| (location.organisation.clone(), 0));
+            network.1 += 1;
+        }
+        location
+    }
+
+    pub fn has_city(&self) -> bool {
+        self.city.is_some()
+    }
+
+    pub fn has_asn(&self) -> bool {
+        self.asn.is_some()
+    }
+
+    /// Countries seen
+    pub fn countries(&self) -> usize {
+        self.countries.len()
+    }
+
+    /// The `k` countries with the most connections, most first
+    pub fn top_countries(&self, k: usize) -> Vec<(&str, u64)> {
+        top(
+            self.countries
+                .iter()
+                .map(|(country, count)| (country.as_str(), *count)),
+            k,
+        )
+    }
+
+    /// Autonomous systems seen
+    pub fn networks(&self) -> usize {
+        self.networks.len()
+    }
+
+    /// The `k` autonomous systems with the most connections, most first, with
+    /// their organisations
+    pub fn top_networks(&self, k: usize) -> Vec<(u32, Option<&str>, u64)> {
+        top(
+            self.networks.iter().map(|(asn, (_, count))| (*asn, *count)),
+            k,
+        )
+        .into_iter()
+        .map(|(asn, count)| (asn, self.networks[&asn].0.as_deref(), count))
+        .collect()
+    }
+}
+
+#[test]
+fn test_geoip() {
+    fn string(out: &mut Vec<u8>, s: &str) {
+        match s.len() {
+            len @ 0..=28 => out.push(2 << 5 | len as u8),
+            len => out.extend_from_slice(&[2 << 5 | 29, len as u8 - 29]),
+        }
+        out.extend_from_slice(s.as_bytes());
+    }
+    fn map(out: &mut Vec<u8>, entries: u8) {
+        out.push(7 << 5 | entries);
+    }
+    fn uint16(out: &mut Vec<u8>, n: u16) {
+        out.push(5 << 5 | 2);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+
+    // A tree of 24-bit records holding only 192.0.2.0/24, with its record
+    // `offset` bytes into `data`
+    fn database(data: impl FnOnce(&mut Vec<u8>), offset: u32) -> Mmdb {
+        let prefix = u32::from(std::net::Ipv4Addr::new(192, 0, 2, 0));
+        let nodes = 24u32;
+        let mut db = vec![];
+        for i in 0..nodes {
+            let next = if i + 1 < nodes {
+                i + 1
+            } else {
+                nodes + 16 + offset
+            };
+            let (left, right) = match prefix >> (31 - i) & 1 {
+                0 => (next, nodes),
+                _ => (nodes, next),
+            };
+            db.extend_from_slice(&left.to_be_bytes()[1..]);
+            db.extend_from_slice(&right.to_be_bytes()[1..]);
+        }
+        db.extend_from_slice(&[0; 16]);
+        data(&mut db);
+
+        db.extend_from_slice(METADATA_MARKER);
+        map(&mut db, 3);
+        string(&mut db, "node_count");
+        uint16(&mut db, nodes as u16);
+        string(&mut db, "record_size");
+        uint16(&mut db, 24);
+        string(&mut db, "ip_version");
+        uint16(&mut db, 4);
+        Mmdb::from_bytes(db).unwrap()
+    }
+
+    // The city's name first, so the record can point to it
+    let city = database(
+        |db| {
+            string(db, "Amsterdam");
+            map(db, 2);
+            string(db, "country");
+            map(db, 1);
+            string(db, "iso_code");
+            string(db, "NL");
+            string(db, "city");
+            map(db, 1);
+            string(db, "names");
+            map(db, 1);
+            string(db, "en");
+            db.extend_from_slice(&[1 << 5, 0]);
+        },
+        10,
+    );
+    let asn = database(
+        |db| {
+            map(db, 2);
+            string(db, "autonomous_system_number");
+            uint16(db, 64496);
+            string(db, "autonomous_system_organization");
+            string(db, "EXAMPLE-AS");
+        },
+        0,
+    );
+
+    let mut geoip = GeoIp {
+        city: Some(city),
+        ..GeoIp::default()
+    };
+    let location = geoip.connected("192.0.2.77".parse().unwrap());
+    assert_eq!(location.to_string(), ", country: NL, city: \"Amsterdam\"");
+    assert_eq!(geoip.lookup("::ffff:192.0.2.1".parse().unwrap()), location);
+    assert_eq!(
+        geoip.lookup("192.0.3.1".parse().unwrap()),
+        Location::default()
+    );
+    assert_eq!(
+        geoip.lookup("2001:db8::1".parse().unwrap()),
+        Location::default()
+    );
+    assert_eq!(geoip.top_countries(5), vec![("NL", 1)]);
+
+    geoip.asn = Some(asn);
+    let location = geoip.connected("192.0.2.78".parse().unwrap());
+    assert_eq!(
+        location.to_string(),
+        ", country: NL, city: \"Amsterdam\", asn: 64496, org: \"EXAMPLE-AS\""
+    );
+    geoip.city = None;
+    geoip.connected("192.0.2.79".parse().unwrap());
+    assert_eq!(geoip.top_countries(5), vec![("NL", 2)]);
+    assert_eq!(geoip.top_networks(5), vec![(64496, Some("EXAMPLE-AS"), 2)]);
+
+    assert!(Mmdb::from_bytes(b"not a database".to_vec()).is_err());
+}
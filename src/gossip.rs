@@ -0,0 +1,144 @@
+//! Sharing offenders between tarssh nodes, so a source that hammers one is
+//! refused by all of them.
+//!
+//! Nodes tell each other about bans with a UDP datagram per ban, holding the
+//! same command the control socket would take:
+//!
+//! ```txt
+//! tarssh-gossip ban 192.0.2.7 1h
+//! tarssh-gossip unban 192.0.2.7
+//! ```
+//!
+//! Datagrams are only accepted from the addresses of configured peers, and
+//! nodes never pass on what they hear, so messages can't loop.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::control::Command;
+
+const PREFIX: &str = "tarssh-gossip ";
+
+/// Most offenders to remember having shared
+const TRACKED: usize = 4096;
+
+pub struct Gossip {
+    sock: Arc<UdpSocket>,
+    peers: Vec<SocketAddr>,
+    ttl: Duration,
+    /// When each offender was last shared, so a source hammering away isn't
+    /// shared again until the ban it earned runs out
+    shared: HashMap<IpAddr, Instant>,
+}
+
+impl Gossip {
+    /// Bind to `addr`, passing on bans heard from `peers`
+    pub async fn bind(
+        addr: SocketAddr,
+        peers: Vec<SocketAddr>,
+        ttl: Duration,
+    ) -> io::Result<(Self, UnboundedReceiver<(SocketAddr, Command)>)> {
+        let sock = Arc::new(UdpSocket::bind(addr).await?);
+        let (tx, rx) = unbounded_channel();
+        tokio::spawn(receive(sock.clone(), peers.clone(), tx));
+        let gossip = Self {
+            sock,
+            peers,
+            ttl,
+            shared: HashMap::new(),
+        };
+        Ok((gossip, rx))
+    }
+
+    /// Tell every peer about a ban or unban
+    pub fn share(&self, command: &Command) {
+        let message = format!("{}{}", PREFIX, command);
+        for peer in &self.peers {
+            if let Err(err) = self.sock.try_send_to(message.as_bytes(), *peer) {
+                debug!("gossip, peer: {}, error: {}", peer, err);
+            }
+        }
+    }
+
+    /// Have every peer ban a source that's been refused here for hammering
+    pub fn offender(&mut self, ip: IpAddr, now: Instant) {
+        if self
+            .shared
+            .get(&ip)
+            .is_some_and(|at| now.duration_since(*at) < self.ttl)
+        {
+            return;
+        }
+        if self.shared.len() >= TRACKED {
+            let ttl = self.ttl;
+            self.shared.retain(|_, at| now.duration_since(*at) < ttl);
+        }
+        if self.shared.len() < TRACKED {
+            self.shared.insert(ip, now);
+        }
+        self.share(&Command::Ban(ip, Some(self.ttl)));
+    }
+}
+
+/// The command in a datagram, if it's one peers may send
+fn parse(data: &[u8]) -> Option<Command> {
+    let line = std::str::from_utf8(data).ok()?.strip_prefix(PREFIX)?;
+    match line.trim().parse().ok()? {
+        command @ (Command::Ban(..) | Command::Unban(..)) => Some(command),
+        _ => None,
+    }
+}
+
+async fn receive(
+    sock: Arc<UdpSocket>,
+    peers: Vec<SocketAddr>,
+    commands: UnboundedSender<(SocketAddr, Command)>,
+) {
+    let mut buf = [0; 512];
+    loop {
+        let (n, from) = match sock.recv_from(&mut buf).await {
+            Ok(recv) => recv,
+            Err(err) => {
+                warn!("gossip, error: {}", err);
+                continue;
+            }
+        };
+        // Peers may send from any port, depending on how they're bound
+        if !peers.iter().any(|peer| peer.ip() == from.ip()) {
+            debug!("gossip, from: {}, error: not a peer", from);
+            continue;
+        }
+        match parse(&buf[..n]) {
+            Some(command) => {
+                if commands.send((from, command)).is_err() {
+                    return;
+                }
+            }
+            None => debug!("gossip, from: {}, error: invalid message", from),
+        }
+    }
+}
+
+#[test]
+fn test_gossip_parse() {
+    let ip: IpAddr = "192.0.2.7".parse().unwrap();
+    let ban = Command::Ban(ip, Some(Duration::from_secs(3600)));
+    assert_eq!(
+        parse(format!("{}{}", PREFIX, ban).as_bytes()),
+        Some(ban.clone())
+    );
+    assert_eq!(
+        parse(b"tarssh-gossip unban 192.0.2.7\n"),
+        Some(Command::Unban(ip))
+    );
+    assert_eq!(parse(b"tarssh-gossip bans"), None);
+    assert_eq!(parse(b"ban 192.0.2.7"), None);
+    assert_eq!(parse(b"tarssh-gossip \xff"), None);
+}
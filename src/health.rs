@@ -0,0 +1,357 @@
+//! A minimal HTTP health endpoint, for load balancers, Docker and Kubernetes
+//! to check on the tarpit without connecting to the trap itself.
+//!
+//! `/healthz` answers 200 while the main loop is still getting through its
+//! ticks, and 503 once it's fallen behind.  Since the endpoint shares the main
+//! loop's thread, a loop that's stuck outright leaves checks unanswered, which
+//! health checkers treat as failure just the same.
+//!
+//! For load balancers spreading scanners over several tarpits, an HAProxy
+//! agent-check responder also reports how much room is left, as a weight:
+//! `up 75%` with a quarter of `--max-clients` in use, or `down` once behind.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::debug;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Most checks to answer at once
+const MAX_PENDING: usize = 64;
+
+/// Longest request line and headers we'll read
+const MAX_REQUEST: usize = 1024;
+
+/// How long a checker has to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// When the main loop last got through a tick, and how busy it was
+#[derive(Debug)]
+pub struct Heartbeat {
+    start: Instant,
+    /// Milliseconds since `start`
+    last: AtomicU64,
+    clients: AtomicUsize,
+    max_clients: AtomicUsize,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            last: AtomicU64::new(0),
+            clients: AtomicUsize::new(0),
+            max_clients: AtomicUsize::new(1),
+        }
+    }
+}
+
+impl Heartbeat {
+    /// Note a tick, with the clients counting against `max_clients`
+    pub fn beat(&self, clients: usize, max_clients: usize) {
+        let now = self.start.elapsed().as_millis() as u64;
+        self.last.store(now, Ordering::Relaxed);
+        self.clients.store(clients, Ordering::Relaxed);
+        self.max_clients
+            .store(max_clients.max(1), Ordering::Relaxed);
+    }
+
+    /// Percentage of `max_clients` still free, rounded down
+    pub fn capacity(&self) -> usize {
+        let max_clients = self.max_clients.load(Ordering::Relaxed) as u128;
+        let clients = (self.clients.load(Ordering::Relaxed) as u128).min(max_clients);
+        ((max_clients - clients) * 100 / max_clients) as usize
+    }
+
+    /// Time since the last tick
+    pub fn since(&self) -> Duration {
+        let last = Duration::from_millis(self.last.load(Ordering::Relaxed));
+        self.start.elapsed().saturating_sub(last)
+    }
+}
+
+pub async fn bind(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    TcpListener::bind(addr).await
+}
+
+/// The status and body answering a request line, given the time since the
+/// last tick and how long that may be before the tarpit counts as unhealthy
+fn respond(request: &str, since: Duration, stale: Duration) -> (&'static str, String) {
+    let mut words = request.split_whitespace();
+    let (method, target) = match (words.next(), words.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return ("400 Bad Request", "bad request\n".to_string()),
+    };
+    if method != "GET" && method != "HEAD" {
+        return ("405 Method Not Allowed", "method not allowed\n".to_string());
+    }
+    let path = target.split('?').next().unwrap_or(target);
+    if path != "/healthz" {
+        return ("404 Not Found", "not found\n".to_string());
+    }
+    if since <= stale {
+        ("200 OK", "ok\n".to_string())
+    } else {
+        (
+            "503 Service Unavailable",
+            format!("stale, last_tick: {}s ago\n", since.as_secs()),
+        )
+    }
+}
+
+/// Answer one checker, reading no further than its request line
+async fn check(mut sock: TcpStream, heartbeat: &Heartbeat, stale: Duration) -> std::io::Result<()> {
+    let mut buf = [0; MAX_REQUEST];
+    let mut len = 0;
+    let line = loop {
+        if let Some(end) = buf[..len].iter().position(|b| *b == b'\n') {
+            break String::from_utf8_lossy(&buf[..end]).into_owned();
+        }
+        if len == buf.len() {
+            break String::new();
+        }
+        let n = tokio::time::timeout(READ_TIMEOUT, sock.read(&mut buf[len..]))
+            .await
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out"))??;
+        if n == 0 {
+            return Ok(());
+        }
+        len += n;
+    };
+
+    let (status, body) = respond(&line, heartbeat.since(), stale);
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    );
+    if !line.starts_with("HEAD ") {
+        response.push_str(&body);
+    }
+    sock.write_all(response.as_bytes()).await?;
+    sock.shutdown().await
+}
+
+/// The agent-check reply: the weight to give the tarpit, by the room it has
+/// left, or down if it's fallen behind
+fn agent(since: Duration, stale: Duration, capacity: usize) -> String {
+    if since <= stale {
+        format!("up {}%\n", capacity)
+    } else {
+        "down\n".to_string()
+    }
+}
+
+/// Answer one agent check, which needs no request
+async fn agent_check(
+    mut sock: TcpStream,
+    heartbeat: &Heartbeat,
+    stale: Duration,
+) -> std::io::Result<()> {
+    let reply = agent(heartbeat.since(), stale, heartbeat.capacity());
+    sock.write_all(reply.as_bytes()).await?;
+    sock.shutdown().await
+}
+
+/// Which protocol a listener answers checks with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    /// HTTP on `/healthz`
+    Http,
+    /// HAProxy's agent-check
+    Agent,
+}
+
+/// Answer health checks on `listener`, calling the tarpit unhealthy once
+/// `stale` passes without a tick
+pub async fn serve(
+    listener: TcpListener,
+    protocol: Protocol,
+    heartbeat: Arc<Heartbeat>,
+    stale: Duration,
+) {
+    let pending = Arc::new(AtomicUsize::new(0));
+    loop {
+        let (sock, peer) = match listener.accept().await {
+            Ok(client) => client,
+            Err(err) => {
+                debug!("health, error: {}", err);
+                continue;
+            }
+        };
+        if pending.load(Ordering::Relaxed) >= MAX_PENDING {
+            continue;
+        }
+
+        pending.fetch_add(1, Ordering::Relaxed);
+        let heartbeat = heartbeat.clone();
+        let pending = pending.clone();
+        tokio::spawn(async move {
+            let checked = match protocol {
+                Protocol::Http => check(sock, &heartbeat, stale).await,
+                Protocol::Agent => agent_check(sock, &heartbeat, stale).await,
+            };
+            if let Err(err) = checked {
+                debug!("health, peer: {}, error: {}", peer, err);
+            }
+            pending.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+#[test]
+fn test_respond_status() {
+    let stale = Duration::from_secs(5);
+    let status = |request| respond(request, Duration::from_secs(1), stale).0;
+    assert_eq!(status("GET /healthz HTTP/1.1\r"), "200 OK");
+    assert_eq!(status("HEAD /healthz?verbose=1 HTTP/1.0"), "200 OK");
+    assert_eq!(status("GET /healthz"), "200 OK");
+    assert_eq!(status("GET / HTTP/1.1"), "404 Not Found");
+    assert_eq!(status("GET /healthz/ HTTP/1.1"), "404 Not Found");
+    assert_eq!(status("POST /healthz HTTP/1.1"), "405 Method Not Allowed");
+    assert_eq!(status("get /healthz HTTP/1.1"), "405 Method Not Allowed");
+    assert_eq!(status("SSH-2.0-Go"), "400 Bad Request");
+    assert_eq!(status(""), "400 Bad Request");
+}
+
+#[test]
+fn test_respond_stale() {
+    let stale = Duration::from_secs(5);
+    let request = "GET /healthz HTTP/1.1";
+    assert_eq!(
+        respond(request, stale, stale),
+        ("200 OK", "ok\n".to_string())
+    );
+    assert_eq!(
+        respond(request, Duration::from_secs(12), stale),
+        (
+            "503 Service Unavailable",
+            "stale, last_tick: 12s ago\n".to_string()
+        )
+    );
+    // Only the health check itself is affected
+    assert_eq!(
+        respond("GET / HTTP/1.1", Duration::from_secs(12), stale).0,
+        "404 Not Found"
+    );
+}
+
+#[test]
+fn test_heartbeat() {
+    let heartbeat = Heartbeat::default();
+    assert_eq!(heartbeat.capacity(), 100);
+    heartbeat.beat(30, 120);
+    assert!(heartbeat.since() < Duration::from_secs(1));
+    assert_eq!(heartbeat.capacity(), 75);
+    heartbeat.beat(120, 120);
+    assert_eq!(heartbeat.capacity(), 0);
+}
+
+#[test]
+fn test_capacity_edges() {
+    let heartbeat = Heartbeat::default();
+
+    // More clients than allowed, as after --max-clients is lowered by SIGHUP
+    heartbeat.beat(130, 120);
+    assert_eq!(heartbeat.capacity(), 0);
+
+    // No room at all is treated as room for one
+    heartbeat.beat(0, 0);
+    assert_eq!(heartbeat.capacity(), 100);
+    heartbeat.beat(1, 0);
+    assert_eq!(heartbeat.capacity(), 0);
+
+    heartbeat.beat(1, usize::MAX);
+    assert_eq!(heartbeat.capacity(), 99);
+    heartbeat.beat(usize::MAX, usize::MAX);
+    assert_eq!(heartbeat.capacity(), 0);
+
+    // Rounded down, so it only reads 100% when empty
+    heartbeat.beat(1, 1000);
+    assert_eq!(heartbeat.capacity(), 99);
+}
+
+#[test]
+fn test_agent() {
+    let stale = Duration::from_secs(5);
+    assert_eq!(agent(Duration::ZERO, stale, 75), "up 75%\n");
+    assert_eq!(agent(stale, stale, 0), "up 0%\n");
+    assert_eq!(agent(Duration::from_secs(6), stale, 75), "down\n");
+    assert_eq!(agent(Duration::from_secs(6), stale, 100), "down\n");
+}
+
+#[test]
+fn test_serve() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let heartbeat = Arc::new(Heartbeat::default());
+        heartbeat.beat(1, 4);
+        let stale = Duration::from_secs(60);
+        let http = bind(([127, 0, 0, 1], 0).into()).await.unwrap();
+        let http_addr = http.local_addr().unwrap();
+        let checks = bind(([127, 0, 0, 1], 0).into()).await.unwrap();
+        let agent_addr = checks.local_addr().unwrap();
+        tokio::spawn(serve(http, Protocol::Http, heartbeat.clone(), stale));
+        tokio::spawn(serve(checks, Protocol::Agent, heartbeat, stale));
+
+        let exchange = |addr: SocketAddr, request: Vec<u8>| async move {
+            let mut sock = TcpStream::connect(addr).await.unwrap();
+            sock.write_all(&request).await.unwrap();
+            let mut response = String::new();
+            sock.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let response = exchange(
+            http_addr,
+            b"GET /healthz HTTP/1.1\r\nHost: x\r\n\r\n".to_vec(),
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("Content-Length: 3\r\nConnection: close\r\n\r\nok\n"));
+
+        // Headers only
+        let response = exchange(http_addr, b"HEAD /healthz HTTP/1.1\r\n\r\n".to_vec()).await;
+        assert!(response.ends_with("Connection: close\r\n\r\n"));
+
+        // No request line within the limit
+        let response = exchange(http_addr, vec![b'x'; MAX_REQUEST]).await;
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        assert_eq!(exchange(agent_addr, vec![]).await, "up 75%\n");
+    });
+}
+
+#[cfg(test)]
+quickcheck::quickcheck! {
+    fn prop_capacity(clients: usize, max_clients: usize) -> bool {
+        let heartbeat = Heartbeat::default();
+        heartbeat.beat(clients, max_clients);
+        let capacity = heartbeat.capacity();
+        capacity <= 100
+            && (clients == 0) == (capacity == 100)
+            && (clients < max_clients.max(1) || capacity == 0)
+    }
+}
+
+#[cfg(test)]
+quickcheck::quickcheck! {
+    fn prop_agent(since: u16, stale: u16, capacity: u8) -> bool {
+        let reply = agent(
+            Duration::from_secs(since as u64),
+            Duration::from_secs(stale as u64),
+            capacity as usize,
+        );
+        if since <= stale {
+            reply == format!("up {}%\n", capacity)
+        } else {
+            reply == "down\n"
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! How long clients were held, broken down by something about how each was
+//! handled, such as the protocol its banner spoke or the rules that chose its
+//! pacing and banner, to compare which setups waste the most time.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// Clients that have disconnected, and how long they were held between them
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Hold {
+    pub connections: u64,
+    pub held: Duration,
+}
+
+impl Hold {
+    pub fn mean(&self) -> Option<Duration> {
+        Some(self.connections)
+            .filter(|connections| *connections > 0)
+            .map(|connections| self.held.div_f64(connections as f64))
+    }
+}
+
+/// Holds for each of a set of names, in name order
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Holds(BTreeMap<String, Hold>);
+
+impl Holds {
+    /// Count a client held for `held` under `name`
+    pub fn record(&mut self, name: &str, held: Duration) {
+        let hold = match self.0.get_mut(name) {
+            Some(hold) => hold,
+            None => self.0.entry(name.to_string()).or_default(),
+        };
+        hold.connections += 1;
+        hold.held += held;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Hold)> + '_ {
+        self.0.iter().map(|(name, hold)| (name.as_str(), hold))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Hold> {
+        self.0.get(name)
+    }
+}
+
+/// The name for a rule of some `kind` that handled a connection, such as
+/// `listener.dmz` for a listener's own settings, with anything in `value` that
+/// a metric name or label couldn't carry as it is replaced by `_`
+pub fn rule(kind: &str, value: &str) -> String {
+    let value: String = value
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    format!("{}.{}", kind, value)
+}
+
+/// Each name with its connection count and mean hold time, as `ssh=40/2m`
+impl fmt::Display for Holds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, hold)) in self.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            match hold.mean() {
+                Some(mean) => write!(f, "{}={}/{:.0?}", name, hold.connections, mean)?,
+                None => write!(f, "{}=0/none", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_holds_record() {
+    let mut holds = Holds::default();
+    assert!(holds.is_empty());
+    assert_eq!(holds.to_string(), "");
+
+    holds.record("ssh", Duration::from_secs(60));
+    holds.record("smtp", Duration::from_secs(5));
+    holds.record("ssh", Duration::from_secs(180));
+    assert_eq!(
+        holds.get("ssh"),
+        Some(&Hold {
+            connections: 2,
+            held: Duration::from_secs(240)
+        })
+    );
+    assert_eq!(holds.get("rdp"), None);
+    assert_eq!(holds.to_string(), "smtp=1/5s ssh=2/120s");
+}
+
+#[test]
+fn test_hold_mean() {
+    assert_eq!(Hold::default().mean(), None);
+    let hold = Hold {
+        connections: 3,
+        held: Duration::from_secs(10),
+    };
+    assert_eq!(hold.mean(), Some(Duration::from_secs(10).div_f64(3.0)));
+}
+
+#[test]
+fn test_rule() {
+    assert_eq!(rule("listener", "dmz"), "listener.dmz");
+    assert_eq!(rule("delay", "30s"), "delay.30s");
+    assert_eq!(
+        rule("banner", "cisco ios,v2=1.txt"),
+        "banner.cisco_ios_v2_1.txt"
+    );
+    assert_eq!(rule("banner", "ünï"), "banner._n_");
+}
+
+#[cfg(test)]
+quickcheck::quickcheck! {
+    fn prop_holds_total(held: Vec<(bool, u32)>) -> bool {
+        let mut holds = Holds::default();
+        for (smtp, secs) in &held {
+            holds.record(if *smtp { "smtp" } else { "ssh" }, Duration::from_secs(*secs as u64));
+        }
+        let total: Duration = held.iter().map(|(_, secs)| Duration::from_secs(*secs as u64)).sum();
+        let connections: u64 = holds.iter().map(|(_, hold)| hold.connections).sum();
+        let summed: Duration = holds.iter().map(|(_, hold)| hold.held).sum();
+        connections == held.len() as u64 && summed == total
+    }
+}
+
+#[cfg(test)]
+quickcheck::quickcheck! {
+    fn prop_rule_escaped(value: String) -> bool {
+        let name = rule("banner", &value);
+        name.strip_prefix("banner.").is_some_and(|rest| rest.chars().count() == value.chars().count())
+            && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+    }
+}
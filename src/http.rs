@@ -0,0 +1,519 @@
+use std::io;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpStream};
+
+/// Largest response body we're willing to hold in memory
+const MAX_BODY: u64 = 1024 * 1024;
+
+/// Most redirects to follow for a GET, as from rdap.org to a registry
+const MAX_REDIRECTS: usize = 5;
+
+/// An `http://` or `https://host[:port]/path` URL
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpUrl {
+    tls: bool,
+    /// The host and any port, as given
+    host: String,
+    /// The host without the port or an IPv6 address's brackets
+    name: String,
+    port: Option<u16>,
+    path: String,
+}
+
+impl FromStr for HttpUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tls, rest) = match (s.strip_prefix("http://"), s.strip_prefix("https://")) {
+            (Some(rest), _) => (false, rest),
+            (_, Some(rest)) if cfg!(feature = "tls") => (true, rest),
+            (_, Some(_)) => {
+                return Err("https:// URLs need tarssh built with the tls feature".to_string())
+            }
+            _ => return Err("only http:// and https:// URLs are supported".to_string()),
+        };
+        let (host, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, "/"),
+        };
+        let (name, port) = match host.strip_prefix('[') {
+            Some(bracketed) => match bracketed.split_once(']') {
+                Some((name, "")) => (name, None),
+                Some((name, port)) => (name, Some(port.strip_prefix(':').unwrap_or(port))),
+                None => return Err(format!("unclosed [ in {:?}", s)),
+            },
+            None => match host.split_once(':') {
+                Some((name, port)) => (name, Some(port)),
+                None => (host, None),
+            },
+        };
+        if name.is_empty() {
+            return Err(format!("missing host in {:?}", s));
+        }
+        let port = port
+            .map(|port| port.parse().map_err(|_| format!("bad port in {:?}", s)))
+            .transpose()?;
+
+        Ok(Self {
+            tls,
+            host: host.to_string(),
+            name: name.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for HttpUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scheme = if self.tls { "https" } else { "http" };
+        write!(f, "{}://{}{}", scheme, self.host, self.path)
+    }
+}
+
+impl HttpUrl {
+    /// This URL with `path` appended, e.g. `ip/192.0.2.0`
+    pub fn join(&self, path: &str) -> Self {
+        Self {
+            path: format!("{}/{}", self.path.trim_end_matches('/'), path),
+            ..self.clone()
+        }
+    }
+
+    /// This URL with `suffix` added to the end of its path, e.g. `.sha256`
+    pub fn suffixed(&self, suffix: &str) -> Self {
+        Self {
+            path: format!("{}{}", self.path, suffix),
+            ..self.clone()
+        }
+    }
+
+    /// This URL with `path` in place of its own, if it has none
+    pub fn or_path(mut self, path: &str) -> Self {
+        if self.path.trim_end_matches('/').is_empty() {
+            self.path = path.to_string();
+        }
+        self
+    }
+
+    /// Whether requests are made over TLS
+    pub fn is_tls(&self) -> bool {
+        self.tls
+    }
+
+    /// The host and port to connect to, with `default` for an `http://` URL
+    /// with no port of its own
+    pub fn host_port(&self, default: u16) -> (&str, u16) {
+        let default = if self.tls { 443 } else { default };
+        (&self.name, self.port.unwrap_or(default))
+    }
+
+    /// Fetch the body of a successful response, giving up after `timeout`
+    /// Redirects are followed, though never from https:// to http://.
+    pub async fn get(&self, timeout: Duration) -> io::Result<Vec<u8>> {
+        let get = async {
+            let mut url = self.clone();
+            for _ in 0..=MAX_REDIRECTS {
+                let response = url.request("GET", &[], &[]).await?;
+                match redirect(&response) {
+                    Some(location) => url = url.follow(&location)?,
+                    None => return parse_response(response),
+                }
+            }
+            Err(invalid(format!("more than {} redirects", MAX_REDIRECTS)))
+        };
+        self.timed(timeout, get).await
+    }
+
+    /// Post `body` with extra `headers`, such as its `Content-Type`, returning
+    /// the body of a successful response, giving up after `timeout`
+    pub async fn post(
+        &self,
+        headers: &[(&str, &str)],
+        body: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let post = async { parse_response(self.request("POST", headers, body).await?) };
+        self.timed(timeout, post).await
+    }
+
+    /// Where a redirect to `location` leads from here
+    fn follow(&self, location: &str) -> io::Result<Self> {
+        let url = if location.starts_with('/') {
+            Self {
+                path: location.to_string(),
+                ..self.clone()
+            }
+        } else {
+            location.parse::<Self>().map_err(invalid)?
+        };
+        if self.tls && !url.tls {
+            return Err(invalid(format!("refusing to follow a redirect to {}", url)));
+        }
+        Ok(url)
+    }
+
+    async fn timed(
+        &self,
+        timeout: Duration,
+        request: impl std::future::Future<Output = io::Result<Vec<u8>>>,
+    ) -> io::Result<Vec<u8>> {
+        tokio::time::timeout(timeout, request)
+            .await
+            .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")))
+    }
+
+    async fn request(
+        &self,
+        method: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        let addr = lookup_host(self.host_port(80))
+            .await?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses found"))?;
+        self.send(addr, method, headers, body).await
+    }
+
+    /// Make a request of the server at `addr`, already resolved from the
+    /// URL's host, returning the body of a successful response
+    pub async fn request_at(
+        &self,
+        addr: SocketAddr,
+        method: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        parse_response(self.send(addr, method, headers, body).await?)
+    }
+
+    /// Make a request of the server at `addr`, returning the whole response
+    async fn send(
+        &self,
+        addr: SocketAddr,
+        method: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        // HTTP/1.0 keeps the response free of chunked encoding
+        let mut request = format!(
+            "{} {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: tarssh/{}\r\n",
+            method,
+            self.path,
+            self.host,
+            env!("CARGO_PKG_VERSION")
+        );
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if method != "GET" {
+            request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        request.push_str("\r\n");
+
+        let sock = TcpStream::connect(addr).await?;
+        #[cfg(feature = "tls")]
+        if self.tls {
+            let sock = tls::connect(&self.name, sock).await?;
+            return exchange(sock, request.as_bytes(), body).await;
+        }
+        exchange(sock, request.as_bytes(), body).await
+    }
+}
+
+/// Send a request and read the whole response, until the server closes
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    mut sock: S,
+    head: &[u8],
+    body: &[u8],
+) -> io::Result<Vec<u8>> {
+    sock.write_all(head).await?;
+    sock.write_all(body).await?;
+    sock.flush().await?;
+
+    let mut response = vec![];
+    match sock
+        .take(MAX_BODY + 16 * 1024)
+        .read_to_end(&mut response)
+        .await
+    {
+        // Plenty of TLS servers close without notice, which is only a problem
+        // if it cuts the body short, as Content-Length will tell
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof && !response.is_empty() => (),
+        result => {
+            result?;
+        }
+    }
+    Ok(response)
+}
+
+#[cfg(feature = "tls")]
+mod tls {
+    use std::convert::TryFrom;
+    use std::io;
+    use std::sync::{Arc, OnceLock};
+
+    use tokio::net::TcpStream;
+    use tokio_rustls::client::TlsStream;
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::rustls::{self, ClientConfig, RootCertStore};
+    use tokio_rustls::TlsConnector;
+
+    /// Client settings, trusting the Mozilla root certificates built in, so
+    /// nothing needs reading from disk after a chroot
+    fn config() -> Arc<ClientConfig> {
+        static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+        CONFIG
+            .get_or_init(|| {
+                let roots = RootCertStore {
+                    roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+                };
+                let provider = Arc::new(rustls::crypto::ring::default_provider());
+                let config = ClientConfig::builder_with_provider(provider)
+                    .with_safe_default_protocol_versions()
+                    .expect("ring supports the default protocol versions")
+                    .with_root_certificates(roots)
+                    .with_no_client_auth();
+                Arc::new(config)
+            })
+            .clone()
+    }
+
+    /// Start a TLS session on `sock`, verifying the server's certificate is
+    /// for `name`
+    pub async fn connect(name: &str, sock: TcpStream) -> io::Result<TlsStream<TcpStream>> {
+        let name = ServerName::try_from(name.to_string())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        TlsConnector::from(config()).connect(name, sock).await
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The status line and headers of a response, and where its body starts
+fn head(response: &[u8]) -> io::Result<(String, usize)> {
+    let end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| invalid("truncated response headers".to_string()))?;
+    Ok((
+        String::from_utf8_lossy(&response[..end]).into_owned(),
+        end + 4,
+    ))
+}
+
+/// The value of a header named `name`, in any case
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Where a response redirects to, if it does
+fn redirect(response: &[u8]) -> Option<String> {
+    let (head, _) = head(response).ok()?;
+    match head.split(' ').nth(1)? {
+        "301" | "302" | "303" | "307" | "308" => header(&head, "location").map(str::to_string),
+        _ => None,
+    }
+}
+
+fn parse_response(mut response: Vec<u8>) -> io::Result<Vec<u8>> {
+    let (head, start) = head(&response)?;
+    let status = head
+        .split(' ')
+        .nth(1)
+        .ok_or_else(|| invalid("missing status".to_string()))?;
+    if !status.starts_with('2') {
+        return Err(invalid(format!("status {}", status)));
+    }
+
+    let mut body = response.split_off(start);
+    if body.len() as u64 > MAX_BODY {
+        return Err(invalid(format!("body larger than {} bytes", MAX_BODY)));
+    }
+    let length = header(&head, "content-length").and_then(|length| length.parse::<usize>().ok());
+    match length {
+        Some(length) if body.len() < length => Err(invalid(format!(
+            "body cut short at {} of {} bytes",
+            body.len(),
+            length
+        ))),
+        Some(length) => {
+            body.truncate(length);
+            Ok(body)
+        }
+        None => Ok(body),
+    }
+}
+
+#[test]
+fn test_http_url() {
+    let url: HttpUrl = "http://config.example:8080".parse().unwrap();
+    assert_eq!(url.to_string(), "http://config.example:8080/");
+    assert_eq!(url.host_port(80), ("config.example", 8080));
+    let url: HttpUrl = "http://[::1]/tarssh/banner.txt".parse().unwrap();
+    assert_eq!(url.path, "/tarssh/banner.txt");
+    assert_eq!(url.host_port(9091), ("::1", 9091));
+    assert_eq!(
+        url.join("ip/192.0.2.0").to_string(),
+        "http://[::1]/tarssh/banner.txt/ip/192.0.2.0"
+    );
+    assert_eq!(
+        url.suffixed(".sha256").to_string(),
+        "http://[::1]/tarssh/banner.txt.sha256"
+    );
+    let url: HttpUrl = "http://[::1]:8080".parse().unwrap();
+    assert_eq!(url.host_port(80), ("::1", 8080));
+}
+
+#[test]
+fn test_http_url_errors() {
+    assert!("http:///banner.txt".parse::<HttpUrl>().is_err());
+    assert!("http://host:port/".parse::<HttpUrl>().is_err());
+    assert!("http://[::1/".parse::<HttpUrl>().is_err());
+    assert!("ftp://host/".parse::<HttpUrl>().is_err());
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn test_https_url() {
+    let url: HttpUrl = "https://rdap.example/".parse().unwrap();
+    assert!(url.is_tls());
+    assert_eq!(url.to_string(), "https://rdap.example/");
+    assert_eq!(url.host_port(9091), ("rdap.example", 443));
+    let url: HttpUrl = "https://[2001:db8::1]:8443".parse().unwrap();
+    assert_eq!(url.host_port(80), ("2001:db8::1", 8443));
+}
+
+#[cfg(not(feature = "tls"))]
+#[test]
+fn test_https_url() {
+    let err = "https://rdap.example/".parse::<HttpUrl>().unwrap_err();
+    assert!(err.contains("tls feature"));
+}
+
+#[test]
+fn test_parse_response() {
+    assert_eq!(
+        parse_response(b"HTTP/1.0 200 OK\r\nServer: x\r\n\r\nhello".to_vec()).unwrap(),
+        b"hello"
+    );
+    assert_eq!(
+        parse_response(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nhello".to_vec()).unwrap(),
+        b"he"
+    );
+    assert!(parse_response(b"HTTP/1.0 200 OK\r\nContent-Length: 9\r\n\r\nhello".to_vec()).is_err());
+    assert!(parse_response(b"HTTP/1.0 404 Not Found\r\n\r\n".to_vec()).is_err());
+    assert!(parse_response(b"HTTP/1.0 200 OK\r\n".to_vec()).is_err());
+}
+
+#[test]
+fn test_request() {
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (mut sock, _) = listener.accept().unwrap();
+        let mut request = vec![];
+        let mut buf = [0; 1024];
+        while !request.ends_with(b"\r\n\r\nx") {
+            let n = sock.read(&mut buf).unwrap();
+            assert!(n > 0, "request cut short");
+            request.extend_from_slice(&buf[..n]);
+        }
+        sock.write_all(b"HTTP/1.0 200 OK\r\nContent-Length: 2\r\n\r\nok")
+            .unwrap();
+        String::from_utf8(request).unwrap()
+    });
+
+    let url: HttpUrl = format!("http://{}/submit", addr).parse().unwrap();
+    let body = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(url.request_at(addr, "PUT", &[("Content-Type", "text/plain")], b"x"))
+        .unwrap();
+    assert_eq!(body, b"ok");
+    let request = server.join().unwrap();
+    assert!(request.starts_with("PUT /submit HTTP/1.0\r\n"));
+    assert!(request.contains("Content-Type: text/plain\r\nContent-Length: 1\r\n\r\nx"));
+}
+
+#[test]
+fn test_redirect() {
+    let response =
+        b"HTTP/1.1 302 Found\r\nlocation: https://rdap.arin.net/registry/ip/192.0.2.0\r\n\r\n";
+    assert_eq!(
+        redirect(response).as_deref(),
+        Some("https://rdap.arin.net/registry/ip/192.0.2.0")
+    );
+    assert_eq!(redirect(b"HTTP/1.1 200 OK\r\nLocation: /x\r\n\r\n"), None);
+    assert_eq!(redirect(b"HTTP/1.1 301 Moved\r\n\r\n"), None);
+
+    let url: HttpUrl = "http://rdap.example/ip/192.0.2.0".parse().unwrap();
+    assert_eq!(
+        url.follow("/registry/ip/192.0.2.0").unwrap().to_string(),
+        "http://rdap.example/registry/ip/192.0.2.0"
+    );
+    assert!(url.follow("ftp://rdap.example/").is_err());
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn test_redirect_downgrade() {
+    let url: HttpUrl = "https://rdap.example/ip/192.0.2.0".parse().unwrap();
+    assert!(url.follow("http://rdap.example/ip/192.0.2.0").is_err());
+    assert!(url.follow("https://rdap.arin.net/ip/192.0.2.0").is_ok());
+}
+
+#[test]
+fn test_get_redirect() {
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let mut paths = vec![];
+        for response in [
+            "HTTP/1.0 302 Found\r\nLocation: /registry/ip/192.0.2.0\r\n\r\n",
+            "HTTP/1.0 200 OK\r\n\r\n{}",
+        ]
+        .iter()
+        {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = sock.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let request = String::from_utf8(request).unwrap();
+            paths.push(request.split(' ').nth(1).unwrap().to_string());
+            sock.write_all(response.as_bytes()).unwrap();
+        }
+        paths
+    });
+
+    let url: HttpUrl = format!("http://{}/ip/192.0.2.0", addr).parse().unwrap();
+    let body = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(url.get(Duration::from_secs(5)))
+        .unwrap();
+    assert_eq!(body, b"{}");
+    assert_eq!(
+        server.join().unwrap(),
+        vec!["/ip/192.0.2.0", "/registry/ip/192.0.2.0"]
+    );
+}
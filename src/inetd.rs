@@ -0,0 +1,40 @@
+//! Running from inetd, which hands us our socket as standard input: either a
+//! connection it's already accepted, or with `wait` a listening socket.
+
+use std::fs::File;
+use std::io;
+use std::mem::ManuallyDrop;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+
+pub enum Inherited {
+    Connection(TcpStream),
+    Listener(TcpListener),
+}
+
+/// Take the socket on standard input
+pub fn take() -> io::Result<Inherited> {
+    // SAFETY: inetd hands standard input to us alone
+    let sock = unsafe { TcpStream::from_raw_fd(0) };
+    if sock.peer_addr().is_ok() {
+        return Ok(Inherited::Connection(sock));
+    }
+    // Fails unless it's really an inet socket
+    sock.local_addr()?;
+    // SAFETY: ownership passes straight from one wrapper to the other
+    Ok(Inherited::Listener(unsafe {
+        TcpListener::from_raw_fd(sock.into_raw_fd())
+    }))
+}
+
+/// Whether standard error is a socket, as it is when inetd passes the
+/// connection as every standard stream, so logging there would reach the
+/// client
+pub fn stderr_is_socket() -> bool {
+    // SAFETY: borrowed only for the duration, and never closed
+    let stderr = ManuallyDrop::new(unsafe { File::from_raw_fd(2) });
+    stderr
+        .metadata()
+        .is_ok_and(|meta| meta.file_type().is_socket())
+}
@@ -0,0 +1,381 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const TEMPLATE_V4: u16 = 256;
+const TEMPLATE_V6: u16 = 257;
+
+/// How often to resend templates, as collectors may have missed them
+const TEMPLATE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Keep messages comfortably within a typical MTU
+const MAX_MESSAGE_BYTES: usize = 1200;
+
+/// Message header: version, length, export time, sequence number and domain
+const HEADER_LEN: usize = 16;
+
+/// Set header: set id and length
+const SET_HEADER_LEN: usize = 4;
+
+/// (Information Element id, length) pairs shared by both templates, after the
+/// addresses.
+const FIELDS: [(u16, u16); 7] = [
+    (7, 2),   // sourceTransportPort
+    (11, 2),  // destinationTransportPort
+    (4, 1),   // protocolIdentifier
+    (1, 8),   // octetDeltaCount
+    (152, 8), // flowStartMilliseconds
+    (153, 8), // flowEndMilliseconds
+    (136, 1), // flowEndReason
+];
+
+/// Why a flow ended, as per IANA's flowEndReason registry
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum EndReason {
+    IdleTimeout = 1,
+    ActiveTimeout = 2,
+    EndOfFlow = 3,
+    ForcedEnd = 4,
+}
+
+/// A finished tarpit connection, from the client to the listener.
+///
+/// `bytes` counts data sent by tarssh, since that's all most clients ever get.
+pub struct Flow {
+    pub peer: SocketAddr,
+    pub local: SocketAddr,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub bytes: u64,
+    pub reason: EndReason,
+}
+
+/// A minimal IPFIX (RFC 7011) exporter sending flow records over UDP.
+///
+/// Records are buffered and sent by `flush()`, or as soon as a message fills.
+pub struct IpfixExporter {
+    sock: UdpSocket,
+    domain: u32,
+    sequence: u32,
+    v4: Vec<u8>,
+    v6: Vec<u8>,
+    templates_sent: Option<Instant>,
+}
+
+impl IpfixExporter {
+    /// Connect to a collector, so it remains usable after sandboxing
+    pub fn connect(collector: SocketAddr, domain: u32) -> io::Result<Self> {
+        let bind: SocketAddr = match collector {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let sock = UdpSocket::bind(bind)?;
+        sock.connect(collector)?;
+        sock.set_nonblocking(true)?;
+
+        Ok(Self {
+            sock,
+            domain,
+            sequence: 0,
+            v4: vec![],
+            v6: vec![],
+            templates_sent: None,
+        })
+    }
+
+    pub fn record(&mut self, flow: &Flow) -> io::Result<()> {
+        let buf = match (unmap(flow.peer.ip()), unmap(flow.local.ip())) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                self.v4.extend_from_slice(&src.octets());
+                self.v4.extend_from_slice(&dst.octets());
+                &mut self.v4
+            }
+            (src, dst) => {
+                self.v6.extend_from_slice(&v6(src).octets());
+                self.v6.extend_from_slice(&v6(dst).octets());
+                &mut self.v6
+            }
+        };
+
+        buf.extend_from_slice(&flow.peer.port().to_be_bytes());
+        buf.extend_from_slice(&flow.local.port().to_be_bytes());
+        buf.push(6); // TCP
+        buf.extend_from_slice(&flow.bytes.to_be_bytes());
+        buf.extend_from_slice(&millis(flow.start).to_be_bytes());
+        buf.extend_from_slice(&millis(flow.end).to_be_bytes());
+        buf.push(flow.reason as u8);
+
+        // Send before another record could take us over the limit
+        if self.message_len() + record_len(16) > MAX_MESSAGE_BYTES {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn templates_due(&self) -> bool {
+        self.templates_sent
+            .is_none_or(|sent| sent.elapsed() >= TEMPLATE_INTERVAL)
+    }
+
+    /// The length of the message `flush()` would send now
+    fn message_len(&self) -> usize {
+        let mut len = HEADER_LEN;
+        if self.templates_due() {
+            len += template_set_len();
+        }
+        for records in [&self.v4, &self.v6].iter() {
+            if !records.is_empty() {
+                len += SET_HEADER_LEN + records.len();
+            }
+        }
+        len
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        let templates = self.templates_due();
+
+        if !templates && self.v4.is_empty() && self.v6.is_empty() {
+            return Ok(());
+        }
+
+        let records = (self.v4.len() / record_len(4)) + (self.v6.len() / record_len(16));
+        let mut msg = Vec::with_capacity(self.message_len());
+        msg.extend_from_slice(&10u16.to_be_bytes()); // version
+        msg.extend_from_slice(&0u16.to_be_bytes()); // length, filled in below
+        msg.extend_from_slice(&((millis(SystemTime::now()) / 1000) as u32).to_be_bytes());
+        msg.extend_from_slice(&self.sequence.to_be_bytes());
+        msg.extend_from_slice(&self.domain.to_be_bytes());
+
+        if templates {
+            let mut set = vec![];
+            template(&mut set, TEMPLATE_V4, (8, 4), (12, 4));
+            template(&mut set, TEMPLATE_V6, (27, 16), (28, 16));
+            push_set(&mut msg, 2, &set);
+        }
+        if !self.v4.is_empty() {
+            push_set(&mut msg, TEMPLATE_V4, &self.v4);
+        }
+        if !self.v6.is_empty() {
+            push_set(&mut msg, TEMPLATE_V6, &self.v6);
+        }
+
+        let len = msg.len() as u16;
+        msg[2..4].copy_from_slice(&len.to_be_bytes());
+
+        self.v4.clear();
+        self.v6.clear();
+        self.sequence = self.sequence.wrapping_add(records as u32);
+        if templates {
+            self.templates_sent = Some(Instant::now());
+        }
+
+        self.sock.send(&msg).map(|_| ())
+    }
+}
+
+fn template(set: &mut Vec<u8>, id: u16, src: (u16, u16), dst: (u16, u16)) {
+    set.extend_from_slice(&id.to_be_bytes());
+    set.extend_from_slice(&(FIELDS.len() as u16 + 2).to_be_bytes());
+    for (ie, len) in [src, dst].iter().chain(FIELDS.iter()) {
+        set.extend_from_slice(&ie.to_be_bytes());
+        set.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+fn push_set(msg: &mut Vec<u8>, id: u16, data: &[u8]) {
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&(data.len() as u16 + 4).to_be_bytes());
+    msg.extend_from_slice(data);
+}
+
+/// The length of the set holding both templates
+fn template_set_len() -> usize {
+    // Each template has a header of id and field count, then its fields
+    SET_HEADER_LEN + 2 * (4 + 4 * (FIELDS.len() + 2))
+}
+
+fn record_len(addr_len: usize) -> usize {
+    addr_len * 2 + FIELDS.iter().map(|(_, len)| *len as usize).sum::<usize>()
+}
+
+fn unmap(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        v4 => v4,
+    }
+}
+
+fn v6(ip: IpAddr) -> std::net::Ipv6Addr {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+        IpAddr::V6(v6) => v6,
+    }
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+fn collector() -> (UdpSocket, IpfixExporter) {
+    let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+    collector
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let exporter = IpfixExporter::connect(collector.local_addr().unwrap(), 42).unwrap();
+    (collector, exporter)
+}
+
+/// Receive a message, checking its header, and split it into sets
+#[cfg(test)]
+fn receive(collector: &UdpSocket) -> (u32, Vec<(u16, Vec<u8>)>) {
+    let mut buf = [0; 65535];
+    let n = collector.recv(&mut buf).unwrap();
+    let msg = &buf[..n];
+    let u16_at = |i: usize| u16::from_be_bytes([msg[i], msg[i + 1]]);
+    let u32_at = |i: usize| u32::from_be_bytes([msg[i], msg[i + 1], msg[i + 2], msg[i + 3]]);
+    assert_eq!(u16_at(0), 10);
+    assert_eq!(u16_at(2) as usize, n);
+    assert_eq!(u32_at(12), 42);
+
+    let mut sets = vec![];
+    let mut pos = HEADER_LEN;
+    while pos < n {
+        let len = u16_at(pos + 2) as usize;
+        assert!(len >= SET_HEADER_LEN && pos + len <= n);
+        sets.push((u16_at(pos), msg[pos + SET_HEADER_LEN..pos + len].to_vec()));
+        pos += len;
+    }
+    assert_eq!(pos, n);
+    (u32_at(8), sets)
+}
+
+#[cfg(test)]
+fn flow(peer: &str, local: &str) -> Flow {
+    Flow {
+        peer: peer.parse().unwrap(),
+        local: local.parse().unwrap(),
+        start: UNIX_EPOCH + Duration::from_millis(1_000),
+        end: UNIX_EPOCH + Duration::from_millis(61_500),
+        bytes: 300,
+        reason: EndReason::EndOfFlow,
+    }
+}
+
+#[test]
+fn test_ipfix_templates() {
+    let (collector, mut exporter) = collector();
+    exporter.flush().unwrap();
+    let (sequence, sets) = receive(&collector);
+    assert_eq!(sequence, 0);
+    assert_eq!(sets.len(), 1);
+
+    // A template set, id 2, holding the IPv4 then IPv6 templates
+    let (id, set) = &sets[0];
+    assert_eq!(*id, 2);
+    assert_eq!(set.len() + SET_HEADER_LEN, template_set_len());
+    let fields = |template: &[u8]| -> Vec<(u16, u16)> {
+        template[4..]
+            .chunks(4)
+            .map(|f| {
+                (
+                    u16::from_be_bytes([f[0], f[1]]),
+                    u16::from_be_bytes([f[2], f[3]]),
+                )
+            })
+            .collect()
+    };
+    let (v4, v6) = set.split_at(set.len() / 2);
+    assert_eq!(v4[..4], [1, 0, 0, 9]);
+    assert_eq!(v6[..4], [1, 1, 0, 9]);
+    assert_eq!(fields(v4)[..2], [(8, 4), (12, 4)]);
+    assert_eq!(fields(v6)[..2], [(27, 16), (28, 16)]);
+    assert_eq!(fields(v4)[2..], FIELDS);
+    assert_eq!(fields(v6)[2..], FIELDS);
+
+    // Not resent until they're due, leaving nothing to send
+    exporter.flush().unwrap();
+    exporter
+        .record(&flow("192.0.2.1:50000", "192.0.2.2:22"))
+        .unwrap();
+    exporter.flush().unwrap();
+    let (_, sets) = receive(&collector);
+    assert_eq!(sets.len(), 1);
+    assert_eq!(sets[0].0, TEMPLATE_V4);
+}
+
+#[test]
+fn test_ipfix_records() {
+    let (collector, mut exporter) = collector();
+    exporter.flush().unwrap();
+    receive(&collector);
+
+    exporter
+        .record(&flow("[::ffff:192.0.2.1]:50000", "192.0.2.2:22"))
+        .unwrap();
+    exporter
+        .record(&flow("[2001:db8::1]:50001", "[2001:db8::2]:2222"))
+        .unwrap();
+    exporter.flush().unwrap();
+    let (sequence, sets) = receive(&collector);
+    assert_eq!(sequence, 0);
+    assert_eq!(sets.len(), 2);
+
+    let (id, v4) = &sets[0];
+    assert_eq!(*id, TEMPLATE_V4);
+    assert_eq!(v4.len(), record_len(4));
+    assert_eq!(v4[..8], [192, 0, 2, 1, 192, 0, 2, 2]);
+    assert_eq!(v4[8..13], [0xc3, 0x50, 0, 22, 6]);
+    assert_eq!(v4[13..21], 300u64.to_be_bytes());
+    assert_eq!(v4[21..29], 1_000u64.to_be_bytes());
+    assert_eq!(v4[29..37], 61_500u64.to_be_bytes());
+    assert_eq!(v4[37], EndReason::EndOfFlow as u8);
+
+    let (id, v6) = &sets[1];
+    assert_eq!(*id, TEMPLATE_V6);
+    assert_eq!(v6.len(), record_len(16));
+    assert_eq!(
+        v6[..16],
+        "2001:db8::1"
+            .parse::<std::net::Ipv6Addr>()
+            .unwrap()
+            .octets()
+    );
+    assert_eq!(v6[32..36], [0xc3, 0x51, 0x08, 0xae]);
+
+    // Sequence numbers count the data records sent before
+    exporter
+        .record(&flow("192.0.2.3:1", "192.0.2.2:22"))
+        .unwrap();
+    exporter.flush().unwrap();
+    assert_eq!(receive(&collector).0, 2);
+}
+
+#[test]
+fn test_ipfix_message_size() {
+    let (collector, mut exporter) = collector();
+    for i in 0..100u16 {
+        exporter
+            .record(&flow(&format!("[2001:db8::{:x}]:1", i), "[2001:db8::2]:22"))
+            .unwrap();
+    }
+    exporter.flush().unwrap();
+
+    let mut records = 0;
+    while records < 100 {
+        let (sequence, sets) = receive(&collector);
+        assert_eq!(sequence as usize, records);
+        let data = sets.iter().find(|(id, _)| *id == TEMPLATE_V6).unwrap();
+        let len = HEADER_LEN
+            + sets
+                .iter()
+                .map(|(_, set)| SET_HEADER_LEN + set.len())
+                .sum::<usize>();
+        assert!(len <= MAX_MESSAGE_BYTES);
+        records += data.1.len() / record_len(16);
+    }
+    assert_eq!(records, 100);
+}
@@ -0,0 +1,176 @@
+//! Logging to the systemd journal, with each `key: value` of a log line as its
+//! own field so entries can be queried by peer, duration and so on.
+
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::sync::Mutex;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::sanitize::Bounded;
+
+const SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Split a line of the usual `event, key: value, ...` form into its event and
+/// fields, leaving commas that don't start a new key in the value before them
+fn fields(message: &str) -> (Option<&str>, Vec<(&str, &str)>) {
+    // Each key, where its part starts, and where its value starts
+    let mut keys = vec![];
+    let mut offset = 0;
+    for part in message.split(", ") {
+        if let Some(at) = part.find(": ") {
+            let key = &part[..at];
+            let valid = key.starts_with(|c: char| c.is_ascii_lowercase())
+                && key
+                    .bytes()
+                    .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_');
+            if valid {
+                keys.push((key, offset, offset + at + 2));
+            }
+        }
+        offset += part.len() + 2;
+    }
+
+    let event = match keys.first() {
+        Some((_, 0, _)) => None,
+        Some((_, start, _)) => Some(&message[..start - 2]),
+        None => Some(message),
+    };
+    let fields = keys
+        .iter()
+        .enumerate()
+        .map(|(i, (key, _, value))| {
+            let end = keys
+                .get(i + 1)
+                .map_or(message.len(), |(_, next, _)| next - 2);
+            (*key, &message[*value..end])
+        })
+        .collect();
+    (event, fields)
+}
+
+/// The journal field for a log key
+fn field_name(key: &str) -> String {
+    match key {
+        "peer" => "PEER_ADDR".to_string(),
+        _ => key.to_ascii_uppercase(),
+    }
+}
+
+/// Append a field in the journal's native format
+fn push_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+fn entry(level: Level, message: &str) -> Vec<u8> {
+    let priority = match level {
+        Level::Error => "3",
+        Level::Warn => "4",
+        Level::Info => "6",
+        Level::Debug | Level::Trace => "7",
+    };
+    let mut entry = Vec::with_capacity(message.len() * 2 + 64);
+    push_field(&mut entry, "MESSAGE", message);
+    push_field(&mut entry, "PRIORITY", priority);
+    push_field(&mut entry, "SYSLOG_IDENTIFIER", "tarssh");
+    let (event, fields) = fields(message);
+    if let Some(event) = event {
+        push_field(&mut entry, "EVENT", event);
+    }
+    for (key, value) in fields {
+        // Debug-formatted strings are quoted, which only gets in the way here
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        push_field(&mut entry, &field_name(key), value);
+    }
+    entry
+}
+
+fn connect() -> io::Result<UnixDatagram> {
+    let sock = UnixDatagram::unbound()?;
+    sock.connect(SOCKET)?;
+    Ok(sock)
+}
+
+struct Journald {
+    sock: Mutex<UnixDatagram>,
+}
+
+impl Log for Journald {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let entry = entry(record.level(), &record.args().to_string());
+        if let Ok(mut sock) = self.sock.lock() {
+            // journald may have restarted, so try a fresh socket once
+            if sock.send(&entry).is_err() {
+                if let Ok(fresh) = connect() {
+                    *sock = fresh;
+                    let _ = sock.send(&entry);
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Send all logging to the journal rather than standard error, filtered by
+/// the maximum level so a reload can change it
+pub fn init(level: LevelFilter) -> io::Result<()> {
+    let sock = Mutex::new(connect()?);
+    log::set_logger(Box::leak(Box::new(Bounded(Journald { sock }))))
+        .map_err(|err| io::Error::new(io::ErrorKind::AlreadyExists, err.to_string()))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+#[test]
+fn test_journald_entry() {
+    assert_eq!(
+        fields("disconnect, peer: 192.0.2.1:22, error: \"a, b\", bytes: 24"),
+        (
+            Some("disconnect"),
+            vec![
+                ("peer", "192.0.2.1:22"),
+                ("error", "\"a, b\""),
+                ("bytes", "24")
+            ]
+        )
+    );
+    assert_eq!(fields("plain text"), (Some("plain text"), vec![]));
+
+    let entry = entry(
+        Level::Warn,
+        "disconnect, peer: 192.0.2.1:22, error: \"Timed Out\"",
+    );
+    assert_eq!(
+        String::from_utf8(entry).unwrap(),
+        "MESSAGE=disconnect, peer: 192.0.2.1:22, error: \"Timed Out\"\n\
+         PRIORITY=4\n\
+         SYSLOG_IDENTIFIER=tarssh\n\
+         EVENT=disconnect\n\
+         PEER_ADDR=192.0.2.1:22\n\
+         ERROR=Timed Out\n"
+    );
+
+    let mut multi = vec![];
+    push_field(&mut multi, "MESSAGE", "a\nb");
+    assert_eq!(multi, b"MESSAGE\n\x03\0\0\0\0\0\0\0a\nb\n");
+}
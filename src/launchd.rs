@@ -0,0 +1,37 @@
+//! Socket activation under macOS launchd.
+
+use std::ffi::CString;
+use std::io;
+use std::net::TcpListener;
+use std::os::raw::{c_char, c_int};
+use std::os::unix::io::FromRawFd;
+
+extern "C" {
+    /// From liblaunch, part of libSystem since macOS 10.10
+    fn launch_activate_socket(name: *const c_char, fds: *mut *mut c_int, cnt: *mut usize) -> c_int;
+}
+
+/// Take the listening sockets launchd opened for the named entry in the
+/// `Sockets` dictionary of our job's plist
+pub fn activate(name: &str) -> io::Result<Vec<TcpListener>> {
+    let name = CString::new(name)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "NUL in socket name"))?;
+    let mut fds: *mut c_int = std::ptr::null_mut();
+    let mut count = 0;
+
+    // SAFETY: on success launchd hands us a malloc'd array of `count`
+    // descriptors, which are ours to own and the array ours to free
+    unsafe {
+        match launch_activate_socket(name.as_ptr(), &mut fds, &mut count) {
+            0 => (),
+            err => return Err(io::Error::from_raw_os_error(err)),
+        }
+
+        let listeners = std::slice::from_raw_parts(fds, count)
+            .iter()
+            .map(|&fd| TcpListener::from_raw_fd(fd))
+            .collect();
+        libc::free(fds.cast());
+        Ok(listeners)
+    }
+}
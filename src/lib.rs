@@ -0,0 +1,13 @@
+//! Components of the tarssh SSH tarpit that are useful outside the daemon.
+//!
+//! [`event`] defines the events tarssh logs, and parses them back out of its
+//! log files.  [`tarpit`] runs the tarpit itself inside another program,
+//! sending any of the [`banner`]s tarssh can, including the fake server
+//! [`chatter`] and [`markov`] text, and [`template`]s for other protocols.
+
+pub mod banner;
+pub mod chatter;
+pub mod event;
+pub mod markov;
+pub mod tarpit;
+pub mod template;
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr};
+use std::time::{Duration, Instant};
+
+/// Period over which --source-rate counts connections
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// How many leading bits of an address identify a source.
+///
+/// A single IPv6 host can usually pick any address in its /64, so limiting
+/// per address would be pointless.
+#[derive(Debug, Clone, Copy)]
+pub struct Prefixes {
+    pub v4: u8,
+    pub v6: u8,
+}
+
+/// A source network, stored as an IPv6 address with IPv4 mapped into it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Source {
+    net: u128,
+    len: u8,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ip = Ipv6Addr::from(self.net);
+        match ip.to_ipv4() {
+            Some(v4) if self.len >= 96 => write!(f, "{}/{}", v4, self.len - 96),
+            _ => write!(f, "{}/{}", ip, self.len),
+        }
+    }
+}
+
+impl Prefixes {
+    pub fn source(&self, ip: IpAddr) -> Source {
+        let (addr, len) = match ip {
+            IpAddr::V4(v4) => (u128::from(v4.to_ipv6_mapped()), 96 + self.v4.min(32)),
+            IpAddr::V6(v6) => (u128::from(v6), self.v6.min(128)),
+        };
+        let mask = u128::MAX.checked_shl(128 - len as u32).unwrap_or(0);
+        Source {
+            net: addr & mask,
+            len,
+        }
+    }
+}
+
+/// Why a client was turned away
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Refusal {
+    /// The source already has as many clients as it's allowed
+    Clients,
+    /// The source has connected too often recently
+    Rate,
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Refusal::Clients => "max-per-source",
+            Refusal::Rate => "source-rate",
+        })
+    }
+}
+
+/// Per-source connection limits, grouping addresses by prefix.
+pub struct SourceLimits {
+    prefixes: Prefixes,
+    max_clients: Option<u32>,
+    /// Most connections per source per minute
+    rate: Option<u32>,
+    active: HashMap<Source, u32>,
+    recent: HashMap<Source, (Instant, u32)>,
+    swept: Instant,
+}
+
+impl SourceLimits {
+    pub fn new(prefixes: Prefixes, max_clients: Option<u32>, rate: Option<u32>) -> Self {
+        Self {
+            prefixes,
+            max_clients,
+            rate,
+            active: HashMap::new(),
+            recent: HashMap::new(),
+            swept: Instant::now(),
+        }
+    }
+
+    /// Count a new client against its source's limits, unless it's over them
+    pub fn admit(&mut self, ip: IpAddr, now: Instant) -> Result<Source, (Source, Refusal)> {
+        let source = self.prefixes.source(ip);
+
+        if now.duration_since(self.swept) >= RATE_WINDOW {
+            self.recent
+                .retain(|_, (start, _)| now.duration_since(*start) < RATE_WINDOW);
+            self.swept = now;
+        }
+
+        let active = self.active.get(&source).copied().unwrap_or(0);
+        if self.max_clients.is_some_and(|max| active >= max) {
+            return Err((source, Refusal::Clients));
+        }
+
+        if let Some(rate) = self.rate {
+            let (start, count) = self.recent.entry(source).or_insert((now, 0));
+            if now.duration_since(*start) >= RATE_WINDOW {
+                *start = now;
+                *count = 0;
+            }
+            if *count >= rate {
+                return Err((source, Refusal::Rate));
+            }
+            *count += 1;
+        }
+
+        if self.max_clients.is_some() {
+            *self.active.entry(source).or_default() += 1;
+        }
+        Ok(source)
+    }
+
+    /// Note that one of a source's clients has gone
+    pub fn release(&mut self, ip: IpAddr) {
+        let source = self.prefixes.source(ip);
+        if let Some(active) = self.active.get_mut(&source) {
+            *active -= 1;
+            if *active == 0 {
+                self.active.remove(&source);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_source_limits() {
+    let prefixes = Prefixes { v4: 32, v6: 64 };
+    let a: IpAddr = "2001:db8:1:2::1".parse().unwrap();
+    let b: IpAddr = "2001:db8:1:2:ffff::9".parse().unwrap();
+    let c: IpAddr = "2001:db8:1:3::1".parse().unwrap();
+    assert_eq!(prefixes.source(b).to_string(), "2001:db8:1:2::/64");
+    assert_eq!(
+        prefixes.source("192.0.2.7".parse().unwrap()).to_string(),
+        "192.0.2.7/32"
+    );
+    assert_eq!(
+        Prefixes { v4: 24, v6: 0 }
+            .source("192.0.2.7".parse().unwrap())
+            .to_string(),
+        "192.0.2.0/24"
+    );
+
+    let now = Instant::now();
+    let mut limits = SourceLimits::new(prefixes, Some(1), Some(3));
+    assert!(limits.admit(a, now).is_ok());
+    assert_eq!(limits.admit(b, now).unwrap_err().1, Refusal::Clients);
+    assert!(limits.admit(c, now).is_ok());
+
+    limits.release(a);
+    assert!(limits.admit(b, now).is_ok());
+    limits.release(b);
+    // The refused attempt didn't count towards the rate
+    assert!(limits.admit(a, now).is_ok());
+    limits.release(a);
+    assert_eq!(limits.admit(a, now).unwrap_err().1, Refusal::Rate);
+    assert!(limits.admit(a, now + RATE_WINDOW).is_ok());
+}
@@ -0,0 +1,509 @@
+//! Binding listeners, and keeping them bound.
+//!
+//! Listeners that fail to bind at startup, or whose accepts keep failing
+//! later, are handed to a background task that retries them with backoff.
+
+use std::fmt;
+use std::io;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::num::{NonZeroU16, NonZeroUsize};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{Stream, StreamExt};
+use log::{info, warn};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::time::Instant;
+use tokio_stream::wrappers::TcpListenerStream;
+
+use crate::pacing::Schedule;
+use tarssh::banner::Preset;
+
+/// First delay before retrying a bind, doubling after each failure
+const RETRY_MIN: Duration = Duration::from_secs(5);
+
+/// Longest delay between retries
+const RETRY_MAX: Duration = Duration::from_secs(300);
+
+/// Accept errors in a row after which a listener is considered broken
+const ERROR_LIMIT: u32 = 50;
+
+/// A listener's stream of accepted connections
+pub type Listener = Pin<Box<dyn Stream<Item = io::Result<TcpStream>> + Send>>;
+
+/// How to bind a listener, kept so it can be bound again
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bind {
+    /// A --listen address
+    Addr(SocketAddr),
+    /// A --listen-dual port, as one dual-stack socket or else a pair
+    Dual(u16),
+    /// One socket of a --listen-dual port, and whether it's dual-stack
+    Part(SocketAddr, bool),
+}
+
+impl Bind {
+    /// The address bound, the IPv6 wildcard for a whole --listen-dual port
+    pub fn addr(self) -> SocketAddr {
+        match self {
+            Bind::Addr(addr) | Bind::Part(addr, _) => addr,
+            Bind::Dual(port) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)),
+        }
+    }
+
+    /// Bind the listener, returning each socket with how to bind it alone
+    pub async fn bind(self) -> io::Result<Vec<(Bind, TcpListenerStream)>> {
+        match self {
+            Bind::Addr(addr) => Ok(vec![(self, crate::listen_socket(addr, None).await?)]),
+            Bind::Dual(port) => {
+                let sockets = crate::listen_dual(port).await?;
+                let dual = sockets.len() == 1;
+                Ok(sockets
+                    .into_iter()
+                    .map(|(addr, listener)| (Bind::Part(addr, dual), listener))
+                    .collect())
+            }
+            Bind::Part(addr, dual) => {
+                let only_v6 = Some(!dual).filter(|_| addr.is_ipv6());
+                Ok(vec![(self, crate::listen_socket(addr, only_v6).await?)])
+            }
+        }
+    }
+}
+
+impl fmt::Display for Bind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bind::Addr(addr) => write!(f, "addr: {}", addr),
+            Bind::Dual(port) => write!(f, "port: {}, dual: true", port),
+            Bind::Part(addr, dual) => write!(f, "addr: {}, dual: {}", addr, dual),
+        }
+    }
+}
+
+/// A --listen address, with any settings of its own in place of the shared
+/// ones, given as `addr[=banner][,delay=secs][,pacing=schedule]`
+#[derive(Debug, Clone)]
+pub struct Listen {
+    pub addr: SocketAddr,
+    /// A --banner preset to send
+    pub banner: Option<Preset>,
+    /// A schedule in place of --delay or --pacing, fixed if given as a delay
+    pub pacing: Option<Schedule>,
+}
+
+impl From<SocketAddr> for Listen {
+    fn from(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            banner: None,
+            pacing: None,
+        }
+    }
+}
+
+impl FromStr for Listen {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, settings) = s.split_at(s.find(&['=', ','][..]).unwrap_or(s.len()));
+        let mut listen = Listen::from(
+            addr.parse::<SocketAddr>()
+                .map_err(|err| format!("{}: {}", addr, err))?,
+        );
+        if !settings.is_empty() {
+            // The banner may be given bare, straight after the address
+            let bare = settings.starts_with('=');
+            let mut rest = Some(&settings[1..]);
+            let mut first = true;
+            while let Some(settings) = rest {
+                // A schedule's steps are comma-separated too, so it takes the rest
+                let (setting, next) = match settings.split_once(',') {
+                    Some((setting, next)) if !settings.starts_with("pacing=") => {
+                        (setting, Some(next))
+                    }
+                    _ => (settings, None),
+                };
+                match setting.split_once('=') {
+                    None if first && bare => listen.banner = Some(setting.parse()?),
+                    Some(("banner", banner)) => listen.banner = Some(banner.parse()?),
+                    Some(("delay" | "pacing", _)) if listen.pacing.is_some() => {
+                        return Err("only one of delay or pacing may be given".to_string())
+                    }
+                    Some(("delay", delay)) => {
+                        let delay: NonZeroU16 = delay
+                            .parse()
+                            .map_err(|err| format!("delay {:?}: {}", delay, err))?;
+                        let delay = Duration::from_secs(u16::from(delay) as u64);
+                        listen.pacing = Some(Schedule::fixed(delay));
+                    }
+                    Some(("pacing", schedule)) => {
+                        listen.pacing = Some(
+                            schedule
+                                .parse()
+                                .map_err(|err| format!("pacing {:?}: {}", schedule, err))?,
+                        )
+                    }
+                    _ => {
+                        return Err(format!(
+                            "unknown setting {:?}, expected a banner, delay=<secs> or pacing=<schedule>",
+                            setting
+                        ))
+                    }
+                }
+                rest = next;
+                first = false;
+            }
+        }
+        Ok(listen)
+    }
+}
+
+impl Listen {
+    /// Whether the listener has any settings of its own
+    pub fn is_own(&self) -> bool {
+        self.banner.is_some() || self.pacing.is_some()
+    }
+}
+
+/// Which of `items` is for the listener `addr` was accepted on: the one for
+/// `addr` itself, or else for a wildcard listener on its port, an IPv6 one also
+/// covering IPv4 as a dual-stack socket would
+pub fn find<T, F: Fn(&T) -> SocketAddr>(items: &[T], addr: SocketAddr, key: F) -> Option<usize> {
+    let wildcard = |item: &T| {
+        let listener = key(item);
+        listener.port() == addr.port()
+            && listener.ip().is_unspecified()
+            && (listener.is_ipv6() || addr.is_ipv4())
+    };
+    items
+        .iter()
+        .position(|item| key(item) == addr)
+        .or_else(|| items.iter().position(wildcard))
+}
+
+/// A name for a listener, given as `name=addr`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub name: String,
+    pub addr: SocketAddr,
+}
+
+impl FromStr for Label {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, addr) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected name=addr, not {:?}", s))?;
+        if name.is_empty()
+            || !name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+        {
+            return Err(format!(
+                "label {:?} must be letters, digits, '-', '_' or '.'",
+                name
+            ));
+        }
+        let addr = addr.parse().map_err(|err| format!("{}: {}", addr, err))?;
+        Ok(Label {
+            name: name.to_string(),
+            addr,
+        })
+    }
+}
+
+/// Listener labels, looked up by the address a listener is bound to or a
+/// connection was accepted on
+#[derive(Debug, Default)]
+pub struct Labels(Vec<Label>);
+
+impl Labels {
+    pub fn new(labels: Vec<Label>) -> Self {
+        Self(labels)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Label> {
+        self.0.iter()
+    }
+
+    /// The label for the listener `addr` was accepted on, as for [`find`]
+    pub fn get(&self, addr: SocketAddr) -> Option<&str> {
+        find(&self.0, addr, |label| label.addr).map(|i| self.0[i].name.as_str())
+    }
+
+    /// Whether a label marks the listener bound to `addr` as one for SSH
+    /// clients, by having `ssh` as a word of its name, as in `ssh-bait-dmz`.
+    ///
+    /// A label covers the listener if either is a wildcard for the other's
+    /// port, so marking any address on a port marks a wildcard listener on it.
+    pub fn is_ssh(&self, addr: SocketAddr) -> bool {
+        self.0.iter().any(|label| {
+            label
+                .name
+                .split(&['-', '_', '.'][..])
+                .any(|word| word.eq_ignore_ascii_case("ssh"))
+                && label.addr.port() == addr.port()
+                && (label.addr.ip() == addr.ip()
+                    || label.addr.ip().is_unspecified()
+                    || addr.ip().is_unspecified())
+        })
+    }
+
+    /// `, label: <name>` for a labelled `addr`, to append to a log line
+    pub fn suffix(&self, addr: SocketAddr) -> String {
+        self.get(addr)
+            .map_or_else(String::new, |name| format!(", label: {}", name))
+    }
+}
+
+/// Accept from `listener` until its accepts fail too many times in a row, then
+/// close it and send `rebind` on to be bound again
+pub fn guard(
+    listener: TcpListenerStream,
+    rebind: Option<Bind>,
+    broken: UnboundedSender<Bind>,
+    labels: Arc<Labels>,
+) -> Listener {
+    Box::pin(async_stream::stream! {
+        let mut listener = listener;
+        let mut errors = 0;
+        while let Some(accepted) = listener.next().await {
+            match &accepted {
+                Ok(_) => errors = 0,
+                Err(err) if matches!(
+                    err.kind(),
+                    io::ErrorKind::ConnectionRefused
+                        | io::ErrorKind::ConnectionAborted
+                        | io::ErrorKind::ConnectionReset
+                ) => (),
+                Err(_) => errors += 1,
+            }
+            yield accepted;
+            if errors >= ERROR_LIMIT {
+                break;
+            }
+        }
+        match rebind {
+            Some(bind) => {
+                warn!("listen, {}{}, broken: true", bind, labels.suffix(bind.addr()));
+                let _ = broken.send(bind);
+            }
+            None => warn!("listen, broken: true, rebind: false"),
+        }
+    })
+}
+
+/// Retry binding each listener sent to `unbound`, backing off after each
+/// failure, and send those that succeed to `rebound`
+pub async fn rebind(
+    mut unbound: UnboundedReceiver<Bind>,
+    rebound: UnboundedSender<Listener>,
+    broken: UnboundedSender<Bind>,
+    failures: Arc<AtomicU64>,
+    labels: Arc<Labels>,
+) {
+    let mut waiting: Vec<(Bind, Instant, Duration)> = vec![];
+    loop {
+        let next = waiting.iter().map(|(_, due, _)| *due).min();
+        tokio::select! {
+            Some(bind) = unbound.recv() => {
+                waiting.push((bind, Instant::now() + RETRY_MIN, RETRY_MIN));
+            }
+            _ = tokio::time::sleep_until(next.unwrap_or_else(Instant::now)), if next.is_some() => {
+                let now = Instant::now();
+                let mut still = vec![];
+                for (bind, due, backoff) in waiting.drain(..) {
+                    if due > now {
+                        still.push((bind, due, backoff));
+                        continue;
+                    }
+                    match bind.bind().await {
+                        Ok(sockets) => {
+                            for (bind, listener) in sockets {
+                                info!("listen, {}{}, retried: true", bind, labels.suffix(bind.addr()));
+                                let _ = rebound.send(guard(
+                                    listener,
+                                    Some(bind),
+                                    broken.clone(),
+                                    labels.clone(),
+                                ));
+                            }
+                        }
+                        Err(err) => {
+                            failures.fetch_add(1, Ordering::Relaxed);
+                            let backoff = (backoff * 2).min(RETRY_MAX);
+                            warn!(
+                                "listen, {}{}, error: {}, retry: {}s",
+                                bind,
+                                labels.suffix(bind.addr()),
+                                err,
+                                backoff.as_secs()
+                            );
+                            still.push((bind, Instant::now() + backoff, backoff));
+                        }
+                    }
+                }
+                waiting = still;
+            }
+            else => return,
+        }
+    }
+}
+
+/// How many listeners must bind for tarssh to start: `all`, `any` or a count
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Required {
+    All,
+    Any,
+    Count(NonZeroUsize),
+}
+
+impl Required {
+    /// Whether `bound` listeners out of `wanted` is enough, a count above
+    /// `wanted` meaning all of them
+    pub fn met(self, bound: usize, wanted: usize) -> bool {
+        match self {
+            Required::All => bound == wanted,
+            Required::Any => bound > 0 || wanted == 0,
+            Required::Count(n) => bound >= n.get().min(wanted),
+        }
+    }
+}
+
+impl FromStr for Required {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Required::All),
+            "any" => Ok(Required::Any),
+            _ => s
+                .parse()
+                .map(Required::Count)
+                .map_err(|_| format!("expected all, any or a number of listeners, not {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Required {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Required::All => f.write_str("all"),
+            Required::Any => f.write_str("any"),
+            Required::Count(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+#[test]
+fn test_required() {
+    assert_eq!("all".parse(), Ok(Required::All));
+    assert_eq!(
+        "2".parse::<Required>().map(|r| r.to_string()),
+        Ok("2".to_string())
+    );
+    assert!("0".parse::<Required>().is_err());
+    assert!("some".parse::<Required>().is_err());
+
+    assert!(Required::All.met(3, 3));
+    assert!(!Required::All.met(2, 3));
+    assert!(Required::Any.met(1, 3));
+    assert!(!Required::Any.met(0, 3));
+    let two: Required = "2".parse().unwrap();
+    assert!(two.met(2, 3));
+    assert!(!two.met(1, 3));
+    assert!(two.met(1, 1));
+}
+
+#[test]
+fn test_labels() {
+    let label: Label = "dmz=0.0.0.0:22".parse().unwrap();
+    assert_eq!(label.name, "dmz");
+    assert!("0.0.0.0:22".parse::<Label>().is_err());
+    assert!("d m z=0.0.0.0:22".parse::<Label>().is_err());
+    assert!("dmz=here".parse::<Label>().is_err());
+
+    let labels = Labels::new(vec![
+        label,
+        "lan=10.0.0.1:22".parse().unwrap(),
+        "v6=[::]:2222".parse().unwrap(),
+    ]);
+    assert_eq!(labels.get("10.0.0.1:22".parse().unwrap()), Some("lan"));
+    assert_eq!(labels.get("192.0.2.1:22".parse().unwrap()), Some("dmz"));
+    assert_eq!(labels.get("192.0.2.1:2222".parse().unwrap()), Some("v6"));
+    assert_eq!(labels.get("[2001:db8::1]:22".parse().unwrap()), None);
+    assert_eq!(labels.suffix("[::1]:2222".parse().unwrap()), ", label: v6");
+}
+
+#[test]
+fn test_labels_ssh() {
+    let labels = Labels::new(vec![
+        "ssh-bait-dmz=0.0.0.0:22".parse().unwrap(),
+        "SSH=10.0.0.1:2222".parse().unwrap(),
+        "sshd=0.0.0.0:2200".parse().unwrap(),
+        "lan=0.0.0.0:25".parse().unwrap(),
+    ]);
+    assert!(labels.is_ssh("0.0.0.0:22".parse().unwrap()));
+    assert!(labels.is_ssh("10.0.0.2:22".parse().unwrap()));
+    assert!(labels.is_ssh("10.0.0.1:2222".parse().unwrap()));
+    assert!(labels.is_ssh("[::]:2222".parse().unwrap()));
+    assert!(!labels.is_ssh("10.0.0.2:2222".parse().unwrap()));
+    assert!(!labels.is_ssh("0.0.0.0:2200".parse().unwrap()));
+    assert!(!labels.is_ssh("0.0.0.0:25".parse().unwrap()));
+    assert!(!Labels::default().is_ssh("0.0.0.0:22".parse().unwrap()));
+}
+
+#[test]
+fn test_listen() {
+    let listen: Listen = "0.0.0.0:25=smtp,delay=20".parse().unwrap();
+    assert!(matches!(listen.banner, Some(Preset::Smtp)));
+    assert_eq!(
+        listen.pacing,
+        Some(Schedule::fixed(Duration::from_secs(20)))
+    );
+    let listen: Listen = "[::]:3306=delay=5,banner=mysql".parse().unwrap();
+    assert!(matches!(listen.banner, Some(Preset::Mysql)));
+    assert!(listen.is_own());
+    assert!(!"[::]:22".parse::<Listen>().unwrap().is_own());
+    let listen: Listen = "[::]:22,delay=30".parse().unwrap();
+    assert!(listen.banner.is_none() && listen.pacing.is_some());
+    assert!("0.0.0.0:22,ssh".parse::<Listen>().is_err());
+    assert!("0.0.0.0:22=delay=5,ssh".parse::<Listen>().is_err());
+    assert!("0.0.0.0:22=delay=0".parse::<Listen>().is_err());
+    assert!("0.0.0.0:22=ssh,colour=blue".parse::<Listen>().is_err());
+    assert!("0.0.0.0:22=ftp".parse::<Listen>().is_err());
+    assert!("0.0.0.0=ssh".parse::<Listen>().is_err());
+}
+
+#[test]
+fn test_listen_pacing() {
+    let listen: Listen = "0.0.0.0:22=ssh,pacing=0s=1s, 1m=10s,10m=30sx2"
+        .parse()
+        .unwrap();
+    assert!(matches!(listen.banner, Some(Preset::Ssh)));
+    assert_eq!(
+        listen.pacing,
+        Some("0s=1s,1m=10s,10m=30sx2".parse().unwrap())
+    );
+    let listen: Listen = "[::]:22,pacing=0s=5s".parse().unwrap();
+    assert!(listen.is_own());
+    assert!(listen.banner.is_none());
+
+    // The schedule takes everything after it
+    assert!("0.0.0.0:22,pacing=0s=1s,banner=ssh"
+        .parse::<Listen>()
+        .is_err());
+    assert!("0.0.0.0:22,delay=5,pacing=0s=1s".parse::<Listen>().is_err());
+    assert!("0.0.0.0:22,pacing=0s=1s,delay=5".parse::<Listen>().is_err());
+    assert!("0.0.0.0:22,pacing=1m=1s".parse::<Listen>().is_err());
+}
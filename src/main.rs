@@ -1,24 +1,157 @@
 #![cfg_attr(feature = "nightly", feature(external_doc))]
 #![cfg_attr(feature = "nightly", doc(include = "../README.md"))]
 
-use std::net::SocketAddr;
-use std::time::{Duration, Instant};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use futures::stream::{self, SelectAll, StreamExt};
+use futures::stream::{self, FuturesUnordered, SelectAll, StreamExt};
 use log::LevelFilter;
-use log::{error, info, warn};
+use log::{debug, info, warn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use structopt::StructOpt;
 use tokio::net::{TcpSocket, TcpStream};
-use tokio::time::sleep;
-use tokio_stream::wrappers::{IntervalStream, TcpListenerStream};
+use tokio::time::{sleep, sleep_until, MissedTickBehavior};
+use tokio_stream::wrappers::{IntervalStream, TcpListenerStream, UnboundedReceiverStream};
 
+mod abuse_report;
+mod abuseipdb;
+mod affinity;
+mod agentx;
+mod ages;
+mod ban_log;
+mod banner_url;
+mod bans;
+mod bench;
+mod budget;
+mod canary;
+mod capabilities;
+mod capture;
+mod churn;
+mod clock;
+mod compress;
+mod config_file;
+#[cfg(feature = "console")]
+mod console;
+mod control;
+mod cork;
+mod deferred;
+mod dns;
+mod dnsbl;
 mod elapsed;
+mod enrich;
+#[cfg(windows)]
+mod eventlog;
+mod fatal;
+mod geoip;
+mod gossip;
+mod health;
+mod holds;
+mod http;
+#[cfg(unix)]
+mod inetd;
+mod ipfix;
+#[cfg(target_os = "linux")]
+mod journald;
+#[cfg(target_os = "macos")]
+mod launchd;
+mod limits;
+mod listeners;
+mod metrics;
+mod notify;
+mod outq;
+mod pacing;
+#[cfg(feature = "packet_capture")]
+mod packet_capture;
 mod peer_addr;
+mod ports;
+mod profile;
+mod proxy_map;
+mod rdap;
+mod remote_config;
+mod replay;
+mod report;
+mod reputation;
 mod retain_unordered;
+mod sanitize;
+#[cfg(windows)]
+mod service;
+#[cfg(feature = "soak")]
+mod soak;
+mod spool;
+#[cfg(unix)]
+mod syslog;
+#[cfg(target_os = "linux")]
+mod systemd;
+mod tags;
+mod tap;
+mod terminate;
+mod transcript;
+mod tuner;
+mod udp;
+mod versions;
+mod watch;
 
+use crate::abuseipdb::AbuseIpDb;
+use crate::affinity::CpuList;
+use crate::agentx::{AgentxTarget, Counters, Oid};
+use crate::ages::Ages;
+use crate::ban_log::BanLog;
+use crate::banner_url::BannerSource;
+use crate::bans::BanList;
+use crate::budget::{MemoryBudget, Shed};
+use crate::canary::{Canary, CanaryWatch};
+use crate::capabilities::Capabilities;
+use crate::capture::Capture;
+use crate::churn::{ChurnWatch, CountryWatch};
+use crate::clock::{Clock, Jump};
+use crate::compress::Compressor;
+use crate::deferred::Deferred;
+use crate::dnsbl::{Action, Dnsbl};
 use crate::elapsed::Elapsed;
+use crate::enrich::{Limits, Overflow};
+use crate::fatal::Fatal;
+use crate::geoip::GeoIp;
+use crate::gossip::Gossip;
+use crate::health::Heartbeat;
+use crate::holds::Holds;
+use crate::http::HttpUrl;
+use crate::ipfix::{EndReason, Flow, IpfixExporter};
+use crate::limits::{Prefixes, SourceLimits};
+use crate::listeners::{Bind, Label, Labels, Listen, Required};
+use crate::metrics::{
+    InfluxSink, InfluxTarget, Metrics, Pusher, Pushgateway, StatsdSink, TextfileSink,
+};
+use crate::notify::Notifier;
+use crate::pacing::{parse_missed_tick, ListenerPacing, Pacing, Schedule, Timeouts};
+#[cfg(feature = "packet_capture")]
+use crate::packet_capture::PacketCapture;
 use crate::peer_addr::PeerAddr;
+use crate::ports::{Pattern, PortPatterns};
+use crate::profile::Profiler;
+use crate::proxy_map::{MapFormat, ProxyMap};
+use crate::rdap::Rdap;
+use crate::reputation::Reputation;
 use crate::retain_unordered::RetainUnordered;
+use crate::sanitize::Field;
+use crate::spool::{Retention, Spool};
+use crate::tags::Tags;
+use crate::tap::Tap;
+use crate::terminate::EarlyTermination;
+use crate::transcript::Transcript;
+use crate::tuner::{Delays, Tuner};
+use crate::versions::{VersionTable, VersionWatch};
+use crate::watch::Watched;
+use tarssh::banner::{Banner, Charset, Preset, RandomLines, RANDOM_LINE_LIMIT};
+use tarssh::chatter::Chatter;
+use tarssh::markov::Markov;
+use tarssh::template::{Escaped, Generator, Template};
 
 #[cfg(all(unix, feature = "sandbox"))]
 use rusty_sandbox::Sandbox;
@@ -26,37 +159,257 @@ use rusty_sandbox::Sandbox;
 #[cfg(all(unix, feature = "drop_privs"))]
 use privdrop::PrivDrop;
 
-#[cfg(all(unix, feature = "drop_privs"))]
-use std::path::PathBuf;
+use std::ffi::{OsStr, OsString};
 
-#[cfg(all(unix, feature = "drop_privs"))]
-use std::ffi::OsString;
-
-static BANNER: &[u8] = b"My name is Yon Yonson,\r\n\
-    I live in Wisconsin.\r\n\
-    I work in a lumber yard there.\r\n\
-    The people I meet as\r\n\
-    I walk down the street,\r\n\
-    They say \"Hello!\"\r\n\
-    I say \"Hello!\"\r\n\
-    They say \"What's your name.\"\r\n\
-    I say: ";
+/// How often to enforce transcript and capture retention limits
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often to save the reputation store and forget stale sources
+const REPUTATION_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Most client data kept from connections held aside by --quarantine or
+/// --require-data, beyond which they're just watched for closing
+const PENDING_DATA_LIMIT: usize = 4096;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "tarssh", about = "A SSH tarpit server")]
 struct Config {
-    /// Listen address(es) to bind to
-    #[structopt(short = "l", long = "listen", default_value = "0.0.0.0:2222")]
-    listen: Vec<SocketAddr>,
+    /// Read options from this TOML file, with the command line taking precedence
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// Read options from a TOML file at this http:// or https:// URL instead,
+    /// checked against the SHA-256 sum at the same URL with .sha256 appended
+    #[structopt(long = "config-url", conflicts_with = "config")]
+    config_url: Option<HttpUrl>,
+    /// Seconds between fetches of --config-url after startup, applying any
+    /// changes as SIGHUP would
+    #[structopt(long = "config-refresh", requires = "config-url")]
+    config_refresh: Option<std::num::NonZeroU32>,
+    /// The SHA-256 sum of the --config-url file last applied
+    #[structopt(skip)]
+    config_sha256: Option<String>,
+    /// Listen address(es) to bind to, by default 0.0.0.0:2222 if no other
+    /// listeners are given, each optionally with its own banner and delay or
+    /// pacing schedule as addr=banner,delay=secs or addr=banner,pacing=schedule
+    #[structopt(short = "l", long = "listen")]
+    listen: Vec<Listen>,
+    /// Listen on this port for both IPv4 and IPv6, with one socket if possible
+    #[structopt(long = "listen-dual", number_of_values = 1)]
+    listen_dual: Vec<u16>,
+    /// How many listeners must bind to start: all, any or a number, retrying
+    /// the rest in the background
+    #[structopt(long = "require-listeners", default_value = "all")]
+    require_listeners: Required,
+    /// Name a listener in logs and metrics, as name=addr, where addr may be a
+    /// wildcard address to cover every connection on its port
+    #[structopt(long = "listener-label", number_of_values = 1)]
+    listener_label: Vec<Label>,
+    /// Start if any listener binds, the same as --require-listeners any
+    #[structopt(long = "ignore-bind-errors", conflicts_with = "require-listeners")]
+    ignore_bind_errors: bool,
+    /// Exit rather than run outside the sandbox
+    #[structopt(long = "require-sandbox")]
+    require_sandbox: bool,
+    /// Take the socket to serve from standard input, as inetd passes it
+    #[cfg(unix)]
+    #[structopt(long = "inetd")]
+    inetd: bool,
+    /// Listen on the sockets launchd opened for this `Sockets` entry instead
+    #[cfg(target_os = "macos")]
+    #[structopt(long = "launchd-socket")]
+    launchd_socket: Option<String>,
+    /// Most clients at once from any one source
+    #[structopt(long = "max-per-source")]
+    max_per_source: Option<std::num::NonZeroU32>,
+    /// Most connections per minute from any one source
+    #[structopt(long = "source-rate")]
+    source_rate: Option<std::num::NonZeroU32>,
+    /// Leading bits of an IPv4 address that identify a source
+    #[structopt(long = "ipv4-prefix", default_value = "32")]
+    ipv4_prefix: u8,
+    /// Leading bits of an IPv6 address that identify a source
+    #[structopt(long = "ipv6-prefix", default_value = "64")]
+    ipv6_prefix: u8,
+    /// Keep bans in this file, across restarts
+    #[structopt(long = "ban-file", parse(from_os_str))]
+    ban_file: Option<PathBuf>,
+    /// Accept commands such as `ban <ip> [ttl]` on a Unix socket at this path
+    #[cfg(unix)]
+    #[structopt(long = "control-socket", parse(from_os_str))]
+    control_socket: Option<PathBuf>,
+    /// Answer HTTP health checks on /healthz at this address
+    #[structopt(long = "health-listen")]
+    health_listen: Option<SocketAddr>,
+    /// Answer HAProxy agent checks with the room left at this address
+    #[structopt(long = "agent-listen")]
+    agent_listen: Option<SocketAddr>,
+    /// Seconds without a tick before health checks report the tarpit down
+    #[structopt(long = "health-stale", default_value = "10")]
+    health_stale: std::num::NonZeroU16,
+    /// Write held and banned sources to this file, as a HAProxy or nginx map
+    #[structopt(long = "proxy-map", parse(from_os_str))]
+    proxy_map: Option<PathBuf>,
+    /// Format of --proxy-map: haproxy or nginx
+    #[structopt(long = "proxy-map-format", default_value = "haproxy")]
+    proxy_map_format: MapFormat,
+    /// Seconds between rewrites of --proxy-map
+    #[structopt(long = "proxy-map-interval", default_value = "60")]
+    proxy_map_interval: std::num::NonZeroU32,
     /// Best-effort connection limit
     #[structopt(short = "c", long = "max-clients", default_value = "4096")]
     max_clients: std::num::NonZeroU32,
+    /// Approximate bytes of memory connections may use, shedding them beyond it
+    #[structopt(long = "memory-budget")]
+    memory_budget: Option<usize>,
     /// Seconds between responses
     #[structopt(short = "d", long = "delay", default_value = "10")]
     delay: std::num::NonZeroU16,
-    /// Socket write timeout
+    /// Schedule of delays over each connection's lifetime, e.g. 0s=1s,1m=10s,10m=30sx2
+    #[structopt(long = "pacing")]
+    pacing: Option<Schedule>,
+    /// Experimental: learn which of these delays holds each network's clients
+    /// longest, e.g. 5s,10s,30s,1m
+    #[structopt(long = "tune-delays", conflicts_with = "pacing")]
+    tune_delays: Option<Delays>,
+    /// What to do when the host falls behind on writes: burst, delay or skip
+    #[structopt(long = "missed-tick", default_value = "burst", parse(try_from_str = parse_missed_tick))]
+    missed_tick: MissedTickBehavior,
+    /// Re-base timers when the clocks jump by more than this between ticks,
+    /// as after a suspend or an NTP step, e.g. 30s
+    #[structopt(
+        long = "clock-jump",
+        default_value = "30s",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    clock_jump: Duration,
+    /// Send each write as a single segment, with TCP_CORK and TCP_NODELAY
+    /// (Linux only)
+    #[structopt(long = "cork")]
+    cork: bool,
+    /// Seconds writes may stall before disconnecting
     #[structopt(short = "t", long = "timeout", default_value = "30")]
     timeout: u16,
+    /// Seconds to wait for a client to send anything, when reading from it
+    #[structopt(long = "initial-data-timeout")]
+    initial_data_timeout: Option<u32>,
+    /// Seconds to hold new connections aside until they send something,
+    /// silently dropping those that don't
+    #[structopt(long = "require-data")]
+    require_data: Option<std::num::NonZeroU16>,
+    /// Hold new connections aside for up to this long, e.g. 2s, dropping those
+    /// that close meanwhile
+    #[structopt(long = "quarantine", parse(try_from_str = humantime::parse_duration))]
+    quarantine: Option<Duration>,
+    /// Seconds after which to disconnect any client
+    #[structopt(long = "max-lifetime")]
+    max_lifetime: Option<u32>,
+    /// Close this percentage of connections early, at random, timing how
+    /// long their clients take to come back
+    #[structopt(long = "terminate-percent")]
+    terminate_percent: Option<f64>,
+    /// Longest a connection picked by --terminate-percent may last, e.g. 10m
+    #[structopt(
+        long = "terminate-within",
+        default_value = "10m",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    terminate_within: Duration,
+    /// Seconds writes may stall for clients older than --relax-after
+    #[structopt(long = "relaxed-timeout")]
+    relaxed_timeout: Option<u32>,
+    /// Seconds a client must last before the relaxed timeout applies
+    #[structopt(long = "relax-after", default_value = "3600")]
+    relax_after: u32,
+    /// Banner to send: ssh, mysql, rdp, vnc, sip, smtp, openssh, random, junk,
+    /// keepalive or template
+    #[structopt(short = "b", long = "banner", default_value = "ssh")]
+    banner: Preset,
+    /// Shortest line for the random banner
+    #[structopt(long = "line-min", default_value = "1")]
+    line_min: usize,
+    /// Longest line for the random banner
+    #[structopt(long = "line-max", default_value = "30")]
+    line_max: usize,
+    /// Characters for the random banner: printable, alnum or binary
+    #[structopt(long = "charset", default_value = "printable")]
+    charset: Charset,
+    /// Send the contents of this file as the banner, a line at a time
+    #[structopt(
+        long = "banner-file",
+        parse(try_from_os_str = parse_banner_file),
+        conflicts_with = "banner-url"
+    )]
+    banner_file: Option<Banner>,
+    /// Send endless pseudo-English generated from the text in this file
+    #[structopt(
+        long = "banner-markov",
+        parse(try_from_os_str = parse_banner_markov),
+        conflicts_with_all = &["banner-file", "banner-url"]
+    )]
+    banner_markov: Option<Banner>,
+    /// Send each connection one of the files in this directory, chosen at random
+    #[structopt(
+        long = "banner-dir",
+        parse(from_os_str),
+        conflicts_with_all = &["banner-file", "banner-markov", "banner-url"]
+    )]
+    banner_dir: Option<PathBuf>,
+    /// Fetch the banner from this https:// or http:// URL, sending each line
+    /// in turn
+    #[structopt(long = "banner-url")]
+    banner_url: Option<HttpUrl>,
+    /// Greeting for --banner template, sent once, with escapes such as \r\n
+    #[structopt(long = "template-greeting", default_value = "")]
+    template_greeting: Escaped,
+    /// Lines for --banner template: random or counter
+    #[structopt(long = "template-lines", default_value = "random")]
+    template_lines: Generator,
+    /// Send --banner template's lines from this file instead, each in turn
+    #[structopt(
+        long = "template-file",
+        parse(from_os_str),
+        conflicts_with = "template-lines"
+    )]
+    template_file: Option<PathBuf>,
+    /// Start each line of --banner template with this
+    #[structopt(long = "template-prefix", default_value = "")]
+    template_prefix: Escaped,
+    /// End each line of --banner template with this
+    #[structopt(long = "template-terminator", default_value = "\\r\\n")]
+    template_terminator: Escaped,
+    /// Keep a copy of the fetched banner here, for when the URL is unreachable
+    #[structopt(long = "banner-cache", parse(from_os_str), requires = "banner-url")]
+    banner_cache: Option<PathBuf>,
+    /// Seconds between fetches of --banner-url after startup
+    #[structopt(long = "banner-refresh", requires = "banner-url")]
+    banner_refresh: Option<std::num::NonZeroU32>,
+    /// Apply changes to --banner-file, --banner-markov, --banner-dir,
+    /// --template-file and --ban-file within seconds, without a SIGHUP
+    #[structopt(long = "watch-files")]
+    watch_files: bool,
+    /// Where --banner-file or --banner-markov was read from, for --watch-files
+    #[structopt(skip)]
+    banner_path: Option<PathBuf>,
+    /// Send this SSH version line after the banner, then stall
+    #[structopt(long = "ssh-version", parse(try_from_str = parse_ssh_version))]
+    ssh_version: Option<String>,
+    /// Seconds of banner to send before the SSH version line
+    #[structopt(long = "ssh-version-after", default_value = "60")]
+    ssh_version_after: u32,
+    /// Seconds between logging runtime metrics
+    #[cfg(feature = "console")]
+    #[structopt(long = "runtime-stats")]
+    runtime_stats: Option<std::num::NonZeroU32>,
+    /// Pin tarssh to these CPUs, e.g. 0,2-3
+    #[structopt(long = "cpu-affinity")]
+    cpu_affinity: Option<CpuList>,
+    /// Time the event loop, writing folded stacks for flamegraph.pl to this
+    /// file with each report
+    #[structopt(long = "profile", parse(from_os_str))]
+    profile: Option<PathBuf>,
+    /// Seed for random behaviour, making it reproducible
+    #[structopt(long = "seed")]
+    seed: Option<u64>,
     /// Verbose level (repeat for more verbosity)
     #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
     verbose: u8,
@@ -69,10 +422,290 @@ struct Config {
     /// Disable log level in logs (e.g. "info")
     #[structopt(long)]
     disable_log_level: bool,
+    /// Log to the local syslog daemon instead of standard error
+    #[cfg(unix)]
+    #[structopt(long = "log-syslog")]
+    log_syslog: bool,
+    /// Syslog facility to log as, e.g. daemon, auth or local0
+    #[cfg(unix)]
+    #[structopt(long = "syslog-facility", default_value = "daemon")]
+    syslog_facility: syslog::Facility,
+    /// Log to the systemd journal, with each detail as a field
+    #[cfg(target_os = "linux")]
+    #[structopt(long = "log-journald", conflicts_with = "log-syslog")]
+    log_journald: bool,
+    /// Longest client-provided field to log, in characters
+    #[structopt(long = "log-field-max", default_value = "128")]
+    log_field_max: usize,
+    /// Longest log line, in bytes
+    #[structopt(long = "log-line-max", default_value = "4096")]
+    log_line_max: usize,
+    /// Leave out the connect line of connections that don't last this long,
+    /// marking their disconnect line short instead, e.g. 5s
+    #[structopt(
+        long = "log-after",
+        conflicts_with = "enrich-wait",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    log_after: Option<Duration>,
+    /// Record a timestamped transcript of each connection in this directory
+    #[structopt(long = "transcript-dir", parse(from_os_str))]
+    transcript_dir: Option<PathBuf>,
+    /// Save raw data sent by each client to a file in this directory
+    #[structopt(long = "capture-dir", parse(from_os_str))]
+    capture_dir: Option<PathBuf>,
+    /// Maximum bytes to capture per connection, or 0 to create no captures
+    #[structopt(long = "capture-limit", default_value = "65536")]
+    capture_limit: u64,
+    /// Most files to keep in each of the transcript and capture directories
+    #[structopt(long = "spool-max-files")]
+    spool_max_files: Option<usize>,
+    /// Gzip transcripts and captures once each connection is done with them
+    #[structopt(long = "spool-compress")]
+    spool_compress: bool,
+    /// Delete transcripts and captures older than this, e.g. 7d
+    #[structopt(long = "spool-max-age", parse(try_from_str = humantime::parse_duration))]
+    spool_max_age: Option<Duration>,
+    /// Most bytes to keep in each of the transcript and capture directories
+    #[structopt(long = "spool-max-size")]
+    spool_max_size: Option<u64>,
+    /// Alert on client data matching this pattern (str:<text>, re:<regex> or hex:<bytes>)
+    #[structopt(long = "canary", number_of_values = 1)]
+    canary: Vec<Canary>,
+    /// Count the SSH versions clients identify as, reporting the most common
+    #[structopt(long = "client-versions")]
+    client_versions: bool,
+    /// Tag peers whose source ports follow the fixed or linear sequences of
+    /// scanners like masscan and zmap
+    #[structopt(long = "port-patterns")]
+    port_patterns: bool,
+    /// Report held peers to AbuseIPDB's bulk-report API at this https:// URL,
+    /// e.g. https://api.abuseipdb.com/api/v2/bulk-report
+    #[structopt(
+        long = "abuseipdb",
+        requires = "abuseipdb-key",
+        parse(try_from_str = abuseipdb::parse_url)
+    )]
+    abuseipdb: Option<HttpUrl>,
+    /// AbuseIPDB API key
+    #[structopt(long = "abuseipdb-key")]
+    abuseipdb_key: Option<String>,
+    /// AbuseIPDB categories to report peers under, e.g. 18,22
+    #[structopt(
+        long = "abuseipdb-categories",
+        default_value = "18,22",
+        parse(try_from_str = abuseipdb::parse_categories)
+    )]
+    abuseipdb_categories: String,
+    /// How often to send reports to AbuseIPDB, e.g. 6h
+    #[structopt(
+        long = "abuseipdb-interval",
+        default_value = "6h",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    abuseipdb_interval: Duration,
+    /// Shortest hold worth reporting to AbuseIPDB, e.g. 1m
+    #[structopt(
+        long = "abuseipdb-min-held",
+        default_value = "1m",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    abuseipdb_min_held: Duration,
+    /// Look up each peer in this DNS blocklist, e.g. zen.spamhaus.org
+    #[structopt(long = "dnsbl", number_of_values = 1, parse(try_from_str = dnsbl::parse_zone))]
+    dnsbl: Vec<String>,
+    /// What to do with peers a --dnsbl lists: tag, delay or close
+    #[structopt(long = "dnsbl-action", default_value = "tag")]
+    dnsbl_action: Action,
+    /// Seconds between responses to listed peers, with --dnsbl-action delay
+    #[structopt(long = "dnsbl-delay", default_value = "60")]
+    dnsbl_delay: std::num::NonZeroU16,
+    /// Longest to wait for a --dnsbl answer, e.g. 2s
+    #[structopt(
+        long = "dnsbl-timeout",
+        default_value = "2s",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    dnsbl_timeout: Duration,
+    /// How long to cache --dnsbl answers, e.g. 1h
+    #[structopt(
+        long = "dnsbl-ttl",
+        default_value = "1h",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    dnsbl_ttl: Duration,
+    /// Look up the network each peer connects from with RDAP at this URL, e.g.
+    /// https://rdap.org/, for its name and abuse contact
+    #[structopt(long = "rdap")]
+    rdap: Option<HttpUrl>,
+    /// Most --rdap lookups to make a minute
+    #[structopt(long = "rdap-rate", default_value = "10")]
+    rdap_rate: std::num::NonZeroU32,
+    /// How long to cache --rdap answers, e.g. 7d
+    #[structopt(
+        long = "rdap-ttl",
+        default_value = "7d",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    rdap_ttl: Duration,
+    /// Hold each connect line up to this long for lookups such as --dnsbl to
+    /// finish, so it's logged with their results, e.g. 500ms
+    #[structopt(long = "enrich-wait", parse(try_from_str = humantime::parse_duration))]
+    enrich_wait: Option<Duration>,
+    /// Lookups each enrichment source such as --dnsbl runs at once
+    #[structopt(long = "enrich-workers", default_value = "16")]
+    enrich_workers: std::num::NonZeroUsize,
+    /// Lookups each enrichment source may have queued
+    #[structopt(long = "enrich-queue", default_value = "1024")]
+    enrich_queue: std::num::NonZeroUsize,
+    /// What to drop when an enrichment queue's full: drop-oldest or drop-newest
+    #[structopt(long = "enrich-overflow", default_value = "drop-oldest")]
+    enrich_overflow: Overflow,
+    /// Add each peer's country and city to its log lines from this MaxMind DB
+    #[structopt(long = "geoip-db", parse(from_os_str))]
+    geoip_db: Option<PathBuf>,
+    /// Add each peer's autonomous system to its log lines from this MaxMind
+    /// DB, such as GeoLite2-ASN
+    #[structopt(long = "asn-db", parse(from_os_str))]
+    asn_db: Option<PathBuf>,
+    /// Keep a history of each source IP in this file, across restarts
+    #[structopt(long = "reputation-file", parse(from_os_str))]
+    reputation_file: Option<PathBuf>,
+    /// Forget sources not seen for this long, e.g. 30d
+    #[structopt(
+        long = "reputation-ttl",
+        default_value = "30days",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    reputation_ttl: Duration,
+    /// Alert when a minute's connections stray this many standard deviations
+    /// from the norm
+    #[structopt(long = "churn-alert")]
+    churn_alert: Option<f64>,
+    /// Alert when a country's share of a minute's connections strays this
+    /// many percentage points from the norm
+    #[structopt(long = "country-alert", requires = "geoip-db")]
+    country_alert: Option<f64>,
+    /// Append alerts to this file
+    #[structopt(long = "alert-file", parse(from_os_str))]
+    alert_file: Option<PathBuf>,
+    /// Append a line for each peer caught to this file, for fail2ban
+    #[structopt(long = "ban-log", parse(from_os_str))]
+    ban_log: Option<PathBuf>,
+    /// Export flow records to this IPFIX collector
+    #[structopt(long = "ipfix")]
+    ipfix: Option<SocketAddr>,
+    /// IPFIX observation domain ID
+    #[structopt(long = "ipfix-domain", default_value = "0")]
+    ipfix_domain: u32,
+    /// Push metrics to this Prometheus Pushgateway http:// or https:// URL
+    #[structopt(long = "push-gateway")]
+    push_gateway: Option<Pushgateway>,
+    /// Send metrics as InfluxDB line protocol to udp://host:port or unix:///path
+    #[structopt(long = "influx")]
+    influx: Option<InfluxTarget>,
+    /// Send counters and gauges to a StatsD or DogStatsD collector at host:port
+    #[structopt(long = "statsd")]
+    statsd: Option<String>,
+    /// Prefix for StatsD metric names
+    #[structopt(long = "statsd-prefix", default_value = "tarssh")]
+    statsd_prefix: String,
+    /// Write metrics to this .prom file for node_exporter's textfile collector
+    #[structopt(long = "textfile", parse(from_os_str))]
+    textfile: Option<PathBuf>,
+    /// Serve counters to this SNMP master agent's AgentX socket (path or tcp:host:port)
+    #[structopt(long = "agentx")]
+    agentx: Option<AgentxTarget>,
+    /// OID to register the counters under with --agentx
+    #[structopt(long = "agentx-oid", default_value = "1.3.6.1.4.1.8072.9999.9999")]
+    agentx_oid: Oid,
+    /// Seconds between pushing or sending metrics
+    #[structopt(long = "push-interval", default_value = "15")]
+    push_interval: std::num::NonZeroU16,
+    /// Answer UDP scanner probes on these address(es)
+    #[structopt(long = "udp-listen", number_of_values = 1)]
+    udp_listen: Vec<SocketAddr>,
+    /// Seconds to wait before answering a UDP probe
+    #[structopt(long = "udp-delay", default_value = "5")]
+    udp_delay: u16,
+    /// Answer DNS queries on these address(es) with --dns-sinkhole
+    #[structopt(long = "dns-listen", number_of_values = 1, requires = "dns-sinkhole")]
+    dns_listen: Vec<SocketAddr>,
+    /// Resolve every name to this address, one of each family at most
+    #[structopt(long = "dns-sinkhole", number_of_values = 1)]
+    dns_sinkhole: Vec<IpAddr>,
+    /// Share bans with other tarssh nodes, listening for theirs on this UDP
+    /// address
+    #[structopt(long = "gossip-listen")]
+    gossip_listen: Option<SocketAddr>,
+    /// Another node's --gossip-listen address
+    #[structopt(long = "gossip-peer", number_of_values = 1, requires = "gossip-listen")]
+    gossip_peer: Vec<SocketAddr>,
+    /// How long other nodes should ban a source refused for source limits
+    #[structopt(
+        long = "gossip-ttl",
+        default_value = "1h",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    gossip_ttl: Duration,
     #[cfg(all(unix, feature = "drop_privs"))]
     #[structopt(flatten)]
     #[cfg(all(unix, feature = "drop_privs"))]
     privdrop: PrivDropConfig,
+    #[cfg(feature = "packet_capture")]
+    #[structopt(flatten)]
+    #[cfg(feature = "packet_capture")]
+    pcap: PcapConfig,
+    /// Run under the Windows Service Control Manager
+    #[cfg(windows)]
+    #[structopt(long = "service")]
+    service: bool,
+    /// Log to the Windows Event Log instead of standard error
+    #[cfg(windows)]
+    #[structopt(long = "event-log")]
+    event_log: bool,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Summarise connections from tarssh logs
+    Report(report::ReportConfig),
+    /// Write or send abuse reports for sources in a --reputation-file
+    AbuseReport(abuse_report::AbuseReportConfig),
+    /// Replay connections from tarssh logs against other delays and limits
+    Replay(replay::ReplayConfig),
+    /// Measure connection admission and write round performance over loopback
+    Bench(bench::BenchConfig),
+    /// Run the tarpit against synthetic clients for hours, checking invariants
+    #[cfg(feature = "soak")]
+    Soak(soak::SoakConfig),
+    /// Install or uninstall the tarssh Windows service
+    #[cfg(windows)]
+    Service(service::ServiceCommand),
+}
+
+#[cfg(feature = "packet_capture")]
+#[derive(Debug, StructOpt)]
+struct PcapConfig {
+    /// Record packets of tarpitted connections to pcap files in this directory
+    #[structopt(name = "pcap-dir", long = "pcap-dir", parse(from_os_str))]
+    dir: Option<PathBuf>,
+    /// Interface to capture packets on
+    #[structopt(
+        name = "pcap-interface",
+        long = "pcap-interface",
+        default_value = "any"
+    )]
+    interface: String,
+    /// Start a new pcap file after this many bytes
+    #[structopt(
+        name = "pcap-rotate-size",
+        long = "pcap-rotate-size",
+        default_value = "67108864"
+    )]
+    rotate_size: u64,
 }
 
 #[cfg(all(unix, feature = "drop_privs"))]
@@ -89,25 +722,376 @@ struct PrivDropConfig {
     chroot: Option<PathBuf>,
 }
 
-#[derive(Debug)]
 struct Connection {
-    sock: TcpStream, // 24b
-    peer: PeerAddr,  // 18b, down from 32b
-    start: Elapsed,  // 4b, a decisecond duration since the daemon epoch, down from 16b
-    bytes: u64,      // 8b, bytes written
-    failed: u16,     // 2b, writes failed on WOULDBLOCK
-} // 56 bytes
-
-fn errx<M: AsRef<str>>(code: i32, message: M) -> ! {
-    error!("{}", message.as_ref());
-    std::process::exit(code);
+    sock: TcpStream,       // 32b
+    peer: PeerAddr,        // 18b, down from 32b
+    start: Elapsed,        // 4b, a decisecond duration since the daemon epoch, down from 16b
+    bytes: u64,            // 8b, bytes written
+    last_write: Elapsed,   // 4b, when the last write was attempted
+    writes: u32,           // 4b, successful writes
+    failed: u32,           // 4b, writes failed on WOULDBLOCK
+    terminate_after: u16,  // 2b, age in seconds to close at for --terminate-percent, or 0
+    version_sent: u8,      // 1b, bytes of the SSH version line written
+    tuned: u8,             // 1b, which of the --tune-delays to use, plus one, or 0
+    banner: u8,            // 1b, which of the --banner-dir banners to send, plus one, or 0
+    listener: u8,          // 1b, which listener's own settings apply, plus one, or 0
+    listed: bool,          // 1b, whether a --dnsbl lists the peer
+    received: bool,        // 1b, whether the client has sent anything
+    evicted: bool,         // 1b, whether to disconnect to save memory
+    tap: Option<Box<Tap>>, // 8b, optional per-connection observers
+} // 96 bytes
+
+impl Connection {
+    fn new(sock: TcpStream, peer: PeerAddr, startup: Instant, tap: Tap) -> Self {
+        Self {
+            sock,
+            peer,
+            start: startup.into(),
+            last_write: startup.into(),
+            bytes: 0,
+            writes: 0,
+            failed: 0,
+            terminate_after: 0,
+            version_sent: 0,
+            tuned: 0,
+            banner: 0,
+            listener: 0,
+            listed: false,
+            received: false,
+            evicted: false,
+            tap: Some(Box::new(tap)).filter(|tap| !tap.is_empty()),
+        }
+    }
+
+    /// Approximate memory used by the connection, for --memory-budget
+    fn memory(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + budget::SOCKET_OVERHEAD
+            + self.tap.as_ref().map_or(0, |tap| tap.memory())
+    }
+
+    fn terminated_early(&self, startup: Instant) -> bool {
+        self.terminate_after > 0
+            && self.start.elapsed(startup).as_secs() >= self.terminate_after as u64
+    }
+
+    /// Mark the connection as from a host a --dnsbl lists
+    fn list(&mut self, listing: &dnsbl::Listing) {
+        self.listed = true;
+        self.tap
+            .get_or_insert_with(Box::default)
+            .tags
+            .insert("dnsbl", &listing.zone);
+    }
+
+    /// The rules that chose the connection's pacing and banner: a listener's
+    /// own settings, named as in `listeners`, a --tune-delays delay, a
+    /// --banner-dir file and a --dnsbl listing that slowed it, or else just
+    /// `default`
+    fn rules(
+        &self,
+        pacing: &Pacing,
+        listeners: &[String],
+        banner_files: &[(String, Banner)],
+    ) -> Vec<String> {
+        let mut rules = vec![];
+        if let Some(i) = self.listener.checked_sub(1) {
+            rules.push(listeners[i as usize].clone());
+        }
+        if let Some(arm) = self.tuned.checked_sub(1) {
+            let delay = pacing.tuned[arm as usize].as_secs();
+            rules.push(holds::rule("delay", &format!("{}s", delay)));
+        }
+        // A --banner-dir that's since lost files leaves its name unknown
+        if let Some((name, _)) = self
+            .banner
+            .checked_sub(1)
+            .and_then(|i| banner_files.get(i as usize))
+        {
+            rules.push(holds::rule("banner", name));
+        }
+        if self.listed && pacing.listed == Action::Delay {
+            rules.push("dnsbl".to_string());
+        }
+        if rules.is_empty() {
+            rules.push("default".to_string());
+        }
+        rules
+    }
+
+    fn stalled(&self, pacing: &Pacing) -> bool {
+        matches!(&pacing.ssh_version, Some((_, line)) if self.version_sent as usize == line.len())
+    }
+
+    /// Service the connection for one tick, returning the number of bytes
+    /// written, or why it should be disconnected.
+    fn tick(
+        &mut self,
+        pacing: &Pacing,
+        startup: Instant,
+        rng: &mut StdRng,
+        notifier: &mut Notifier,
+    ) -> Result<usize, (std::io::Error, EndReason)> {
+        let stalled = self.stalled(pacing);
+        let reading = stalled || self.tap.is_some();
+        let age = self.start.elapsed(startup);
+
+        if pacing
+            .timeouts
+            .lifetime
+            .is_some_and(|lifetime| age >= lifetime)
+        {
+            let e = std::io::Error::other("Max Lifetime");
+            return Err((e, EndReason::ActiveTimeout));
+        }
+        if self.evicted {
+            let e = std::io::Error::other("Memory Budget");
+            return Err((e, EndReason::ForcedEnd));
+        }
+        if self.listed && pacing.listed == Action::Close {
+            let e = std::io::Error::other("DNSBL Listed");
+            return Err((e, EndReason::ForcedEnd));
+        }
+        if self.terminated_early(startup) {
+            let e = std::io::Error::other("Early Termination");
+            return Err((e, EndReason::ForcedEnd));
+        }
+        if reading && !self.received && pacing.timeouts.initial_data.is_some_and(|t| age >= t) {
+            let e = std::io::Error::other("No Data");
+            return Err((e, EndReason::IdleTimeout));
+        }
+
+        if reading {
+            let mut buf = [0; 256];
+            match self.sock.try_read(&mut buf) {
+                Ok(0) if stalled => {
+                    let e = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Closed");
+                    return Err((e, EndReason::EndOfFlow));
+                }
+                Ok(n @ 1..) => {
+                    self.received = true;
+                    if let Some(tap) = self.tap.as_mut() {
+                        tap.received(self.peer, &buf[..n], notifier);
+                    }
+                }
+                Err(e) if stalled && e.kind() != std::io::ErrorKind::WouldBlock => {
+                    return Err((e, EndReason::EndOfFlow));
+                }
+                Ok(_) | Err(_) => (),
+            }
+        }
+
+        if stalled {
+            return Ok(0);
+        }
+
+        // Connections are visited at the shortest interval in the schedule, so
+        // skip those that aren't yet due
+        let own = self
+            .listener
+            .checked_sub(1)
+            .map(|i| &pacing.listeners[i as usize]);
+        let schedule = own
+            .and_then(|own| own.schedule.as_ref())
+            .unwrap_or(&pacing.schedule);
+        let step = schedule.at(age);
+        let interval = match self.tuned.checked_sub(1) {
+            Some(arm) => pacing.tuned[arm as usize],
+            None => step.interval,
+        };
+        let interval = match pacing.listed {
+            Action::Delay if self.listed => interval.max(pacing.listed_delay),
+            _ => interval,
+        };
+        if self.last_write.elapsed(startup) + pacing.schedule.min_interval() / 2 < interval {
+            return Ok(0);
+        }
+        self.last_write = startup.into();
+
+        let own_banner = own.and_then(|own| own.banner.as_ref());
+        let banner = match (own_banner, self.banner.checked_sub(1)) {
+            (Some(banner), _) => banner,
+            // One sent from a --banner-dir that's since lost files
+            (None, Some(i)) => pacing.banners.get(i as usize).unwrap_or(&pacing.banner),
+            (None, None) => &pacing.banner,
+        };
+        let mut scratch = [0; 256];
+        let mut joined = vec![];
+        let (slice, version) = match &pacing.ssh_version {
+            Some((after, line))
+                if own_banner.is_none() && (self.version_sent > 0 || age >= *after) =>
+            {
+                (&line[self.version_sent as usize..], true)
+            }
+            _ if step.chunks > 1 => {
+                for _ in 0..step.chunks {
+                    let sent = self.bytes + joined.len() as u64;
+                    joined.extend_from_slice(banner.next(sent, rng, &mut scratch));
+                }
+                (&joined[..], false)
+            }
+            _ => (banner.next(self.bytes, rng, &mut scratch), false),
+        };
+
+        // Errors here would just as well fail the write
+        if pacing.cork {
+            let _ = cork::set(&self.sock, true);
+        }
+        let written = self.sock.try_write(slice);
+        if pacing.cork {
+            let _ = cork::set(&self.sock, false);
+        }
+
+        match written {
+            Ok(n) => {
+                self.bytes += n as u64;
+                self.writes = self.writes.saturating_add(1);
+                self.failed = 0;
+                if version {
+                    self.version_sent += n as u8;
+                }
+                if let Some(tap) = self.tap.as_mut() {
+                    tap.sent(self.peer, &slice[..n]);
+                }
+                Ok(n)
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => Ok(0),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                self.failed = self.failed.saturating_add(1);
+                if pacing.timeouts.write_expired(age, interval, self.failed) {
+                    let e = std::io::Error::other("Timed Out");
+                    Err((e, EndReason::IdleTimeout))
+                } else {
+                    Ok(0)
+                }
+            }
+            Err(e) => Err((e, EndReason::EndOfFlow)),
+        }
+    }
+
+    /// Average time between successful writes, if there were any
+    fn average_interval(&self, startup: Instant) -> Option<Duration> {
+        Some(self.writes)
+            .filter(|writes| *writes > 0)
+            .map(|writes| self.start.elapsed(startup) / writes)
+    }
+
+    fn flow(&self, epoch: SystemTime, startup: Instant, reason: EndReason) -> Flow {
+        Flow {
+            peer: self.peer.into(),
+            local: self
+                .sock
+                .local_addr()
+                .unwrap_or_else(|_| ([0, 0, 0, 0], 0).into()),
+            start: epoch + Duration::from(self.start),
+            end: epoch + startup.elapsed(),
+            bytes: self.bytes,
+            reason,
+        }
+    }
+}
+
+fn parse_banner_file(path: &OsStr) -> Result<Banner, OsString> {
+    let text = std::fs::read(path).map_err(|e| OsString::from(e.to_string()))?;
+    Banner::verbatim(&text).map_err(OsString::from)
+}
+
+/// Load each file in a directory as a banner, as for --banner-file, in name
+/// order and skipping hidden files
+fn load_banner_dir(path: &Path) -> Result<Vec<(String, Banner)>, String> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(path).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || !entry.path().is_file() {
+            continue;
+        }
+        let text = std::fs::read(entry.path()).map_err(|e| format!("{}: {}", name, e))?;
+        let banner = Banner::verbatim(&text).map_err(|e| format!("{}: {}", name, e))?;
+        files.push((name, banner));
+    }
+    if files.is_empty() {
+        return Err("no banners found".to_string());
+    }
+    if files.len() > u8::MAX as usize {
+        return Err(format!("at most {} banners may be given", u8::MAX));
+    }
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(files)
+}
+
+/// The banner for a --banner preset, shaped by the options for it
+fn preset_banner(preset: Preset, opt: &Config, seed: u64) -> Banner {
+    let random = RandomLines {
+        min: opt.line_min,
+        max: opt.line_max,
+        charset: opt.charset,
+    };
+    match Banner::from(preset) {
+        Banner::Random(_) => Banner::Random(random),
+        Banner::Chatter(_) => Banner::Chatter(Chatter::new(&mut StdRng::seed_from_u64(seed))),
+        Banner::Template(_) => template_banner(opt).unwrap_or_else(|err| {
+            structopt::clap::Error::with_description(
+                &format!("--banner template: {}", err),
+                structopt::clap::ErrorKind::ValueValidation,
+            )
+            .exit()
+        }),
+        banner => banner,
+    }
+}
+
+/// The banner for --banner template, reading any --template-file
+fn template_banner(opt: &Config) -> Result<Banner, String> {
+    let random = RandomLines {
+        min: opt.line_min,
+        max: opt.line_max,
+        charset: opt.charset,
+    };
+    let template = Template::new(
+        opt.template_greeting.0.clone(),
+        opt.template_prefix.0.clone(),
+        opt.template_terminator.0.clone(),
+        opt.template_lines,
+        random,
+    )
+    .and_then(|template| match &opt.template_file {
+        Some(path) => std::fs::read(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+            .and_then(|text| template.file(&text)),
+        None => Ok(template),
+    })?;
+    Ok(Banner::Template(Arc::new(template)))
+}
+
+fn parse_banner_markov(path: &OsStr) -> Result<Banner, OsString> {
+    let text = std::fs::read(path).map_err(|e| OsString::from(e.to_string()))?;
+    let markov = Markov::new(&String::from_utf8_lossy(&text))?;
+    Ok(Banner::Markov(Arc::new(markov)))
 }
 
-async fn listen_socket(addr: SocketAddr) -> std::io::Result<TcpListenerStream> {
+/// Check an identification string is valid per RFC 4253 section 4.2
+fn parse_ssh_version(s: &str) -> Result<String, String> {
+    if !s.starts_with("SSH-2.0-") {
+        return Err("must start with SSH-2.0-".to_string());
+    }
+    if s.len() > 253 || s.contains(|c: char| !c.is_ascii_graphic() && c != ' ') {
+        return Err("must be at most 253 printable ASCII characters".to_string());
+    }
+    Ok(s.to_string())
+}
+
+async fn listen_socket(
+    addr: SocketAddr,
+    only_v6: Option<bool>,
+) -> std::io::Result<TcpListenerStream> {
     let sock = match addr {
         SocketAddr::V4(_) => TcpSocket::new_v4()?,
         SocketAddr::V6(_) => TcpSocket::new_v6()?,
     };
+    if let Some(only_v6) = only_v6 {
+        socket2::SockRef::from(&sock).set_only_v6(only_v6)?;
+    }
 
     sock.set_recv_buffer_size(1)
         .unwrap_or_else(|err| warn!("set_recv_buffer_size(), error: {}", err));
@@ -129,103 +1113,1173 @@ async fn listen_socket(addr: SocketAddr) -> std::io::Result<TcpListenerStream> {
     sock.listen(1024).map(TcpListenerStream::new)
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() {
-    let opt = Config::from_args();
+/// Listen on `port` for both IPv4 and IPv6: with one IPv6 socket that also
+/// accepts IPv4 clients where the platform allows, or else with one of each.
+async fn listen_dual(port: u16) -> std::io::Result<Vec<(SocketAddr, TcpListenerStream)>> {
+    let v6 = SocketAddr::from((Ipv6Addr::UNSPECIFIED, port));
+    match listen_socket(v6, Some(false)).await {
+        Ok(listener) => Ok(vec![(v6, listener)]),
+        Err(err)
+            if matches!(
+                err.kind(),
+                std::io::ErrorKind::AddrInUse | std::io::ErrorKind::PermissionDenied
+            ) =>
+        {
+            Err(err)
+        }
+        Err(err) => {
+            debug!("listen, addr: {}, dual: false, error: {}", v6, err);
+            let v4 = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+            Ok(vec![
+                (v4, listen_socket(v4, None).await?),
+                (v6, listen_socket(v6, Some(true)).await?),
+            ])
+        }
+    }
+}
+
+/// Take over a listening socket opened for us by a service manager
+#[cfg(unix)]
+fn inherit(sock: std::net::TcpListener) -> std::io::Result<(SocketAddr, TcpListenerStream)> {
+    let addr = sock.local_addr()?;
+    sock.set_nonblocking(true)?;
+    let listener = tokio::net::TcpListener::from_std(sock)?;
+    Ok((addr, TcpListenerStream::new(listener)))
+}
+
+/// Turn an IPv4-mapped IPv6 address, as seen on dual-stack sockets, back into
+/// plain IPv4 so each client has one identity in logs, limits and bans
+fn unmap(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(v4) => SocketAddr::from((v4, v6.port())),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+/// Parse the command line, filling in options it doesn't give from any
+/// `--config` file
+fn config() -> Config {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let matches = Config::clap().get_matches_from(&args);
+    let remote = Config::from_clap(&matches).config_url.map(|url| {
+        runtime()
+            .block_on(remote_config::fetch(&url))
+            .unwrap_or_else(|err| {
+                eprintln!("config, url: {}, error: {}", url, err);
+                std::process::exit(Fatal::Remote.code());
+            })
+    });
+    let text = remote.as_ref().map(|fetched| fetched.text.as_str());
+    let mut opt = with_config_file(&args, &matches, text).unwrap_or_else(|err| {
+        eprintln!("config, {}", err);
+        std::process::exit(Fatal::Config.code());
+    });
+    opt.config_sha256 = remote.map(|fetched| fetched.sha256);
+    opt
+}
+
+/// Parse the command line again and re-read any `--config` file, or use the
+/// `remote` text fetched from `--config-url`, for SIGHUP
+fn reload_config(remote: Option<&str>) -> Result<Config, String> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let matches = Config::clap()
+        .setting(structopt::clap::AppSettings::ColorNever)
+        .get_matches_from_safe(&args)
+        .map_err(clap_message)?;
+    let opt = with_config_file(&args, &matches, remote)?;
+    check_listen_pacing(&opt)?;
+    Ok(opt)
+}
+
+/// Check no listener's own pacing is quicker than the shared schedule, which
+/// sets how often connections are looked at
+fn check_listen_pacing(opt: &Config) -> Result<(), String> {
+    let delay = Duration::from_secs(u16::from(opt.delay) as u64);
+    let min_interval = opt.pacing.as_ref().map_or(delay, Schedule::min_interval);
+    match opt.listen.iter().find(|listen| {
+        listen
+            .pacing
+            .as_ref()
+            .is_some_and(|pacing| pacing.min_interval() < min_interval)
+    }) {
+        Some(listen) => Err(format!(
+            "--listen {}: intervals can't be shorter than --delay, or the shortest in --pacing",
+            listen.addr
+        )),
+        None => Ok(()),
+    }
+}
+
+fn with_config_file(
+    args: &[OsString],
+    matches: &structopt::clap::ArgMatches<'_>,
+    remote: Option<&str>,
+) -> Result<Config, String> {
+    let opt = from_matches(matches);
+    let given = |key: &str| matches.occurrences_of(key) > 0;
+    let (source, file) = match (&opt.config_url, &opt.config) {
+        (Some(url), _) => (
+            format!("url: {}", url),
+            config_file::parse(remote.unwrap_or_default(), given),
+        ),
+        (None, Some(path)) => (
+            format!("path: {}", path.display()),
+            config_file::args(path, given),
+        ),
+        (None, None) => return Ok(opt),
+    };
+
+    let fail = |err: String| format!("{}, error: {}", source, err);
+    let file = file.map_err(fail)?;
+
+    // Before the command line's own, so any subcommand stays last
+    let args = args[..1]
+        .iter()
+        .cloned()
+        .chain(file)
+        .chain(args[1..].iter().cloned());
+    Config::clap()
+        .setting(structopt::clap::AppSettings::ColorNever)
+        .get_matches_from_safe(args)
+        .map(|matches| from_matches(&matches))
+        .map_err(|err| fail(clap_message(err)))
+}
+
+/// The options in `matches`, noting where any banner file came from
+fn from_matches(matches: &structopt::clap::ArgMatches<'_>) -> Config {
+    let mut opt = Config::from_clap(matches);
+    opt.banner_path = matches
+        .value_of_os("banner-file")
+        .or_else(|| matches.value_of_os("banner-markov"))
+        .map(PathBuf::from);
+    opt
+}
+
+/// The first line of a clap error, without its usage or `error:` prefix
+fn clap_message(err: structopt::clap::Error) -> String {
+    let line = err.message.lines().next().unwrap_or_default();
+    line.trim_start_matches("error: ").to_string()
+}
+
+fn main() {
+    let opt = config();
 
-    let max_clients = u32::from(opt.max_clients) as usize;
+    #[cfg(windows)]
+    if opt.service {
+        service::dispatch();
+        return;
+    }
+
+    runtime().block_on(run(opt));
+}
+
+/// The single-threaded runtime the tarpit runs on
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap_or_else(|err| {
+            eprintln!("runtime, error: {}", err);
+            std::process::exit(exitcode::OSERR);
+        })
+}
+
+async fn run(mut opt: Config) {
+    match opt.command {
+        Some(Command::Report(config)) => {
+            report::run(config).unwrap_or_else(|err| {
+                eprintln!("report, error: {}", err);
+                std::process::exit(exitcode::IOERR);
+            });
+            return;
+        }
+        Some(Command::AbuseReport(config)) => {
+            abuse_report::run(config).unwrap_or_else(|err| {
+                eprintln!("abuse-report, error: {}", err);
+                std::process::exit(exitcode::IOERR);
+            });
+            return;
+        }
+        Some(Command::Replay(config)) => {
+            replay::run(config).await.unwrap_or_else(|err| {
+                eprintln!("replay, error: {}", err);
+                std::process::exit(exitcode::IOERR);
+            });
+            return;
+        }
+        Some(Command::Bench(config)) => {
+            bench::run(config).await.unwrap_or_else(|err| {
+                eprintln!("bench, error: {}", err);
+                std::process::exit(exitcode::OSERR);
+            });
+            return;
+        }
+        #[cfg(feature = "soak")]
+        Some(Command::Soak(config)) => {
+            let passed = soak::run(config).await.unwrap_or_else(|err| {
+                eprintln!("soak, error: {}", err);
+                std::process::exit(exitcode::OSERR);
+            });
+            if !passed {
+                std::process::exit(exitcode::SOFTWARE);
+            }
+            return;
+        }
+        #[cfg(windows)]
+        Some(Command::Service(command)) => {
+            service::manage(command).unwrap_or_else(|err| {
+                eprintln!("service, error: {}", err);
+                std::process::exit(exitcode::OSERR);
+            });
+            return;
+        }
+        None => (),
+    }
+
+    let mut max_clients = u32::from(opt.max_clients) as usize;
     let delay = Duration::from_secs(u16::from(opt.delay) as u64);
     let timeout = Duration::from_secs(opt.timeout as u64);
-    let log_level = match opt.verbose {
-        0 => LevelFilter::Off,
-        1 => LevelFilter::Info,
-        2 => LevelFilter::Debug,
-        _ => LevelFilter::Trace,
+    let capture_limit = opt.capture_limit;
+    if opt.line_min == 0 || opt.line_min > opt.line_max || opt.line_max > RANDOM_LINE_LIMIT {
+        structopt::clap::Error::with_description(
+            &format!(
+                "line lengths must satisfy 1 <= --line-min <= --line-max <= {}",
+                RANDOM_LINE_LIMIT
+            ),
+            structopt::clap::ErrorKind::ValueValidation,
+        )
+        .exit();
+    }
+
+    // Connections are only visited every --delay seconds
+    if opt
+        .tune_delays
+        .as_ref()
+        .is_some_and(|delays| delays.min() < delay)
+    {
+        structopt::clap::Error::with_description(
+            "--tune-delays can't be shorter than --delay",
+            structopt::clap::ErrorKind::ValueValidation,
+        )
+        .exit();
+    }
+
+    if let Err(err) = check_listen_pacing(&opt) {
+        structopt::clap::Error::with_description(&err, structopt::clap::ErrorKind::ValueValidation)
+            .exit();
+    }
+
+    if opt
+        .country_alert
+        .is_some_and(|points| !(points > 0.0 && points <= 100.0))
+    {
+        structopt::clap::Error::with_description(
+            "--country-alert must be more than 0 and at most 100 percentage points",
+            structopt::clap::ErrorKind::ValueValidation,
+        )
+        .exit();
+    }
+
+    if opt.ipv4_prefix > 32 || opt.ipv6_prefix > 128 {
+        structopt::clap::Error::with_description(
+            "prefixes must satisfy --ipv4-prefix <= 32 and --ipv6-prefix <= 128",
+            structopt::clap::ErrorKind::ValueValidation,
+        )
+        .exit();
+    }
+
+    if opt
+        .terminate_percent
+        .is_some_and(|p| !(0.0..=100.0).contains(&p))
+        || opt.terminate_within < Duration::from_secs(1)
+        || opt.terminate_within.as_secs() > u16::MAX as u64
+    {
+        structopt::clap::Error::with_description(
+            &format!(
+                "--terminate-percent must be 0 to 100 and --terminate-within 1s to {}s",
+                u16::MAX
+            ),
+            structopt::clap::ErrorKind::ValueValidation,
+        )
+        .exit();
+    }
+
+    // Taken now so they count as listeners, but only reported once logging is
+    // up
+    #[cfg(target_os = "linux")]
+    let inherited = systemd::listen_fds();
+    #[cfg(target_os = "linux")]
+    let socket_activated = inherited.as_ref().map_or(true, |fds| !fds.is_empty());
+    #[cfg(not(target_os = "linux"))]
+    let socket_activated = false;
+    #[cfg(unix)]
+    let inetd = Some(opt.inetd)
+        .filter(|inetd| *inetd)
+        .map(|_| inetd::take());
+    #[cfg(unix)]
+    let socket_activated = socket_activated || inetd.is_some();
+
+    if opt.listen.is_empty() && opt.listen_dual.is_empty() && !socket_activated {
+        opt.listen
+            .push(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 2222)).into());
+    }
+    // Junk would make SSH clients bail immediately, so it's kept off listeners
+    // labelled as being for them, whatever their port
+    let ssh_labels = Labels::new(opt.listener_label.clone());
+    let junk_listener = opt
+        .listen
+        .iter()
+        .filter(|listen| matches!(listen.banner.unwrap_or(opt.banner), Preset::Junk))
+        .map(|listen| listen.addr)
+        .chain(
+            opt.listen_dual
+                .iter()
+                .filter(|_| matches!(opt.banner, Preset::Junk))
+                .map(|port| SocketAddr::from((Ipv6Addr::UNSPECIFIED, *port))),
+        )
+        .find(|addr| ssh_labels.is_ssh(*addr));
+    if let Some(addr) = junk_listener {
+        structopt::clap::Error::with_description(
+            &format!("junk can't be used on SSH listener {}", addr),
+            structopt::clap::ErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
+    if matches!(opt.banner, Preset::Junk) && opt.ssh_version.is_some() {
+        structopt::clap::Error::with_description(
+            "--banner junk can't be combined with --ssh-version",
+            structopt::clap::ErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
+
+    // Chosen now so the chatter's host is as reproducible as everything else
+    let seed = opt.seed.unwrap_or_else(rand::random);
+
+    let mut banner_files = opt.banner_dir.as_deref().map_or_else(Vec::new, |path| {
+        load_banner_dir(path).unwrap_or_else(|err| {
+            structopt::clap::Error::with_description(
+                &format!("--banner-dir {}: {}", path.display(), err),
+                structopt::clap::ErrorKind::ValueValidation,
+            )
+            .exit()
+        })
+    });
+
+    // What the banner speaks, for per-protocol hold times
+    let protocol = if opt.banner_file.is_some() {
+        "file"
+    } else if opt.banner_markov.is_some() {
+        "markov"
+    } else if opt.banner_dir.is_some() {
+        "dir"
+    } else if opt.banner_url.is_some() {
+        "url"
+    } else {
+        opt.banner.name()
+    };
+    let parse_banner: fn(&OsStr) -> Result<Banner, OsString> = match opt.banner_markov {
+        Some(_) => parse_banner_markov,
+        None => parse_banner_file,
     };
+    let file = opt.banner_file.take().or_else(|| opt.banner_markov.take());
+    let banner = file.unwrap_or_else(|| preset_banner(opt.banner, &opt, seed));
+    if let (Banner::Chatter(chatter), None) = (&banner, &opt.ssh_version) {
+        // Eventually claim to be the OpenSSH the host would really run
+        opt.ssh_version = Some(chatter.version().to_string());
+    }
 
-    env_logger::Builder::from_default_env()
-        .filter(None, log_level)
-        .format_timestamp(if opt.disable_log_timestamps {
-            None
-        } else {
-            Some(env_logger::fmt::TimestampPrecision::Millis)
+    let own: Vec<_> = opt.listen.iter().filter(|listen| listen.is_own()).collect();
+    if own.len() > u8::MAX as usize {
+        structopt::clap::Error::with_description(
+            &format!("at most {} listeners may have their own settings", u8::MAX),
+            structopt::clap::ErrorKind::ValueValidation,
+        )
+        .exit();
+    }
+    // Indexed as connections' `listener`
+    let protocols: Vec<&str> = std::iter::once(protocol)
+        .chain(
+            own.iter()
+                .map(|listen| listen.banner.map_or(protocol, Preset::name)),
+        )
+        .collect();
+    let listener_pacing = own
+        .into_iter()
+        .map(|listen| ListenerPacing {
+            addr: listen.addr,
+            banner: listen
+                .banner
+                .map(|preset| preset_banner(preset, &opt, seed)),
+            schedule: listen.pacing.clone(),
         })
-        .format_module_path(!opt.disable_log_ident)
-        .format_level(!opt.disable_log_level)
-        .init();
+        .collect();
+
+    let mut pacing = Pacing {
+        banner,
+        schedule: opt.pacing.take().unwrap_or_else(|| Schedule::fixed(delay)),
+        timeouts: Timeouts {
+            write: timeout,
+            initial_data: opt
+                .initial_data_timeout
+                .map(|secs| Duration::from_secs(secs as u64)),
+            lifetime: opt
+                .max_lifetime
+                .map(|secs| Duration::from_secs(secs as u64)),
+            relaxed: opt.relaxed_timeout.map(|secs| {
+                let after = Duration::from_secs(opt.relax_after as u64);
+                (after, Duration::from_secs(secs as u64))
+            }),
+        },
+        ssh_version: opt.ssh_version.as_ref().map(|version| {
+            let after = Duration::from_secs(opt.ssh_version_after as u64);
+            (after, format!("{}\r\n", version).into_bytes())
+        }),
+        cork: opt.cork,
+        tuned: opt
+            .tune_delays
+            .as_ref()
+            .map_or_else(Vec::new, |delays| delays.as_slice().to_vec()),
+        banners: banner_files
+            .iter()
+            .map(|(_, banner)| banner.clone())
+            .collect(),
+        listeners: listener_pacing,
+        listed: opt.dnsbl_action,
+        listed_delay: Duration::from_secs(u16::from(opt.dnsbl_delay) as u64),
+    };
+
+    let log_level = level_filter(opt.verbose);
+    sanitize::set_limits(opt.log_field_max, opt.log_line_max);
+
+    #[cfg(windows)]
+    let use_event_log = opt.event_log;
+    #[cfg(not(windows))]
+    let use_event_log = false;
+
+    #[cfg(target_os = "linux")]
+    let use_journald = opt.log_journald;
+    #[cfg(not(target_os = "linux"))]
+    let use_journald = false;
+
+    // Standard error may be the client's connection under inetd
+    #[cfg(unix)]
+    let use_syslog = opt.log_syslog || (opt.inetd && !use_journald && inetd::stderr_is_socket());
+    #[cfg(not(unix))]
+    let use_syslog = false;
+
+    if use_event_log {
+        #[cfg(windows)]
+        eventlog::init(log_level)
+            .unwrap_or_else(|err| Fatal::Other.exit(format!("event-log, error: {}", err)));
+    } else if use_journald {
+        #[cfg(target_os = "linux")]
+        journald::init(log_level).unwrap_or_else(|err| {
+            eprintln!("journald, error: {}", err);
+            std::process::exit(Fatal::Io.code());
+        });
+    } else if use_syslog {
+        #[cfg(unix)]
+        syslog::init(opt.syslog_facility, log_level).unwrap_or_else(|err| {
+            eprintln!("syslog, error: {}", err);
+            std::process::exit(Fatal::Io.code());
+        });
+    } else {
+        // Filtered by the maximum level instead, so a reload can raise it
+        let logger = env_logger::Builder::from_default_env()
+            .filter(None, LevelFilter::Trace)
+            .format_timestamp(if opt.disable_log_timestamps {
+                None
+            } else {
+                Some(env_logger::fmt::TimestampPrecision::Millis)
+            })
+            .format_module_path(!opt.disable_log_ident)
+            .format_level(!opt.disable_log_level)
+            .build();
+        log::set_boxed_logger(Box::new(sanitize::Bounded(logger))).expect("logger already set");
+        log::set_max_level(log_level);
+    }
 
     info!(
         "init, pid: {}, version: {}",
         std::process::id(),
         env!("CARGO_PKG_VERSION")
     );
+    #[cfg(windows)]
+    if opt.service {
+        info!("service, name: {}, state: running", service::SERVICE_NAME);
+    }
+
+    // Before any other threads start, so they inherit it
+    if opt.cork && !cork::SUPPORTED {
+        Fatal::Config.exit("cork, error: not supported on this platform");
+    }
+
+    if let Some(cpus) = &opt.cpu_affinity {
+        affinity::pin(cpus).unwrap_or_else(|err| {
+            Fatal::Other.exit(format!("affinity, cpus: {}, error: {}", cpus, err))
+        });
+        info!("affinity, cpus: {}", cpus);
+    }
+
+    #[cfg(feature = "console")]
+    {
+        console::init().unwrap_or_else(|err| Fatal::Other.exit(format!("console, error: {}", err)));
+        info!("console, enabled: true");
+    }
+
+    let banner_source = opt.banner_url.take().map(|url| BannerSource {
+        url,
+        cache: opt.banner_cache.take(),
+    });
+    if let Some(source) = &banner_source {
+        pacing.banner = source.load().await.unwrap_or_else(|err| {
+            Fatal::Remote.exit(format!("banner, url: {}, error: {}", source.url, err))
+        });
+    }
+
+    let mut startup = Instant::now();
+    let mut epoch = SystemTime::now();
+
+    let labels = Arc::new(Labels::new(std::mem::take(&mut opt.listener_label)));
+    // Each listener's own settings as a rule, by its label or else its port
+    let listener_rules: Vec<String> = pacing
+        .listeners
+        .iter()
+        .map(|own| {
+            let port = own.addr.port().to_string();
+            holds::rule("listener", labels.get(own.addr).unwrap_or(&port))
+        })
+        .collect();
+
+    #[cfg(target_os = "macos")]
+    let activated = opt.launchd_socket.as_deref().map(|name| {
+        let sockets = launchd::activate(name).unwrap_or_else(|err| {
+            Fatal::Bind.exit(format!("launchd, socket: {}, error: {}", name, err))
+        });
+        sockets
+            .into_iter()
+            .map(|sock| {
+                let (addr, listener) = inherit(sock)?;
+                info!(
+                    "listen, addr: {}, launchd: {}{}",
+                    addr,
+                    name,
+                    labels.suffix(addr)
+                );
+                Ok(listener)
+            })
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap_or_else(|err: std::io::Error| {
+                Fatal::Bind.exit(format!("launchd, socket: {}, error: {}", name, err))
+            })
+    });
+    #[cfg(not(target_os = "macos"))]
+    let activated: Option<Vec<TcpListenerStream>> = None;
+
+    let required = if opt.ignore_bind_errors {
+        Required::Any
+    } else {
+        opt.require_listeners
+    };
+    // Fatal unless told to carry on with whichever listeners did bind
+    let bind_error = |message: String| {
+        if required == Required::All {
+            Fatal::Bind.exit(message);
+        } else {
+            warn!("{}", message);
+        }
+    };
 
-    let startup = Instant::now();
+    let (broken_tx, broken_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (rebound_tx, mut rebound_rx) = tokio::sync::mpsc::unbounded_channel();
+    let bind_failures = Arc::new(AtomicU64::new(0));
+    let mut unbound = vec![];
+    let mut listeners: SelectAll<listeners::Listener> = SelectAll::new();
+    let mut listening = vec![];
+    #[cfg(target_os = "linux")]
+    for sock in inherited.unwrap_or_else(|err| Fatal::Bind.exit(format!("systemd, error: {}", err)))
+    {
+        let (addr, listener) = inherit(sock)
+            .unwrap_or_else(|err| Fatal::Bind.exit(format!("systemd, error: {}", err)));
+        info!(
+            "listen, addr: {}, systemd: true{}",
+            addr,
+            labels.suffix(addr)
+        );
+        listening.push(addr);
+        listeners.push(listeners::guard(
+            listener,
+            None,
+            broken_tx.clone(),
+            labels.clone(),
+        ));
+    }
+    // Whether to shut down once the client inetd passed us is done
+    let mut inetd_connection = false;
+    #[cfg(unix)]
+    match inetd {
+        Some(Ok(inetd::Inherited::Connection(sock))) => {
+            let sock = sock
+                .set_nonblocking(true)
+                .and_then(|()| sock.local_addr())
+                .and_then(|addr| Ok((addr, TcpStream::from_std(sock)?)));
+            let (addr, sock) =
+                sock.unwrap_or_else(|err| Fatal::Bind.exit(format!("inetd, error: {}", err)));
+            info!(
+                "listen, addr: {}, inetd: connection{}",
+                addr,
+                labels.suffix(addr)
+            );
+            listening.push(addr);
+            listeners.push(Box::pin(stream::once(async { Ok(sock) })));
+            inetd_connection = true;
+        }
+        Some(Ok(inetd::Inherited::Listener(sock))) => {
+            let (addr, listener) = inherit(sock)
+                .unwrap_or_else(|err| Fatal::Bind.exit(format!("inetd, error: {}", err)));
+            info!(
+                "listen, addr: {}, inetd: listener{}",
+                addr,
+                labels.suffix(addr)
+            );
+            listening.push(addr);
+            listeners.push(listeners::guard(
+                listener,
+                None,
+                broken_tx.clone(),
+                labels.clone(),
+            ));
+        }
+        Some(Err(err)) => Fatal::Bind.exit(format!("inetd, error: {}", err)),
+        None => (),
+    }
+    match activated {
+        Some(sockets) => {
+            for listener in sockets {
+                listening.extend(listener.as_ref().local_addr().ok());
+                listeners.push(listeners::guard(
+                    listener,
+                    None,
+                    broken_tx.clone(),
+                    labels.clone(),
+                ));
+            }
+        }
+        None => {
+            let wanted: Vec<Bind> = opt
+                .listen
+                .iter()
+                .map(|listen| Bind::Addr(listen.addr))
+                .chain(opt.listen_dual.iter().map(|port| Bind::Dual(*port)))
+                .collect();
+            for bind in &wanted {
+                match bind.bind().await {
+                    Ok(sockets) => {
+                        for (bind, listener) in sockets {
+                            info!("listen, {}{}", bind, labels.suffix(bind.addr()));
+                            listening.extend(listener.as_ref().local_addr().ok());
+                            listeners.push(listeners::guard(
+                                listener,
+                                Some(bind),
+                                broken_tx.clone(),
+                                labels.clone(),
+                            ));
+                        }
+                    }
+                    Err(err) => {
+                        bind_failures.fetch_add(1, Ordering::Relaxed);
+                        bind_error(format!(
+                            "listen, {}{}, error: {}",
+                            bind,
+                            labels.suffix(bind.addr()),
+                            err
+                        ));
+                        unbound.push(*bind);
+                    }
+                }
+            }
+
+            let bound = wanted.len() - unbound.len();
+            if !required.met(bound, wanted.len()) {
+                Fatal::Bind.exit(format!(
+                    "listen, bound: {}, wanted: {}, required: {}",
+                    bound,
+                    wanted.len(),
+                    required
+                ));
+            }
+        }
+    }
+
+    let mut udp_sockets = vec![];
+    for addr in &opt.udp_listen {
+        match udp::bind(*addr).await {
+            Ok(sock) => {
+                info!("listen, udp: {}", addr);
+                udp_sockets.push(sock);
+            }
+            Err(err) => bind_error(format!("listen, udp: {}, error: {}", addr, err)),
+        }
+    }
+
+    let mut dns_sockets = vec![];
+    for addr in &opt.dns_listen {
+        match dns::bind(*addr).await {
+            Ok(sock) => {
+                info!("listen, dns: {}", addr);
+                dns_sockets.push(sock);
+            }
+            Err(err) => bind_error(format!("listen, dns: {}, error: {}", addr, err)),
+        }
+    }
+
+    if listeners.is_empty() {
+        Fatal::Bind.exit("listen, error: no listeners");
+    }
+
+    let mut health = vec![];
+    let checks = [
+        (opt.health_listen, health::Protocol::Http),
+        (opt.agent_listen, health::Protocol::Agent),
+    ];
+    for (addr, protocol) in checks
+        .iter()
+        .filter_map(|&(addr, protocol)| Some((addr?, protocol)))
+    {
+        let name = match protocol {
+            health::Protocol::Http => "health",
+            health::Protocol::Agent => "agent",
+        };
+        let listener = health::bind(addr).await.unwrap_or_else(|err| {
+            Fatal::Bind.exit(format!("{}, listen: {}, error: {}", name, addr, err))
+        });
+        info!("{}, listen: {}", name, addr);
+        health.push((listener, protocol));
+    }
+
+    let (mut gossip, mut gossip_rx) = match opt.gossip_listen {
+        Some(addr) => {
+            let peers = std::mem::take(&mut opt.gossip_peer);
+            let count = peers.len();
+            let (gossip, rx) = Gossip::bind(addr, peers, opt.gossip_ttl)
+                .await
+                .unwrap_or_else(|err| {
+                    Fatal::Bind.exit(format!("gossip, listen: {}, error: {}", addr, err))
+                });
+            info!("gossip, listen: {}, peers: {}", addr, count);
+            (Some(gossip), rx)
+        }
+        None => (None, tokio::sync::mpsc::unbounded_channel().1),
+    };
+
+    let retrying = unbound.len();
+    if retrying > 0 {
+        info!("listen, retrying: {}", unbound.len());
+    }
+    for bind in unbound {
+        let _ = broken_tx.send(bind);
+    }
+    tokio::spawn(listeners::rebind(
+        broken_rx,
+        rebound_tx,
+        broken_tx.clone(),
+        bind_failures.clone(),
+        labels.clone(),
+    ));
+
+    let open_spool = |kind: &str, path: &PathBuf| {
+        Spool::open(path).unwrap_or_else(|err| {
+            Fatal::Io.exit(format!(
+                "{}, path: {}, error: {}",
+                kind,
+                path.display(),
+                err
+            ))
+        })
+    };
+    let transcripts = opt
+        .transcript_dir
+        .as_ref()
+        .map(|path| open_spool("transcript", path));
+    let captures = opt
+        .capture_dir
+        .as_ref()
+        .map(|path| open_spool("capture", path));
+    let retention = Retention {
+        max_files: opt.spool_max_files,
+        max_age: opt.spool_max_age,
+        max_bytes: opt.spool_max_size,
+    };
+    let pruned: Vec<&Spool> = if retention.is_empty() {
+        vec![]
+    } else {
+        transcripts.iter().chain(captures.iter()).collect()
+    };
+    let compressor = if opt.spool_compress && (transcripts.is_some() || captures.is_some()) {
+        Some(
+            Compressor::start()
+                .unwrap_or_else(|err| Fatal::Other.exit(format!("compress, error: {}", err))),
+        )
+    } else {
+        None
+    };
+    let canaries: Option<Rc<[Canary]>> = Some(std::mem::take(&mut opt.canary))
+        .filter(|canaries| !canaries.is_empty())
+        .map(Rc::from);
+    let versions = opt
+        .client_versions
+        .then(|| Rc::new(RefCell::new(VersionTable::default())));
+    let mut port_patterns = opt.port_patterns.then(PortPatterns::default);
+
+    let mut ipfix = opt.ipfix.map(|collector| {
+        IpfixExporter::connect(collector, opt.ipfix_domain).unwrap_or_else(|err| {
+            Fatal::Remote.exit(format!("ipfix, collector: {}, error: {}", collector, err))
+        })
+    });
+
+    let pusher = opt.push_gateway.take().map(|gateway| {
+        let url = gateway.to_string();
+        Pusher::resolve(gateway)
+            .unwrap_or_else(|err| Fatal::Remote.exit(format!("push, url: {}, error: {}", url, err)))
+    });
+
+    let influx = opt.influx.as_ref().map(|target| {
+        InfluxSink::connect(target).unwrap_or_else(|err| {
+            Fatal::Remote.exit(format!("influx, target: {}, error: {}", target, err))
+        })
+    });
+
+    let mut statsd = opt.statsd.as_ref().map(|addr| {
+        StatsdSink::connect(addr, opt.statsd_prefix.clone()).unwrap_or_else(|err| {
+            Fatal::Remote.exit(format!("statsd, target: {}, error: {}", addr, err))
+        })
+    });
+
+    let textfile = opt.textfile.take().map(|path| {
+        let display = path.display().to_string();
+        TextfileSink::create(path).unwrap_or_else(|err| {
+            Fatal::Io.exit(format!("textfile, path: {}, error: {}", display, err))
+        })
+    });
+
+    let snmp = opt.agentx.take().map(|target| {
+        let counters = Arc::new(Counters::default());
+        agentx::spawn(target, opt.agentx_oid.clone(), counters.clone(), startup);
+        counters
+    });
+
+    #[cfg(feature = "packet_capture")]
+    let packets = opt.pcap.dir.as_ref().map(|path| {
+        let ports = opt
+            .listen
+            .iter()
+            .map(|listen| listen.addr.port())
+            .chain(opt.listen_dual.iter().copied())
+            .collect();
+        let spool = open_spool("pcap", path);
+        match PacketCapture::start(&opt.pcap.interface, ports, spool, opt.pcap.rotate_size) {
+            Ok(packets) => {
+                info!(
+                    "pcap, interface: {}, path: {}",
+                    opt.pcap.interface,
+                    path.display()
+                );
+                packets
+            }
+            Err(err) => Fatal::Other.exit(format!(
+                "pcap, interface: {}, error: {}",
+                opt.pcap.interface, err
+            )),
+        }
+    });
+
+    let mut tuner = opt.tune_delays.take().map(Tuner::new);
+
+    let mut reputation = opt.reputation_file.as_ref().map(|path| {
+        let store = Reputation::open(path, opt.reputation_ttl).unwrap_or_else(|err| {
+            Fatal::Io.exit(format!(
+                "reputation, path: {}, error: {}",
+                path.display(),
+                err
+            ))
+        });
+        info!(
+            "reputation, path: {}, sources: {}",
+            path.display(),
+            store.len()
+        );
+        store
+    });
+
+    let mut bans = match &opt.ban_file {
+        Some(path) => {
+            let bans = BanList::open(path).unwrap_or_else(|err| {
+                Fatal::Io.exit(format!("bans, path: {}, error: {}", path.display(), err))
+            });
+            info!("bans, path: {}, bans: {}", path.display(), bans.len());
+            bans
+        }
+        None => BanList::default(),
+    };
+
+    let mut geoip = (opt.geoip_db.is_some() || opt.asn_db.is_some()).then(|| {
+        let mut db = GeoIp::default();
+        let opened = |path: &Path, result: std::io::Result<()>| {
+            if let Err(err) = result {
+                Fatal::Io.exit(format!("geoip, path: {}, error: {}", path.display(), err));
+            }
+            info!("geoip, path: {}", path.display());
+        };
+        if let Some(path) = &opt.geoip_db {
+            opened(path, db.open_city(path));
+        }
+        if let Some(path) = &opt.asn_db {
+            opened(path, db.open_asn(path));
+        }
+        db
+    });
+
+    let enrich_limits = Limits {
+        workers: opt.enrich_workers.get(),
+        queue: opt.enrich_queue.get(),
+        overflow: opt.enrich_overflow,
+    };
+    let (mut dnsbl, mut dnsbl_rx) = match opt.dnsbl.len() {
+        0 => (None, tokio::sync::mpsc::unbounded_channel().1),
+        _ => {
+            info!(
+                "dnsbl, zones: {}, action: {}",
+                opt.dnsbl.join(" "),
+                opt.dnsbl_action
+            );
+            let zones = std::mem::take(&mut opt.dnsbl);
+            let (dnsbl, rx) = Dnsbl::new(zones, opt.dnsbl_timeout, opt.dnsbl_ttl, enrich_limits);
+            (Some(dnsbl), rx)
+        }
+    };
+
+    let (mut rdap, mut rdap_rx) = match opt.rdap.take() {
+        None => (None, tokio::sync::mpsc::unbounded_channel().1),
+        Some(url) => {
+            info!("rdap, url: {}, rate: {}", url, opt.rdap_rate);
+            let (rdap, rx) = Rdap::new(url, opt.rdap_rate.get(), opt.rdap_ttl, enrich_limits);
+            (Some(rdap), rx)
+        }
+    };
+
+    let mut abuseipdb = opt.abuseipdb.take().map(|url| {
+        info!(
+            "abuseipdb, url: {}, interval: {}",
+            url,
+            humantime::format_duration(opt.abuseipdb_interval)
+        );
+        AbuseIpDb::new(
+            url,
+            opt.abuseipdb_key.take().unwrap_or_default(),
+            opt.abuseipdb_categories.clone(),
+            opt.abuseipdb_min_held,
+        )
+    });
+
+    let proxy_map = opt.proxy_map.take().map(|path| {
+        info!("proxy-map, path: {}", path.display());
+        ProxyMap::new(path, opt.proxy_map_format)
+    });
+
+    let (control_tx, mut control_rx) = tokio::sync::mpsc::unbounded_channel::<control::Request>();
+    #[cfg(unix)]
+    if let Some(path) = &opt.control_socket {
+        control::spawn(path, control_tx).unwrap_or_else(|err| {
+            Fatal::Bind.exit(format!("control, path: {}, error: {}", path.display(), err))
+        });
+        info!("control, path: {}", path.display());
+    }
+    #[cfg(not(unix))]
+    drop(control_tx);
 
-    let mut listeners = stream::iter(opt.listen.iter())
-        .then(|addr| async move {
-            match listen_socket(*addr).await {
-                Ok(listener) => {
-                    info!("listen, addr: {}", addr);
-                    listener
-                }
-                Err(err) => {
-                    errx(
-                        exitcode::OSERR,
-                        format!("listen, addr: {}, error: {}", addr, err),
-                    );
-                }
-            }
+    let mut ban_log = opt.ban_log.as_ref().map(|path| {
+        BanLog::open(path).unwrap_or_else(|err| {
+            Fatal::Io.exit(format!("ban_log, path: {}, error: {}", path.display(), err))
         })
-        .collect::<SelectAll<_>>()
-        .await;
+    });
+    let mut notifier = match &opt.alert_file {
+        Some(path) => Notifier::with_file(path).unwrap_or_else(|err| {
+            Fatal::Io.exit(format!("alert, path: {}, error: {}", path.display(), err))
+        }),
+        None => Notifier::default(),
+    };
 
     #[cfg(all(unix, feature = "drop_privs"))]
-    {
+    let privdropped = {
         if opt.privdrop.user.is_some()
             || opt.privdrop.group.is_some()
             || opt.privdrop.chroot.is_some()
         {
             let mut pd = PrivDrop::default();
-            if let Some(path) = opt.privdrop.chroot {
+            if let Some(path) = opt.privdrop.chroot.take() {
                 info!("privdrop, chroot: {}", path.display());
                 pd = pd.chroot(path);
             }
 
-            if let Some(user) = opt.privdrop.user {
+            if let Some(user) = opt.privdrop.user.take() {
                 info!("privdrop, user: {}", user.to_string_lossy());
                 pd = pd.user(user);
             }
 
-            if let Some(group) = opt.privdrop.group {
+            if let Some(group) = opt.privdrop.group.take() {
                 info!("privdrop, group: {}", group.to_string_lossy());
                 pd = pd.group(group);
             }
 
             pd.apply()
-                .unwrap_or_else(|err| errx(exitcode::OSERR, format!("privdrop, error: {}", err)));
+                .unwrap_or_else(|err| Fatal::Privdrop.exit(format!("privdrop, error: {}", err)));
 
             info!("privdrop, enabled: true");
+            true
         } else {
             info!("privdrop, enabled: false");
+            false
         }
-    }
+    };
+    #[cfg(not(all(unix, feature = "drop_privs")))]
+    let privdropped = false;
 
     #[cfg(all(unix, feature = "sandbox"))]
-    {
-        let sandboxed = Sandbox::new().sandbox_this_process().is_ok();
-        info!("sandbox, enabled: {}", sandboxed);
+    let sandboxed = {
+        let mut sandbox = Sandbox::new();
+        if let Some(spool) = &transcripts {
+            sandbox.add_directory("transcripts", spool.path());
+        }
+        if let Some(spool) = &captures {
+            sandbox.add_directory("captures", spool.path());
+        }
+        #[cfg(feature = "packet_capture")]
+        if let Some(path) = &opt.pcap.dir {
+            sandbox.add_directory("pcap", path);
+        }
+        // Capability mode forbids sendto() with an address, which the UDP
+        // responder needs to reply to arbitrary peers, and connect(), which
+        // pushing metrics needs
+        let disabled = if !udp_sockets.is_empty() {
+            Some("udp")
+        } else if !dns_sockets.is_empty() {
+            Some("dns")
+        } else if gossip.is_some() {
+            Some("gossip")
+        } else if pusher.is_some() {
+            Some("push")
+        } else if abuseipdb.is_some() {
+            Some("abuseipdb")
+        } else if snmp.is_some() {
+            Some("agentx")
+        } else if banner_source.is_some() && opt.banner_refresh.is_some() {
+            Some("banner-refresh")
+        } else if opt.config_url.is_some() {
+            Some("config-url")
+        } else if opt.watch_files {
+            Some("watch-files")
+        } else if !pruned.is_empty() {
+            Some("retention")
+        } else if reputation.is_some() {
+            Some("reputation")
+        } else if textfile.is_some() {
+            Some("textfile")
+        } else if compressor.is_some() {
+            Some("compress")
+        } else if opt.ban_file.is_some() {
+            Some("ban-file")
+        } else if proxy_map.is_some() {
+            Some("proxy-map")
+        } else if opt.profile.is_some() {
+            Some("profile")
+        } else if dnsbl.is_some() {
+            Some("dnsbl")
+        } else if rdap.is_some() {
+            Some("rdap")
+        } else if retrying > 0 {
+            Some("listener-retry")
+        } else if cfg!(feature = "console") {
+            Some("console")
+        } else {
+            None
+        };
+        match disabled {
+            Some(reason) if opt.require_sandbox => {
+                Fatal::Sandbox.exit(format!("sandbox, error: unavailable with {}", reason))
+            }
+            Some(reason) => {
+                info!("sandbox, enabled: false, reason: {}", reason);
+                false
+            }
+            None => {
+                let sandboxed = sandbox.sandbox_this_process().is_ok();
+                if !sandboxed && opt.require_sandbox {
+                    Fatal::Sandbox.exit("sandbox, error: failed to enter sandbox");
+                }
+                info!("sandbox, enabled: {}", sandboxed);
+                sandboxed
+            }
+        }
+    };
+    #[cfg(not(all(unix, feature = "sandbox")))]
+    let sandboxed = false;
+
+    #[cfg(not(all(unix, feature = "sandbox")))]
+    if opt.require_sandbox {
+        Fatal::Sandbox.exit("sandbox, error: not supported by this build");
     }
 
     info!(
         "start, servers: {}, max_clients: {}, delay: {}s, timeout: {}s",
         listeners.len(),
         opt.max_clients,
-        delay.as_secs(),
+        pacing.schedule.min_interval().as_secs(),
         timeout.as_secs()
     );
+    info!(
+        "capabilities, {}",
+        Capabilities::new(
+            sandboxed,
+            privdropped,
+            listening
+                .into_iter()
+                .map(|addr| labels
+                    .get(addr)
+                    .map_or_else(|| addr.to_string(), str::to_string))
+                .collect(),
+            retrying
+        )
+    );
+
+    #[cfg(feature = "console")]
+    if let Some(secs) = opt.runtime_stats {
+        let period = Duration::from_secs(u32::from(secs) as u64);
+        tokio::spawn(console::log_runtime_stats(period));
+    }
+
+    let udp_delay = Duration::from_secs(opt.udp_delay as u64);
+    for sock in udp_sockets {
+        tokio::spawn(udp::serve(sock, udp_delay));
+    }
+    let sinkhole = dns::Sinkhole::new(&opt.dns_sinkhole);
+    for sock in dns_sockets {
+        tokio::spawn(dns::serve(sock, sinkhole));
+    }
+    let heartbeat = Arc::new(Heartbeat::default());
+    let stale = Duration::from_secs(u16::from(opt.health_stale) as u64);
+    for (listener, protocol) in health {
+        tokio::spawn(health::serve(listener, protocol, heartbeat.clone(), stale));
+    }
+
+    // Log the seed even if it's random, so interesting runs can be repeated
+    let mut rng = StdRng::seed_from_u64(seed);
+    info!("rng, seed: {}", seed);
 
-    let max_tick = delay.as_secs() as usize;
+    let max_tick = pacing.schedule.min_interval().as_secs() as usize;
     let mut last_tick = 0;
     let mut num_clients = 0;
     let mut total_clients: u64 = 0;
@@ -235,16 +2289,92 @@ async fn main() {
         .take(max_tick)
         .collect::<Vec<Vec<_>>>()
         .into_boxed_slice();
+    let mut budget = opt
+        .memory_budget
+        .map(|limit| MemoryBudget::new(limit, max_tick));
 
-    let timer = IntervalStream::new(tokio::time::interval(Duration::from_secs(1)));
-    let mut ticker = stream::iter(0..max_tick).cycle().zip(timer);
-    let mut signals = signal_stream();
+    let mut ticker = tick_stream(max_tick, 0, opt.missed_tick);
+    let mut late_since = tokio::time::Instant::now();
+    let mut clock = Clock::new(opt.clock_jump, startup, epoch);
+    let mut deferred = Deferred::default();
+    let log_after = opt.log_after;
+    // For shutting down as if signalled, once an inetd connection's over
+    let (closed_tx, closed_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut signals = stream::select(signal_stream(), UnboundedReceiverStream::new(closed_rx));
+    let push_interval = Duration::from_secs(u16::from(opt.push_interval) as u64);
+    let mut push_timer = IntervalStream::new(tokio::time::interval(push_interval));
+    let mut prune_timer = IntervalStream::new(tokio::time::interval(PRUNE_INTERVAL));
+    let mut limits = if opt.max_per_source.is_some() || opt.source_rate.is_some() {
+        let prefixes = Prefixes {
+            v4: opt.ipv4_prefix,
+            v6: opt.ipv6_prefix,
+        };
+        Some(SourceLimits::new(
+            prefixes,
+            opt.max_per_source.map(u32::from),
+            opt.source_rate.map(u32::from),
+        ))
+    } else {
+        None
+    };
+    let mut churn = opt.churn_alert.map(ChurnWatch::new);
+    let mut countries = opt.country_alert.map(CountryWatch::new);
+    let terminate_within = opt.terminate_within.as_secs() as u16;
+    let mut early = opt
+        .terminate_percent
+        .map(|percent| EarlyTermination::new(percent, terminate_within));
+    let mut churn_timer = IntervalStream::new(tokio::time::interval_at(
+        tokio::time::Instant::now() + churn::WINDOW,
+        churn::WINDOW,
+    ));
+    let mut abuseipdb_timer = IntervalStream::new(tokio::time::interval_at(
+        tokio::time::Instant::now() + opt.abuseipdb_interval,
+        opt.abuseipdb_interval,
+    ));
+    let mut reputation_timer = IntervalStream::new(tokio::time::interval(REPUTATION_SAVE_INTERVAL));
+    let refresh_interval = Duration::from_secs(opt.banner_refresh.map_or(3600, u32::from) as u64);
+    let mut refresh_timer = IntervalStream::new(tokio::time::interval_at(
+        tokio::time::Instant::now() + refresh_interval,
+        refresh_interval,
+    ));
+    let (banner_tx, mut banner_rx) = tokio::sync::mpsc::unbounded_channel();
+    let config_interval = Duration::from_secs(opt.config_refresh.map_or(300, u32::from) as u64);
+    let mut config_timer = IntervalStream::new(tokio::time::interval_at(
+        tokio::time::Instant::now() + config_interval,
+        config_interval,
+    ));
+    let (reload_tx, mut reload_rx) = tokio::sync::mpsc::unbounded_channel();
+    let watch =
+        |path: Option<&PathBuf>| path.filter(|_| opt.watch_files).cloned().map(Watched::new);
+    let mut watched_banner = watch(opt.banner_path.as_ref());
+    let mut watched_dir = watch(opt.banner_dir.as_ref());
+    let mut watched_template = watch(opt.template_file.as_ref());
+    let mut watched_bans = watch(opt.ban_file.as_ref());
+    let mut watch_timer = IntervalStream::new(tokio::time::interval_at(
+        tokio::time::Instant::now() + watch::INTERVAL,
+        watch::INTERVAL,
+    ));
+    let mut proxy_map_timer = IntervalStream::new(tokio::time::interval(Duration::from_secs(
+        u32::from(opt.proxy_map_interval) as u64,
+    )));
+    let require_data = opt
+        .require_data
+        .map(|secs| Duration::from_secs(u16::from(secs) as u64));
+    let mut pending = FuturesUnordered::new();
+    let mut tagged: BTreeMap<&'static str, u64> = BTreeMap::new();
+    // Connections accepted by each labelled listener
+    let mut listened: BTreeMap<String, u64> = BTreeMap::new();
+    let mut protocol_holds = Holds::default();
+    let mut rule_holds = Holds::default();
+    let mut profiler = Profiler::new(opt.profile.is_some());
 
     loop {
+        let mut arrived = None;
+
         tokio::select! {
             Some(signal) = signals.next() => {
                 let action = match signal {
-                    "INFO" | "HUP" => "info",
+                    "INFO" | "HUP" | "BREAK" => "info",
                     _ => "shutdown",
                 };
                 info!(
@@ -257,68 +2387,610 @@ async fn main() {
                     total_clients,
                     bytes
                 );
+                let ages: Ages = slots
+                    .iter()
+                    .flatten()
+                    .map(|connection| connection.start.elapsed(startup))
+                    .collect();
+                info!("ages, {}", ages);
+                if !protocol_holds.is_empty() {
+                    info!("protocols, {}", protocol_holds);
+                }
+                if !rule_holds.is_empty() {
+                    info!("rules, {}", rule_holds);
+                }
+                if let Some(path) = opt.profile.as_ref().filter(|_| !profiler.is_empty()) {
+                    info!("profile, {}", profiler);
+                    if let Err(err) = std::fs::write(path, profiler.folded()) {
+                        warn!("profile, path: {}, error: {}", path.display(), err);
+                    }
+                    profiler.reset();
+                }
+                if let Some(table) = &versions {
+                    let top = table
+                        .borrow()
+                        .top(versions::TOP)
+                        .iter()
+                        .map(|(version, count)| format!("{}={}", version, count))
+                        .collect::<Vec<_>>();
+                    info!("versions, top: {}", top.join(" "));
+                }
+                if let Some(store) = &reputation {
+                    let top = store
+                        .top(5)
+                        .iter()
+                        .map(|(ip, record)| match record.interval_mean() {
+                            Some(mean) => format!(
+                                "{}={:.0?}/{:.0?}/{:.0?}",
+                                ip, record.held, record.interval_min, mean
+                            ),
+                            None => format!("{}={:.0?}", ip, record.held),
+                        })
+                        .collect::<Vec<_>>();
+                    info!("reputation, sources: {}, top: {}", store.len(), top.join(" "));
+                }
+                if let Some(db) = geoip.as_ref().filter(|db| db.has_city()) {
+                    let top = db
+                        .top_countries(5)
+                        .iter()
+                        .map(|(country, count)| format!("{}={}", country, count))
+                        .collect::<Vec<_>>();
+                    info!("geoip, countries: {}, top: {}", db.countries(), top.join(" "));
+                }
+                if let Some(db) = geoip.as_ref().filter(|db| db.has_asn()) {
+                    let top = db
+                        .top_networks(5)
+                        .iter()
+                        .map(|(asn, organisation, count)| match organisation {
+                            Some(organisation) => {
+                                format!("AS{} {}={}", asn, Field(organisation.as_bytes()), count)
+                            }
+                            None => format!("AS{}={}", asn, count),
+                        })
+                        .collect::<Vec<_>>();
+                    info!("asn, networks: {}, top: {}", db.networks(), top.join(" "));
+                }
+                if let Some(dnsbl) = &dnsbl {
+                    info!("dnsbl, {}", dnsbl);
+                }
+                if let Some(rdap) = &rdap {
+                    info!("rdap, {}", rdap);
+                }
+                if let Some(abuse) = &abuseipdb {
+                    info!("abuseipdb, {}", abuse);
+                }
+                if let Some(patterns) = &port_patterns {
+                    info!("ports, {}", patterns);
+                }
+                if let Some(tuner) = &tuner {
+                    info!("tune, {}", tuner);
+                }
+                if let Some(early) = &early {
+                    info!("terminate, {}", early);
+                }
+                if signal == "HUP" {
+                    match opt.config_url.clone() {
+                        Some(url) => fetch_config(url, None, reload_tx.clone()),
+                        None => {
+                            let _ = reload_tx.send(None);
+                        }
+                    }
+                }
                 if action != "info" {
+                    for line in deferred.flush() {
+                        info!("{}", line);
+                    }
+                    if let Some(store) = reputation.as_mut() {
+                        let now = SystemTime::now();
+                        for connection in slots.iter().flatten() {
+                            let ip = SocketAddr::from(connection.peer).ip();
+                            store.disconnected(ip, connection.start.elapsed(startup), EndReason::ForcedEnd, now);
+                        }
+                        if let Err(err) = store.save() {
+                            warn!("reputation, error: {}", err);
+                        }
+                    }
+                    if let Some(ipfix) = ipfix.as_mut() {
+                        for connection in slots.iter().flatten() {
+                            let _ = ipfix.record(&connection.flow(epoch, startup, EndReason::ForcedEnd));
+                        }
+                        let _ = ipfix.flush();
+                    }
                     break;
                 }
             }
-            Some((tick, _)) = ticker.next() => {
+            Some((tick, deadline)) = ticker.next() => {
+                if let Some(jump) = clock.check(Instant::now(), SystemTime::now()) {
+                    // Time lost to a pause shouldn't count towards connections'
+                    // ages, nor be caught up on with a burst of rounds
+                    if let Jump::Gap(gap) = jump {
+                        startup += gap;
+                        ticker = tick_stream(slots.len(), (tick + 1) % slots.len(), opt.missed_tick);
+                    }
+                    epoch = SystemTime::now() - startup.elapsed();
+                    warn!("clock, {}", jump);
+                }
+                // Only report the first of a run of late ticks, since bursting
+                // will follow up with many more
+                let lag = deadline.elapsed();
+                if lag >= Duration::from_secs(1) && deadline >= late_since {
+                    late_since = tokio::time::Instant::now();
+                    warn!(
+                        "tick, missed: {}, lag: {:.2?}",
+                        lag.as_secs(),
+                        lag
+                    );
+                }
                 last_tick = tick;
+                // A full memory budget leaves no more room than a full house
+                let accepting = budget.as_ref().is_none_or(MemoryBudget::accepting);
+                let busy = if accepting { num_clients + pending.len() } else { max_clients };
+                heartbeat.beat(busy, max_clients);
+                let ticking = profiler.start();
+                let mut slot_memory = 0;
                 slots[tick].retain_unordered(|connection| {
-                    let pos = &BANNER[connection.bytes as usize % BANNER.len()..];
-                    let slice = &pos[..=pos.iter().position(|b| *b == b'\n').unwrap_or(pos.len() - 1)];
-                    match connection.sock.try_write(slice) {
+                    match connection.tick(&pacing, startup, &mut rng, &mut notifier) {
                         Ok(n) => {
                             bytes += n as u64;
-                            connection.bytes += n as u64;
-                            connection.failed = 0;
+                            slot_memory += connection.memory();
                             true
-                        },
-                        Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => { true },
-                        Err(mut e) => {
-                            if e.kind() == std::io::ErrorKind::WouldBlock {
-                                connection.failed += 1;
-                                if delay * (connection.failed as u32) < timeout {
-                                    return true;
+                        }
+                        Err((e, reason)) => {
+                            let disconnecting = profiler.start();
+                            if let Some(ipfix) = ipfix.as_mut() {
+                                if let Err(err) = ipfix.record(&connection.flow(epoch, startup, reason)) {
+                                    debug!("ipfix, error: {}", err);
                                 }
-                                e = std::io::Error::new(std::io::ErrorKind::Other, "Timed Out");
                             }
                             num_clients -= 1;
+                            if let Some(early) = early.as_mut() {
+                                if connection.terminated_early(startup) {
+                                    early.terminated(SocketAddr::from(connection.peer).ip(), Instant::now());
+                                }
+                            }
+                            if let Some(limits) = limits.as_mut() {
+                                limits.release(SocketAddr::from(connection.peer).ip());
+                            }
+                            if let Some(store) = reputation.as_mut() {
+                                let ip = SocketAddr::from(connection.peer).ip();
+                                store.disconnected(ip, connection.start.elapsed(startup), reason, SystemTime::now());
+                            }
+                            if let Some(abuse) = abuseipdb.as_mut() {
+                                let ip = SocketAddr::from(connection.peer).ip();
+                                abuse.disconnected(ip, connection.start.elapsed(startup), SystemTime::now());
+                            }
+                            protocol_holds.record(protocols[connection.listener as usize], connection.start.elapsed(startup));
+                            for rule in connection.rules(&pacing, &listener_rules, &banner_files) {
+                                rule_holds.record(&rule, connection.start.elapsed(startup));
+                            }
+                            if let (Some(tuner), Some(arm)) = (tuner.as_mut(), connection.tuned.checked_sub(1)) {
+                                let ip = SocketAddr::from(connection.peer).ip();
+                                tuner.record(ip, arm as usize, connection.start.elapsed(startup));
+                            }
+                            #[cfg(feature = "packet_capture")]
+                            if let Some(packets) = &packets {
+                                packets.close(connection.peer.into());
+                            }
+                            let tags = connection.tap.as_mut().map_or_else(Tags::default, |tap| {
+                                tap.closed(&e);
+                                std::mem::take(&mut tap.tags)
+                            });
+                            for key in tags.keys() {
+                                *tagged.entry(key).or_insert(0) += 1;
+                            }
+                            let tags = if tags.is_empty() {
+                                String::new()
+                            } else {
+                                format!(", tags: \"{}\"", tags)
+                            };
+                            let interval = connection
+                                .average_interval(startup)
+                                .map_or_else(|| "none".to_string(), |i| format!("{:.2?}", i));
+                            let unacked = outq::unacked(&connection.sock)
+                                .map_or_else(|_| String::new(), |n| format!(", unacked: {}", n));
+                            let location = geoip
+                                .as_ref()
+                                .map(|db| db.lookup(SocketAddr::from(connection.peer).ip()))
+                                .unwrap_or_default();
+                            let short = match deferred.release(connection.peer.into(), &"") {
+                                Some(_) if log_after.is_some() => {
+                                    let listener = Some(&labels)
+                                        .filter(|labels| !labels.is_empty())
+                                        .and_then(|labels| labels.get(unmap(connection.sock.local_addr().ok()?)));
+                                    match listener {
+                                        Some(name) => format!(", listener: {}, short: true", name),
+                                        None => ", short: true".to_string(),
+                                    }
+                                }
+                                Some(line) => {
+                                    info!("{}", line);
+                                    String::new()
+                                }
+                                None => String::new(),
+                            };
                             info!(
-                                "disconnect, peer: {}, duration: {:.2?}, bytes: {}, error: \"{}\", clients: {}",
+                                "disconnect, peer: {}, duration: {:.2?}, bytes: {}, error: \"{}\", clients: {}, writes: {}, failed: {}, interval: {}{}{}{}{}",
                                 connection.peer,
                                 connection.start.elapsed(startup),
                                 connection.bytes,
                                 e,
-                                num_clients
+                                num_clients,
+                                connection.writes,
+                                connection.failed,
+                                interval,
+                                unacked,
+                                location,
+                                tags,
+                                short
                             );
+                            profiler.record("tick;disconnect", disconnecting);
 
                             false
                         }
                     }
                 });
+                profiler.record("tick", ticking);
+                if let Some(budget) = budget.as_mut() {
+                    budget.set(tick, slot_memory);
+                    match budget.check(pending.len()) {
+                        Some(shed @ Shed::Paused { .. }) => warn!("shed, {}, budget: {}", shed, budget.limit()),
+                        Some(shed @ Shed::Resumed { .. }) => info!("shed, {}, budget: {}", shed, budget.limit()),
+                        None => (),
+                    }
+                    let excess = budget.excess(pending.len());
+                    if excess > 0 {
+                        let evicted = evict_newest(&mut slots, budget, excess);
+                        warn!(
+                            "shed, evicted: {}, used: {}, budget: {}",
+                            evicted,
+                            budget.used(pending.len()),
+                            budget.limit()
+                        );
+                    }
+                }
+                if let Some(counters) = &snmp {
+                    counters.clients.store(num_clients as u64, Ordering::Relaxed);
+                    counters.connects.store(total_clients, Ordering::Relaxed);
+                    counters.bytes.store(bytes, Ordering::Relaxed);
+                }
+
+                if let Some(ipfix) = ipfix.as_mut() {
+                    if let Err(err) = ipfix.flush() {
+                        debug!("ipfix, error: {}", err);
+                    }
+                }
+            }
+            Some(_) = push_timer.next(), if pusher.is_some() || influx.is_some() || statsd.is_some() || textfile.is_some() => {
+                let metrics = Metrics {
+                    clients: num_clients,
+                    connects: total_clients,
+                    bytes,
+                    uptime: startup.elapsed(),
+                    listeners: listeners.len(),
+                    bind_failures: bind_failures.load(Ordering::Relaxed),
+                    versions: versions
+                        .as_ref()
+                        .map(|table| table.borrow().top(versions::TOP))
+                        .unwrap_or_default(),
+                    tags: tagged.iter().map(|(key, count)| (key.to_string(), *count)).collect(),
+                    listened: listened.iter().map(|(name, count)| (name.clone(), *count)).collect(),
+                    protocols: protocol_holds.clone(),
+                    rules: rule_holds.clone(),
+                    ages: slots
+                        .iter()
+                        .flatten()
+                        .map(|connection| connection.start.elapsed(startup))
+                        .collect(),
+                };
+                if let Some(pusher) = &pusher {
+                    pusher.push(&metrics);
+                }
+                if let Some(influx) = &influx {
+                    if let Err(err) = influx.send(&metrics) {
+                        debug!("influx, error: {}", err);
+                    }
+                }
+                if let Some(statsd) = statsd.as_mut() {
+                    if let Err(err) = statsd.send(&metrics) {
+                        debug!("statsd, error: {}", err);
+                    }
+                }
+                if let Some(textfile) = &textfile {
+                    if let Err(err) = textfile.send(&metrics) {
+                        warn!("textfile, error: {}", err);
+                    }
+                }
+            }
+            Some(_) = proxy_map_timer.next(), if proxy_map.is_some() => {
+                if let Some(map) = &proxy_map {
+                    let active = slots
+                        .iter()
+                        .flatten()
+                        .map(|connection| SocketAddr::from(connection.peer).ip());
+                    let banned = bans.list(SystemTime::now()).into_iter().map(|(ip, _)| ip);
+                    match map.write(active, banned) {
+                        Ok(sources) => debug!("proxy-map, sources: {}", sources),
+                        Err(err) => warn!("proxy-map, error: {}", err),
+                    }
+                }
+            }
+            Some(remote) = reload_rx.recv() => {
+                match reload_config(remote.as_ref().map(|fetched| fetched.text.as_str())) {
+                    Ok(new) => {
+                        log::set_max_level(level_filter(new.verbose));
+                        max_clients = u32::from(new.max_clients) as usize;
+                        pacing.timeouts.write = Duration::from_secs(new.timeout as u64);
+                        let delay = Duration::from_secs(u16::from(new.delay) as u64);
+                        pacing.schedule = new.pacing.unwrap_or_else(|| Schedule::fixed(delay));
+
+                        let ticks = pacing.schedule.min_interval().as_secs() as usize;
+                        if ticks != slots.len() {
+                            reslot(&mut slots, ticks);
+                            ticker = tick_stream(ticks, 0, opt.missed_tick);
+                            last_tick = 0;
+                            if let Some(old) = budget.take() {
+                                let mut resized = MemoryBudget::new(old.limit(), ticks);
+                                for (slot, connections) in slots.iter().enumerate() {
+                                    resized.set(slot, connections.iter().map(Connection::memory).sum());
+                                }
+                                if let Some(shed) = resized.check(pending.len()) {
+                                    warn!("shed, {}, budget: {}", shed, resized.limit());
+                                }
+                                budget = Some(resized);
+                            }
+                        }
+                        if let (Some(url), Some(fetched)) = (&opt.config_url, remote) {
+                            info!("config, url: {}, sha256: {}", url, fetched.sha256);
+                            opt.config_sha256 = Some(fetched.sha256);
+                        }
+                        info!(
+                            "reload, max_clients: {}, delay: {}s, timeout: {}s, verbose: {}",
+                            max_clients,
+                            ticks,
+                            pacing.timeouts.write.as_secs(),
+                            new.verbose
+                        );
+                    }
+                    Err(err) => warn!("reload, {}", err),
+                }
+            }
+            Some(_) = config_timer.next(), if opt.config_refresh.is_some() => {
+                if let Some(url) = opt.config_url.clone() {
+                    fetch_config(url, opt.config_sha256.clone(), reload_tx.clone());
+                }
+            }
+            Some(_) = refresh_timer.next(), if banner_source.is_some() && opt.banner_refresh.is_some() => {
+                if let Some(source) = banner_source.clone() {
+                    let banner_tx = banner_tx.clone();
+                    tokio::spawn(async move {
+                        match source.load().await {
+                            Ok(banner) => {
+                                let _ = banner_tx.send(banner);
+                            }
+                            Err(err) => warn!("banner, url: {}, error: {}", source.url, err),
+                        }
+                    });
+                }
+            }
+            Some(banner) = banner_rx.recv() => {
+                pacing.banner = banner;
+            }
+            Some(_) = watch_timer.next(), if opt.watch_files => {
+                if let Some(path) = watched_banner.as_mut().and_then(Watched::changed) {
+                    match parse_banner(path.as_os_str()) {
+                        Ok(banner) => {
+                            pacing.banner = banner;
+                            info!("banner, path: {}, reloaded: true", path.display());
+                        }
+                        Err(err) => warn!(
+                            "banner, path: {}, error: {}",
+                            path.display(),
+                            err.to_string_lossy()
+                        ),
+                    }
+                }
+                if let Some(path) = watched_dir.as_mut().and_then(Watched::changed) {
+                    match load_banner_dir(path) {
+                        Ok(files) => {
+                            pacing.banners = files.iter().map(|(_, banner)| banner.clone()).collect();
+                            banner_files = files;
+                            info!("banner, dir: {}, files: {}", path.display(), banner_files.len());
+                        }
+                        Err(err) => warn!("banner, dir: {}, error: {}", path.display(), err),
+                    }
+                }
+                if let Some(path) = watched_template.as_mut().and_then(Watched::changed) {
+                    match template_banner(&opt) {
+                        Ok(template) => {
+                            let own = pacing.listeners.iter_mut().filter_map(|own| own.banner.as_mut());
+                            for banner in std::iter::once(&mut pacing.banner).chain(own) {
+                                if let Banner::Template(_) = banner {
+                                    *banner = template.clone();
+                                }
+                            }
+                            info!("template, path: {}, reloaded: true", path.display());
+                        }
+                        Err(err) => warn!("template, error: {}", err),
+                    }
+                }
+                if let Some(path) = watched_bans.as_mut().and_then(Watched::changed) {
+                    match bans.reload() {
+                        Ok(true) => info!("bans, path: {}, bans: {}", path.display(), bans.len()),
+                        Ok(false) => (),
+                        Err(err) => warn!("bans, path: {}, error: {}", path.display(), err),
+                    }
+                }
+            }
+            Some(_) = churn_timer.next(), if churn.is_some() || countries.is_some() => {
+                if let Some(anomaly) = churn.as_mut().and_then(ChurnWatch::sample) {
+                    notifier.system_alert("churn", anomaly);
+                }
+                for shift in countries.as_mut().map_or_else(Vec::new, CountryWatch::sample) {
+                    notifier.system_alert("country", shift);
+                }
+            }
+            Some(_) = abuseipdb_timer.next(), if abuseipdb.is_some() => {
+                if let Some(abuse) = abuseipdb.as_mut() {
+                    abuse.flush();
+                }
+            }
+            Some(_) = reputation_timer.next(), if reputation.is_some() => {
+                if let Some(store) = reputation.as_mut() {
+                    let expired = store.expire(SystemTime::now());
+                    if expired > 0 {
+                        debug!("reputation, expired: {}", expired);
+                    }
+                    if let Err(err) = store.save() {
+                        warn!("reputation, error: {}", err);
+                    }
+                }
+            }
+            Some(_) = prune_timer.next(), if !pruned.is_empty() || early.is_some() => {
+                if let Some(early) = early.as_mut() {
+                    let expired = early.expire(Instant::now());
+                    if expired > 0 {
+                        debug!("terminate, abandoned: {}", expired);
+                    }
+                }
+                for spool in &pruned {
+                    match spool.prune(&retention) {
+                        Ok(0) => (),
+                        Ok(removed) => info!("prune, path: {}, removed: {}", spool.path().display(), removed),
+                        Err(err) => warn!("prune, path: {}, error: {}", spool.path().display(), err),
+                    }
+                }
+            }
+            Some((from, command)) = gossip_rx.recv() => {
+                info!("gossip, from: {}, command: \"{}\"", from, command);
+                let _ = command.apply(&mut bans, &[], SystemTime::now());
+            }
+            _ = sleep_until(deferred.next_due().unwrap_or_else(Instant::now).into()), if deferred.next_due().is_some() => {
+                let extra = if opt.log_after.is_some() { "" } else { ", enrich: timeout" };
+                for line in deferred.expire(Instant::now(), &extra) {
+                    info!("{}", line);
+                }
+            }
+            Some(resolved) = rdap_rx.recv() => {
+                if let Some(rdap) = rdap.as_mut() {
+                    let (network, result) = &resolved;
+                    let waiting = rdap.resolved(&resolved, Instant::now());
+                    match result {
+                        Ok(registration) => {
+                            debug!("rdap, network: {}, {}", network, registration);
+                            if let Some(store) = reputation.as_mut() {
+                                for connection in waiting.iter().filter_map(|&i| slots.get(i)).flatten() {
+                                    let ip = SocketAddr::from(connection.peer).ip();
+                                    if rdap::network(ip) == *network {
+                                        store.registered(ip, registration);
+                                    }
+                                }
+                            }
+                        }
+                        Err(err) => debug!("rdap, network: {}, error: {}", network, err),
+                    }
+                }
+            }
+            Some(resolved) = dnsbl_rx.recv() => {
+                if let Some(dnsbl) = dnsbl.as_mut() {
+                    let (ip, result) = &resolved;
+                    let waiting = dnsbl.resolved(&resolved, Instant::now());
+                    match result {
+                        Ok(Some(listing)) => {
+                            for i in waiting {
+                                let slot = match slots.get_mut(i) {
+                                    Some(slot) => slot,
+                                    None => continue,
+                                };
+                                for connection in slot.iter_mut() {
+                                    if SocketAddr::from(connection.peer).ip() == *ip && !connection.listed {
+                                        connection.list(listing);
+                                        dnsbl.listed();
+                                        let extra = format!(", dnsbl: {}, dnsbl_result: {}", listing.zone, listing.result);
+                                        let held = opt.enrich_wait.and_then(|_| deferred.release(connection.peer.into(), &extra));
+                                        match held {
+                                            Some(line) => info!("{}", line),
+                                            None => info!("dnsbl, peer: {}, {}, action: {}", connection.peer, listing, pacing.listed),
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                        Ok(None) => (),
+                        Err(err) => debug!("dnsbl, peer: {}, error: {}", ip, err),
+                    }
+                    if opt.enrich_wait.is_some() {
+                        for line in deferred.release_ip(*ip) {
+                            info!("{}", line);
+                        }
+                    }
+                }
+            }
+            Some(listener) = rebound_rx.recv() => {
+                listeners.push(listener);
+            }
+            Some((command, reply)) = control_rx.recv() => {
+                if let (Some(gossip), control::Command::Ban(..) | control::Command::Unban(..)) = (&gossip, &command) {
+                    gossip.share(&command);
+                }
+                let labelled: Vec<_> = labels
+                    .iter()
+                    .map(|label| (label, listened.get(&label.name).copied().unwrap_or(0)))
+                    .collect();
+                let _ = reply.send(command.apply(&mut bans, &labelled, SystemTime::now()));
+            }
+            Some(ready) = pending.next(), if !pending.is_empty() => {
+                match ready {
+                    Ok(client) => arrived = Some(client),
+                    Err((peer, reason)) => {
+                        debug!("pending, peer: {}, dropped: {}", peer, reason);
+                        if let Some(limits) = limits.as_mut() {
+                            limits.release(SocketAddr::ip(&peer));
+                        }
+                    }
+                }
             }
-            Some(client) = listeners.next(), if num_clients < max_clients => {
+            Some(client) = listeners.next(), if num_clients + pending.len() < max_clients && budget.as_ref().is_none_or(MemoryBudget::accepting) => {
                 match client {
                     Ok(sock) => {
                         let peer = match sock.peer_addr() {
-                            Ok(peer) => peer,
+                            Ok(peer) => unmap(peer),
                             Err(e) => {
                                 warn!("reject, peer: unknown, error: {:?}", e);
                                 continue;
                             }
                         };
-                        num_clients += 1;
-                        total_clients += 1;
-
-                        info!("connect, peer: {}, clients: {}", peer, num_clients);
-                        let connection = Connection {
-                            sock,
-                            peer: peer.into(),
-                            start: startup.into(),
-                            bytes: 0,
-                            failed: 0,
-                        };
-                        slots[last_tick].push(connection);
+                        if let Some(churn) = churn.as_mut() {
+                            churn.accepted();
+                        }
+                        if let Some(delay) = early.as_mut().and_then(|early| early.connected(peer.ip(), Instant::now())) {
+                            info!("terminate, peer: {}, reconnect: {:.2?}", peer, delay);
+                        }
+                        if bans.is_banned(peer.ip(), SystemTime::now()) {
+                            info!("reject, peer: {}, reason: banned", peer);
+                            continue;
+                        }
+                        // Before any hold-aside, so clients held there count
+                        // towards their source's limits too
+                        if let Some(limits) = limits.as_mut() {
+                            if let Err((source, refusal)) = limits.admit(peer.ip(), Instant::now()) {
+                                info!(
+                                    "reject, peer: {}, source: {}, reason: {}",
+                                    peer, source, refusal
+                                );
+                                if let Some(gossip) = gossip.as_mut() {
+                                    gossip.offender(peer.ip(), Instant::now());
+                                }
+                                continue;
+                            }
+                        }
+                        if opt.quarantine.is_some() || require_data.is_some() {
+                            let quarantine = opt
+                                .quarantine
+                                .map_or(Duration::ZERO, |max| rng.gen_range(max / 2..=max));
+                            pending.push(hold_aside(sock, peer, quarantine, require_data));
+                        } else {
+                            arrived = Some((sock, peer, vec![]));
+                        }
                     }
                     Err(err) => match err.kind() {
                         std::io::ErrorKind::ConnectionRefused
@@ -333,17 +3005,371 @@ async fn main() {
                 }
             }
         }
+
+        if let Some((sock, peer, initial)) = arrived {
+            let accepting = profiler.start();
+            num_clients += 1;
+            total_clients += 1;
+
+            let listener = Some(&labels)
+                .filter(|labels| !labels.is_empty())
+                .and_then(|labels| labels.get(unmap(sock.local_addr().ok()?)));
+            let location = geoip
+                .as_mut()
+                .map(|db| db.connected(peer.ip()))
+                .unwrap_or_default();
+            if let Some(countries) = countries.as_mut() {
+                countries.accepted(location.country.as_deref());
+            }
+            let seen = reputation
+                .as_mut()
+                .map(|store| store.connected(peer.ip(), SystemTime::now()).clone());
+            let history = seen.as_ref().map_or_else(String::new, |record| {
+                format!(
+                    ", first_seen: {}, times_seen: {}",
+                    humantime::format_rfc3339_seconds(
+                        UNIX_EPOCH + Duration::from_secs(record.first_seen)
+                    ),
+                    record.connections
+                )
+            });
+            let mut connect = match listener {
+                Some(name) => {
+                    *listened.entry(name.to_string()).or_insert(0) += 1;
+                    format!(
+                        "connect, peer: {}, clients: {}{}, listener: {}{}",
+                        peer, num_clients, history, name, location
+                    )
+                }
+                None => format!(
+                    "connect, peer: {}, clients: {}{}{}",
+                    peer, num_clients, history, location
+                ),
+            };
+            match opt.log_after {
+                Some(after) => {
+                    deferred.hold(peer, std::mem::take(&mut connect), Instant::now() + after)
+                }
+                None if opt.enrich_wait.is_none() => info!("{}", connect),
+                None => (),
+            }
+            if let Some(ban_log) = ban_log.as_mut() {
+                ban_log.connected(peer, Instant::now(), SystemTime::now());
+            }
+            if let Some(record) = &seen {
+                match record.interval_mean() {
+                    Some(mean) => debug!(
+                        "reputation, peer: {}, connections: {}, held: {:.2?}, last: {}, interval_min: {:.2?}, interval_mean: {:.2?}",
+                        peer, record.connections, record.held, record.last_action, record.interval_min, mean
+                    ),
+                    None => debug!(
+                        "reputation, peer: {}, connections: {}, held: {:.2?}, last: {}",
+                        peer, record.connections, record.held, record.last_action
+                    ),
+                }
+            }
+            #[cfg(feature = "packet_capture")]
+            if let Some(packets) = &packets {
+                packets.open(peer);
+            }
+            let mut tags = Tags::default();
+            if let Some(patterns) = port_patterns.as_mut() {
+                if let Some(pattern) = patterns.connected(peer.ip(), peer.port(), Instant::now()) {
+                    tags.insert("ports", pattern);
+                    let stride = match pattern {
+                        Pattern::Linear(stride) => stride,
+                        Pattern::Fixed => 0,
+                    };
+                    debug!(
+                        "ports, peer: {}, pattern: {}, stride: {}, ports: {}",
+                        peer,
+                        pattern,
+                        stride,
+                        patterns.ports(peer.ip())
+                    );
+                }
+            }
+            let peer = PeerAddr::from(peer);
+            let tap = Tap {
+                transcript: transcripts.as_ref().and_then(|spool| {
+                    Transcript::create(spool, total_clients, peer)
+                        .map_err(|err| warn!("transcript, peer: {}, error: {}", peer, err))
+                        .ok()
+                }),
+                capture: captures
+                    .as_ref()
+                    .filter(|_| capture_limit > 0)
+                    .and_then(|spool| {
+                        Capture::create(spool, total_clients, capture_limit)
+                            .map_err(|err| warn!("capture, peer: {}, error: {}", peer, err))
+                            .ok()
+                    }),
+                canary: canaries.clone().map(CanaryWatch::new),
+                versions: versions.clone().map(VersionWatch::new),
+                compressor: compressor.clone(),
+                tags,
+            };
+            if pacing.cork {
+                if let Err(err) = sock.set_nodelay(true) {
+                    debug!("cork, peer: {}, error: {}", peer, err);
+                }
+            }
+            let mut connection = Connection::new(sock, peer, startup, tap);
+            let own = if pacing.listeners.is_empty() {
+                None
+            } else {
+                connection.sock.local_addr().ok().and_then(|local| {
+                    listeners::find(&pacing.listeners, unmap(local), |own| own.addr)
+                })
+            };
+            connection.listener = own.map_or(0, |i| i as u8 + 1);
+            let own = own.map(|i| &pacing.listeners[i]);
+            if let (Some(tuner), None) = (&tuner, own.and_then(|own| own.schedule.as_ref())) {
+                let arm = tuner.choose(SocketAddr::from(peer).ip(), &mut rng);
+                connection.tuned = arm as u8 + 1;
+                debug!(
+                    "tune, peer: {}, delay: {}s",
+                    peer,
+                    tuner.delay(arm).as_secs()
+                );
+            }
+            if !banner_files.is_empty() && own.is_none_or(|own| own.banner.is_none()) {
+                let i = rng.gen_range(0..banner_files.len());
+                connection.banner = i as u8 + 1;
+                debug!("banner, peer: {}, file: {}", peer, banner_files[i].0);
+            }
+            if let Some(early) = &early {
+                connection.terminate_after = early.pick(&mut rng);
+            }
+            if let Some(dnsbl) = dnsbl.as_mut() {
+                let ip = SocketAddr::from(peer).ip();
+                match dnsbl.check(ip, last_tick, Instant::now()) {
+                    Some(listing) if opt.enrich_wait.is_some() => {
+                        connection.list(&listing);
+                        connect.push_str(&format!(
+                            ", dnsbl: {}, dnsbl_result: {}",
+                            listing.zone, listing.result
+                        ));
+                    }
+                    Some(listing) => {
+                        connection.list(&listing);
+                        info!(
+                            "dnsbl, peer: {}, {}, action: {}",
+                            peer, listing, pacing.listed
+                        );
+                    }
+                    None => {
+                        if let Some(wait) = opt.enrich_wait.filter(|_| dnsbl.pending(ip)) {
+                            deferred.hold(
+                                peer.into(),
+                                std::mem::take(&mut connect),
+                                Instant::now() + wait,
+                            );
+                        }
+                    }
+                }
+            }
+            if let Some(rdap) = rdap.as_mut() {
+                let ip = SocketAddr::from(peer).ip();
+                if let Some(registration) = rdap.check(ip, last_tick, Instant::now()) {
+                    if let Some(store) = reputation.as_mut() {
+                        store.registered(ip, &registration);
+                    }
+                }
+            }
+            if opt.enrich_wait.is_some() && !connect.is_empty() {
+                info!("{}", connect);
+            }
+            if !initial.is_empty() {
+                connection.received = true;
+                if let Some(tap) = connection.tap.as_mut() {
+                    tap.received(peer, &initial, &mut notifier);
+                }
+            }
+            if let Some(budget) = budget.as_mut() {
+                budget.add(last_tick, connection.memory());
+                if let Some(shed) = budget.check(pending.len()) {
+                    warn!("shed, {}, budget: {}", shed, budget.limit());
+                }
+            }
+            slots[last_tick].push(connection);
+            profiler.record("accept", accepting);
+        }
+
+        if inetd_connection && listeners.is_empty() && pending.is_empty() && num_clients == 0 {
+            let _ = closed_tx.send("CLOSED");
+            inetd_connection = false;
+        }
+    }
+}
+
+/// Fetch the config file at `url` in the background, passing it on to be
+/// applied unless its sum is still `applied`
+fn fetch_config(
+    url: HttpUrl,
+    applied: Option<String>,
+    reload_tx: tokio::sync::mpsc::UnboundedSender<Option<remote_config::Fetched>>,
+) {
+    tokio::spawn(async move {
+        match remote_config::fetch(&url).await {
+            Ok(fetched) if applied.as_ref() == Some(&fetched.sha256) => {
+                debug!("config, url: {}, unchanged: true", url);
+            }
+            Ok(fetched) => {
+                let _ = reload_tx.send(Some(fetched));
+            }
+            Err(err) => warn!("config, url: {}, error: {}", url, err),
+        }
+    });
+}
+
+fn level_filter(verbose: u8) -> LevelFilter {
+    match verbose {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Which slot of connections to service, once a second
+fn tick_stream(
+    slots: usize,
+    first: usize,
+    missed: MissedTickBehavior,
+) -> impl futures::Stream<Item = (usize, tokio::time::Instant)> + Unpin {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    interval.set_missed_tick_behavior(missed);
+    stream::iter(0..slots)
+        .cycle()
+        .skip(first)
+        .zip(IntervalStream::new(interval))
+}
+
+/// Spread connections over a new number of slots, as when a reload changes
+/// the delay
+fn reslot(slots: &mut Box<[Vec<Connection>]>, count: usize) {
+    let mut resized: Vec<Vec<Connection>> = std::iter::repeat_with(Vec::new).take(count).collect();
+    for (i, connection) in slots.iter_mut().flat_map(std::mem::take).enumerate() {
+        resized[i % count].push(connection);
+    }
+    *slots = resized.into_boxed_slice();
+}
+
+/// Mark the newest connections to be disconnected until about `excess` bytes
+/// are freed, returning how many were marked
+fn evict_newest(
+    slots: &mut [Vec<Connection>],
+    budget: &mut MemoryBudget,
+    mut excess: usize,
+) -> usize {
+    let mut newest: Vec<(Duration, usize, usize)> = slots
+        .iter()
+        .enumerate()
+        .flat_map(|(slot, connections)| {
+            connections
+                .iter()
+                .enumerate()
+                .filter(|(_, connection)| !connection.evicted)
+                .map(move |(i, connection)| (Duration::from(connection.start), slot, i))
+        })
+        .collect();
+    newest.sort_unstable_by_key(|&(start, _, _)| std::cmp::Reverse(start));
+
+    let mut evicted = 0;
+    for (_, slot, i) in newest {
+        if excess == 0 {
+            break;
+        }
+        let connection = &mut slots[slot][i];
+        let memory = connection.memory();
+        connection.evicted = true;
+        budget.sub(slot, memory);
+        excess = excess.saturating_sub(memory);
+        evicted += 1;
+    }
+    evicted
+}
+
+/// Hold a new client aside, sending it nothing, for `quarantine` and then for
+/// as long as `require_data` allows until it's sent something.  Hands back the
+/// socket and anything sent, or the peer and why it was dropped if it closes
+/// or never sends anything required of it.
+async fn hold_aside(
+    sock: TcpStream,
+    peer: SocketAddr,
+    quarantine: Duration,
+    require_data: Option<Duration>,
+) -> Result<(TcpStream, SocketAddr, Vec<u8>), (SocketAddr, &'static str)> {
+    let start = tokio::time::Instant::now();
+    let mut data = vec![];
+    let mut buf = [0; 256];
+
+    loop {
+        let wait = match require_data {
+            Some(wait) if data.is_empty() => wait.max(quarantine),
+            _ => quarantine,
+        };
+        let deadline = start + wait;
+        if data.len() >= PENDING_DATA_LIMIT {
+            tokio::time::sleep_until(deadline).await;
+            break;
+        }
+
+        match tokio::time::timeout_at(deadline, sock.readable()).await {
+            Err(_) => break,
+            Ok(Err(_)) => return Err((peer, "error")),
+            Ok(Ok(())) => match sock.try_read(&mut buf) {
+                Ok(0) => return Err((peer, "closed")),
+                Ok(n) => data.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => (),
+                Err(_) => return Err((peer, "error")),
+            },
+        }
+    }
+
+    if require_data.is_some() && data.is_empty() {
+        Err((peer, "no data"))
+    } else {
+        Ok((sock, peer, data))
     }
 }
 
 fn signal_stream() -> impl futures::Stream<Item = &'static str> + 'static {
-    #[cfg(not(unix))]
+    #[cfg(not(any(unix, windows)))]
     {
-        let sig = async_stream::stream! {
+        async_stream::stream! {
             let _ = tokio::signal::ctrl_c().await;
             yield "INT";
-        };
-        sig.boxed()
+        }
+        .boxed()
+    }
+
+    #[cfg(windows)]
+    {
+        use tokio::signal::windows::{ctrl_break, ctrl_c, ctrl_close, ctrl_shutdown};
+
+        // Each console event has its own type, so a function won't do
+        macro_rules! console_event_stream {
+            ($event:ident, $tag:expr) => {
+                async_stream::stream! {
+                    let mut event = $event().unwrap();
+
+                    while let Some(()) = event.recv().await {
+                        yield $tag;
+                    }
+                }
+                .boxed()
+            };
+        }
+
+        futures::stream::select_all(vec![
+            console_event_stream!(ctrl_break, "BREAK"),
+            console_event_stream!(ctrl_close, "CLOSE"),
+            console_event_stream!(ctrl_shutdown, "SHUTDOWN"),
+            console_event_stream!(ctrl_c, "INT"),
+            service::controls().boxed(),
+        ])
     }
 
     #[cfg(unix)]
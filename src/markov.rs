@@ -0,0 +1,142 @@
+//! Pseudo-English from a word-level Markov chain, so each client is sent its
+//! own text rather than the same banner every other client sees.
+//!
+//! The chain is built once from a corpus of plain text: for every pair of
+//! neighbouring words, the words that followed them.  Lines start where a
+//! sentence did and wander from pair to pair until a sentence ends or the line
+//! is full.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+/// Line length to aim for, as prose wrapped for a terminal would be
+const WIDTH: usize = 72;
+
+pub struct Markov {
+    words: Vec<Box<str>>,
+    /// Pairs of words that started a sentence
+    starts: Vec<(u32, u32)>,
+    /// Every word seen following each pair, repeated as often as it was
+    next: HashMap<(u32, u32), Vec<u32>>,
+}
+
+impl fmt::Debug for Markov {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Markov")
+            .field("words", &self.words.len())
+            .field("starts", &self.starts.len())
+            .field("pairs", &self.next.len())
+            .finish()
+    }
+}
+
+fn ends_sentence(word: &str) -> bool {
+    word.trim_end_matches(['"', '\'', ')'])
+        .ends_with(['.', '!', '?'])
+}
+
+impl Markov {
+    /// Build a chain from `corpus`, which needs at least three words.
+    ///
+    /// Words containing control characters are skipped, so nothing sent can
+    /// end a line early or upset a terminal.
+    pub fn new(corpus: &str) -> Result<Self, String> {
+        let mut ids = HashMap::new();
+        let mut words = vec![];
+        let text: Vec<u32> = corpus
+            .split_whitespace()
+            .filter(|word| !word.chars().any(char::is_control))
+            .map(|word| {
+                *ids.entry(word).or_insert_with(|| {
+                    words.push(Box::from(word));
+                    words.len() as u32 - 1
+                })
+            })
+            .collect();
+
+        let mut starts = vec![];
+        let mut next: HashMap<_, Vec<_>> = HashMap::new();
+        for (i, window) in text.windows(3).enumerate() {
+            let pair = (window[0], window[1]);
+            if i == 0 || ends_sentence(&words[text[i - 1] as usize]) {
+                starts.push(pair);
+            }
+            next.entry(pair).or_default().push(window[2]);
+        }
+        if next.is_empty() {
+            return Err("corpus needs at least three words".to_string());
+        }
+
+        Ok(Self {
+            words,
+            starts,
+            next,
+        })
+    }
+
+    fn word(&self, id: u32) -> &str {
+        &self.words[id as usize]
+    }
+
+    /// Write a random line of no more than `max` bytes, without its line ending
+    pub fn line<R: Rng>(&self, rng: &mut R, max: usize, out: &mut String) {
+        let width = WIDTH.min(max);
+        let (mut a, mut b) = *self.starts.choose(rng).expect("starts");
+        out.push_str(self.word(a));
+        while !ends_sentence(self.word(a)) {
+            let word = self.word(b);
+            if out.len() + 1 + word.len() > width {
+                break;
+            }
+            out.push(' ');
+            out.push_str(word);
+            let c = match self.next.get(&(a, b)) {
+                Some(next) => *next.choose(rng).expect("successors"),
+                None => break,
+            };
+            a = b;
+            b = c;
+        }
+
+        // A single overlong word
+        if out.len() > max {
+            let mut end = max;
+            while !out.is_char_boundary(end) {
+                end -= 1;
+            }
+            out.truncate(end);
+        }
+    }
+}
+
+#[test]
+fn test_markov() {
+    use rand::SeedableRng;
+
+    assert!(Markov::new("too short").is_err());
+    assert!(Markov::new("").is_err());
+
+    let corpus = "The cat sat on the mat. The dog sat on the cat. \
+        A bird\x07 flew over the dog, and the cat sat still.";
+    let markov = Markov::new(corpus).unwrap();
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let mut lines = std::collections::HashSet::new();
+    for _ in 0..100 {
+        let mut line = String::new();
+        markov.line(&mut rng, 30, &mut line);
+        assert!(line.len() <= 30);
+        assert!(line.split(' ').all(|word| corpus.contains(word)));
+        assert!(!line.contains("bird"));
+        assert!(["The ", "A "].iter().any(|s| line.starts_with(s)));
+        lines.insert(line);
+    }
+    assert!(lines.len() > 5);
+
+    let markov = Markov::new("Supercalifragilistic\u{e9}xpialidocious is long").unwrap();
+    let mut line = String::new();
+    markov.line(&mut rng, 21, &mut line);
+    assert_eq!(line, "Supercalifragilistic");
+}
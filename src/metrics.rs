@@ -0,0 +1,725 @@
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::ages::Ages;
+use crate::holds::Holds;
+use crate::http::HttpUrl;
+
+/// How long a push may take before we give up on it
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A snapshot of the tarpit's counters and gauges
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Metrics {
+    /// Clients currently connected
+    pub clients: usize,
+    /// Clients accepted since startup
+    pub connects: u64,
+    /// Bytes sent since startup
+    pub bytes: u64,
+    pub uptime: Duration,
+    /// Listeners currently accepting
+    pub listeners: usize,
+    /// Failed attempts to bind listeners, at startup or since
+    pub bind_failures: u64,
+    /// The most common SSH client versions, with how many clients sent each
+    pub versions: Vec<(String, u64)>,
+    /// Disconnected clients carrying each tag key, e.g. `canary`
+    pub tags: Vec<(String, u64)>,
+    /// Clients accepted by each labelled listener
+    pub listened: Vec<(String, u64)>,
+    /// Disconnected clients and how long they were held, by the protocol
+    /// their banner spoke
+    pub protocols: Holds,
+    /// Disconnected clients and how long they were held, by each rule that
+    /// chose their pacing or banner
+    pub rules: Holds,
+    /// Clients currently connected by how long they've been held
+    pub ages: Ages,
+}
+
+impl Metrics {
+    /// Render in the Prometheus text exposition format
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: &dyn std::fmt::Display| {
+            let _ = writeln!(out, "# HELP tarssh_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE tarssh_{} {}", name, kind);
+            let _ = writeln!(out, "tarssh_{} {}", name, value);
+        };
+
+        metric(
+            "clients",
+            "gauge",
+            "Clients currently connected.",
+            &self.clients,
+        );
+        metric(
+            "connections_total",
+            "counter",
+            "Clients accepted.",
+            &self.connects,
+        );
+        metric(
+            "sent_bytes_total",
+            "counter",
+            "Bytes sent to clients.",
+            &self.bytes,
+        );
+        metric(
+            "uptime_seconds",
+            "gauge",
+            "Seconds since tarssh started.",
+            &self.uptime.as_secs_f64(),
+        );
+        metric(
+            "listeners",
+            "gauge",
+            "Listeners currently accepting.",
+            &self.listeners,
+        );
+        metric(
+            "bind_failures_total",
+            "counter",
+            "Failed attempts to bind listeners.",
+            &self.bind_failures,
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP tarssh_clients_by_age Clients currently connected, by how long they've been held."
+        );
+        let _ = writeln!(out, "# TYPE tarssh_clients_by_age gauge");
+        for (age, count) in self.ages.iter() {
+            let _ = writeln!(out, "tarssh_clients_by_age{{age=\"{}\"}} {}", age, count);
+        }
+
+        if !self.versions.is_empty() {
+            let _ = writeln!(
+                out,
+                "# HELP tarssh_client_version_connections Clients sending the most common SSH versions."
+            );
+            let _ = writeln!(out, "# TYPE tarssh_client_version_connections gauge");
+            for (version, count) in &self.versions {
+                let _ = writeln!(
+                    out,
+                    "tarssh_client_version_connections{{version=\"{}\"}} {}",
+                    version, count
+                );
+            }
+        }
+
+        if !self.tags.is_empty() {
+            let _ = writeln!(
+                out,
+                "# HELP tarssh_tagged_connections_total Disconnected clients carrying each tag."
+            );
+            let _ = writeln!(out, "# TYPE tarssh_tagged_connections_total counter");
+            for (tag, count) in &self.tags {
+                let _ = writeln!(
+                    out,
+                    "tarssh_tagged_connections_total{{tag=\"{}\"}} {}",
+                    tag, count
+                );
+            }
+        }
+
+        if !self.listened.is_empty() {
+            let _ = writeln!(
+                out,
+                "# HELP tarssh_listener_connections_total Clients accepted by each labelled listener."
+            );
+            let _ = writeln!(out, "# TYPE tarssh_listener_connections_total counter");
+            for (name, count) in &self.listened {
+                let _ = writeln!(
+                    out,
+                    "tarssh_listener_connections_total{{listener=\"{}\"}} {}",
+                    name, count
+                );
+            }
+        }
+
+        prometheus_holds(
+            &mut out,
+            "protocol",
+            "the protocol their banner spoke",
+            &self.protocols,
+        );
+        prometheus_holds(
+            &mut out,
+            "rule",
+            "each rule that chose their pacing or banner",
+            &self.rules,
+        );
+        out
+    }
+
+    /// Render as a single line of InfluxDB line protocol, timestamped with
+    /// nanoseconds since the Unix epoch
+    pub fn influx(&self, timestamp: u128) -> String {
+        let mut out = format!(
+            "tarssh clients={}i,connections={}i,sent_bytes={}i,uptime={},listeners={}i,bind_failures={}i {}\n",
+            self.clients,
+            self.connects,
+            self.bytes,
+            self.uptime.as_secs_f64(),
+            self.listeners,
+            self.bind_failures,
+            timestamp
+        );
+        for (age, count) in self.ages.iter() {
+            let _ = writeln!(
+                out,
+                "tarssh_age,age={} clients={}i {}",
+                age, count, timestamp
+            );
+        }
+        for (version, count) in &self.versions {
+            let _ = writeln!(
+                out,
+                "tarssh_client_version,version={} connections={}i {}",
+                version, count, timestamp
+            );
+        }
+        for (tag, count) in &self.tags {
+            let _ = writeln!(
+                out,
+                "tarssh_tag,tag={} connections={}i {}",
+                tag, count, timestamp
+            );
+        }
+        for (name, count) in &self.listened {
+            let _ = writeln!(
+                out,
+                "tarssh_listener,listener={} connections={}i {}",
+                name, count, timestamp
+            );
+        }
+        for (name, hold) in self.protocols.iter() {
+            let _ = writeln!(
+                out,
+                "tarssh_protocol,protocol={} connections={}i,held={} {}",
+                name,
+                hold.connections,
+                hold.held.as_secs_f64(),
+                timestamp
+            );
+        }
+        for (name, hold) in self.rules.iter() {
+            let _ = writeln!(
+                out,
+                "tarssh_rule,rule={} connections={}i,held={} {}",
+                name,
+                hold.connections,
+                hold.held.as_secs_f64(),
+                timestamp
+            );
+        }
+        out
+    }
+}
+
+/// Where to send InfluxDB line protocol: `udp://host:port` or `unix:///path`
+#[derive(Debug, Clone)]
+pub enum InfluxTarget {
+    Udp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for InfluxTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(addr) = s.strip_prefix("udp://") {
+            Ok(InfluxTarget::Udp(addr.to_string()))
+        } else if let Some(path) = s.strip_prefix("unix://") {
+            Ok(InfluxTarget::Unix(path.into()))
+        } else {
+            Err("expected udp://host:port or unix:///path".to_string())
+        }
+    }
+}
+
+impl std::fmt::Display for InfluxTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InfluxTarget::Udp(addr) => write!(f, "udp://{}", addr),
+            InfluxTarget::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+/// A connected datagram socket for sending line protocol to Telegraf or
+/// collectd
+pub enum InfluxSink {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+
+impl InfluxSink {
+    pub fn connect(target: &InfluxTarget) -> io::Result<Self> {
+        let sink = match target {
+            InfluxTarget::Udp(addr) => InfluxSink::Udp(udp_connect(addr)?),
+            #[cfg(unix)]
+            InfluxTarget::Unix(path) => {
+                let sock = UnixDatagram::unbound()?;
+                sock.connect(path)?;
+                sock.set_nonblocking(true)?;
+                InfluxSink::Unix(sock)
+            }
+            #[cfg(not(unix))]
+            InfluxTarget::Unix(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Unix sockets are not supported on this platform",
+                ))
+            }
+        };
+        Ok(sink)
+    }
+
+    pub fn send(&self, metrics: &Metrics) -> io::Result<()> {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let line = metrics.influx(timestamp);
+        match self {
+            InfluxSink::Udp(sock) => sock.send(line.as_bytes()),
+            #[cfg(unix)]
+            InfluxSink::Unix(sock) => sock.send(line.as_bytes()),
+        }
+        .map(drop)
+    }
+}
+
+/// Counters and gauges sent to a StatsD or DogStatsD collector, counters as
+/// the change since the last send
+pub struct StatsdSink {
+    sock: UdpSocket,
+    prefix: String,
+    last: Metrics,
+}
+
+impl StatsdSink {
+    pub fn connect(addr: &str, prefix: String) -> io::Result<Self> {
+        Ok(Self {
+            sock: udp_connect(addr)?,
+            prefix,
+            last: Metrics::default(),
+        })
+    }
+
+    pub fn send(&mut self, metrics: &Metrics) -> io::Result<()> {
+        let lines = self.lines(metrics);
+        self.sock.send(lines.as_bytes()).map(drop)
+    }
+
+    fn lines(&mut self, metrics: &Metrics) -> String {
+        let prefix = &self.prefix;
+        let last = &self.last;
+        let mut out = String::new();
+        let mut counter = |name: &str, now: u64, then: u64| {
+            let _ = writeln!(out, "{}.{}:{}|c", prefix, name, now.saturating_sub(then));
+        };
+        counter("connects", metrics.connects, last.connects);
+        counter(
+            "disconnects",
+            metrics.connects - metrics.clients as u64,
+            last.connects - last.clients as u64,
+        );
+        counter("bytes", metrics.bytes, last.bytes);
+        counter("bind_failures", metrics.bind_failures, last.bind_failures);
+        for (tag, count) in &metrics.tags {
+            let then = last
+                .tags
+                .iter()
+                .find(|(key, _)| key == tag)
+                .map_or(0, |(_, count)| *count);
+            counter(&format!("tagged.{}", tag), *count, then);
+        }
+        for (name, count) in &metrics.listened {
+            let then = last
+                .listened
+                .iter()
+                .find(|(key, _)| key == name)
+                .map_or(0, |(_, count)| *count);
+            counter(&format!("listener.{}", name), *count, then);
+        }
+        let by = [
+            ("protocol", &metrics.protocols, &last.protocols),
+            ("rule", &metrics.rules, &last.rules),
+        ];
+        for (kind, holds, last) in &by {
+            for (name, hold) in holds.iter() {
+                let then = last.get(name).copied().unwrap_or_default();
+                counter(
+                    &format!("{}.{}.connections", kind, name),
+                    hold.connections,
+                    then.connections,
+                );
+                counter(
+                    &format!("{}.{}.held_ms", kind, name),
+                    hold.held.as_millis() as u64,
+                    then.held.as_millis() as u64,
+                );
+            }
+        }
+        let _ = writeln!(out, "{}.listeners:{}|g", prefix, metrics.listeners);
+        for (age, count) in metrics.ages.iter() {
+            let _ = writeln!(out, "{}.ages.{}:{}|g", prefix, age, count);
+        }
+        let _ = write!(out, "{}.clients:{}|g", prefix, metrics.clients);
+        self.last = metrics.clone();
+        out
+    }
+}
+
+/// Counters of the clients and seconds held under each of `holds`, labelled
+/// with `label`
+fn prometheus_holds(out: &mut String, label: &str, by: &str, holds: &Holds) {
+    if holds.is_empty() {
+        return;
+    }
+    let _ = writeln!(
+        out,
+        "# HELP tarssh_{}_connections_total Disconnected clients, by {}.",
+        label, by
+    );
+    let _ = writeln!(out, "# TYPE tarssh_{}_connections_total counter", label);
+    for (name, hold) in holds.iter() {
+        let _ = writeln!(
+            out,
+            "tarssh_{}_connections_total{{{}=\"{}\"}} {}",
+            label, label, name, hold.connections
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP tarssh_{}_held_seconds_total Seconds disconnected clients were held, by {}.",
+        label, by
+    );
+    let _ = writeln!(out, "# TYPE tarssh_{}_held_seconds_total counter", label);
+    for (name, hold) in holds.iter() {
+        let _ = writeln!(
+            out,
+            "tarssh_{}_held_seconds_total{{{}=\"{}\"}} {}",
+            label,
+            label,
+            name,
+            hold.held.as_secs_f64()
+        );
+    }
+}
+
+/// A UDP socket connected to `addr`, a host and port
+fn udp_connect(addr: &str) -> io::Result<UdpSocket> {
+    let addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses found"))?;
+    let bind: SocketAddr = match addr {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+    };
+    let sock = UdpSocket::bind(bind)?;
+    sock.connect(addr)?;
+    sock.set_nonblocking(true)?;
+    Ok(sock)
+}
+
+/// A file for node_exporter's textfile collector to pick metrics up from, so
+/// they're scraped along with the host's own
+pub struct TextfileSink {
+    path: PathBuf,
+    partial: PathBuf,
+}
+
+impl TextfileSink {
+    /// Write to `path`, which must end in `.prom` for the collector to read
+    /// it, checking it can be written now rather than on the first send
+    pub fn create(path: PathBuf) -> io::Result<Self> {
+        if path.extension().is_none_or(|ext| ext != "prom") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "file name must end in .prom",
+            ));
+        }
+        // Written alongside and renamed over, as the collector may read at
+        // any time, and ignores files not ending in .prom
+        let mut partial = OsString::from(path.as_os_str());
+        partial.push(".part");
+        let partial = PathBuf::from(partial);
+        std::fs::File::create(&partial)?;
+        std::fs::remove_file(&partial)?;
+        Ok(Self { path, partial })
+    }
+
+    /// Replace the file's metrics
+    pub fn send(&self, metrics: &Metrics) -> io::Result<()> {
+        std::fs::write(&self.partial, metrics.prometheus())?;
+        std::fs::rename(&self.partial, &self.path)
+    }
+}
+
+/// A Prometheus Pushgateway to push metrics to, given as an `http://` or
+/// `https://` URL.
+///
+/// Without a path, metrics are pushed to the `tarssh` job.
+#[derive(Debug, Clone)]
+pub struct Pushgateway {
+    url: HttpUrl,
+}
+
+impl FromStr for Pushgateway {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let url: HttpUrl = s.trim_end_matches('/').parse()?;
+        Ok(Self {
+            url: url.or_path("/metrics/job/tarssh"),
+        })
+    }
+}
+
+impl std::fmt::Display for Pushgateway {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.url.fmt(f)
+    }
+}
+
+/// A resolved Pushgateway, ready to push to without further lookups
+pub struct Pusher {
+    gateway: Pushgateway,
+    addr: SocketAddr,
+}
+
+impl Pusher {
+    /// Resolve the gateway's address, defaulting to port 9091 over HTTP or
+    /// 443 over HTTPS
+    pub fn resolve(gateway: Pushgateway) -> io::Result<Self> {
+        let addr = gateway
+            .url
+            .host_port(9091)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses found"))?;
+
+        Ok(Self { gateway, addr })
+    }
+
+    /// Replace the metrics held by the gateway in the background
+    pub fn push(&self, metrics: &Metrics) {
+        let body = metrics.prometheus();
+        let url = self.gateway.url.clone();
+        let addr = self.addr;
+
+        tokio::spawn(async move {
+            let headers = [("Content-Type", "text/plain; version=0.0.4")];
+            let put = url.request_at(addr, "PUT", &headers, body.as_bytes());
+            match tokio::time::timeout(PUSH_TIMEOUT, put).await {
+                Ok(Ok(_)) => debug!("push, addr: {}, status: ok", addr),
+                Ok(Err(err)) => warn!("push, addr: {}, error: {}", addr, err),
+                Err(_) => warn!("push, addr: {}, error: timed out", addr),
+            }
+        });
+    }
+}
+
+#[test]
+fn test_pushgateway_url() {
+    let gateway: Pushgateway = "http://pushgateway:9091".parse().unwrap();
+    assert_eq!(
+        gateway.to_string(),
+        "http://pushgateway:9091/metrics/job/tarssh"
+    );
+    let gateway: Pushgateway = "http://[::1]:9091/metrics/job/tarpit/instance/a/"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        gateway.to_string(),
+        "http://[::1]:9091/metrics/job/tarpit/instance/a"
+    );
+    assert_eq!(gateway.url.host_port(9091), ("::1", 9091));
+    let gateway: Pushgateway = "http://pushgateway".parse().unwrap();
+    assert_eq!(gateway.url.host_port(9091), ("pushgateway", 9091));
+
+    assert!("http:///metrics".parse::<Pushgateway>().is_err());
+    assert!("pushgateway:9091".parse::<Pushgateway>().is_err());
+    assert_eq!(
+        "https://pushgateway".parse::<Pushgateway>().is_ok(),
+        cfg!(feature = "tls")
+    );
+}
+
+#[test]
+fn test_textfile() {
+    let dir = std::env::temp_dir().join(format!("tarssh-textfile-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    assert!(TextfileSink::create(dir.join("tarssh.txt")).is_err());
+    assert!(TextfileSink::create(dir.join("missing/tarssh.prom")).is_err());
+
+    let sink = TextfileSink::create(dir.join("tarssh.prom")).unwrap();
+    let metrics = Metrics {
+        clients: 2,
+        ..Metrics::default()
+    };
+    sink.send(&metrics).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dir.join("tarssh.prom")).unwrap(),
+        metrics.prometheus()
+    );
+    assert!(!dir.join("tarssh.prom.part").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_influx_line() {
+    let metrics = Metrics {
+        clients: 2,
+        connects: 5,
+        bytes: 120,
+        uptime: Duration::from_millis(1500),
+        listeners: 1,
+        bind_failures: 0,
+        versions: vec![("SSH-2.0-Go".to_string(), 3)],
+        tags: vec![("canary".to_string(), 1)],
+        listened: vec![("dmz".to_string(), 4)],
+        protocols: {
+            let mut holds = Holds::default();
+            holds.record("smtp", Duration::from_millis(2500));
+            holds
+        },
+        rules: {
+            let mut holds = Holds::default();
+            holds.record("delay.30s", Duration::from_secs(40));
+            holds
+        },
+        ages: [Duration::from_secs(5), Duration::from_secs(7200)]
+            .iter()
+            .copied()
+            .collect(),
+    };
+    assert_eq!(
+        metrics.influx(1_654_560_000_000_000_000),
+        "tarssh clients=2i,connections=5i,sent_bytes=120i,uptime=1.5,listeners=1i,bind_failures=0i 1654560000000000000\n\
+         tarssh_age,age=under_1m clients=1i 1654560000000000000\n\
+         tarssh_age,age=under_10m clients=0i 1654560000000000000\n\
+         tarssh_age,age=under_1h clients=0i 1654560000000000000\n\
+         tarssh_age,age=under_1d clients=1i 1654560000000000000\n\
+         tarssh_age,age=over_1d clients=0i 1654560000000000000\n\
+         tarssh_client_version,version=SSH-2.0-Go connections=3i 1654560000000000000\n\
+         tarssh_tag,tag=canary connections=1i 1654560000000000000\n\
+         tarssh_listener,listener=dmz connections=4i 1654560000000000000\n\
+         tarssh_protocol,protocol=smtp connections=1i,held=2.5 1654560000000000000\n\
+         tarssh_rule,rule=delay.30s connections=1i,held=40 1654560000000000000\n"
+    );
+}
+
+#[test]
+fn test_prometheus_holds() {
+    let mut metrics = Metrics::default();
+    assert!(!metrics.prometheus().contains("tarssh_protocol"));
+    assert!(!metrics.prometheus().contains("tarssh_rule"));
+
+    metrics.protocols.record("ssh", Duration::from_secs(90));
+    metrics.protocols.record("ssh", Duration::from_millis(500));
+    metrics.protocols.record("smtp", Duration::from_secs(3));
+    let text = metrics.prometheus();
+    let start = text.find("# HELP tarssh_protocol").unwrap();
+    assert_eq!(
+        &text[start..],
+        "# HELP tarssh_protocol_connections_total Disconnected clients, by the protocol their banner spoke.\n\
+         # TYPE tarssh_protocol_connections_total counter\n\
+         tarssh_protocol_connections_total{protocol=\"smtp\"} 1\n\
+         tarssh_protocol_connections_total{protocol=\"ssh\"} 2\n\
+         # HELP tarssh_protocol_held_seconds_total Seconds disconnected clients were held, by the protocol their banner spoke.\n\
+         # TYPE tarssh_protocol_held_seconds_total counter\n\
+         tarssh_protocol_held_seconds_total{protocol=\"smtp\"} 3\n\
+         tarssh_protocol_held_seconds_total{protocol=\"ssh\"} 90.5\n"
+    );
+
+    metrics
+        .rules
+        .record("banner.cisco.txt", Duration::from_secs(7));
+    let text = metrics.prometheus();
+    assert!(text.contains("tarssh_rule_connections_total{rule=\"banner.cisco.txt\"} 1\n"));
+    assert!(text.contains("tarssh_rule_held_seconds_total{rule=\"banner.cisco.txt\"} 7\n"));
+}
+
+#[test]
+fn test_statsd_lines() {
+    let mut sink = StatsdSink::connect("127.0.0.1:8125", "tarpit".to_string()).unwrap();
+    let mut metrics = Metrics {
+        clients: 2,
+        connects: 5,
+        bytes: 120,
+        tags: vec![("canary".to_string(), 1)],
+        ..Metrics::default()
+    };
+    assert_eq!(
+        sink.lines(&metrics),
+        "tarpit.connects:5|c\n\
+         tarpit.disconnects:3|c\n\
+         tarpit.bytes:120|c\n\
+         tarpit.bind_failures:0|c\n\
+         tarpit.tagged.canary:1|c\n\
+         tarpit.listeners:0|g\n\
+         tarpit.ages.under_1m:0|g\n\
+         tarpit.ages.under_10m:0|g\n\
+         tarpit.ages.under_1h:0|g\n\
+         tarpit.ages.under_1d:0|g\n\
+         tarpit.ages.over_1d:0|g\n\
+         tarpit.clients:2|g"
+    );
+
+    metrics.connects = 6;
+    metrics.bytes = 200;
+    assert_eq!(
+        sink.lines(&metrics),
+        "tarpit.connects:1|c\n\
+         tarpit.disconnects:1|c\n\
+         tarpit.bytes:80|c\n\
+         tarpit.bind_failures:0|c\n\
+         tarpit.tagged.canary:0|c\n\
+         tarpit.listeners:0|g\n\
+         tarpit.ages.under_1m:0|g\n\
+         tarpit.ages.under_10m:0|g\n\
+         tarpit.ages.under_1h:0|g\n\
+         tarpit.ages.under_1d:0|g\n\
+         tarpit.ages.over_1d:0|g\n\
+         tarpit.clients:2|g"
+    );
+}
+
+#[test]
+fn test_statsd_holds() {
+    let mut sink = StatsdSink::connect("127.0.0.1:8125", "tarpit".to_string()).unwrap();
+    let mut metrics = Metrics::default();
+    metrics.protocols.record("ssh", Duration::from_secs(2));
+    metrics.rules.record("listener.dmz", Duration::from_secs(2));
+    let lines = sink.lines(&metrics);
+    assert!(lines.contains(
+        "tarpit.rule.listener.dmz.connections:1|c\ntarpit.rule.listener.dmz.held_ms:2000|c\n"
+    ));
+    assert!(
+        lines.contains("tarpit.protocol.ssh.connections:1|c\ntarpit.protocol.ssh.held_ms:2000|c\n")
+    );
+
+    // Only what's changed since
+    metrics.protocols.record("ssh", Duration::from_millis(1500));
+    let lines = sink.lines(&metrics);
+    assert!(
+        lines.contains("tarpit.protocol.ssh.connections:1|c\ntarpit.protocol.ssh.held_ms:1500|c\n")
+    );
+}
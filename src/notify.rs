@@ -0,0 +1,59 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use log::warn;
+
+use crate::peer_addr::PeerAddr;
+use crate::sanitize;
+use crate::spool::unix_time;
+
+/// Delivers alerts on noteworthy client behaviour.
+///
+/// Alerts are always logged at warning level, and optionally appended to a
+/// file opened at startup, one line per alert:
+///
+/// ```txt
+/// 1654560000 canary 127.0.0.1:61986 str:db01.corp.example
+/// ```
+#[derive(Default)]
+pub struct Notifier {
+    file: Option<File>,
+}
+
+impl Notifier {
+    pub fn with_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self {
+            file: Some(OpenOptions::new().create(true).append(true).open(path)?),
+        })
+    }
+
+    pub fn alert<D: std::fmt::Display>(&mut self, kind: &str, peer: PeerAddr, detail: D) {
+        warn!("alert, kind: {}, peer: {}, detail: {}", kind, peer, detail);
+        self.append(kind, &peer, &detail);
+    }
+
+    /// Alert on something about the tarpit as a whole, rather than a client,
+    /// with `-` in place of the peer in the alert file
+    pub fn system_alert<D: std::fmt::Display>(&mut self, kind: &str, detail: D) {
+        warn!("alert, kind: {}, detail: {}", kind, detail);
+        self.append(kind, &"-", &detail);
+    }
+
+    fn append(&mut self, kind: &str, peer: &dyn std::fmt::Display, detail: &dyn std::fmt::Display) {
+        if let Some(file) = self.file.as_mut() {
+            let detail = detail.to_string();
+            let line = format!(
+                "{} {} {} {}",
+                unix_time(),
+                kind,
+                peer,
+                sanitize::text(&detail)
+            );
+            if let Err(err) = writeln!(file, "{}", line) {
+                warn!("alert, error: {}", err);
+                self.file = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! How much of what's been written to a connection is still waiting in the
+//! kernel, unsent or unacknowledged, which separates clients that actually
+//! read what they were sent from those whose network silently dropped it.
+
+use std::io;
+
+/// Bytes written to `sock` that the peer hasn't yet acknowledged
+#[cfg(target_os = "linux")]
+pub fn unacked<S: std::os::unix::io::AsRawFd>(sock: &S) -> io::Result<usize> {
+    let mut queued: libc::c_int = 0;
+    // SAFETY: SIOCOUTQ writes a single c_int to the pointer given
+    let ret = unsafe { libc::ioctl(sock.as_raw_fd(), libc::TIOCOUTQ, &mut queued) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(queued.max(0) as usize)
+}
+
+/// Bytes written to `sock` that the peer hasn't yet acknowledged
+#[cfg(target_os = "macos")]
+pub fn unacked<S: std::os::unix::io::AsRawFd>(sock: &S) -> io::Result<usize> {
+    let mut queued: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: the value is a live c_int of the length given
+    let ret = unsafe {
+        libc::getsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_NWRITE,
+            (&mut queued as *mut libc::c_int).cast(),
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(queued.max(0) as usize)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn unacked<S>(_sock: &S) -> io::Result<usize> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "output queues can't be measured on this platform",
+    ))
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[test]
+fn test_unacked() {
+    use std::io::Write;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sock = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let _peer = listener.accept().unwrap();
+    assert_eq!(unacked(&sock).unwrap(), 0);
+    sock.write_all(b"SSH-2.0-OpenSSH\r\n").unwrap();
+    assert!(unacked(&sock).unwrap() <= 17);
+}
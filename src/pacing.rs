@@ -0,0 +1,250 @@
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use tokio::time::MissedTickBehavior;
+
+use crate::dnsbl::Action;
+use tarssh::banner::Banner;
+
+/// How connections are paced and what they're sent, shared by all of them
+pub struct Pacing {
+    pub banner: Banner,
+    pub schedule: Schedule,
+    pub timeouts: Timeouts,
+    /// An SSH version line to send once a connection reaches this age, after
+    /// which it's left to stall
+    pub ssh_version: Option<(Duration, Vec<u8>)>,
+    /// Whether to cork connections around each write
+    pub cork: bool,
+    /// Delays for --tune-delays to choose between for each connection, in
+    /// place of the schedule
+    pub tuned: Vec<Duration>,
+    /// Banners for --banner-dir to choose between for each connection, in
+    /// place of the banner
+    pub banners: Vec<Banner>,
+    /// Settings of the --listen addresses that have their own, in place of
+    /// the shared ones
+    pub listeners: Vec<ListenerPacing>,
+    /// What to do with connections from hosts a --dnsbl lists
+    pub listed: Action,
+    /// How often to write to listed hosts, if they're to be slowed
+    pub listed_delay: Duration,
+}
+
+/// A listener's own banner and schedule
+pub struct ListenerPacing {
+    pub addr: SocketAddr,
+    /// Sent in place of the banner, and not followed by an SSH version line
+    pub banner: Option<Banner>,
+    /// Followed in place of the shared schedule
+    pub schedule: Option<Schedule>,
+}
+
+/// When to give up on a connection
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// How long writes may keep failing on a full socket buffer
+    pub write: Duration,
+    /// How long a connection that reads may go without the client sending
+    /// anything
+    pub initial_data: Option<Duration>,
+    /// How long any connection may last
+    pub lifetime: Option<Duration>,
+    /// A longer write timeout for connections that have lasted this long,
+    /// having proven they're real clients that are just slow
+    pub relaxed: Option<(Duration, Duration)>,
+}
+
+impl Timeouts {
+    /// The write timeout for a connection of the given age
+    pub fn write_at(&self, age: Duration) -> Duration {
+        match self.relaxed {
+            Some((after, relaxed)) if age >= after => relaxed.max(self.write),
+            _ => self.write,
+        }
+    }
+
+    /// Whether `failed` writes in a row, `interval` apart, have run out a
+    /// connection of the given age's write timeout
+    pub fn write_expired(&self, age: Duration, interval: Duration, failed: u32) -> bool {
+        interval.saturating_mul(failed) >= self.write_at(age)
+    }
+}
+
+/// One step of a pacing schedule
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Step {
+    /// Connection age this step applies from
+    pub after: Duration,
+    /// Time between writes
+    pub interval: Duration,
+    /// Banner chunks to send per write
+    pub chunks: u8,
+}
+
+/// A piecewise schedule of write intervals over a connection's lifetime.
+///
+/// Written as comma-separated `<age>=<interval>[x<chunks>]` steps, starting from
+/// an age of zero, e.g. `0s=1s,1m=10s,10m=30sx2` to write a chunk every second
+/// for the first minute, then every ten seconds, then two at a time every 30.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule(Vec<Step>);
+
+impl Schedule {
+    /// A schedule that never changes
+    pub fn fixed(interval: Duration) -> Self {
+        Self(vec![Step {
+            after: Duration::ZERO,
+            interval,
+            chunks: 1,
+        }])
+    }
+
+    /// The step in effect at the given connection age
+    pub fn at(&self, age: Duration) -> &Step {
+        self.0
+            .iter()
+            .rev()
+            .find(|step| step.after <= age)
+            .unwrap_or(&self.0[0])
+    }
+
+    /// The shortest interval in the schedule, which sets how often connections
+    /// are visited
+    pub fn min_interval(&self) -> Duration {
+        self.0
+            .iter()
+            .map(|step| step.interval)
+            .min()
+            .expect("schedule is never empty")
+    }
+
+    /// How many writes a connection held this long is sent
+    pub fn writes(&self, held: Duration) -> u64 {
+        let mut writes = 0;
+        let mut last = Duration::ZERO;
+        for (i, step) in self.0.iter().enumerate() {
+            let end = self.0.get(i + 1).map_or(held, |next| next.after.min(held));
+            if end > last {
+                let n = ((end - last).as_nanos() / step.interval.as_nanos()) as u64;
+                last += step.interval * n as u32;
+                writes += n;
+            }
+        }
+        writes
+    }
+}
+
+/// Parse what to do when ticks are missed: burst, delay or skip
+pub fn parse_missed_tick(s: &str) -> Result<MissedTickBehavior, String> {
+    match s {
+        "burst" => Ok(MissedTickBehavior::Burst),
+        "delay" => Ok(MissedTickBehavior::Delay),
+        "skip" => Ok(MissedTickBehavior::Skip),
+        _ => Err(format!(
+            "unknown behaviour {:?}, expected one of burst, delay, skip",
+            s
+        )),
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut steps: Vec<Step> = vec![];
+
+        for step in s.split(',') {
+            let (after, rest) = step
+                .split_once('=')
+                .ok_or_else(|| format!("expected <age>=<interval> in {:?}", step))?;
+            let (interval, chunks) = rest.split_once('x').unwrap_or((rest, "1"));
+
+            let step = Step {
+                after: humantime::parse_duration(after.trim()).map_err(|e| e.to_string())?,
+                interval: humantime::parse_duration(interval.trim()).map_err(|e| e.to_string())?,
+                chunks: chunks
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("chunks must be between 1 and 255 in {:?}", step))?,
+            };
+
+            if step.interval < Duration::from_secs(1) || step.interval.subsec_nanos() != 0 {
+                return Err(format!("interval must be whole seconds in {:?}", s));
+            }
+            if step.chunks == 0 {
+                return Err(format!("chunks must be between 1 and 255 in {:?}", s));
+            }
+            match steps.last() {
+                None if step.after != Duration::ZERO => {
+                    return Err("the first step must start at 0s".to_string())
+                }
+                Some(last) if last.after >= step.after => {
+                    return Err("step ages must increase".to_string())
+                }
+                _ => steps.push(step),
+            }
+        }
+
+        Ok(Self(steps))
+    }
+}
+
+#[test]
+fn test_schedule() {
+    let schedule: Schedule = "0s=1s, 1m=10s, 10m=30sx2".parse().unwrap();
+    assert_eq!(schedule.min_interval(), Duration::from_secs(1));
+    assert_eq!(
+        schedule.at(Duration::from_secs(59)).interval,
+        Duration::from_secs(1)
+    );
+    assert_eq!(
+        schedule.at(Duration::from_secs(60)).interval,
+        Duration::from_secs(10)
+    );
+    assert_eq!(schedule.at(Duration::from_secs(3600)).chunks, 2);
+    assert_eq!(schedule.writes(Duration::from_secs(59)), 59);
+    assert_eq!(schedule.writes(Duration::from_secs(11 * 60)), 60 + 54 + 2);
+
+    assert!("1s=1s".parse::<Schedule>().is_err());
+    assert!("0s=1s,1m=2s,30s=3s".parse::<Schedule>().is_err());
+    assert!("0s=500ms".parse::<Schedule>().is_err());
+    assert!("0s=1sx0".parse::<Schedule>().is_err());
+}
+
+#[test]
+fn test_relaxed_timeout() {
+    let timeouts = Timeouts {
+        write: Duration::from_secs(30),
+        initial_data: None,
+        lifetime: None,
+        relaxed: Some((Duration::from_secs(600), Duration::from_secs(300))),
+    };
+    assert_eq!(timeouts.write_at(Duration::from_secs(599)), timeouts.write);
+    assert_eq!(
+        timeouts.write_at(Duration::from_secs(600)),
+        Duration::from_secs(300)
+    );
+}
+
+#[test]
+fn test_write_expired() {
+    let timeouts = Timeouts {
+        write: Duration::from_secs(30),
+        initial_data: None,
+        lifetime: None,
+        relaxed: Some((
+            Duration::from_secs(600),
+            Duration::from_secs(u32::MAX as u64),
+        )),
+    };
+    let second = Duration::from_secs(1);
+    assert!(!timeouts.write_expired(Duration::ZERO, second, 29));
+    assert!(timeouts.write_expired(Duration::ZERO, second, 30));
+    // Relaxed for longer than a u16 of failures could count
+    let old = Duration::from_secs(600);
+    assert!(!timeouts.write_expired(old, second, u16::MAX as u32 + 1));
+    assert!(timeouts.write_expired(old, second, u32::MAX));
+    assert!(timeouts.write_expired(old, Duration::from_secs(u16::MAX as u64), u32::MAX));
+}
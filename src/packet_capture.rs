@@ -0,0 +1,379 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use log::{info, warn};
+
+use crate::spool::{self, Spool};
+
+/// Largest packet to record
+const SNAPLEN: u32 = 65535;
+
+/// The TCP SYN flag
+const SYN: u8 = 0x02;
+
+/// Link types we can find the IP header in
+const LINKTYPE_NULL: i32 = 0;
+const LINKTYPE_ETHERNET: i32 = 1;
+const LINKTYPE_RAW: i32 = 12;
+const LINKTYPE_RAW_ALT: i32 = 101;
+const LINKTYPE_LOOP: i32 = 108;
+const LINKTYPE_LINUX_SLL: i32 = 113;
+const LINKTYPE_LINUX_SLL2: i32 = 276;
+
+enum Peer {
+    Open(SocketAddr),
+    Close(SocketAddr),
+}
+
+/// Handle for telling the capture thread which peers to record.
+///
+/// Packets are recorded by a background thread into pcap files in a spool,
+/// starting a new file when the current one exceeds `rotate_size` bytes.  The
+/// BPF filter admits TCP on the listen ports, set once, and the recorder keeps
+/// new connections plus any packets to or from currently active peers.  Peers
+/// are matched in userspace rather than in the filter, which would otherwise
+/// need recompiling as each came and went, and grow a term for every one.
+pub struct PacketCapture {
+    tx: Sender<Peer>,
+}
+
+impl PacketCapture {
+    pub fn start(
+        interface: &str,
+        ports: Vec<u16>,
+        spool: Spool,
+        rotate_size: u64,
+    ) -> Result<Self, pcap::Error> {
+        let mut cap = pcap::Capture::from_device(interface)?
+            .promisc(false)
+            .snaplen(SNAPLEN as i32)
+            .timeout(1000)
+            .open()?;
+        cap.filter(&filter(&ports), true)?;
+
+        let (tx, rx) = mpsc::channel();
+        let recorder = Recorder {
+            linktype: cap.get_datalink().0,
+            cap,
+            rx,
+            peers: HashSet::new(),
+            spool,
+            rotate_size,
+            out: None,
+            written: 0,
+            files: 0,
+        };
+
+        std::thread::Builder::new()
+            .name("pcap".to_string())
+            .spawn(move || recorder.run())
+            .map_err(|e| pcap::Error::IoError(e.kind()))?;
+
+        Ok(Self { tx })
+    }
+
+    pub fn open(&self, peer: SocketAddr) {
+        let _ = self.tx.send(Peer::Open(peer));
+    }
+
+    pub fn close(&self, peer: SocketAddr) {
+        let _ = self.tx.send(Peer::Close(peer));
+    }
+}
+
+struct Recorder {
+    cap: pcap::Capture<pcap::Active>,
+    rx: Receiver<Peer>,
+    peers: HashSet<SocketAddr>,
+    linktype: i32,
+    spool: Spool,
+    rotate_size: u64,
+    out: Option<BufWriter<File>>,
+    written: u64,
+    files: u64,
+}
+
+impl Recorder {
+    fn run(mut self) {
+        loop {
+            loop {
+                match self.rx.try_recv() {
+                    Ok(Peer::Open(peer)) => self.peers.insert(canonical(peer)),
+                    Ok(Peer::Close(peer)) => self.peers.remove(&canonical(peer)),
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => return self.finish(),
+                };
+            }
+
+            let (header, data) = match self.cap.next() {
+                Ok(packet) if wanted(self.linktype, packet.data, &self.peers) => {
+                    (*packet.header, packet.data.to_vec())
+                }
+                Ok(_) | Err(pcap::Error::TimeoutExpired) => continue,
+                Err(err) => {
+                    warn!("pcap, error: {}", err);
+                    return self.finish();
+                }
+            };
+
+            if let Err(err) = self.write(&header, &data) {
+                warn!("pcap, error: {}", err);
+                return self.finish();
+            }
+        }
+    }
+
+    fn write(&mut self, header: &pcap::PacketHeader, data: &[u8]) -> io::Result<()> {
+        if self.out.is_none() || self.written >= self.rotate_size {
+            self.rotate()?;
+        }
+
+        let out = self.out.as_mut().expect("rotate() opens a file");
+        out.write_all(&(header.ts.tv_sec as u32).to_le_bytes())?;
+        out.write_all(&(header.ts.tv_usec as u32).to_le_bytes())?;
+        out.write_all(&header.caplen.to_le_bytes())?;
+        out.write_all(&header.len.to_le_bytes())?;
+        out.write_all(data)?;
+        self.written += 16 + data.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if let Some(mut out) = self.out.take() {
+            out.flush()?;
+        }
+
+        let name = spool::file_name(self.files, "pcap");
+        self.files += 1;
+        let mut out = BufWriter::new(self.spool.create(&name)?);
+
+        // Classic pcap global header: magic, version 2.4, UTC, no sigfigs
+        out.write_all(&0xa1b2_c3d4_u32.to_le_bytes())?;
+        out.write_all(&2_u16.to_le_bytes())?;
+        out.write_all(&4_u16.to_le_bytes())?;
+        out.write_all(&0_i32.to_le_bytes())?;
+        out.write_all(&0_u32.to_le_bytes())?;
+        out.write_all(&SNAPLEN.to_le_bytes())?;
+        out.write_all(&(self.linktype as u32).to_le_bytes())?;
+
+        info!("pcap, file: {}", name);
+        self.out = Some(out);
+        self.written = 24;
+        Ok(())
+    }
+
+    fn finish(mut self) {
+        if let Some(mut out) = self.out.take() {
+            let _ = out.flush();
+        }
+    }
+}
+
+/// The BPF filter for TCP on any of `ports`
+fn filter(ports: &[u16]) -> String {
+    let ports = ports
+        .iter()
+        .map(|port| format!("port {}", port))
+        .collect::<Vec<_>>()
+        .join(" or ");
+    format!("tcp and ({})", ports)
+}
+
+/// `peer` with any IPv4-mapped address unmapped, as it appears on the wire
+fn canonical(peer: SocketAddr) -> SocketAddr {
+    SocketAddr::new(peer.ip().to_canonical(), peer.port())
+}
+
+/// A TCP segment's source, destination and flags
+#[derive(Debug, PartialEq)]
+struct Segment {
+    src: SocketAddr,
+    dst: SocketAddr,
+    flags: u8,
+}
+
+/// Find the TCP segment in a captured frame, if we know its link type and it
+/// carries one without IPv6 extension headers in the way
+fn segment(linktype: i32, frame: &[u8]) -> Option<Segment> {
+    let packet = match linktype {
+        LINKTYPE_NULL | LINKTYPE_LOOP => frame.get(4..)?,
+        LINKTYPE_RAW | LINKTYPE_RAW_ALT => frame,
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            // Skip any 802.1Q or 802.1ad tags
+            while matches!(frame.get(offset..offset + 2)?, [0x81, 0x00] | [0x88, 0xa8]) {
+                offset += 4;
+            }
+            frame.get(offset + 2..)?
+        }
+        LINKTYPE_LINUX_SLL => frame.get(16..)?,
+        LINKTYPE_LINUX_SLL2 => frame.get(20..)?,
+        _ => return None,
+    };
+
+    let (src, dst, tcp) = match packet.first()? >> 4 {
+        4 => {
+            let header = (*packet.first()? & 0x0f) as usize * 4;
+            if *packet.get(9)? != 6 || header < 20 {
+                return None;
+            }
+            let src = <[u8; 4]>::try_from(packet.get(12..16)?).ok()?;
+            let dst = <[u8; 4]>::try_from(packet.get(16..20)?).ok()?;
+            (
+                IpAddr::from(Ipv4Addr::from(src)),
+                IpAddr::from(Ipv4Addr::from(dst)),
+                packet.get(header..)?,
+            )
+        }
+        6 => {
+            if *packet.get(6)? != 6 {
+                return None;
+            }
+            let src = <[u8; 16]>::try_from(packet.get(8..24)?).ok()?;
+            let dst = <[u8; 16]>::try_from(packet.get(24..40)?).ok()?;
+            (
+                IpAddr::from(Ipv6Addr::from(src)),
+                IpAddr::from(Ipv6Addr::from(dst)),
+                packet.get(40..)?,
+            )
+        }
+        _ => return None,
+    };
+
+    let ports = tcp.get(0..4)?;
+    Some(Segment {
+        src: SocketAddr::new(src, u16::from_be_bytes([ports[0], ports[1]])),
+        dst: SocketAddr::new(dst, u16::from_be_bytes([ports[2], ports[3]])),
+        flags: *tcp.get(13)?,
+    })
+}
+
+/// Whether to record a frame the filter let through: a new connection, or one
+/// to or from an active peer.  Frames we can't make sense of are kept, since
+/// the filter has already narrowed them down to the tarpit's own ports.
+fn wanted(linktype: i32, frame: &[u8], peers: &HashSet<SocketAddr>) -> bool {
+    match segment(linktype, frame) {
+        Some(segment) => {
+            segment.flags & SYN != 0 || peers.contains(&segment.src) || peers.contains(&segment.dst)
+        }
+        None => true,
+    }
+}
+
+#[cfg(test)]
+fn ipv4_frame(src: [u8; 4], dst: [u8; 4], ports: (u16, u16), flags: u8) -> Vec<u8> {
+    let mut frame = vec![0; 14];
+    frame[12..14].copy_from_slice(&[0x08, 0x00]);
+    let mut ip = vec![0x45, 0, 0, 40, 0, 0, 0x40, 0, 64, 6, 0, 0];
+    ip.extend_from_slice(&src);
+    ip.extend_from_slice(&dst);
+    frame.extend_from_slice(&ip);
+    let mut tcp = vec![0; 20];
+    tcp[0..2].copy_from_slice(&ports.0.to_be_bytes());
+    tcp[2..4].copy_from_slice(&ports.1.to_be_bytes());
+    tcp[12] = 0x50;
+    tcp[13] = flags;
+    frame.extend_from_slice(&tcp);
+    frame
+}
+
+#[test]
+fn test_filter() {
+    assert_eq!(filter(&[22]), "tcp and (port 22)");
+    assert_eq!(filter(&[22, 2222]), "tcp and (port 22 or port 2222)");
+}
+
+#[test]
+fn test_segment_ipv4() {
+    let frame = ipv4_frame([192, 0, 2, 1], [192, 0, 2, 2], (50000, 22), SYN);
+    assert_eq!(
+        segment(LINKTYPE_ETHERNET, &frame),
+        Some(Segment {
+            src: "192.0.2.1:50000".parse().unwrap(),
+            dst: "192.0.2.2:22".parse().unwrap(),
+            flags: SYN,
+        })
+    );
+
+    // The same behind a VLAN tag
+    let mut tagged = frame[..12].to_vec();
+    tagged.extend_from_slice(&[0x81, 0x00, 0x00, 0x2a]);
+    tagged.extend_from_slice(&frame[12..]);
+    assert_eq!(
+        segment(LINKTYPE_ETHERNET, &tagged),
+        segment(LINKTYPE_ETHERNET, &frame)
+    );
+
+    // Truncated frames and unknown link types don't parse
+    assert_eq!(segment(LINKTYPE_ETHERNET, &frame[..40]), None);
+    assert_eq!(
+        segment(LINKTYPE_RAW, &frame[14..]),
+        segment(LINKTYPE_ETHERNET, &frame)
+    );
+    assert_eq!(segment(147, &frame), None);
+}
+
+#[test]
+fn test_segment_ipv6() {
+    let mut frame = vec![0; 16];
+    frame[14..16].copy_from_slice(&[0x86, 0xdd]);
+    let mut ip = vec![0x60, 0, 0, 0, 0, 20, 6, 64];
+    ip.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+    ip.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+    frame.extend_from_slice(&ip);
+    let mut tcp = vec![0; 20];
+    tcp[0..2].copy_from_slice(&22u16.to_be_bytes());
+    tcp[2..4].copy_from_slice(&40000u16.to_be_bytes());
+    tcp[13] = 0x18;
+    frame.extend_from_slice(&tcp);
+
+    assert_eq!(
+        segment(LINKTYPE_LINUX_SLL, &frame),
+        Some(Segment {
+            src: "[2001:db8::1]:22".parse().unwrap(),
+            dst: "[2001:db8::2]:40000".parse().unwrap(),
+            flags: 0x18,
+        })
+    );
+
+    // Extension headers aren't followed
+    frame[16 + 6] = 0;
+    assert_eq!(segment(LINKTYPE_LINUX_SLL, &frame), None);
+}
+
+#[test]
+fn test_wanted() {
+    let peer: SocketAddr = "192.0.2.1:50000".parse().unwrap();
+    let peers: HashSet<_> = Some(canonical("[::ffff:192.0.2.1]:50000".parse().unwrap()))
+        .into_iter()
+        .collect();
+    assert!(peers.contains(&peer));
+
+    let from = |src: [u8; 4], flags| ipv4_frame(src, [192, 0, 2, 9], (50000, 22), flags);
+    let to = |dst: [u8; 4]| ipv4_frame([192, 0, 2, 9], dst, (22, 50000), 0x10);
+    // New connections from anyone, and anything to or from active peers
+    assert!(wanted(
+        LINKTYPE_ETHERNET,
+        &from([198, 51, 100, 1], SYN),
+        &peers
+    ));
+    assert!(wanted(
+        LINKTYPE_ETHERNET,
+        &from([192, 0, 2, 1], 0x18),
+        &peers
+    ));
+    assert!(wanted(LINKTYPE_ETHERNET, &to([192, 0, 2, 1]), &peers));
+    // Stragglers from connections already closed or never accepted
+    assert!(!wanted(
+        LINKTYPE_ETHERNET,
+        &from([198, 51, 100, 1], 0x10),
+        &peers
+    ));
+    assert!(!wanted(LINKTYPE_ETHERNET, &to([198, 51, 100, 1]), &peers));
+    // Anything unparseable is kept
+    assert!(wanted(147, &[0; 8], &peers));
+}
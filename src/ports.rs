@@ -0,0 +1,149 @@
+//! The source ports of each peer's connections, flagging the fixed or linear
+//! sequences left by scanners such as masscan and zmap, which craft their own
+//! packets, where real clients get randomised ports from their OS.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::IpAddr;
+use std::time::Instant;
+
+/// Ports remembered per peer
+const HISTORY: usize = 8;
+
+/// Connections a peer must make before its ports are classified
+const MIN_SAMPLES: usize = 4;
+
+/// Peers tracked at once
+const TRACKED: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    /// The same port every time
+    Fixed,
+    /// Ports a constant stride apart
+    Linear(i32),
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Fixed => f.write_str("fixed"),
+            Pattern::Linear(_) => f.write_str("linear"),
+        }
+    }
+}
+
+/// The pattern in a peer's recent ports, if there's one to see
+fn classify(ports: &VecDeque<u16>) -> Option<Pattern> {
+    if ports.len() < MIN_SAMPLES {
+        return None;
+    }
+    // Strides wrap around the port range
+    let stride = |a: u16, b: u16| b.wrapping_sub(a) as i16 as i32;
+    let first = stride(ports[0], ports[1]);
+    let constant = ports
+        .iter()
+        .zip(ports.iter().skip(1))
+        .all(|(a, b)| stride(*a, *b) == first);
+    match first {
+        _ if !constant => None,
+        0 => Some(Pattern::Fixed),
+        _ => Some(Pattern::Linear(first)),
+    }
+}
+
+struct History {
+    ports: VecDeque<u16>,
+    seen: Instant,
+}
+
+#[derive(Default)]
+pub struct PortPatterns {
+    peers: HashMap<IpAddr, History>,
+    fixed: u64,
+    linear: u64,
+}
+
+impl PortPatterns {
+    /// Note a connection's source port, returning the pattern its peer's
+    /// ports now follow, if any
+    pub fn connected(&mut self, ip: IpAddr, port: u16, now: Instant) -> Option<Pattern> {
+        if !self.peers.contains_key(&ip) && self.peers.len() >= TRACKED {
+            // Make room by forgetting the least recently seen
+            let stalest = self
+                .peers
+                .iter()
+                .min_by_key(|(_, history)| history.seen)
+                .map(|(ip, _)| *ip);
+            if let Some(stalest) = stalest {
+                self.peers.remove(&stalest);
+            }
+        }
+
+        let history = self.peers.entry(ip).or_insert_with(|| History {
+            ports: VecDeque::with_capacity(HISTORY),
+            seen: now,
+        });
+        if history.ports.len() == HISTORY {
+            history.ports.pop_front();
+        }
+        history.ports.push_back(port);
+        history.seen = now;
+
+        let pattern = classify(&history.ports);
+        match pattern {
+            Some(Pattern::Fixed) => self.fixed += 1,
+            Some(Pattern::Linear(_)) => self.linear += 1,
+            None => (),
+        }
+        pattern
+    }
+
+    /// A peer's recent ports, oldest first, e.g. `1024,1025,1026`
+    pub fn ports(&self, ip: IpAddr) -> String {
+        self.peers.get(&ip).map_or_else(String::new, |history| {
+            let ports: Vec<_> = history.ports.iter().map(u16::to_string).collect();
+            ports.join(",")
+        })
+    }
+}
+
+/// Peers tracked, and connections flagged with each pattern
+impl fmt::Display for PortPatterns {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "peers: {}, fixed: {}, linear: {}",
+            self.peers.len(),
+            self.fixed,
+            self.linear
+        )
+    }
+}
+
+#[test]
+fn test_port_patterns() {
+    let mut patterns = PortPatterns::default();
+    let now = Instant::now();
+    let a: IpAddr = [192, 0, 2, 1].into();
+    let b: IpAddr = [192, 0, 2, 2].into();
+    let c: IpAddr = [192, 0, 2, 3].into();
+
+    for _ in 0..MIN_SAMPLES - 1 {
+        assert_eq!(patterns.connected(a, 61000, now), None);
+    }
+    assert_eq!(patterns.connected(a, 61000, now), Some(Pattern::Fixed));
+    assert_eq!(patterns.connected(a, 40000, now), None);
+
+    for port in [65534, 65535, 0, 1] {
+        patterns.connected(b, port, now);
+    }
+    assert_eq!(patterns.ports(b), "65534,65535,0,1");
+    assert_eq!(patterns.connected(b, 2, now), Some(Pattern::Linear(1)));
+    assert_eq!(Pattern::Linear(1).to_string(), "linear");
+
+    for port in [51234, 38211, 60012, 44107, 33960] {
+        assert_eq!(patterns.connected(c, port, now), None);
+    }
+    assert_eq!(patterns.to_string(), "peers: 3, fixed: 1, linear: 2");
+}
@@ -0,0 +1,152 @@
+//! A lightweight profiler for the event loop, timing each phase of its work so
+//! slow ticks at huge connection counts can be diagnosed on hosts where
+//! external profilers aren't an option.
+//!
+//! Phases are named as `;`-separated stacks, e.g. `tick;disconnect`, and can
+//! be dumped as folded stacks for `flamegraph.pl` or `inferno-flamegraph`.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Span {
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+pub struct Profiler {
+    enabled: bool,
+    since: Instant,
+    spans: BTreeMap<&'static str, Span>,
+}
+
+impl Profiler {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            since: Instant::now(),
+            spans: BTreeMap::new(),
+        }
+    }
+
+    /// Start timing a phase, if profiling
+    pub fn start(&self) -> Option<Instant> {
+        self.enabled.then(Instant::now)
+    }
+
+    /// Finish timing a phase started with `start`
+    pub fn record(&mut self, stack: &'static str, started: Option<Instant>) {
+        if let Some(started) = started {
+            let elapsed = started.elapsed();
+            let span = self.spans.entry(stack).or_default();
+            span.count += 1;
+            span.total += elapsed;
+            span.max = span.max.max(elapsed);
+        }
+    }
+
+    /// Time in a phase less the time in the phases within it
+    fn self_time(&self, stack: &str) -> Duration {
+        let children: Duration = self
+            .spans
+            .iter()
+            .filter(|(child, _)| {
+                child
+                    .strip_prefix(stack)
+                    .and_then(|rest| rest.strip_prefix(';'))
+                    .is_some_and(|rest| !rest.contains(';'))
+            })
+            .map(|(_, span)| span.total)
+            .sum();
+        self.spans[stack].total.saturating_sub(children)
+    }
+
+    /// Folded stacks of microseconds spent in each phase
+    pub fn folded(&self) -> String {
+        let mut out = String::new();
+        for stack in self.spans.keys() {
+            let micros = self.self_time(stack).as_micros();
+            if micros > 0 {
+                let _ = writeln!(out, "tarssh;{} {}", stack, micros);
+            }
+        }
+        out
+    }
+
+    /// Whether nothing's been timed this window
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// Start a fresh window
+    pub fn reset(&mut self) {
+        self.since = Instant::now();
+        self.spans.clear();
+    }
+}
+
+/// The window, the share of it spent busy, and `phase=count/total/max` for
+/// each phase, costliest first
+impl fmt::Display for Profiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let window = self.since.elapsed();
+        let busy: Duration = self
+            .spans
+            .iter()
+            .filter(|(stack, _)| !stack.contains(';'))
+            .map(|(_, span)| span.total)
+            .sum();
+        write!(
+            f,
+            "window: {:.2?}, busy: {:.2}%, phases:",
+            window,
+            busy.as_secs_f64() * 100.0 / window.as_secs_f64().max(f64::EPSILON)
+        )?;
+
+        let mut spans: Vec<_> = self.spans.iter().collect();
+        spans.sort_by(|a, b| b.1.total.cmp(&a.1.total).then_with(|| a.0.cmp(b.0)));
+        for (stack, span) in spans {
+            write!(
+                f,
+                " {}={}/{:.2?}/{:.2?}",
+                stack, span.count, span.total, span.max
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_profiler() {
+    let mut profiler = Profiler::new(false);
+    profiler.record("tick", profiler.start());
+    assert!(profiler.spans.is_empty());
+
+    let mut profiler = Profiler::new(true);
+    let mut span = |stack, millis| {
+        let span = profiler.spans.entry(stack).or_default();
+        span.count += 1;
+        span.total += Duration::from_millis(millis);
+    };
+    span("tick", 10);
+    span("tick;disconnect", 3);
+    span("tick;disconnect;log", 1);
+    span("accept", 2);
+    assert_eq!(
+        profiler.folded(),
+        "tarssh;accept 2000\n\
+         tarssh;tick 7000\n\
+         tarssh;tick;disconnect 2000\n\
+         tarssh;tick;disconnect;log 1000\n"
+    );
+    assert!(profiler
+        .to_string()
+        .ends_with("phases: tick=1/10.00ms/0.00ns tick;disconnect=1/3.00ms/0.00ns accept=1/2.00ms/0.00ns tick;disconnect;log=1/1.00ms/0.00ns"));
+
+    profiler.record("accept", profiler.start());
+    assert_eq!(profiler.spans["accept"].count, 2);
+    profiler.reset();
+    assert_eq!(profiler.folded(), "");
+}
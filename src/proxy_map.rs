@@ -0,0 +1,101 @@
+//! Sources seen by the tarpit, written out as a map file for the HAProxy or
+//! nginx in front of real services, so they can route or refuse them too.
+//!
+//! Each line maps an address to `active`, for one with a connection held in
+//! the tarpit, or `banned`.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MapFormat {
+    /// `<ip> <value>`, for HAProxy's `map()` converters
+    Haproxy,
+    /// `<ip> <value>;`, for an nginx `map` or `geo` block to `include`
+    Nginx,
+}
+
+impl FromStr for MapFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "haproxy" => Ok(MapFormat::Haproxy),
+            "nginx" => Ok(MapFormat::Nginx),
+            _ => Err(format!(
+                "unknown map format {:?}, expected one of haproxy, nginx",
+                s
+            )),
+        }
+    }
+}
+
+pub struct ProxyMap {
+    path: PathBuf,
+    format: MapFormat,
+}
+
+impl ProxyMap {
+    pub fn new(path: PathBuf, format: MapFormat) -> Self {
+        Self { path, format }
+    }
+
+    fn render(
+        &self,
+        active: impl IntoIterator<Item = IpAddr>,
+        banned: impl IntoIterator<Item = IpAddr>,
+    ) -> (String, usize) {
+        let mut sources = BTreeMap::new();
+        sources.extend(active.into_iter().map(|ip| (ip, "active")));
+        // A ban outranks a connection that was held before it
+        sources.extend(banned.into_iter().map(|ip| (ip, "banned")));
+
+        let end = match self.format {
+            MapFormat::Haproxy => "",
+            MapFormat::Nginx => ";",
+        };
+        let mut text = String::new();
+        for (ip, value) in &sources {
+            let _ = writeln!(text, "{} {}{}", ip, value, end);
+        }
+        (text, sources.len())
+    }
+
+    /// Replace the map atomically, returning how many sources it lists
+    pub fn write(
+        &self,
+        active: impl IntoIterator<Item = IpAddr>,
+        banned: impl IntoIterator<Item = IpAddr>,
+    ) -> io::Result<usize> {
+        let (text, count) = self.render(active, banned);
+        let mut partial = OsString::from(self.path.as_os_str());
+        partial.push(".part");
+        std::fs::write(&partial, text)?;
+        std::fs::rename(&partial, &self.path)?;
+        Ok(count)
+    }
+}
+
+#[test]
+fn test_proxy_map() {
+    let a: IpAddr = [192, 0, 2, 1].into();
+    let b: IpAddr = "2001:db8::1".parse().unwrap();
+    let c: IpAddr = [192, 0, 2, 7].into();
+
+    let map = ProxyMap::new(PathBuf::new(), MapFormat::Haproxy);
+    assert_eq!(
+        map.render(vec![c, a, c, b], vec![c]),
+        (
+            "192.0.2.1 active\n192.0.2.7 banned\n2001:db8::1 active\n".to_string(),
+            3
+        )
+    );
+    let map = ProxyMap::new(PathBuf::new(), MapFormat::Nginx);
+    assert_eq!(map.render(vec![], vec![a]).0, "192.0.2.1 banned;\n");
+    assert!("apache".parse::<MapFormat>().is_err());
+}
@@ -0,0 +1,198 @@
+//! RDAP lookups for the networks peers connect from, for the name each is
+//! registered under and the address to report abuse to.
+//!
+//! Lookups are made once per /24 (or IPv6 /48) while cached, on an
+//! enrichment pipeline, and spaced out to stay within registries' rate
+//! limits.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::enrich::{self, Limits, Pipeline};
+use crate::http::HttpUrl;
+use crate::sanitize::Field;
+
+/// How long to wait for a registry's answer
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a network's registered as
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Registration {
+    /// The network's name, e.g. `GOOGLE`
+    pub netname: Option<String>,
+    /// The abuse contact's email address
+    pub abuse: Option<String>,
+}
+
+impl fmt::Display for Registration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let field = |s: &Option<String>| {
+            s.as_deref()
+                .map_or_else(|| "none".to_string(), |s| Field(s.as_bytes()).to_string())
+        };
+        write!(
+            f,
+            "netname: {}, abuse: {}",
+            field(&self.netname),
+            field(&self.abuse)
+        )
+    }
+}
+
+/// The /24 or /48 that `ip` is looked up by
+pub fn network(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(v4) => Ipv4Addr::from(u32::from(v4) & !0xff).into(),
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => network(v4.into()),
+            None => Ipv6Addr::from(u128::from(v6) & !((1u128 << 80) - 1)).into(),
+        },
+    }
+}
+
+/// The first email address of an entity with the abuse role, looking into
+/// the entities of entities
+fn abuse_email(entities: &Value) -> Option<String> {
+    entities.as_array()?.iter().find_map(|entity| {
+        let abuse = entity["roles"]
+            .as_array()
+            .is_some_and(|roles| roles.iter().any(|role| role == "abuse"));
+        let email = || {
+            entity["vcardArray"][1]
+                .as_array()?
+                .iter()
+                .find(|property| property[0] == "email")
+                .and_then(|property| property[3].as_str())
+                .map(str::to_string)
+        };
+        abuse
+            .then(email)
+            .flatten()
+            .or_else(|| abuse_email(&entity["entities"]))
+    })
+}
+
+/// The registration in an RDAP IP network response
+fn parse(body: &[u8]) -> Result<Registration, String> {
+    let network: Value = serde_json::from_slice(body).map_err(|err| err.to_string())?;
+    if network["objectClassName"] != "ip network" {
+        return Err("not an ip network".to_string());
+    }
+    Ok(Registration {
+        netname: network["name"].as_str().map(str::to_string),
+        abuse: abuse_email(&network["entities"]),
+    })
+}
+
+/// A finished lookup, for the network rather than the peer
+pub type Resolved = enrich::Resolved<Registration>;
+
+pub struct Rdap {
+    pipeline: Pipeline<Registration>,
+}
+
+impl Rdap {
+    /// Look networks up under `url`, such as `https://rdap.org/` for
+    /// `https://rdap.org/ip/192.0.2.0`, at most `rate` times a minute
+    pub fn new(
+        url: HttpUrl,
+        rate: u32,
+        ttl: Duration,
+        limits: Limits,
+    ) -> (Self, UnboundedReceiver<Resolved>) {
+        let spacing = Duration::from_secs(60) / rate.max(1);
+        let next = Arc::new(Mutex::new(tokio::time::Instant::now()));
+        let (pipeline, rx) = Pipeline::new(limits, ttl, move |network: IpAddr| {
+            let url = url.join(&format!("ip/{}", network));
+            let next = next.clone();
+            async move {
+                let at = {
+                    let mut next = next.lock().unwrap();
+                    let at = (*next).max(tokio::time::Instant::now());
+                    *next = at + spacing;
+                    at
+                };
+                tokio::time::sleep_until(at).await;
+                let body = url
+                    .get(FETCH_TIMEOUT)
+                    .await
+                    .map_err(|err| err.to_string())?;
+                parse(&body)
+            }
+        });
+        (Self { pipeline }, rx)
+    }
+
+    /// The cached registration for the network of a peer just accepted into
+    /// `slot`, or `None` if a lookup's needed, in which case one is queued
+    pub fn check(&mut self, ip: IpAddr, slot: usize, now: Instant) -> Option<Registration> {
+        self.pipeline.check(network(ip), slot, now)
+    }
+
+    /// Cache a finished lookup, returning the slots of the connections waiting
+    /// on it
+    pub fn resolved(&mut self, resolved: &Resolved, now: Instant) -> Vec<usize> {
+        self.pipeline.resolved(resolved, now)
+    }
+}
+
+/// The pipeline's lookups
+impl fmt::Display for Rdap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.pipeline.fmt(f)
+    }
+}
+
+#[test]
+fn test_rdap() {
+    assert_eq!(
+        network([192, 0, 2, 99].into()),
+        IpAddr::from([192, 0, 2, 0])
+    );
+    assert_eq!(
+        network("::ffff:198.51.100.7".parse().unwrap()),
+        IpAddr::from([198, 51, 100, 0])
+    );
+    assert_eq!(
+        network("2001:db8:1234:5678::1".parse().unwrap()),
+        "2001:db8:1234::".parse::<IpAddr>().unwrap()
+    );
+
+    let body = br#"{
+        "objectClassName": "ip network",
+        "handle": "NET-192-0-2-0-1",
+        "name": "TEST-NET-1",
+        "entities": [
+            {
+                "roles": ["registrant"],
+                "vcardArray": ["vcard", [["fn", {}, "text", "Example"]]],
+                "entities": [
+                    {
+                        "roles": ["technical", "abuse"],
+                        "vcardArray": ["vcard", [
+                            ["version", {}, "text", "4.0"],
+                            ["email", {}, "text", "abuse@example.net"]
+                        ]]
+                    }
+                ]
+            }
+        ]
+    }"#;
+    let registration = parse(body).unwrap();
+    assert_eq!(
+        registration.to_string(),
+        "netname: \"TEST-NET-1\", abuse: \"abuse@example.net\""
+    );
+    assert_eq!(
+        parse(br#"{"objectClassName": "ip network"}"#)
+            .unwrap()
+            .to_string(),
+        "netname: none, abuse: none"
+    );
+    assert!(parse(br#"{"objectClassName": "autnum"}"#).is_err());
+}
@@ -0,0 +1,82 @@
+//! Options fetched from a TOML file at a URL, for fleets managed without a
+//! config-management agent.
+//!
+//! Beside the file, at the same URL with `.sha256` appended, the server
+//! publishes its SHA-256 sum as `sha256sum` writes it, so a file that was cut
+//! short or is only half-updated is caught before it's applied.
+
+use std::io;
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+
+use crate::http::HttpUrl;
+
+/// How long fetching the file, and again its sum, may take
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A configuration file that matched its sum
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fetched {
+    pub text: String,
+    /// The file's SHA-256 sum, in lowercase hex
+    pub sha256: String,
+}
+
+/// Fetch the file at `url` and its sum, checking one against the other
+pub async fn fetch(url: &HttpUrl) -> io::Result<Fetched> {
+    let text = url.get(FETCH_TIMEOUT).await?;
+    let sum = url.suffixed(".sha256").get(FETCH_TIMEOUT).await?;
+    check(text, &sum)
+}
+
+/// Check `text` against a `sha256sum` line, or a bare sum
+fn check(text: Vec<u8>, sum: &[u8]) -> io::Result<Fetched> {
+    let expected = std::str::from_utf8(sum)
+        .ok()
+        .and_then(|sum| sum.split_whitespace().next())
+        .filter(|sum| sum.len() == 64 && sum.bytes().all(|b| b.is_ascii_hexdigit()))
+        .ok_or_else(|| invalid("no SHA-256 sum found".to_string()))?
+        .to_ascii_lowercase();
+
+    let sha256: String = Sha256::digest(&text)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    if sha256 != expected {
+        return Err(invalid(format!(
+            "checksum mismatch, expected: {}, got: {}",
+            expected, sha256
+        )));
+    }
+
+    let text = String::from_utf8(text).map_err(|_| invalid("not UTF-8".to_string()))?;
+    Ok(Fetched { text, sha256 })
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+const DELAY_SHA256: &str = "8dfca171a0add4ee2ceb463b16bda57001e2af3280e8931a1325db7fc320e571";
+
+#[test]
+fn test_check_sum() {
+    let fetched = check(b"delay = 5\n".to_vec(), DELAY_SHA256.as_bytes()).unwrap();
+    assert_eq!(fetched.text, "delay = 5\n");
+    assert_eq!(fetched.sha256, DELAY_SHA256);
+
+    // As sha256sum writes it, in any case
+    let line = format!("{}  tarssh.toml\n", DELAY_SHA256.to_ascii_uppercase());
+    assert!(check(b"delay = 5\n".to_vec(), line.as_bytes()).is_ok());
+}
+
+#[test]
+fn test_check_mismatch() {
+    let err = check(b"delay = 50\n".to_vec(), DELAY_SHA256.as_bytes()).unwrap_err();
+    assert!(err.to_string().starts_with("checksum mismatch"));
+    assert!(check(b"delay = 5\n".to_vec(), b"").is_err());
+    assert!(check(b"delay = 5\n".to_vec(), &DELAY_SHA256.as_bytes()[1..]).is_err());
+    assert!(check(b"delay = 5\n".to_vec(), b"<html>not found</html>").is_err());
+}
@@ -0,0 +1,381 @@
+//! Replay recorded connections against a proposed configuration, to see how
+//! delays and limits would have fared with real traffic before applying them.
+//!
+//! Each connection in the logs arrives when it did and hangs up after as long
+//! as it stayed then, unless the replayed limits refuse it or its lifetime
+//! runs out first.  Connections the tarpit closed itself, or still open when
+//! the logs end, stay until the end.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use structopt::StructOpt;
+use tarssh::event::{Event, Record};
+
+use crate::limits::{Prefixes, SourceLimits};
+use crate::pacing::Schedule;
+
+/// How often progress is printed, in recorded time
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Disconnects the tarpit chose, rather than the client
+const TARPIT_ERRORS: [&str; 4] = [
+    "Max Lifetime",
+    "Memory Budget",
+    "Early Termination",
+    "No Data",
+];
+
+#[derive(Debug, StructOpt)]
+pub struct ReplayConfig {
+    /// Log files to read, as text or JSON events, or standard input if none
+    #[structopt(parse(from_os_str))]
+    files: Vec<PathBuf>,
+    /// Replay this many times faster than recorded, or as fast as possible if 0
+    #[structopt(long = "speed", default_value = "0")]
+    speed: f64,
+    /// Best-effort connection limit
+    #[structopt(short = "c", long = "max-clients", default_value = "4096")]
+    max_clients: std::num::NonZeroU32,
+    /// Seconds between responses
+    #[structopt(short = "d", long = "delay", default_value = "10")]
+    delay: std::num::NonZeroU16,
+    /// Schedule of delays over each connection's lifetime, e.g. 0s=1s,1m=10s,10m=30sx2
+    #[structopt(long = "pacing")]
+    pacing: Option<Schedule>,
+    /// Most clients at once from any one source
+    #[structopt(long = "max-per-source")]
+    max_per_source: Option<std::num::NonZeroU32>,
+    /// Most connections per minute from any one source
+    #[structopt(long = "source-rate")]
+    source_rate: Option<std::num::NonZeroU32>,
+    /// Leading bits of an IPv4 address that identify a source
+    #[structopt(long = "ipv4-prefix", default_value = "32")]
+    ipv4_prefix: u8,
+    /// Leading bits of an IPv6 address that identify a source
+    #[structopt(long = "ipv6-prefix", default_value = "64")]
+    ipv6_prefix: u8,
+    /// Seconds after which to disconnect any client
+    #[structopt(long = "max-lifetime")]
+    max_lifetime: Option<u32>,
+}
+
+/// A recorded connection
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Arrival {
+    /// When it connected, since the logs began
+    at: Duration,
+    ip: IpAddr,
+    /// How long the client stayed, if it hung up itself
+    stayed: Option<Duration>,
+}
+
+/// What happened to the connections, as recorded or replayed
+#[derive(Debug, Default, PartialEq)]
+struct Totals {
+    connections: u64,
+    held: Duration,
+    writes: u64,
+    peak: usize,
+}
+
+/// The proposed configuration, run over recorded arrivals in order
+struct Simulation {
+    schedule: Schedule,
+    max_clients: usize,
+    lifetime: Option<Duration>,
+    limits: SourceLimits,
+    /// A stand-in for the recorded time at offset zero, for `limits`
+    epoch: Instant,
+    /// Who's held, and when they go
+    departures: BinaryHeap<Reverse<(Duration, Duration, IpAddr)>>,
+    totals: Totals,
+    refused: HashMap<String, u64>,
+    expired: u64,
+}
+
+impl Simulation {
+    fn new(config: &ReplayConfig) -> Self {
+        let prefixes = Prefixes {
+            v4: config.ipv4_prefix,
+            v6: config.ipv6_prefix,
+        };
+        Self {
+            schedule: config
+                .pacing
+                .clone()
+                .unwrap_or_else(|| Schedule::fixed(Duration::from_secs(config.delay.get().into()))),
+            max_clients: config.max_clients.get() as usize,
+            lifetime: config
+                .max_lifetime
+                .map(|secs| Duration::from_secs(secs.into())),
+            limits: SourceLimits::new(
+                prefixes,
+                config.max_per_source.map(|n| n.get()),
+                config.source_rate.map(|n| n.get()),
+            ),
+            epoch: Instant::now(),
+            departures: BinaryHeap::new(),
+            totals: Totals::default(),
+            refused: HashMap::new(),
+            expired: 0,
+        }
+    }
+
+    /// Let go of everyone due to leave by `now`
+    fn advance(&mut self, now: Duration) {
+        while let Some(Reverse((end, start, ip))) = self.departures.peek().copied() {
+            if end > now {
+                break;
+            }
+            self.departures.pop();
+            self.limits.release(ip);
+            self.totals.held += end - start;
+            self.totals.writes += self.schedule.writes(end - start);
+        }
+    }
+
+    fn arrive(&mut self, arrival: &Arrival, end: Duration) {
+        self.advance(arrival.at);
+        if self.departures.len() >= self.max_clients {
+            *self.refused.entry("max-clients".to_string()).or_default() += 1;
+            return;
+        }
+        if let Err((_, refusal)) = self.limits.admit(arrival.ip, self.epoch + arrival.at) {
+            *self.refused.entry(refusal.to_string()).or_default() += 1;
+            return;
+        }
+
+        let stays = arrival.stayed.unwrap_or(end - arrival.at);
+        let held = match self.lifetime {
+            Some(lifetime) if lifetime < stays => {
+                self.expired += 1;
+                lifetime
+            }
+            _ => stays,
+        };
+        self.departures
+            .push(Reverse((arrival.at + held, arrival.at, arrival.ip)));
+        self.totals.connections += 1;
+        self.totals.peak = self.totals.peak.max(self.departures.len());
+    }
+
+    fn refused(&self) -> u64 {
+        self.refused.values().sum()
+    }
+}
+
+/// Recorded connections in order of arrival, how long the logs cover, and the
+/// totals they recorded
+fn load(inputs: Vec<Box<dyn BufRead>>) -> io::Result<(Vec<Arrival>, Duration, Totals)> {
+    let mut connections: Vec<(SystemTime, IpAddr, Option<Duration>)> = vec![];
+    let mut open: HashMap<SocketAddr, SystemTime> = HashMap::new();
+    let mut recorded = Totals::default();
+    let mut first = None;
+    let mut last = UNIX_EPOCH;
+
+    for input in inputs {
+        for line in input.lines() {
+            let line = line?;
+            let record = match Record::parse(&line) {
+                Some(record) => record,
+                None => continue,
+            };
+            let time = match record.time.as_deref().map(humantime::parse_rfc3339_weak) {
+                Some(Ok(time)) => time,
+                _ => continue,
+            };
+            first = Some(first.map_or(time, |first: SystemTime| first.min(time)));
+            last = last.max(time);
+
+            match record.event {
+                Event::Connect { peer, clients, .. } => {
+                    open.insert(peer, time);
+                    recorded.peak = recorded.peak.max(clients);
+                }
+                Event::Disconnect {
+                    peer,
+                    duration,
+                    error,
+                    writes,
+                    ..
+                } => {
+                    open.remove(&peer);
+                    let start = time.checked_sub(duration).unwrap_or(time);
+                    first = first.map(|first| first.min(start));
+                    let stayed = Some(duration).filter(|_| !TARPIT_ERRORS.contains(&&*error));
+                    connections.push((start, peer.ip(), stayed));
+                    recorded.connections += 1;
+                    recorded.held += duration;
+                    recorded.writes += writes.unwrap_or(0) as u64;
+                }
+                _ => (),
+            }
+        }
+    }
+    for (peer, time) in open {
+        connections.push((time, peer.ip(), None));
+        recorded.connections += 1;
+        recorded.held += last.duration_since(time).unwrap_or_default();
+    }
+
+    let first = first.unwrap_or(last);
+    let mut arrivals: Vec<_> = connections
+        .into_iter()
+        .map(|(time, ip, stayed)| Arrival {
+            at: time.duration_since(first).unwrap_or_default(),
+            ip,
+            stayed,
+        })
+        .collect();
+    arrivals.sort_by_key(|arrival| arrival.at);
+    Ok((
+        arrivals,
+        last.duration_since(first).unwrap_or_default(),
+        recorded,
+    ))
+}
+
+fn held(d: Duration) -> humantime::FormattedDuration {
+    humantime::format_duration(Duration::from_secs(d.as_secs()))
+}
+
+pub async fn run(config: ReplayConfig) -> io::Result<()> {
+    let mut inputs: Vec<Box<dyn BufRead>> = vec![];
+    if config.files.is_empty() {
+        inputs.push(Box::new(BufReader::new(io::stdin())));
+    }
+    for path in &config.files {
+        let file = File::open(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        inputs.push(Box::new(BufReader::new(file)));
+    }
+    let (arrivals, end, recorded) = load(inputs)?;
+    println!(
+        "replay, connections: {}, recorded: {}, speed: {}",
+        arrivals.len(),
+        held(end),
+        if config.speed > 0.0 {
+            format!("{}x", config.speed)
+        } else {
+            "max".to_string()
+        }
+    );
+
+    let mut sim = Simulation::new(&config);
+    let started = tokio::time::Instant::now();
+    let mut progress = PROGRESS_INTERVAL;
+    for arrival in &arrivals {
+        while arrival.at >= progress {
+            sim.advance(progress);
+            println!(
+                "replay, time: {}, clients: {}, connections: {}, refused: {}",
+                held(progress),
+                sim.departures.len(),
+                sim.totals.connections,
+                sim.refused()
+            );
+            progress += PROGRESS_INTERVAL;
+        }
+        if config.speed > 0.0 {
+            tokio::time::sleep_until(started + arrival.at.div_f64(config.speed)).await;
+        }
+        sim.arrive(arrival, end);
+    }
+    sim.advance(end);
+
+    let mut refused: Vec<_> = sim.refused.iter().collect();
+    refused.sort();
+    let refused = refused
+        .iter()
+        .map(|(reason, count)| format!("{}={}", reason, count))
+        .collect::<Vec<_>>();
+    println!("\n{:<13} {:>12} {:>12}", "", "recorded", "replayed");
+    for (name, recorded, replayed) in [
+        (
+            "connections",
+            recorded.connections.to_string(),
+            sim.totals.connections.to_string(),
+        ),
+        (
+            "held",
+            held(recorded.held).to_string(),
+            held(sim.totals.held).to_string(),
+        ),
+        (
+            "writes",
+            recorded.writes.to_string(),
+            sim.totals.writes.to_string(),
+        ),
+        (
+            "peak clients",
+            recorded.peak.to_string(),
+            sim.totals.peak.to_string(),
+        ),
+    ] {
+        println!("{:<13} {:>12} {:>12}", name, recorded, replayed);
+    }
+    println!(
+        "\nrefused: {}{}\nmax-lifetime: {}",
+        sim.refused(),
+        if refused.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", refused.join(" "))
+        },
+        sim.expired
+    );
+    Ok(())
+}
+
+#[test]
+fn test_replay() {
+    let log = "\
+        [2022-06-07T00:00:00.000Z INFO  tarssh] connect, peer: 192.0.2.1:1000, clients: 1\n\
+        [2022-06-07T00:00:05.000Z INFO  tarssh] connect, peer: 192.0.2.1:1001, clients: 2\n\
+        [2022-06-07T00:00:10.000Z INFO  tarssh] connect, peer: 192.0.2.2:1000, clients: 3\n\
+        [2022-06-07T00:01:00.000Z INFO  tarssh] disconnect, peer: 192.0.2.1:1000, duration: 60.00s, bytes: 60, error: \"Broken pipe\", clients: 2, writes: 6\n\
+        [2022-06-07T00:01:05.000Z INFO  tarssh] disconnect, peer: 192.0.2.1:1001, duration: 60.00s, bytes: 60, error: \"Max Lifetime\", clients: 1, writes: 6\n\
+        [2022-06-07T00:02:10.000Z INFO  tarssh] info, pid: 1, signal: INFO, uptime: 130.00s, clients: 1, total: 3, bytes: 120\n";
+    let (arrivals, end, recorded) = load(vec![Box::new(log.as_bytes())]).unwrap();
+    assert_eq!(end, Duration::from_secs(130));
+    assert_eq!(arrivals.len(), 3);
+    assert_eq!(arrivals[0].stayed, Some(Duration::from_secs(60)));
+    assert_eq!(arrivals[1].stayed, None);
+    assert_eq!(arrivals[2].at, Duration::from_secs(10));
+    assert_eq!(
+        recorded,
+        Totals {
+            connections: 3,
+            held: Duration::from_secs(240),
+            writes: 12,
+            peak: 3,
+        }
+    );
+
+    let config = ReplayConfig::from_iter(&["replay", "--max-per-source", "1", "-d", "5"]);
+    let mut sim = Simulation::new(&config);
+    for arrival in &arrivals {
+        sim.arrive(arrival, end);
+    }
+    sim.advance(end);
+    assert_eq!(sim.totals.connections, 2);
+    assert_eq!(sim.totals.held, Duration::from_secs(180));
+    assert_eq!(sim.totals.writes, 36);
+    assert_eq!(sim.refused["max-per-source"], 1);
+
+    let config = ReplayConfig::from_iter(&["replay", "-c", "1", "--max-lifetime", "30"]);
+    let mut sim = Simulation::new(&config);
+    for arrival in &arrivals {
+        sim.arrive(arrival, end);
+    }
+    sim.advance(end);
+    assert_eq!(sim.totals.connections, 1);
+    assert_eq!(sim.expired, 1);
+    assert_eq!(sim.refused["max-clients"], 2);
+}
@@ -0,0 +1,388 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use structopt::StructOpt;
+use tarssh::event::{Event, Location, Record};
+
+/// Upper bounds of the duration histogram buckets
+const BUCKETS: [(Duration, &str); 6] = [
+    (Duration::from_secs(1), "<1s"),
+    (Duration::from_secs(10), "1s-10s"),
+    (Duration::from_secs(60), "10s-1m"),
+    (Duration::from_secs(600), "1m-10m"),
+    (Duration::from_secs(3600), "10m-1h"),
+    (Duration::from_secs(86400), "1h-1d"),
+];
+
+#[derive(Debug, StructOpt)]
+pub struct ReportConfig {
+    /// Log files to read, or standard input if none
+    #[structopt(parse(from_os_str))]
+    files: Vec<PathBuf>,
+    /// Number of peers to list
+    #[structopt(long = "top", default_value = "10")]
+    top: usize,
+}
+
+#[derive(Default)]
+struct PeerStats {
+    connections: u64,
+    duration: Duration,
+    bytes: u64,
+    /// When the peer last connected, if the log was timestamped
+    last_connect: Option<SystemTime>,
+    /// Gaps between successive connections
+    intervals: u32,
+    interval_min: Duration,
+    interval_total: Duration,
+}
+
+impl PeerStats {
+    fn interval_mean(&self) -> Option<Duration> {
+        Some(self.interval_total)
+            .filter(|_| self.intervals > 0)
+            .map(|total| total / self.intervals)
+    }
+}
+
+/// Connections from a country or ASN
+#[derive(Default)]
+struct GroupStats {
+    connections: u64,
+    duration: Duration,
+    peers: HashSet<IpAddr>,
+    /// The organisation announcing an ASN, as last logged
+    name: Option<String>,
+}
+
+impl GroupStats {
+    fn add(&mut self, peer: IpAddr, duration: Duration) {
+        self.connections += 1;
+        self.duration += duration;
+        self.peers.insert(peer);
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    connections: u64,
+    duration: Duration,
+    bytes: u64,
+    longest: Duration,
+    peers: HashMap<IpAddr, PeerStats>,
+    histogram: [u64; BUCKETS.len() + 1],
+    hours: [u64; 24],
+    countries: HashMap<String, GroupStats>,
+    asns: HashMap<u32, GroupStats>,
+}
+
+impl Report {
+    fn add(
+        &mut self,
+        hour: Option<u8>,
+        peer: IpAddr,
+        duration: Duration,
+        bytes: u64,
+        location: &Location,
+    ) {
+        self.connections += 1;
+        self.duration += duration;
+        self.bytes += bytes;
+        self.longest = self.longest.max(duration);
+
+        let stats = self.peers.entry(peer).or_default();
+        stats.connections += 1;
+        stats.duration += duration;
+        stats.bytes += bytes;
+
+        let bucket = BUCKETS
+            .iter()
+            .position(|(limit, _)| duration < *limit)
+            .unwrap_or(BUCKETS.len());
+        self.histogram[bucket] += 1;
+
+        if let Some(hour) = hour {
+            self.hours[hour as usize] += 1;
+        }
+
+        if let Some(country) = &location.country {
+            let stats = self.countries.entry(country.clone()).or_default();
+            stats.add(peer, duration);
+        }
+        if let Some(asn) = location.asn {
+            let stats = self.asns.entry(asn).or_default();
+            stats.add(peer, duration);
+            if location.org.is_some() {
+                stats.name = location.org.clone();
+            }
+        }
+    }
+
+    fn connect(&mut self, time: SystemTime, peer: IpAddr) {
+        let stats = self.peers.entry(peer).or_default();
+        if let Some(last) = stats.last_connect {
+            let interval = time.duration_since(last).unwrap_or_default();
+            stats.interval_min = if stats.intervals == 0 {
+                interval
+            } else {
+                stats.interval_min.min(interval)
+            };
+            stats.intervals += 1;
+            stats.interval_total += interval;
+        }
+        stats.last_connect = Some(time);
+    }
+
+    fn write<W: Write>(&self, out: &mut W, top: usize) -> io::Result<()> {
+        writeln!(out, "connections: {}", self.connections)?;
+        writeln!(out, "unique peers: {}", self.peers.len())?;
+        writeln!(out, "time wasted: {}", human(self.duration))?;
+        writeln!(out, "longest: {}", human(self.longest))?;
+        writeln!(out, "bytes sent: {}", self.bytes)?;
+
+        let mut peers: Vec<_> = self.peers.iter().collect();
+        peers.sort_unstable_by(|a, b| b.1.duration.cmp(&a.1.duration).then(a.0.cmp(b.0)));
+        writeln!(out, "\ntop peers by time wasted:")?;
+        for (ip, stats) in peers.iter().take(top) {
+            writeln!(
+                out,
+                "  {:<39} {:>12} {:>6} conns {:>10} bytes",
+                ip,
+                human(stats.duration),
+                stats.connections,
+                stats.bytes
+            )?;
+        }
+
+        peers.sort_unstable_by(|a, b| b.1.connections.cmp(&a.1.connections).then(a.0.cmp(b.0)));
+        writeln!(out, "\ntop peers by connections:")?;
+        for (ip, stats) in peers.iter().take(top) {
+            let (min, mean) = match stats.interval_mean() {
+                Some(mean) => (human(stats.interval_min), human(mean)),
+                None => ("-".to_string(), "-".to_string()),
+            };
+            writeln!(
+                out,
+                "  {:<39} {:>6} conns {:>12} {:>8} min gap {:>8} avg gap",
+                ip,
+                stats.connections,
+                human(stats.duration),
+                min,
+                mean
+            )?;
+        }
+
+        if !self.countries.is_empty() {
+            writeln!(out, "\ntop countries by time wasted:")?;
+            for (country, stats) in by_duration(&self.countries).take(top) {
+                writeln!(out, "{}", group(country, stats))?;
+            }
+        }
+
+        if !self.asns.is_empty() {
+            writeln!(out, "\ntop ASNs by time wasted:")?;
+            for (asn, stats) in by_duration(&self.asns).take(top) {
+                writeln!(out, "{}", group(format!("AS{}", asn), stats))?;
+            }
+        }
+
+        writeln!(out, "\nduration histogram:")?;
+        let labels = BUCKETS.iter().map(|(_, label)| *label).chain(Some(">1d"));
+        for (label, count) in labels.zip(self.histogram.iter()) {
+            let line = format!(
+                "  {:>8} {:>8} {}",
+                label,
+                count,
+                bar(*count, self.connections)
+            );
+            writeln!(out, "{}", line.trim_end())?;
+        }
+
+        if self.hours.iter().any(|count| *count > 0) {
+            writeln!(out, "\ndisconnects by hour (UTC):")?;
+            for (hour, count) in self.hours.iter().enumerate() {
+                let line = format!(
+                    "  {:02}:00 {:>8} {}",
+                    hour,
+                    count,
+                    bar(*count, self.connections)
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Groups from most to least time wasted
+fn by_duration<K: Ord>(groups: &HashMap<K, GroupStats>) -> impl Iterator<Item = (&K, &GroupStats)> {
+    let mut groups: Vec<_> = groups.iter().collect();
+    groups.sort_unstable_by(|a, b| b.1.duration.cmp(&a.1.duration).then(a.0.cmp(b.0)));
+    groups.into_iter()
+}
+
+/// A line of a country or ASN table
+fn group(key: impl std::fmt::Display, stats: &GroupStats) -> String {
+    let line = format!(
+        "  {:<10} {:>12} {:>6} conns {:>6} peers  {}",
+        key,
+        human(stats.duration),
+        stats.connections,
+        stats.peers.len(),
+        stats.name.as_deref().unwrap_or("")
+    );
+    line.trim_end().to_string()
+}
+
+fn bar(count: u64, total: u64) -> String {
+    "#".repeat((count * 40).checked_div(total).unwrap_or(0) as usize)
+}
+
+fn human(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{:.1}s", duration.as_secs_f64()),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        3600..=86399 => format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60),
+        _ => format!("{}d{:02}h", secs / 86400, (secs % 86400) / 3600),
+    }
+}
+
+pub fn run(config: ReportConfig) -> io::Result<()> {
+    let mut report = Report::default();
+
+    let mut inputs: Vec<Box<dyn BufRead>> = vec![];
+    if config.files.is_empty() {
+        inputs.push(Box::new(BufReader::new(io::stdin())));
+    }
+    for path in &config.files {
+        let file = File::open(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        inputs.push(Box::new(BufReader::new(file)));
+    }
+
+    for input in inputs {
+        for line in input.lines() {
+            let line = line?;
+            if let Some(record) = Record::parse(&line) {
+                let hour = record.hour();
+                match record.event {
+                    Event::Connect { peer, .. } => {
+                        let time = record.time.as_deref().map(humantime::parse_rfc3339_weak);
+                        if let Some(Ok(time)) = time {
+                            report.connect(time, peer.ip());
+                        }
+                    }
+                    Event::Disconnect {
+                        peer,
+                        duration,
+                        bytes,
+                        location,
+                        short,
+                        ..
+                    } => {
+                        // A short connection's disconnect stands in for its connect
+                        let time = record.time.as_deref().map(humantime::parse_rfc3339_weak);
+                        if let (true, Some(Ok(time))) = (short, time) {
+                            report.connect(time.checked_sub(duration).unwrap_or(time), peer.ip());
+                        }
+                        report.add(hour, peer.ip(), duration, bytes, &location)
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    report.write(&mut io::stdout().lock(), config.top)
+}
+
+#[cfg(test)]
+fn write_report(report: &Report) -> String {
+    let mut out = vec![];
+    report.write(&mut out, 2).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_report_countries() {
+    let mut report = Report::default();
+    let nl = Location {
+        country: Some("NL".to_string()),
+        ..Location::default()
+    };
+    let cn = Location {
+        country: Some("CN".to_string()),
+        ..Location::default()
+    };
+    let us = Location {
+        country: Some("US".to_string()),
+        ..Location::default()
+    };
+    let secs = Duration::from_secs;
+    report.add(None, [192, 0, 2, 1].into(), secs(30), 0, &nl);
+    report.add(None, [192, 0, 2, 1].into(), secs(30), 0, &nl);
+    report.add(None, [192, 0, 2, 2].into(), secs(120), 0, &cn);
+    report.add(None, [192, 0, 2, 3].into(), secs(10), 0, &us);
+    report.add(
+        None,
+        [192, 0, 2, 4].into(),
+        secs(600),
+        0,
+        &Location::default(),
+    );
+
+    let out = write_report(&report);
+    let table: Vec<_> = out
+        .split("top countries by time wasted:\n")
+        .nth(1)
+        .unwrap()
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .collect();
+    assert_eq!(
+        table,
+        [
+            "  CN                2m00s      1 conns      1 peers",
+            "  NL                1m00s      2 conns      1 peers",
+        ]
+    );
+    assert!(!out.contains("top ASNs"));
+}
+
+#[test]
+fn test_report_asns() {
+    let mut report = Report::default();
+    let location = |org: Option<&str>| Location {
+        asn: Some(64512),
+        org: org.map(str::to_string),
+        ..Location::default()
+    };
+    let secs = Duration::from_secs;
+    report.add(
+        None,
+        [192, 0, 2, 1].into(),
+        secs(5),
+        0,
+        &location(Some("Old")),
+    );
+    report.add(
+        None,
+        [192, 0, 2, 2].into(),
+        secs(5),
+        0,
+        &location(Some("Example")),
+    );
+    report.add(None, [192, 0, 2, 2].into(), secs(5), 0, &location(None));
+
+    let out = write_report(&report);
+    assert!(out.contains(
+        "top ASNs by time wasted:\n  AS64512           15.0s      3 conns      2 peers  Example\n"
+    ));
+    assert!(!out.contains("top countries"));
+}
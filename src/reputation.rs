@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::ipfix::EndReason;
+use crate::rdap::Registration;
+
+/// What a source has been up to, across restarts
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// Unix time of the first connection
+    pub first_seen: u64,
+    /// Unix time of the latest connection or disconnection
+    pub last_seen: u64,
+    pub connections: u64,
+    /// Total time spent connected
+    pub held: Duration,
+    /// How the last connection went, e.g. `connect` or `timeout`
+    pub last_action: &'static str,
+    /// Time since the epoch of the latest connection, zero if unknown
+    pub last_connect: Duration,
+    /// How many reconnections have been timed
+    pub intervals: u64,
+    /// Shortest time between successive connections
+    pub interval_min: Duration,
+    /// Total time between successive connections
+    pub interval_total: Duration,
+    /// The network's registered name, from RDAP
+    pub netname: Option<String>,
+    /// Where to report abuse from the network, from RDAP
+    pub abuse: Option<String>,
+}
+
+impl Record {
+    /// Average time between successive connections, if any were timed
+    pub fn interval_mean(&self) -> Option<Duration> {
+        Some(self.interval_total)
+            .filter(|_| self.intervals > 0)
+            .map(|total| total.div_f64(self.intervals as f64))
+    }
+}
+
+/// Per-IP history, kept in memory and saved to a file.
+///
+/// Sources not seen for `ttl` are forgotten.  The file has a line per source:
+/// `<ip> <first seen> <last seen> <connections> <held secs> <last action>
+/// <last connect> <intervals> <min interval secs> <total interval secs>
+/// <netname> <abuse>`, with `-` for an unknown netname or abuse contact, the
+/// last six or two missing from stores written by older versions.
+pub struct Reputation {
+    path: PathBuf,
+    ttl: Duration,
+    records: HashMap<IpAddr, Record>,
+    dirty: bool,
+}
+
+fn unix(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn action(reason: EndReason) -> &'static str {
+    match reason {
+        EndReason::IdleTimeout => "timeout",
+        EndReason::ActiveTimeout => "lifetime",
+        EndReason::EndOfFlow => "closed",
+        EndReason::ForcedEnd => "shutdown",
+    }
+}
+
+fn parse_action(s: &str) -> &'static str {
+    let actions = ["connect", "timeout", "lifetime", "closed", "shutdown"];
+    actions
+        .iter()
+        .find(|action| **action == s)
+        .copied()
+        .unwrap_or("unknown")
+}
+
+fn parse_secs(s: &str) -> Option<Duration> {
+    Duration::try_from_secs_f64(s.parse().ok()?).ok()
+}
+
+fn parse_line(line: &str) -> Option<(IpAddr, Record)> {
+    let mut fields = line.split_whitespace();
+    let ip = fields.next()?.parse().ok()?;
+    let mut record = Record {
+        first_seen: fields.next()?.parse().ok()?,
+        last_seen: fields.next()?.parse().ok()?,
+        connections: fields.next()?.parse().ok()?,
+        held: parse_secs(fields.next()?)?,
+        last_action: parse_action(fields.next()?),
+        last_connect: Duration::ZERO,
+        intervals: 0,
+        interval_min: Duration::ZERO,
+        interval_total: Duration::ZERO,
+        netname: None,
+        abuse: None,
+    };
+    if let Some(last_connect) = fields.next() {
+        record.last_connect = parse_secs(last_connect)?;
+        record.intervals = fields.next()?.parse().ok()?;
+        record.interval_min = parse_secs(fields.next()?)?;
+        record.interval_total = parse_secs(fields.next()?)?;
+    }
+    let known = |field: &str| Some(field.to_string()).filter(|field| field != "-");
+    if let Some(netname) = fields.next() {
+        record.netname = known(netname);
+        record.abuse = known(fields.next()?);
+    }
+    Some((ip, record))
+}
+
+impl Reputation {
+    /// Load the store at `path`, starting empty if it doesn't exist yet
+    pub fn open<P: AsRef<Path>>(path: P, ttl: Duration) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut records = HashMap::new();
+
+        match std::fs::File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    if let Some((ip, record)) = parse_line(&line?) {
+                        records.insert(ip, record);
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+
+        Ok(Self {
+            path,
+            ttl,
+            records,
+            dirty: false,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = (&IpAddr, &Record)> {
+        self.records.iter()
+    }
+
+    /// Record a new connection, returning the source's history including it
+    pub fn connected(&mut self, ip: IpAddr, now: SystemTime) -> &Record {
+        let at = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let now = unix(now);
+        self.dirty = true;
+
+        let record = self.records.entry(ip).or_insert(Record {
+            first_seen: now,
+            last_seen: now,
+            connections: 0,
+            held: Duration::ZERO,
+            last_action: "connect",
+            last_connect: Duration::ZERO,
+            intervals: 0,
+            interval_min: Duration::ZERO,
+            interval_total: Duration::ZERO,
+            netname: None,
+            abuse: None,
+        });
+        if record.last_connect > Duration::ZERO {
+            let interval = at.saturating_sub(record.last_connect);
+            record.interval_min = if record.intervals == 0 {
+                interval
+            } else {
+                record.interval_min.min(interval)
+            };
+            record.intervals += 1;
+            record.interval_total += interval;
+        }
+        record.last_seen = now;
+        record.last_connect = at;
+        record.connections += 1;
+        record.last_action = "connect";
+        record
+    }
+
+    pub fn disconnected(&mut self, ip: IpAddr, held: Duration, reason: EndReason, now: SystemTime) {
+        if let Some(record) = self.records.get_mut(&ip) {
+            record.last_seen = unix(now);
+            record.held += held;
+            record.last_action = action(reason);
+            self.dirty = true;
+        }
+    }
+
+    /// Note what RDAP says of the network a source connected from
+    pub fn registered(&mut self, ip: IpAddr, registration: &Registration) {
+        if let Some(record) = self.records.get_mut(&ip) {
+            if record.netname != registration.netname || record.abuse != registration.abuse {
+                record.netname = registration.netname.clone();
+                record.abuse = registration.abuse.clone();
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Forget sources not seen within the TTL, returning how many went
+    pub fn expire(&mut self, now: SystemTime) -> usize {
+        let cutoff = unix(now).saturating_sub(self.ttl.as_secs());
+        let before = self.records.len();
+        self.records.retain(|_, record| record.last_seen >= cutoff);
+        let expired = before - self.records.len();
+        self.dirty |= expired > 0;
+        expired
+    }
+
+    /// The `k` sources that have been held longest, longest first
+    pub fn top(&self, k: usize) -> Vec<(IpAddr, &Record)> {
+        let mut top: Vec<_> = self.records.iter().map(|(ip, r)| (*ip, r)).collect();
+        top.sort_by(|a, b| b.1.held.cmp(&a.1.held).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(k);
+        top
+    }
+
+    /// Write the store out if it's changed, replacing the file atomically
+    pub fn save(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let mut partial = OsString::from(self.path.as_os_str());
+        partial.push(".part");
+        let mut out = BufWriter::new(std::fs::File::create(&partial)?);
+        // Fields are split on whitespace, so netnames mustn't have any
+        let field = |s: &Option<String>| {
+            s.as_deref().map_or_else(
+                || "-".to_string(),
+                |s| s.split_whitespace().collect::<Vec<_>>().join("_"),
+            )
+        };
+        for (ip, record) in &self.records {
+            writeln!(
+                out,
+                "{} {} {} {} {:.3} {} {:.3} {} {:.3} {:.3} {} {}",
+                ip,
+                record.first_seen,
+                record.last_seen,
+                record.connections,
+                record.held.as_secs_f64(),
+                record.last_action,
+                record.last_connect.as_secs_f64(),
+                record.intervals,
+                record.interval_min.as_secs_f64(),
+                record.interval_total.as_secs_f64(),
+                field(&record.netname),
+                field(&record.abuse)
+            )?;
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&partial, &self.path)?;
+
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_reputation() {
+    let path = std::env::temp_dir().join(format!("tarssh-reputation-{}", std::process::id()));
+    let ttl = Duration::from_secs(3600);
+    let start = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let ip: IpAddr = [192, 0, 2, 1].into();
+
+    let mut store = Reputation::open(&path, ttl).unwrap();
+    store.connected(ip, start);
+    store.disconnected(
+        ip,
+        Duration::from_millis(1500),
+        EndReason::IdleTimeout,
+        start,
+    );
+    assert_eq!(store.connected(ip, start + ttl).connections, 2);
+    store.connected([192, 0, 2, 2].into(), start);
+    let registration = Registration {
+        netname: Some("TEST NET".to_string()),
+        abuse: None,
+    };
+    store.registered(ip, &registration);
+    assert_eq!(
+        parse_line("192.0.2.3 1 2 3 4.000 closed")
+            .unwrap()
+            .1
+            .intervals,
+        0
+    );
+    store.save().unwrap();
+
+    let mut store = Reputation::open(&path, ttl).unwrap();
+    assert_eq!(store.len(), 2);
+    assert_eq!(store.expire(start + ttl + Duration::from_secs(1)), 1);
+    let (top, record) = store.top(1)[0];
+    assert_eq!(top, ip);
+    assert_eq!(
+        *record,
+        Record {
+            first_seen: 1_000_000,
+            last_seen: 1_003_600,
+            connections: 2,
+            held: Duration::from_millis(1500),
+            last_action: "connect",
+            last_connect: Duration::from_secs(1_003_600),
+            intervals: 1,
+            interval_min: ttl,
+            interval_total: ttl,
+            netname: Some("TEST_NET".to_string()),
+            abuse: None,
+        }
+    );
+    assert_eq!(record.interval_mean(), Some(ttl));
+    std::fs::remove_file(path).unwrap();
+}
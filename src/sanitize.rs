@@ -0,0 +1,139 @@
+//! Keeping hostile client data from mangling the logs: fields taken from a
+//! client are quoted, escaped and cut short, and every line is capped and has
+//! anything a terminal would act on escaped on its way to whichever backend is
+//! in use.
+
+use std::borrow::Cow;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::{Log, Metadata, Record};
+
+/// Most characters of a client-provided field to log
+static FIELD_MAX: AtomicUsize = AtomicUsize::new(128);
+
+/// Most bytes of a log line
+static LINE_MAX: AtomicUsize = AtomicUsize::new(4096);
+
+/// Marks where a field or line was cut short
+const ELLIPSIS: &str = "...";
+
+pub fn set_limits(field_max: usize, line_max: usize) {
+    FIELD_MAX.store(field_max, Ordering::Relaxed);
+    LINE_MAX.store(line_max, Ordering::Relaxed);
+}
+
+/// Whether a terminal might act on a character rather than display it: the C0
+/// and C1 controls, which introduce every ANSI/VT escape sequence, and the
+/// marks and overrides that reorder bidirectional text
+fn hazardous(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c,
+            '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}'
+        )
+}
+
+/// Text with anything hazardous escaped, safe to write to a terminal or a
+/// line-based file
+pub fn text(s: &str) -> Cow<'_, str> {
+    if !s.contains(hazardous) {
+        return Cow::Borrowed(s);
+    }
+
+    let mut text = String::with_capacity(s.len() + 8);
+    for c in s.chars() {
+        if hazardous(c) {
+            write!(text, "{}", c.escape_default()).expect("writing to a String");
+        } else {
+            text.push(c);
+        }
+    }
+    Cow::Owned(text)
+}
+
+/// Client-provided bytes as a quoted log field, with anything that could end
+/// the quote, the line, or reach a terminal as an escape sequence escaped
+pub struct Field<'a>(pub &'a [u8]);
+
+impl fmt::Display for Field<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = String::from_utf8_lossy(self.0);
+        let mut chars = text.chars();
+        f.write_char('"')?;
+        for c in chars.by_ref().take(FIELD_MAX.load(Ordering::Relaxed)) {
+            match c {
+                '"' | '\\' => write!(f, "{}", c.escape_default())?,
+                c if hazardous(c) => write!(f, "{}", c.escape_default())?,
+                c => f.write_char(c)?,
+            }
+        }
+        if chars.next().is_some() {
+            f.write_str(ELLIPSIS)?;
+        }
+        f.write_char('"')
+    }
+}
+
+/// A log line as [`text`], cut short at the line limit
+pub fn line(message: &str) -> Cow<'_, str> {
+    let max = LINE_MAX.load(Ordering::Relaxed);
+    let mut line = text(message);
+    if line.len() > max {
+        let mut end = max;
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        let line = line.to_mut();
+        line.truncate(end);
+        line.push_str(ELLIPSIS);
+    }
+    line
+}
+
+/// A logger that applies the line rules before passing lines on
+pub struct Bounded<L>(pub L);
+
+impl<L: Log> Log for Bounded<L> {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let message = record.args().to_string();
+        self.0.log(
+            &Record::builder()
+                .metadata(record.metadata().clone())
+                .args(format_args!("{}", line(&message)))
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build(),
+        );
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+#[test]
+fn test_sanitize() {
+    set_limits(8, 16);
+    assert_eq!(Field(b"SSH-2.0").to_string(), "\"SSH-2.0\"");
+    assert_eq!(
+        Field(b"a\"b\\c\x1b[").to_string(),
+        "\"a\\\"b\\\\c\\u{1b}[\""
+    );
+    assert_eq!(Field(b"SSH-2.0-OpenSSH").to_string(), "\"SSH-2.0-...\"");
+    assert_eq!(line("connect, peer: x"), Cow::Borrowed("connect, peer: x"));
+    assert_eq!(line("a\nb"), "a\\nb");
+    assert_eq!(text("\x1b[2J\u{9b}2J"), "\\u{1b}[2J\\u{9b}2J");
+    assert_eq!(text("abc\u{202e}fed"), "abc\\u{202e}fed");
+    assert_eq!(line("disconnect, peer: x"), "disconnect, peer...");
+    assert_eq!(line("disconnect, pxxé"), "disconnect, pxx...");
+}
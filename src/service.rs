@@ -0,0 +1,142 @@
+//! Running as a Windows service under the Service Control Manager.
+
+use std::ffi::OsString;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::Stream;
+use structopt::StructOpt;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+pub const SERVICE_NAME: &str = "tarssh";
+
+/// Service control events, handed to `signal_stream()` once the tarpit starts
+static CONTROLS: Mutex<Option<UnboundedReceiver<&'static str>>> = Mutex::new(None);
+
+#[derive(Debug, StructOpt)]
+pub enum ServiceCommand {
+    /// Register tarssh as a service, started with the given options
+    Install {
+        /// Options to run the service with, after --
+        #[structopt(parse(from_os_str))]
+        args: Vec<OsString>,
+    },
+    /// Remove the tarssh service
+    Uninstall,
+}
+
+pub fn manage(command: ServiceCommand) -> windows_service::Result<()> {
+    let access = ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE;
+    let manager = ServiceManager::local_computer(None::<&str>, access)?;
+
+    match command {
+        ServiceCommand::Install { args } => {
+            let info = ServiceInfo {
+                name: SERVICE_NAME.into(),
+                display_name: "tarssh".into(),
+                service_type: ServiceType::OWN_PROCESS,
+                start_type: ServiceStartType::AutoStart,
+                error_control: ServiceErrorControl::Normal,
+                executable_path: std::env::current_exe().map_err(windows_service::Error::Winapi)?,
+                launch_arguments: std::iter::once("--service".into()).chain(args).collect(),
+                dependencies: vec![],
+                account_name: None,
+                account_password: None,
+            };
+            let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG)?;
+            service.set_description(env!("CARGO_PKG_DESCRIPTION"))?;
+            crate::eventlog::register().map_err(windows_service::Error::Winapi)?;
+        }
+        ServiceCommand::Uninstall => {
+            manager
+                .open_service(SERVICE_NAME, ServiceAccess::DELETE)?
+                .delete()?;
+            // The source may never have been registered; that's fine
+            let _ = crate::eventlog::unregister();
+        }
+    }
+
+    Ok(())
+}
+
+/// Hand the process over to the Service Control Manager, returning once the
+/// service has stopped
+pub fn dispatch() {
+    if let Err(err) = service_dispatcher::start(SERVICE_NAME, ffi_service_main) {
+        eprintln!("service, error: {}", err);
+        std::process::exit(exitcode::OSERR);
+    }
+}
+
+/// Service control events, mapped to signal names
+pub fn controls() -> impl Stream<Item = &'static str> {
+    let receiver = CONTROLS
+        .lock()
+        .ok()
+        .and_then(|mut controls| controls.take());
+    async_stream::stream! {
+        if let Some(mut receiver) = receiver {
+            while let Some(control) = receiver.recv().await {
+                yield control;
+            }
+        }
+    }
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(err) = run_service() {
+        log::error!("service, error: {}", err);
+    }
+}
+
+fn status(state: ServiceState, accept: ServiceControlAccept) -> ServiceStatus {
+    ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted: accept,
+        exit_code: ServiceExitCode::Win32(0),
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    }
+}
+
+fn run_service() -> windows_service::Result<()> {
+    let (sender, receiver) = unbounded_channel();
+    if let Ok(mut controls) = CONTROLS.lock() {
+        *controls = Some(receiver);
+    }
+
+    let handle = service_control_handler::register(SERVICE_NAME, move |control| match control {
+        ServiceControl::Stop => {
+            let _ = sender.send("STOP");
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Shutdown => {
+            let _ = sender.send("SHUTDOWN");
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })?;
+
+    handle.set_service_status(status(
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+    ))?;
+
+    // The launch arguments given at install time, not the start parameters
+    crate::runtime().block_on(crate::run(crate::config()));
+    log::info!("service, name: {}, state: stopped", SERVICE_NAME);
+
+    handle.set_service_status(status(ServiceState::Stopped, ServiceControlAccept::empty()))
+}
@@ -0,0 +1,287 @@
+//! A long-running soak test: the tarpit and a population of synthetic clients
+//! in one process, churning for hours while invariants are checked, for
+//! validating tarssh before a large deployment.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use structopt::StructOpt;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tokio_stream::wrappers::IntervalStream;
+
+use crate::bench::rss;
+use crate::dnsbl::Action;
+use crate::notify::Notifier;
+use crate::pacing::{Pacing, Schedule, Timeouts};
+use crate::peer_addr::PeerAddr;
+use crate::tap::Tap;
+use crate::Connection;
+use tarssh::banner::{Banner, Preset};
+
+/// Resident memory may grow this much past its level at the first check
+const RSS_GROWTH: f64 = 1.25;
+
+/// Slack on top of `RSS_GROWTH`, for allocator noise in small populations
+const RSS_SLACK: u64 = 4 << 20;
+
+#[derive(Debug, StructOpt)]
+pub struct SoakConfig {
+    /// How long to run, e.g. 6h
+    #[structopt(
+        long = "duration",
+        default_value = "1h",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    duration: Duration,
+    /// Synthetic clients, each needing two file descriptors while connected
+    #[structopt(short = "n", long = "clients", default_value = "500")]
+    clients: usize,
+    /// How long between invariant checks, e.g. 1m
+    #[structopt(
+        long = "check-interval",
+        default_value = "1m",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    check_interval: Duration,
+    /// Longest a client stays connected before reconnecting, in seconds
+    #[structopt(long = "max-lifetime", default_value = "120")]
+    max_lifetime: u64,
+    /// Seed for client lifetimes, making runs reproducible
+    #[structopt(long = "seed", default_value = "0")]
+    seed: u64,
+}
+
+/// What the clients have seen, shared between their tasks
+#[derive(Default)]
+struct ClientStats {
+    connects: AtomicU64,
+    closes: AtomicU64,
+    received: AtomicU64,
+}
+
+/// What the tarpit has done
+#[derive(Debug, Default)]
+struct ServerStats {
+    accepted: u64,
+    dropped: u64,
+    written: u64,
+}
+
+/// Connect, read for a random lifetime, hang up, and do it all again.
+async fn client(addr: SocketAddr, stats: Arc<ClientStats>, seed: u64, max_lifetime: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut buf = [0; 1024];
+    loop {
+        // Stagger starts and reconnections
+        let pause = Duration::from_millis(rng.gen_range(0..1000));
+        tokio::time::sleep(pause).await;
+
+        let mut sock = match TcpStream::connect(addr).await {
+            Ok(sock) => sock,
+            Err(_) => continue,
+        };
+        stats.connects.fetch_add(1, Ordering::Relaxed);
+        let lifetime = Duration::from_secs(rng.gen_range(5..=max_lifetime.max(5)));
+        let _ = tokio::time::timeout(lifetime, async {
+            while let Ok(n @ 1..) = sock.read(&mut buf).await {
+                stats.received.fetch_add(n as u64, Ordering::Relaxed);
+            }
+        })
+        .await;
+        drop(sock);
+        stats.closes.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Check the invariants, returning a description of each that failed
+fn check(
+    config: &SoakConfig,
+    held: usize,
+    server: &ServerStats,
+    clients: &ClientStats,
+    rss: Option<(u64, u64)>,
+) -> Vec<String> {
+    let mut failures = vec![];
+    let connects = clients.connects.load(Ordering::Relaxed);
+    let closes = clients.closes.load(Ordering::Relaxed);
+    let open = connects - closes;
+
+    if server.accepted != held as u64 + server.dropped {
+        failures.push(format!(
+            "slots leaked: {} accepted, {} held, {} dropped",
+            server.accepted, held, server.dropped
+        ));
+    }
+    // Each client has at most one closed connection not yet noticed
+    if held as u64 > open + config.clients as u64 {
+        failures.push(format!(
+            "stale connections: {} held for {} open clients",
+            held, open
+        ));
+    }
+    if server.accepted > connects {
+        failures.push(format!(
+            "phantom connections: {} accepted, {} connected",
+            server.accepted, connects
+        ));
+    }
+    let received = clients.received.load(Ordering::Relaxed);
+    if received > server.written {
+        failures.push(format!(
+            "bytes: {} received, {} written",
+            received, server.written
+        ));
+    }
+    if let Some((baseline, now)) = rss {
+        if now as f64 > baseline as f64 * RSS_GROWTH + RSS_SLACK as f64 {
+            failures.push(format!("rss: grew from {} to {} bytes", baseline, now));
+        }
+    }
+    failures
+}
+
+/// Run the soak, returning whether every check passed
+pub async fn run(config: SoakConfig) -> io::Result<bool> {
+    let addr: SocketAddr = ([127, 0, 0, 1], 0).into();
+    let mut listener = crate::listen_socket(addr, None).await?;
+    let addr = listener.as_ref().local_addr()?;
+
+    let delay = Duration::from_secs(1);
+    let pacing = Pacing {
+        banner: Banner::from(Preset::Ssh),
+        schedule: Schedule::fixed(delay),
+        timeouts: Timeouts {
+            write: Duration::from_secs(30),
+            initial_data: None,
+            lifetime: None,
+            relaxed: None,
+        },
+        ssh_version: None,
+        cork: false,
+        tuned: vec![],
+        banners: vec![],
+        listeners: vec![],
+        listed: Action::Tag,
+        listed_delay: Duration::ZERO,
+    };
+    let startup = Instant::now();
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut notifier = Notifier::default();
+
+    let clients = Arc::new(ClientStats::default());
+    for i in 0..config.clients {
+        let seed = config.seed.wrapping_add(i as u64 + 1);
+        tokio::spawn(client(addr, clients.clone(), seed, config.max_lifetime));
+    }
+
+    let mut connections: Vec<Connection> = Vec::with_capacity(config.clients);
+    let mut server = ServerStats::default();
+    let mut ticker = IntervalStream::new(tokio::time::interval(delay));
+    let mut checker = IntervalStream::new(tokio::time::interval_at(
+        tokio::time::Instant::now() + config.check_interval,
+        config.check_interval,
+    ));
+    let deadline = tokio::time::sleep(config.duration);
+    tokio::pin!(deadline);
+
+    let mut baseline = None;
+    let mut checks = 0;
+    let mut failed = 0;
+    println!(
+        "soak, clients: {}, duration: {}, check-interval: {}",
+        config.clients,
+        humantime::format_duration(config.duration),
+        humantime::format_duration(config.check_interval)
+    );
+
+    loop {
+        tokio::select! {
+            Some(sock) = listener.next() => {
+                let sock = sock?;
+                let peer = PeerAddr::from(sock.peer_addr()?);
+                connections.push(Connection::new(sock, peer, startup, Tap::default()));
+                server.accepted += 1;
+            }
+            Some(_) = ticker.next() => {
+                let before = connections.len();
+                connections.retain_mut(|connection| {
+                    match connection.tick(&pacing, startup, &mut rng, &mut notifier) {
+                        Ok(n) => {
+                            server.written += n as u64;
+                            true
+                        }
+                        Err(_) => false,
+                    }
+                });
+                server.dropped += (before - connections.len()) as u64;
+            }
+            Some(_) = checker.next() => {
+                checks += 1;
+                let rss = rss().map(|now| (*baseline.get_or_insert(now), now));
+                let failures = check(&config, connections.len(), &server, &clients, rss);
+                println!(
+                    "soak, check: {}, elapsed: {}, held: {}, accepted: {}, dropped: {}, \
+                     written: {}, received: {}, rss: {}",
+                    checks,
+                    humantime::format_duration(Duration::from_secs(startup.elapsed().as_secs())),
+                    connections.len(),
+                    server.accepted,
+                    server.dropped,
+                    server.written,
+                    clients.received.load(Ordering::Relaxed),
+                    rss.map_or_else(|| "unknown".to_string(), |(_, now)| now.to_string())
+                );
+                for failure in &failures {
+                    println!("soak, check: {}, failed: {}", checks, failure);
+                }
+                if !failures.is_empty() {
+                    failed += 1;
+                }
+            }
+            _ = &mut deadline => break,
+        }
+    }
+
+    println!(
+        "\nchecks: {}, failed: {}\nclient connects: {}, closes: {}\n\
+         accepted: {}, dropped: {}, held: {}\nwritten: {} bytes, received: {} bytes",
+        checks,
+        failed,
+        clients.connects.load(Ordering::Relaxed),
+        clients.closes.load(Ordering::Relaxed),
+        server.accepted,
+        server.dropped,
+        connections.len(),
+        server.written,
+        clients.received.load(Ordering::Relaxed)
+    );
+    Ok(failed == 0)
+}
+
+#[test]
+fn test_soak_check() {
+    let config = SoakConfig::from_iter(&["soak", "--clients", "10"]);
+    let clients = ClientStats::default();
+    clients.connects.store(20, Ordering::Relaxed);
+    clients.closes.store(12, Ordering::Relaxed);
+    clients.received.store(1000, Ordering::Relaxed);
+    let mut server = ServerStats {
+        accepted: 20,
+        dropped: 10,
+        written: 1200,
+    };
+    assert!(check(&config, 10, &server, &clients, Some((1 << 20, 2 << 20))).is_empty());
+
+    server.dropped = 9;
+    server.written = 900;
+    let failures = check(&config, 10, &server, &clients, Some((1 << 20, 8 << 20)));
+    assert_eq!(failures.len(), 3);
+    assert!(failures[0].starts_with("slots leaked"));
+}
@@ -0,0 +1,199 @@
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Limits on what a spool may keep, with the oldest files going first
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Retention {
+    pub max_files: Option<usize>,
+    pub max_age: Option<Duration>,
+    /// Largest total size of the spool's files
+    pub max_bytes: Option<u64>,
+}
+
+impl Retention {
+    pub fn is_empty(&self) -> bool {
+        self.max_files.is_none() && self.max_age.is_none() && self.max_bytes.is_none()
+    }
+}
+
+/// A directory that per-connection files are created in.
+///
+/// The directory is opened up front, so on sandboxed builds files can still be
+/// created relative to it after a chroot or entering capability mode.
+#[derive(Clone)]
+pub struct Spool {
+    path: PathBuf,
+    #[cfg(all(unix, feature = "sandbox"))]
+    dir: rusty_sandbox::fs::Directory,
+}
+
+impl Spool {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        std::fs::create_dir_all(path)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            #[cfg(all(unix, feature = "sandbox"))]
+            dir: rusty_sandbox::fs::Directory::new(path)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not a directory"))?,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Delete the oldest files until the spool is within `retention`,
+    /// returning how many were removed.
+    ///
+    /// This works by path, so needs the directory to be reachable outside of
+    /// any chroot or sandbox.
+    pub fn prune(&self, retention: &Retention) -> io::Result<usize> {
+        let mut files = vec![];
+        for entry in std::fs::read_dir(&self.path)? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            if meta.is_file() {
+                files.push((meta.modified()?, meta.len(), entry.path()));
+            }
+        }
+        files.sort_unstable_by(|a, b| b.cmp(a));
+
+        let now = SystemTime::now();
+        let mut bytes = 0;
+        let mut removed = 0;
+        for (i, (modified, len, path)) in files.into_iter().enumerate() {
+            bytes += len;
+            let age = now.duration_since(modified).unwrap_or_default();
+            if retention.max_files.is_some_and(|max| i >= max)
+                || retention.max_age.is_some_and(|max| age > max)
+                || retention.max_bytes.is_some_and(|max| bytes > max)
+            {
+                std::fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Create a file for connection `id`, named as by [`file_name`], returning
+    /// it along with its path.
+    pub fn create_for(&self, id: u64, ext: &str) -> io::Result<(File, PathBuf)> {
+        let name = file_name(id, ext);
+        Ok((self.create(&name)?, self.path.join(name)))
+    }
+
+    /// Create a file in the spool.
+    ///
+    /// The sandbox's `openat()` wrapper can't ask for `O_EXCL`, so an existing
+    /// file of that name is truncated, and names must be kept unique instead.
+    #[cfg(all(unix, feature = "sandbox"))]
+    pub fn create(&self, name: &str) -> io::Result<File> {
+        self.dir
+            .open_options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o640)
+            .open(name)
+    }
+
+    /// Create a file in the spool, failing if one of that name exists.
+    #[cfg(not(all(unix, feature = "sandbox")))]
+    pub fn create(&self, name: &str) -> io::Result<File> {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.path.join(name))
+    }
+}
+
+/// A name for the file numbered `id` by this process, as
+/// `<unix time>-<pid>-<id>.<ext>`.
+///
+/// Ids start over with each process, so the PID keeps a restart, or another
+/// process sharing the spool under inetd, from reusing a name within the same
+/// second.
+pub fn file_name(id: u64, ext: &str) -> String {
+    format!("{}-{}-{}.{}", unix_time(), std::process::id(), id, ext)
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[test]
+fn test_prune() {
+    let path = std::env::temp_dir().join(format!("tarssh-prune-{}", std::process::id()));
+    let spool = Spool::open(&path).unwrap();
+    for name in ["1-1.log", "2-2.log", "3-3.log"] {
+        use std::io::Write;
+        spool
+            .create(name)
+            .unwrap()
+            .write_all(b"0123456789")
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    let retention = Retention {
+        max_bytes: Some(25),
+        ..Default::default()
+    };
+    assert_eq!(spool.prune(&retention).unwrap(), 1);
+    assert!(!path.join("1-1.log").exists());
+
+    let retention = Retention {
+        max_files: Some(1),
+        ..Default::default()
+    };
+    assert_eq!(spool.prune(&retention).unwrap(), 1);
+    assert!(path.join("3-3.log").exists());
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn test_file_name() {
+    let name = file_name(7, "transcript");
+    let mut parts = name.trim_end_matches(".transcript").split('-');
+    assert!(parts.next().unwrap().parse::<u64>().unwrap() >= unix_time() - 1);
+    assert_eq!(parts.next(), Some(std::process::id().to_string().as_str()));
+    assert_eq!(parts.next(), Some("7"));
+    assert_eq!(parts.next(), None);
+    assert_ne!(file_name(7, "capture"), file_name(8, "capture"));
+}
+
+#[test]
+fn test_create_for() {
+    let path = std::env::temp_dir().join(format!("tarssh-create-{}", std::process::id()));
+    let spool = Spool::open(&path).unwrap();
+    let (_, transcript) = spool.create_for(1, "transcript").unwrap();
+    let (_, capture) = spool.create_for(1, "capture").unwrap();
+    assert!(transcript.exists());
+    assert!(capture.exists());
+    assert_ne!(transcript, capture);
+    assert_eq!(transcript.parent(), Some(path.as_path()));
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[cfg(not(all(unix, feature = "sandbox")))]
+#[test]
+fn test_create_existing() {
+    use std::io::Write;
+
+    let path = std::env::temp_dir().join(format!("tarssh-existing-{}", std::process::id()));
+    let spool = Spool::open(&path).unwrap();
+    spool.create("1-1.log").unwrap().write_all(b"kept").unwrap();
+    let err = spool.create("1-1.log").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(std::fs::read(path.join("1-1.log")).unwrap(), b"kept");
+    std::fs::remove_dir_all(&path).unwrap();
+}
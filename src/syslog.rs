@@ -0,0 +1,159 @@
+//! Logging to the local syslog daemon.
+
+use std::fmt;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::sanitize::Bounded;
+
+/// Where syslog daemons listen: Linux, macOS and FreeBSD, in that order
+const SOCKETS: &[&str] = &["/dev/log", "/var/run/syslog", "/var/run/log"];
+
+const FACILITIES: &[(&str, u8)] = &[
+    ("kern", 0),
+    ("user", 1),
+    ("mail", 2),
+    ("daemon", 3),
+    ("auth", 4),
+    ("syslog", 5),
+    ("lpr", 6),
+    ("news", 7),
+    ("uucp", 8),
+    ("cron", 9),
+    ("authpriv", 10),
+    ("ftp", 11),
+    ("local0", 16),
+    ("local1", 17),
+    ("local2", 18),
+    ("local3", 19),
+    ("local4", 20),
+    ("local5", 21),
+    ("local6", 22),
+    ("local7", 23),
+];
+
+/// A syslog facility, e.g. `daemon` or `auth`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Facility(u8);
+
+impl FromStr for Facility {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        FACILITIES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|(_, code)| Facility(*code))
+            .ok_or_else(|| format!("unknown syslog facility {:?}", s))
+    }
+}
+
+impl fmt::Display for Facility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = FACILITIES
+            .iter()
+            .find(|(_, code)| *code == self.0)
+            .map_or("unknown", |(name, _)| name);
+        f.write_str(name)
+    }
+}
+
+/// Format one message, leaving the daemon to add the time and hostname
+fn line(facility: Facility, level: Level, pid: u32, message: &str) -> String {
+    let severity = match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    };
+    format!(
+        "<{}>tarssh[{}]: {}",
+        u32::from(facility.0) * 8 + severity,
+        pid,
+        message
+    )
+}
+
+fn connect() -> io::Result<UnixDatagram> {
+    let mut last = io::Error::new(io::ErrorKind::NotFound, "no syslog socket found");
+    for path in SOCKETS {
+        let sock = UnixDatagram::unbound()?;
+        match sock.connect(path) {
+            Ok(()) => return Ok(sock),
+            Err(err) => last = err,
+        }
+    }
+    Err(last)
+}
+
+struct Syslog {
+    sock: Mutex<UnixDatagram>,
+    facility: Facility,
+    pid: u32,
+}
+
+impl Log for Syslog {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = line(
+            self.facility,
+            record.level(),
+            self.pid,
+            &record.args().to_string(),
+        );
+        if let Ok(mut sock) = self.sock.lock() {
+            // The daemon may have restarted, so try a fresh socket once
+            if sock.send(line.as_bytes()).is_err() {
+                if let Ok(fresh) = connect() {
+                    *sock = fresh;
+                    let _ = sock.send(line.as_bytes());
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Send all logging to syslog rather than standard error, filtered by the
+/// maximum level so a reload can change it
+pub fn init(facility: Facility, level: LevelFilter) -> io::Result<()> {
+    let sock = Mutex::new(connect()?);
+    let pid = std::process::id();
+    log::set_logger(Box::leak(Box::new(Bounded(Syslog {
+        sock,
+        facility,
+        pid,
+    }))))
+    .map_err(|err| io::Error::new(io::ErrorKind::AlreadyExists, err.to_string()))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+#[test]
+fn test_syslog_line() {
+    let daemon: Facility = "daemon".parse().unwrap();
+    assert_eq!(daemon.to_string(), "daemon");
+    assert_eq!("LOCAL3".parse::<Facility>().unwrap().to_string(), "local3");
+    assert!("daemons".parse::<Facility>().is_err());
+
+    assert_eq!(
+        line(daemon, Level::Info, 42, "start, servers: 1"),
+        "<30>tarssh[42]: start, servers: 1"
+    );
+    assert_eq!(
+        line("auth".parse().unwrap(), Level::Error, 42, "x"),
+        "<35>tarssh[42]: x"
+    );
+}
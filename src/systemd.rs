@@ -0,0 +1,60 @@
+//! Socket activation under systemd, following `sd_listen_fds(3)`.
+
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::os::unix::io::{FromRawFd, RawFd};
+
+/// The first descriptor systemd passes
+const LISTEN_FDS_START: RawFd = 3;
+
+/// How many descriptors the environment says systemd passed us, if any
+fn passed(pid: Option<&str>, fds: Option<&str>) -> io::Result<usize> {
+    let (pid, fds) = match (pid, fds) {
+        (Some(pid), Some(fds)) => (pid, fds),
+        _ => return Ok(0),
+    };
+    let invalid = |what| io::Error::new(io::ErrorKind::InvalidInput, what);
+    // Meant for some other process, which exec'd us without clearing it
+    if pid
+        .parse::<u32>()
+        .map_err(|_| invalid("invalid LISTEN_PID"))?
+        != std::process::id()
+    {
+        return Ok(0);
+    }
+    fds.parse().map_err(|_| invalid("invalid LISTEN_FDS"))
+}
+
+/// Take the listening sockets systemd passed us, if any, clearing the
+/// variables that describe them so nothing else can take them too
+pub fn listen_fds() -> io::Result<Vec<TcpListener>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let count = passed(pid.as_deref(), fds.as_deref())?;
+    (LISTEN_FDS_START..)
+        .take(count)
+        .map(|fd| {
+            // SAFETY: systemd hands these descriptors to us alone
+            let listener = unsafe { TcpListener::from_raw_fd(fd) };
+            // Fails unless it's really an inet socket
+            listener.local_addr()?;
+            Ok(listener)
+        })
+        .collect()
+}
+
+#[test]
+fn test_passed() {
+    let pid = std::process::id().to_string();
+    assert_eq!(passed(None, None).unwrap(), 0);
+    assert_eq!(passed(Some(&pid), None).unwrap(), 0);
+    assert_eq!(passed(Some(&pid), Some("2")).unwrap(), 2);
+    assert_eq!(passed(Some("1"), Some("2")).unwrap(), 0);
+    assert!(passed(Some(&pid), Some("two")).is_err());
+    assert!(passed(Some("me"), Some("2")).is_err());
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+
+/// Labels picked up by a connection, e.g. `canary=str:secret`, logged on its
+/// disconnect line and counted by key in metrics.
+///
+/// Values are often client-supplied, so anything that could break up a log
+/// field or metric label is replaced with `_`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Tags(Vec<(&'static str, String)>);
+
+impl Tags {
+    pub fn insert<V: fmt::Display>(&mut self, key: &'static str, value: V) {
+        let value: String = value
+            .to_string()
+            .chars()
+            .map(|c| {
+                if c.is_ascii_graphic() && !matches!(c, '"' | ',' | '\\') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        if !self.0.iter().any(|(k, v)| *k == key && *v == value) {
+            self.0.push((key, value));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Each distinct key, in the order first inserted
+    pub fn keys(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.0
+            .iter()
+            .enumerate()
+            .filter(move |(i, (key, _))| !self.0[..*i].iter().any(|(k, _)| k == key))
+            .map(|(_, (key, _))| *key)
+    }
+
+    pub fn memory(&self) -> usize {
+        self.0.capacity() * std::mem::size_of::<(&str, String)>()
+            + self.0.iter().map(|(_, v)| v.capacity()).sum::<usize>()
+    }
+}
+
+impl fmt::Display for Tags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (key, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_tags() {
+    let mut tags = Tags::default();
+    assert!(tags.is_empty());
+    tags.insert("canary", "str:db01");
+    tags.insert("version", "SSH-2.0-Go \"x\", y");
+    tags.insert("canary", "str:db01");
+    tags.insert("canary", "re:pass");
+    assert_eq!(
+        tags.to_string(),
+        "canary=str:db01 version=SSH-2.0-Go__x___y canary=re:pass"
+    );
+    assert_eq!(tags.keys().collect::<Vec<_>>(), vec!["canary", "version"]);
+}
@@ -0,0 +1,164 @@
+use std::fmt;
+
+use log::{debug, warn};
+
+use crate::canary::CanaryWatch;
+use crate::capture::Capture;
+use crate::compress::Compressor;
+use crate::notify::Notifier;
+use crate::peer_addr::PeerAddr;
+use crate::sanitize::Field;
+use crate::tags::Tags;
+use crate::transcript::Transcript;
+use crate::versions::VersionWatch;
+
+/// Optional observers of a connection's traffic.
+///
+/// Observers that fail are logged and dropped, leaving the connection itself
+/// untouched.
+#[derive(Default)]
+pub struct Tap {
+    pub transcript: Option<Transcript>,
+    pub capture: Option<Capture>,
+    pub canary: Option<CanaryWatch>,
+    pub versions: Option<VersionWatch>,
+    /// Compresses transcripts and captures once they're complete
+    pub compressor: Option<Compressor>,
+    pub tags: Tags,
+}
+
+impl Tap {
+    pub fn is_empty(&self) -> bool {
+        self.transcript.is_none()
+            && self.capture.is_none()
+            && self.canary.is_none()
+            && self.versions.is_none()
+            && self.tags.is_empty()
+    }
+
+    /// Approximate heap usage, for --memory-budget
+    pub fn memory(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.transcript.as_ref().map_or(0, Transcript::memory)
+            + self.capture.as_ref().map_or(0, Capture::memory)
+            + self.canary.as_ref().map_or(0, CanaryWatch::memory)
+            + self.versions.as_ref().map_or(0, VersionWatch::memory)
+            + self.tags.memory()
+    }
+
+    pub fn sent(&mut self, peer: PeerAddr, data: &[u8]) {
+        if let Some(transcript) = self.transcript.as_mut() {
+            if let Err(err) = transcript.sent(data) {
+                warn!("transcript, peer: {}, error: {}", peer, err);
+                self.transcript = None;
+            }
+        }
+    }
+
+    pub fn received(&mut self, peer: PeerAddr, data: &[u8], notifier: &mut Notifier) {
+        if let Some(watch) = self.canary.as_mut() {
+            for canary in watch.scan(data) {
+                notifier.alert("canary", peer, canary);
+                self.tags.insert("canary", canary);
+            }
+        }
+
+        if let Some(version) = self.versions.as_mut().and_then(|watch| watch.scan(data)) {
+            debug!(
+                "version, peer: {}, client: {}",
+                peer,
+                Field(version.as_bytes())
+            );
+            self.tags.insert("version", version);
+        }
+
+        if let Some(transcript) = self.transcript.as_mut() {
+            if let Err(err) = transcript.received(data) {
+                warn!("transcript, peer: {}, error: {}", peer, err);
+                self.transcript = None;
+            }
+        }
+
+        if let Some(capture) = self.capture.as_mut() {
+            match capture.received(data) {
+                Ok(true) => (),
+                Ok(false) => {
+                    debug!("capture, peer: {}, truncated: true", peer);
+                    if let (Some(capture), Some(compressor)) =
+                        (self.capture.take(), &self.compressor)
+                    {
+                        compressor.compress(capture.path());
+                    }
+                }
+                Err(err) => {
+                    warn!("capture, peer: {}, error: {}", peer, err);
+                    self.capture = None;
+                }
+            }
+        }
+    }
+
+    pub fn closed<E: fmt::Display>(&mut self, reason: E) {
+        if let Some(transcript) = self.transcript.as_mut() {
+            let _ = transcript.closed(reason);
+        }
+
+        if let Some(compressor) = &self.compressor {
+            let transcript = self.transcript.as_ref().map(Transcript::path);
+            let capture = self.capture.as_ref().map(Capture::path);
+            for path in transcript.into_iter().chain(capture) {
+                compressor.compress(path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn wait_for(path: &std::path::Path) -> bool {
+    (0..500).any(|_| {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        path.exists()
+    })
+}
+
+#[test]
+fn test_tap_fan_out() {
+    use crate::spool::Spool;
+    use std::path::PathBuf;
+
+    let path = std::env::temp_dir().join(format!("tarssh-tap-{}", std::process::id()));
+    let spool = Spool::open(&path).unwrap();
+    let peer = PeerAddr::from("127.0.0.1:61986".parse::<std::net::SocketAddr>().unwrap());
+    let canaries: std::rc::Rc<[_]> = vec!["str:db01".parse().unwrap()].into();
+    let mut tap = Tap {
+        transcript: Some(Transcript::create(&spool, 1, peer).unwrap()),
+        capture: Some(Capture::create(&spool, 1, 6).unwrap()),
+        canary: Some(CanaryWatch::new(canaries)),
+        compressor: Some(Compressor::start().unwrap()),
+        ..Default::default()
+    };
+    assert!(!tap.is_empty());
+    let transcript = tap.transcript.as_ref().unwrap().path().to_path_buf();
+    let capture = tap.capture.as_ref().unwrap().path().to_path_buf();
+    let gz = |path: &PathBuf| PathBuf::from(format!("{}.gz", path.display()));
+
+    let mut notifier = Notifier::default();
+    tap.sent(peer, b"hi");
+    tap.received(peer, b"db0", &mut notifier);
+    assert!(tap.tags.is_empty());
+    tap.received(peer, b"1 go", &mut notifier);
+    assert_eq!(tap.tags.to_string(), "canary=str:db01");
+
+    // The capture's full, so it's done with and compressed straight away,
+    // while the transcript carries on until the connection closes
+    assert!(tap.capture.is_none());
+    assert!(wait_for(&gz(&capture)));
+    assert!(!capture.exists());
+    tap.received(peer, b"more", &mut notifier);
+    assert!(transcript.exists());
+
+    tap.closed("Broken pipe");
+    assert!(wait_for(&gz(&transcript)));
+    assert!(!transcript.exists());
+    std::fs::remove_dir_all(&path).unwrap();
+}
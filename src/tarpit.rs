@@ -0,0 +1,322 @@
+//! The tarpit itself, without the daemon around it, for embedding in other
+//! programs.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use tarssh::banner::{Banner, Preset};
+//! use tarssh::tarpit::Tarpit;
+//!
+//! # async fn serve() -> std::io::Result<()> {
+//! Tarpit::builder()
+//!     .listen("0.0.0.0:2222".parse().unwrap())
+//!     .delay(Duration::from_secs(10))
+//!     .banner(Banner::from(Preset::Ssh))
+//!     .on_connect(|event| println!("{:?}", event))
+//!     .on_disconnect(|event| println!("{:?}", event))
+//!     .build()?
+//!     .run()
+//!     .await
+//! # }
+//! ```
+//!
+//! The tarpit runs entirely within the task that awaits [`Tarpit::run`], so
+//! callbacks needn't be `Send`, and neither is the future.
+
+use std::io;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::stream::{FuturesUnordered, SelectAll, StreamExt};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_stream::wrappers::TcpListenerStream;
+
+use crate::banner::{Banner, Preset};
+use crate::event::Event;
+
+type Callback = Box<dyn FnMut(&Event)>;
+
+/// Configures a [`Tarpit`], with the same defaults as the tarssh daemon.
+pub struct TarpitBuilder {
+    listen: Vec<SocketAddr>,
+    max_clients: usize,
+    delay: Duration,
+    timeout: Duration,
+    banner: Banner,
+    on_connect: Option<Callback>,
+    on_disconnect: Option<Callback>,
+}
+
+impl Default for TarpitBuilder {
+    fn default() -> Self {
+        Self {
+            listen: vec![],
+            max_clients: 4096,
+            delay: Duration::from_secs(10),
+            timeout: Duration::from_secs(30),
+            banner: Banner::from(Preset::Ssh),
+            on_connect: None,
+            on_disconnect: None,
+        }
+    }
+}
+
+impl TarpitBuilder {
+    /// Listen on this address, in addition to any given before
+    pub fn listen(mut self, addr: SocketAddr) -> Self {
+        self.listen.push(addr);
+        self
+    }
+
+    /// Stop accepting connections while this many are held
+    pub fn max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = max_clients;
+        self
+    }
+
+    /// Time between each chunk of banner sent to a client
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// How long a write may stall before the client is disconnected
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// What to send each client
+    pub fn banner(mut self, banner: Banner) -> Self {
+        self.banner = banner;
+        self
+    }
+
+    /// Called with an [`Event::Connect`] as each client is accepted
+    pub fn on_connect<F: FnMut(&Event) + 'static>(mut self, callback: F) -> Self {
+        self.on_connect = Some(Box::new(callback));
+        self
+    }
+
+    /// Called with an [`Event::Disconnect`] as each client goes away
+    pub fn on_disconnect<F: FnMut(&Event) + 'static>(mut self, callback: F) -> Self {
+        self.on_disconnect = Some(Box::new(callback));
+        self
+    }
+
+    /// Bind the listening addresses, ready to run
+    pub fn build(self) -> io::Result<Tarpit> {
+        if self.listen.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no listen addresses",
+            ));
+        }
+
+        let listeners = self
+            .listen
+            .iter()
+            .map(|addr| {
+                let listener = std::net::TcpListener::bind(addr)?;
+                listener.set_nonblocking(true)?;
+                Ok(listener)
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Tarpit {
+            listeners,
+            max_clients: self.max_clients,
+            delay: self.delay,
+            timeout: self.timeout,
+            banner: Rc::new(self.banner),
+            on_connect: self.on_connect,
+            on_disconnect: self.on_disconnect,
+        })
+    }
+}
+
+/// A tarpit with its listening sockets bound.
+pub struct Tarpit {
+    listeners: Vec<std::net::TcpListener>,
+    max_clients: usize,
+    delay: Duration,
+    timeout: Duration,
+    banner: Rc<Banner>,
+    on_connect: Option<Callback>,
+    on_disconnect: Option<Callback>,
+}
+
+/// How a client's connection ended
+struct Ended {
+    peer: SocketAddr,
+    start: Instant,
+    bytes: u64,
+    writes: u32,
+    error: io::Error,
+}
+
+impl Tarpit {
+    pub fn builder() -> TarpitBuilder {
+        TarpitBuilder::default()
+    }
+
+    /// The addresses actually bound, such as the port picked for port 0
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.listeners.iter().map(|l| l.local_addr()).collect()
+    }
+
+    /// Serve clients forever, or until accepting fails for good
+    pub async fn run(mut self) -> io::Result<()> {
+        let mut listeners = SelectAll::new();
+        for listener in self.listeners.drain(..) {
+            listeners.push(TcpListenerStream::new(TcpListener::from_std(listener)?));
+        }
+        let mut clients = FuturesUnordered::new();
+        let mut rng = StdRng::from_entropy();
+
+        loop {
+            tokio::select! {
+                Some(ended) = clients.next(), if !clients.is_empty() => {
+                    let ended: Ended = ended;
+                    let event = Event::Disconnect {
+                        peer: ended.peer,
+                        duration: ended.start.elapsed(),
+                        bytes: ended.bytes,
+                        error: ended.error.to_string(),
+                        clients: clients.len(),
+                        writes: Some(ended.writes),
+                        failed: None,
+                        interval: None,
+                        unacked: None,
+                        location: Default::default(),
+                        tags: vec![],
+                        listener: None,
+                        short: false,
+                    };
+                    if let Some(callback) = self.on_disconnect.as_mut() {
+                        callback(&event);
+                    }
+                }
+                sock = listeners.next(), if clients.len() < self.max_clients => {
+                    let sock = match sock {
+                        Some(Ok(sock)) => sock,
+                        // Usually a client that's already gone, or a
+                        // transient shortage of descriptors
+                        Some(Err(_)) => continue,
+                        None => return Ok(()),
+                    };
+                    let peer = match sock.peer_addr() {
+                        Ok(peer) => peer,
+                        Err(_) => continue,
+                    };
+                    clients.push(serve(
+                        sock,
+                        peer,
+                        self.banner.clone(),
+                        self.delay,
+                        self.timeout,
+                        StdRng::seed_from_u64(rng.gen()),
+                    ));
+                    let event = Event::Connect {
+                        peer,
+                        clients: clients.len(),
+                        first_seen: None,
+                        times_seen: None,
+                        listener: None,
+                        location: Default::default(),
+                        dnsbl: None,
+                        dnsbl_result: None,
+                        enrich: None,
+                    };
+                    if let Some(callback) = self.on_connect.as_mut() {
+                        callback(&event);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Drip the banner to a client until it goes away or stops reading
+async fn serve(
+    mut sock: TcpStream,
+    peer: SocketAddr,
+    banner: Rc<Banner>,
+    delay: Duration,
+    timeout: Duration,
+    mut rng: StdRng,
+) -> Ended {
+    let start = Instant::now();
+    let mut bytes = 0;
+    let mut writes = 0u32;
+    let mut scratch = [0; 256];
+    let error = loop {
+        tokio::time::sleep(delay).await;
+        let chunk = banner.next(bytes, &mut rng, &mut scratch);
+        match tokio::time::timeout(timeout, sock.write(chunk)).await {
+            Ok(Ok(n)) => {
+                bytes += n as u64;
+                writes = writes.saturating_add(1);
+            }
+            Ok(Err(e)) => break e,
+            Err(_) => break io::Error::new(io::ErrorKind::TimedOut, "Timed Out"),
+        }
+    };
+    Ended {
+        peer,
+        start,
+        bytes,
+        writes,
+        error,
+    }
+}
+
+#[test]
+fn test_tarpit() {
+    use std::cell::RefCell;
+    use tokio::io::AsyncReadExt;
+
+    let events = Rc::new(RefCell::new(vec![]));
+    let (connected, disconnected) = (events.clone(), events.clone());
+    let tarpit = Tarpit::builder()
+        .listen("127.0.0.1:0".parse().unwrap())
+        .delay(Duration::from_millis(10))
+        .on_connect(move |event| connected.borrow_mut().push(event.clone()))
+        .on_disconnect(move |event| disconnected.borrow_mut().push(event.clone()))
+        .build()
+        .unwrap();
+    let addr = tarpit.local_addrs().unwrap()[0];
+
+    let client = async {
+        let mut sock = TcpStream::connect(addr).await.unwrap();
+        let mut buf = [0; 24];
+        sock.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"My name is Yon Yonson,\r\n");
+        drop(sock);
+        while events.borrow().len() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async {
+            tokio::select! {
+                res = tarpit.run() => panic!("tarpit stopped: {:?}", res),
+                _ = client => (),
+            }
+        });
+
+    let events = events.borrow();
+    assert!(matches!(events[0], Event::Connect { clients: 1, .. }));
+    assert!(matches!(
+        events[1],
+        Event::Disconnect { clients: 0, bytes, .. } if bytes >= 24
+    ));
+}
@@ -0,0 +1,292 @@
+//! Banners for arbitrary line-based text protocols, described rather than
+//! coded: a greeting sent once, then lines from a file, random text or a
+//! counter, each with an optional prefix and ended however the protocol ends
+//! lines.
+//!
+//! An SMTP server that never finishes its multi-line greeting, for example,
+//! is a greeting of `220-mail.example.com ESMTP\r\n`, then counted lines
+//! prefixed `220-`.
+
+use std::str::FromStr;
+
+use rand::Rng;
+
+use crate::banner::RandomLines;
+
+/// Longest line prefix or terminator
+pub const AFFIX_MAX: usize = 32;
+
+/// Where a template's lines come from, other than a file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Generator {
+    /// Random text, shaped like the random banner's
+    Random,
+    /// 1, 2, 3, ...
+    Counter,
+}
+
+impl FromStr for Generator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(Generator::Random),
+            "counter" => Ok(Generator::Counter),
+            _ => Err(format!(
+                "unknown generator {:?}, expected random or counter",
+                s
+            )),
+        }
+    }
+}
+
+/// Bytes given with backslash escapes, so line endings and control
+/// characters can be written on a command line: `\r`, `\n`, `\t`, `\0`,
+/// `\\` and `\xNN`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Escaped(pub Vec<u8>);
+
+impl FromStr for Escaped {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bytes = Vec::with_capacity(s.len());
+        let mut rest = s.as_bytes();
+        while let Some((&b, tail)) = rest.split_first() {
+            rest = tail;
+            if b != b'\\' {
+                bytes.push(b);
+                continue;
+            }
+            let (&escape, tail) = rest
+                .split_first()
+                .ok_or_else(|| "trailing backslash".to_string())?;
+            rest = tail;
+            bytes.push(match escape {
+                b'r' => b'\r',
+                b'n' => b'\n',
+                b't' => b'\t',
+                b'0' => b'\0',
+                b'\\' => b'\\',
+                b'x' => {
+                    let hex = rest
+                        .get(..2)
+                        .and_then(|hex| std::str::from_utf8(hex).ok())
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                        .ok_or_else(|| "\\x must be followed by two hex digits".to_string())?;
+                    rest = &rest[2..];
+                    hex
+                }
+                _ => return Err(format!("unknown escape \\{}", escape as char)),
+            });
+        }
+        Ok(Escaped(bytes))
+    }
+}
+
+/// A template's lines, ready to send
+#[derive(Debug, Clone)]
+enum Lines {
+    /// A file's lines, prefixed and terminated, with where each ends
+    File {
+        text: Vec<u8>,
+        ends: Vec<usize>,
+    },
+    Random(RandomLines),
+    Counter,
+}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    greeting: Vec<u8>,
+    prefix: Vec<u8>,
+    terminator: Vec<u8>,
+    lines: Lines,
+}
+
+impl Default for Template {
+    /// Counted lines ending in CRLF, with no greeting or prefix
+    fn default() -> Self {
+        Self {
+            greeting: vec![],
+            prefix: vec![],
+            terminator: b"\r\n".to_vec(),
+            lines: Lines::Counter,
+        }
+    }
+}
+
+impl Template {
+    /// A template whose lines are `prefix`, then the generator's text, then
+    /// `terminator`
+    pub fn new(
+        greeting: Vec<u8>,
+        prefix: Vec<u8>,
+        terminator: Vec<u8>,
+        generator: Generator,
+        random: RandomLines,
+    ) -> Result<Self, String> {
+        if prefix.len() > AFFIX_MAX {
+            return Err(format!("prefix is longer than {} bytes", AFFIX_MAX));
+        }
+        if terminator.is_empty() || terminator.len() > AFFIX_MAX {
+            return Err(format!("terminator must be 1 to {} bytes", AFFIX_MAX));
+        }
+        let lines = match generator {
+            Generator::Random => Lines::Random(random),
+            Generator::Counter => Lines::Counter,
+        };
+        Ok(Self {
+            greeting,
+            prefix,
+            terminator,
+            lines,
+        })
+    }
+
+    /// Take lines from `file` instead, sending each in turn, forever.
+    ///
+    /// Lines are split on LF, with any CR before it dropped, so the file can
+    /// be written with whichever endings are handy.
+    pub fn file(mut self, file: &[u8]) -> Result<Self, String> {
+        let file = file.strip_suffix(b"\n").unwrap_or(file);
+        if file.iter().all(|b| b.is_ascii_whitespace()) {
+            return Err("template file is empty".to_string());
+        }
+        let mut text = vec![];
+        let mut ends = vec![];
+        for line in file.split(|b| *b == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            text.extend_from_slice(&self.prefix);
+            text.extend_from_slice(line);
+            text.extend_from_slice(&self.terminator);
+            ends.push(text.len());
+        }
+        self.lines = Lines::File { text, ends };
+        Ok(self)
+    }
+
+    /// The next chunk to send, given how many bytes have been sent so far:
+    /// the greeting a line at a time, then a line per write.
+    ///
+    /// Generated lines are written to `buf`, which must have room for the
+    /// prefix and terminator and then some.
+    pub fn next<'a, R: Rng>(&'a self, sent: u64, rng: &mut R, buf: &'a mut [u8]) -> &'a [u8] {
+        let sent = match sent.checked_sub(self.greeting.len() as u64) {
+            Some(sent) => sent,
+            None => {
+                let rest = &self.greeting[sent as usize..];
+                let len = rest
+                    .iter()
+                    .position(|b| *b == b'\n')
+                    .map_or(rest.len(), |i| i + 1);
+                return &rest[..len];
+            }
+        };
+
+        match &self.lines {
+            Lines::File { text, ends } => {
+                let pos = (sent % text.len() as u64) as usize;
+                let end = ends[ends.partition_point(|end| *end <= pos)];
+                &text[pos..end]
+            }
+            Lines::Random(lines) => {
+                let room = buf.len() - self.prefix.len() - self.terminator.len();
+                let len = rng.gen_range(lines.min..=lines.max).min(room);
+                let (prefix, rest) = buf.split_at_mut(self.prefix.len());
+                prefix.copy_from_slice(&self.prefix);
+                rest[..len]
+                    .iter_mut()
+                    .for_each(|b| *b = lines.charset.sample(rng));
+                rest[len..len + self.terminator.len()].copy_from_slice(&self.terminator);
+                &buf[..self.prefix.len() + len + self.terminator.len()]
+            }
+            Lines::Counter => {
+                // Partway through a line if the last write was short
+                let (n, offset) = self.count(sent);
+                let mut line = self.prefix.clone();
+                line.extend_from_slice(n.to_string().as_bytes());
+                line.extend_from_slice(&self.terminator);
+                buf[..line.len()].copy_from_slice(&line);
+                &buf[offset..line.len()]
+            }
+        }
+    }
+
+    /// The counter's line `sent` bytes into the lines, and how far into it
+    fn count(&self, mut sent: u64) -> (u64, usize) {
+        let affixes = (self.prefix.len() + self.terminator.len()) as u64;
+        let mut first = 1u64;
+        for digits in 1.. {
+            let len = affixes + digits;
+            // Lines with this many digits, all of them once the bytes for
+            // the lot would overflow
+            let numbers = first.saturating_mul(9);
+            match numbers.checked_mul(len) {
+                Some(bytes) if sent >= bytes => sent -= bytes,
+                _ => return (first.saturating_add(sent / len), (sent % len) as usize),
+            }
+            first = first.saturating_mul(10);
+        }
+        unreachable!()
+    }
+}
+
+#[test]
+fn test_template() {
+    use rand::SeedableRng;
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let mut buf = [0; 256];
+    let mut next =
+        |template: &Template, sent: u64| template.next(sent, &mut rng, &mut buf).to_vec();
+
+    assert_eq!(
+        "220-\\x41\\r\\n".parse(),
+        Ok(Escaped(b"220-A\r\n".to_vec()))
+    );
+    assert!("\\".parse::<Escaped>().is_err());
+    assert!("\\q".parse::<Escaped>().is_err());
+    assert!("\\x4".parse::<Escaped>().is_err());
+
+    let template = Template::new(
+        b"220-mail.example.com ESMTP\r\n220-hi\r\n".to_vec(),
+        b"220-".to_vec(),
+        b"\r\n".to_vec(),
+        Generator::Counter,
+        RandomLines::default(),
+    )
+    .unwrap();
+    assert_eq!(next(&template, 0), b"220-mail.example.com ESMTP\r\n");
+    assert_eq!(next(&template, 28), b"220-hi\r\n");
+    let greeting = 36;
+    assert_eq!(next(&template, greeting), b"220-1\r\n");
+    assert_eq!(next(&template, greeting + 7), b"220-2\r\n");
+    assert_eq!(next(&template, greeting + 10), b"-2\r\n");
+    assert_eq!(next(&template, greeting + 9 * 7), b"220-10\r\n");
+    assert_eq!(next(&template, greeting + 9 * 7 + 90 * 8), b"220-100\r\n");
+    assert!(template.count(u64::MAX).0 > 10u64.pow(17));
+
+    let template = template.file(b"one\r\ntwo\n").unwrap();
+    assert_eq!(next(&template, greeting), b"220-one\r\n");
+    assert_eq!(next(&template, greeting + 9), b"220-two\r\n");
+    assert_eq!(next(&template, greeting + 13), b"two\r\n");
+    assert_eq!(next(&template, greeting + 18), b"220-one\r\n");
+    assert!(Template::default().file(b"\n").is_err());
+
+    let lines = RandomLines {
+        min: 5,
+        max: 5,
+        ..RandomLines::default()
+    };
+    let template = Template::new(
+        vec![],
+        b"> ".to_vec(),
+        b"\0".to_vec(),
+        Generator::Random,
+        lines,
+    )
+    .unwrap();
+    let line = next(&template, 0);
+    assert!(line.starts_with(b"> ") && line.ends_with(b"\0") && line.len() == 8);
+    assert!(Template::new(vec![], vec![], vec![], Generator::Counter, lines).is_err());
+}
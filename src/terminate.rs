@@ -0,0 +1,121 @@
+//! Closing a share of connections early at random, and timing how long their
+//! clients take to come back, to study how different bots react to a flaky
+//! tarpit.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+/// How long to wait for a client to come back before giving up on it
+const RECONNECT_WINDOW: Duration = Duration::from_secs(3600);
+
+pub struct EarlyTermination {
+    chance: f64,
+    within: u16,
+    /// Clients whose connections were closed early, and when
+    waiting: HashMap<IpAddr, Instant>,
+    terminated: u64,
+    reconnected: u64,
+    abandoned: u64,
+    reconnect_total: Duration,
+}
+
+impl EarlyTermination {
+    /// Close `percent` of connections, each after up to `within` seconds
+    pub fn new(percent: f64, within: u16) -> Self {
+        Self {
+            chance: percent / 100.0,
+            within,
+            waiting: HashMap::new(),
+            terminated: 0,
+            reconnected: 0,
+            abandoned: 0,
+            reconnect_total: Duration::ZERO,
+        }
+    }
+
+    /// Pick the age in seconds at which to close a new connection, or 0 to
+    /// leave it be
+    pub fn pick<R: Rng>(&self, rng: &mut R) -> u16 {
+        if rng.gen_bool(self.chance) {
+            rng.gen_range(1..=self.within)
+        } else {
+            0
+        }
+    }
+
+    pub fn terminated(&mut self, ip: IpAddr, now: Instant) {
+        self.terminated += 1;
+        self.waiting.insert(ip, now);
+    }
+
+    /// Note a new connection, returning how long since its client's last
+    /// connection was closed early, if it was
+    pub fn connected(&mut self, ip: IpAddr, now: Instant) -> Option<Duration> {
+        let closed = self.waiting.remove(&ip)?;
+        let delay = now.saturating_duration_since(closed);
+        self.reconnected += 1;
+        self.reconnect_total += delay;
+        Some(delay)
+    }
+
+    /// Give up on clients that haven't come back, returning how many
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.waiting.len();
+        self.waiting
+            .retain(|_, closed| now.saturating_duration_since(*closed) < RECONNECT_WINDOW);
+        let expired = before - self.waiting.len();
+        self.abandoned += expired as u64;
+        expired
+    }
+}
+
+impl fmt::Display for EarlyTermination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "terminated: {}, reconnected: {}, abandoned: {}, waiting: {}, mean_reconnect: ",
+            self.terminated,
+            self.reconnected,
+            self.abandoned,
+            self.waiting.len()
+        )?;
+        if self.reconnected > 0 {
+            write!(f, "{:.2?}", self.reconnect_total / self.reconnected as u32)
+        } else {
+            f.write_str("none")
+        }
+    }
+}
+
+#[test]
+fn test_early_termination() {
+    use rand::SeedableRng;
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let all = EarlyTermination::new(100.0, 60);
+    assert!((0..100)
+        .map(|_| all.pick(&mut rng))
+        .all(|at| (1..=60).contains(&at)));
+    let none = EarlyTermination::new(0.0, 60);
+    assert!((0..100).all(|_| none.pick(&mut rng) == 0));
+
+    let now = Instant::now();
+    let (a, b): (IpAddr, IpAddr) = ([192, 0, 2, 1].into(), [192, 0, 2, 2].into());
+    let mut early = EarlyTermination::new(10.0, 60);
+    early.terminated(a, now);
+    early.terminated(b, now);
+    assert_eq!(
+        early.connected(a, now + Duration::from_secs(30)),
+        Some(Duration::from_secs(30))
+    );
+    assert_eq!(early.connected(a, now + Duration::from_secs(40)), None);
+    assert_eq!(early.expire(now + RECONNECT_WINDOW), 1);
+    assert_eq!(
+        early.to_string(),
+        "terminated: 2, reconnected: 1, abandoned: 1, waiting: 0, mean_reconnect: 30.00s"
+    );
+}
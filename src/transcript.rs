@@ -0,0 +1,128 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::peer_addr::PeerAddr;
+use crate::spool::{unix_time, Spool};
+
+/// A timestamped record of everything sent to and received from a client.
+///
+/// One event per line, easily replayed by splitting on whitespace:
+///
+/// ```txt
+/// # tarssh transcript v1
+/// # peer 127.0.0.1:61986 start 1654560000
+/// 0 > 4d79206e616d6520697320596f6e20596f6e736f6e2c0d0a
+/// 10012 < 5353482d322e30
+/// 19800 x Broken pipe (os error 32)
+/// ```
+///
+/// Timestamps are milliseconds since the connection was accepted; `>` is sent,
+/// `<` is received, and `x` marks the disconnect.
+pub struct Transcript {
+    out: BufWriter<File>,
+    path: PathBuf,
+    start: Instant,
+}
+
+impl Transcript {
+    pub fn create(spool: &Spool, id: u64, peer: PeerAddr) -> io::Result<Self> {
+        let (out, path) = spool.create_for(id, "transcript")?;
+        let mut out = BufWriter::new(out);
+        writeln!(out, "# tarssh transcript v1")?;
+        writeln!(out, "# peer {} start {}", peer, unix_time())?;
+
+        Ok(Self {
+            out,
+            path,
+            start: Instant::now(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn memory(&self) -> usize {
+        self.out.capacity() + self.path.capacity()
+    }
+
+    pub fn sent(&mut self, data: &[u8]) -> io::Result<()> {
+        self.event('>', &hex(data))
+    }
+
+    pub fn received(&mut self, data: &[u8]) -> io::Result<()> {
+        self.event('<', &hex(data))
+    }
+
+    pub fn closed<E: std::fmt::Display>(&mut self, reason: E) -> io::Result<()> {
+        self.event('x', &reason.to_string())?;
+        self.out.flush()
+    }
+
+    fn event(&mut self, kind: char, data: &str) -> io::Result<()> {
+        writeln!(
+            self.out,
+            "{} {} {}",
+            self.start.elapsed().as_millis(),
+            kind,
+            data
+        )
+    }
+}
+
+pub fn hex(data: &[u8]) -> String {
+    data.iter()
+        .fold(String::with_capacity(data.len() * 2), |mut s, b| {
+            let _ = write!(s, "{:02x}", b);
+            s
+        })
+}
+
+#[test]
+fn test_transcript_format() {
+    let path = std::env::temp_dir().join(format!("tarssh-transcript-{}", std::process::id()));
+    let spool = Spool::open(&path).unwrap();
+    let peer = PeerAddr::from("127.0.0.1:61986".parse::<std::net::SocketAddr>().unwrap());
+    let mut transcript = Transcript::create(&spool, 1, peer).unwrap();
+    transcript.sent(b"hi\r\n").unwrap();
+    transcript.received(b"SSH-2.0").unwrap();
+    transcript.received(b"").unwrap();
+    transcript.closed("Broken pipe").unwrap();
+
+    let text = std::fs::read_to_string(transcript.path()).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines[0], "# tarssh transcript v1");
+    let start = lines[1]
+        .strip_prefix("# peer 127.0.0.1:61986 start ")
+        .unwrap();
+    assert!(start.parse::<u64>().unwrap() >= unix_time() - 1);
+
+    let events: Vec<Vec<&str>> = lines[2..]
+        .iter()
+        .map(|line| line.splitn(3, ' ').collect())
+        .collect();
+    for event in &events {
+        assert!(event[0].parse::<u64>().is_ok());
+    }
+    let kinds: Vec<(&str, &str)> = events.iter().map(|e| (e[1], e[2])).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            (">", "68690d0a"),
+            ("<", "5353482d322e30"),
+            ("<", ""),
+            ("x", "Broken pipe")
+        ]
+    );
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn test_hex() {
+    assert_eq!(hex(b""), "");
+    assert_eq!(hex(b"\x00\x0f\xff"), "000fff");
+}
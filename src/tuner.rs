@@ -0,0 +1,190 @@
+//! Experimental delay tuning: a simple epsilon-greedy bandit that learns which
+//! of a few delays holds clients from each network longest, on the theory that
+//! neighbouring sources tend to run the same bot with the same patience.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Most delays to choose between
+const ARMS_MAX: usize = 16;
+
+/// How often to try a delay at random rather than the best so far
+const EXPLORE: f64 = 0.1;
+
+/// Most networks to keep results for
+const TRACKED: usize = 4096;
+
+/// The delays to choose between, written as comma-separated durations of whole
+/// seconds, e.g. `5s,10s,30s,1m`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delays(Vec<Duration>);
+
+impl FromStr for Delays {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut delays = vec![];
+        for delay in s.split(',') {
+            let delay = humantime::parse_duration(delay.trim()).map_err(|e| e.to_string())?;
+            if delay < Duration::from_secs(1) || delay.subsec_nanos() != 0 {
+                return Err(format!("delays must be whole seconds in {:?}", s));
+            }
+            if delays.contains(&delay) {
+                return Err(format!("duplicate delay in {:?}", s));
+            }
+            delays.push(delay);
+        }
+        if delays.len() > ARMS_MAX {
+            return Err(format!("at most {} delays may be given", ARMS_MAX));
+        }
+        delays.sort();
+        Ok(Self(delays))
+    }
+}
+
+impl Delays {
+    pub fn min(&self) -> Duration {
+        self.0[0]
+    }
+
+    pub fn as_slice(&self) -> &[Duration] {
+        &self.0
+    }
+}
+
+/// How one delay has fared
+#[derive(Debug, Default, Clone, Copy)]
+struct Arm {
+    pulls: u64,
+    held: Duration,
+}
+
+impl Arm {
+    fn mean(&self) -> Option<Duration> {
+        Some(self.pulls)
+            .filter(|pulls| *pulls > 0)
+            .map(|pulls| self.held.div_f64(pulls as f64))
+    }
+}
+
+/// The network a source is judged alongside: its /24, or /48 for IPv6
+fn network(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(v4) => Ipv4Addr::from(u32::from(v4) & 0xffff_ff00).into(),
+        IpAddr::V6(v6) => Ipv6Addr::from(u128::from(v6) & !((1 << 80) - 1)).into(),
+    }
+}
+
+pub struct Tuner {
+    delays: Delays,
+    /// Results across every network, for networks not yet seen
+    overall: Vec<Arm>,
+    networks: HashMap<IpAddr, Vec<Arm>>,
+}
+
+impl Tuner {
+    pub fn new(delays: Delays) -> Self {
+        Self {
+            overall: vec![Arm::default(); delays.0.len()],
+            networks: HashMap::new(),
+            delays,
+        }
+    }
+
+    pub fn delay(&self, arm: usize) -> Duration {
+        self.delays.0[arm]
+    }
+
+    /// Pick a delay for a client, trying each once for its network before
+    /// favouring whichever has held its clients longest on average
+    pub fn choose<R: Rng>(&self, ip: IpAddr, rng: &mut R) -> usize {
+        let arms = self.networks.get(&network(ip)).unwrap_or(&self.overall);
+        if rng.gen_bool(EXPLORE) {
+            return rng.gen_range(0..arms.len());
+        }
+        if let Some(untried) = arms.iter().position(|arm| arm.pulls == 0) {
+            return untried;
+        }
+        (0..arms.len())
+            .max_by_key(|i| arms[*i].mean())
+            .unwrap_or_default()
+    }
+
+    /// Credit a delay with how long it held a client
+    pub fn record(&mut self, ip: IpAddr, arm: usize, held: Duration) {
+        let network = network(ip);
+        if !self.networks.contains_key(&network) && self.networks.len() >= TRACKED {
+            // Make room by forgetting the least tried
+            let rarest = self
+                .networks
+                .iter()
+                .min_by_key(|(_, arms)| arms.iter().map(|arm| arm.pulls).sum::<u64>())
+                .map(|(network, _)| *network);
+            if let Some(rarest) = rarest {
+                self.networks.remove(&rarest);
+            }
+        }
+
+        let count = self.delays.0.len();
+        let arms = self
+            .networks
+            .entry(network)
+            .or_insert_with(|| vec![Arm::default(); count]);
+        arms[arm].pulls += 1;
+        arms[arm].held += held;
+        self.overall[arm].pulls += 1;
+        self.overall[arm].held += held;
+    }
+}
+
+impl fmt::Display for Tuner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "networks: {}, delays: ", self.networks.len())?;
+        for (i, (delay, arm)) in self.delays.0.iter().zip(&self.overall).enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            match arm.mean() {
+                Some(mean) => write!(f, "{:.0?}={}/{:.0?}", delay, arm.pulls, mean)?,
+                None => write!(f, "{:.0?}=0/none", delay)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_tuner() {
+    use rand::SeedableRng;
+
+    assert!("1s,0s".parse::<Delays>().is_err());
+    assert!("5s,5s".parse::<Delays>().is_err());
+    let delays: Delays = "30s,5s,1m".parse().unwrap();
+    assert_eq!(delays.min(), Duration::from_secs(5));
+
+    let mut tuner = Tuner::new(delays);
+    assert_eq!(
+        tuner.to_string(),
+        "networks: 0, delays: 5s=0/none 30s=0/none 60s=0/none"
+    );
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let bot: IpAddr = "192.0.2.1".parse().unwrap();
+    let neighbour: IpAddr = "192.0.2.200".parse().unwrap();
+    tuner.record(bot, 0, Duration::from_secs(60));
+    tuner.record(bot, 1, Duration::from_secs(600));
+    tuner.record(bot, 2, Duration::from_secs(120));
+
+    let chosen = (0..100)
+        .filter(|_| tuner.choose(neighbour, &mut rng) == 1)
+        .count();
+    assert!(chosen > 80);
+    assert_eq!(
+        tuner.to_string(),
+        "networks: 1, delays: 5s=1/60s 30s=1/600s 60s=1/120s"
+    );
+}
@@ -0,0 +1,139 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{debug, info, warn};
+use tokio::net::UdpSocket;
+
+/// Most responses to have in flight at once, across all sockets
+const MAX_PENDING: usize = 256;
+
+/// Seconds between the NTP and Unix epochs
+const NTP_EPOCH: u64 = 2_208_988_800;
+
+/// Kinds of scanner probe we recognise
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Probe {
+    Ssdp,
+    Ntp,
+    Dns,
+    Unknown,
+}
+
+impl Probe {
+    fn classify(data: &[u8]) -> Self {
+        if data.starts_with(b"M-SEARCH ") {
+            Probe::Ssdp
+        } else if data.len() == 48 && data[0] & 0x07 == 3 {
+            Probe::Ntp
+        } else if data.len() >= 12 && data[2] & 0x80 == 0 && data[4..6] == [0, 1] {
+            Probe::Dns
+        } else {
+            Probe::Unknown
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Probe::Ssdp => "ssdp",
+            Probe::Ntp => "ntp",
+            Probe::Dns => "dns",
+            Probe::Unknown => "unknown",
+        }
+    }
+
+    /// A plausible answer to the probe, never larger than the probe itself so
+    /// we're useless as a reflection amplifier.
+    fn respond(self, data: &[u8]) -> Option<Vec<u8>> {
+        let mut response = match self {
+            Probe::Ssdp => b"HTTP/1.1 200 OK\r\n\
+                CACHE-CONTROL: max-age=1800\r\n\
+                ST: upnp:rootdevice\r\n\
+                SERVER: Linux/3.14 UPnP/1.0 IpBridge/1.26.0\r\n\r\n"
+                .to_vec(),
+            Probe::Ntp => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                let secs = ((now.as_secs() + NTP_EPOCH) as u32).to_be_bytes();
+                let frac =
+                    ((now.subsec_nanos() as u64 * (1 << 32) / 1_000_000_000) as u32).to_be_bytes();
+
+                let mut r = vec![0u8; 48];
+                r[0] = 0x24; // no leap warning, version 4, server mode
+                r[1] = 2; // stratum
+                r[2] = data[2]; // poll
+                r[3] = 0xe9; // precision
+                r[12..16].copy_from_slice(b"GPS\0");
+                r[16..20].copy_from_slice(&secs); // reference
+                r[24..32].copy_from_slice(&data[40..48]); // origin
+                r[32..36].copy_from_slice(&secs); // receive
+                r[36..40].copy_from_slice(&frac);
+                r[40..44].copy_from_slice(&secs); // transmit
+                r[44..48].copy_from_slice(&frac);
+                r
+            }
+            Probe::Dns => {
+                let mut r = data.to_vec();
+                r[2] = 0x80 | (data[2] & 0x79); // response, keep opcode and RD
+                r[3] = 0x85; // recursion available, REFUSED
+                r[6..12].fill(0); // no answers, authorities or additionals
+                r
+            }
+            Probe::Unknown => return None,
+        };
+
+        response.truncate(data.len());
+        Some(response)
+    }
+}
+
+pub async fn bind(addr: SocketAddr) -> std::io::Result<UdpSocket> {
+    UdpSocket::bind(addr).await
+}
+
+/// Answer probes on `sock` after `delay`, logging each source.
+pub async fn serve(sock: UdpSocket, delay: Duration) {
+    let sock = Arc::new(sock);
+    let pending = Arc::new(AtomicUsize::new(0));
+    let mut buf = [0u8; 1500];
+
+    loop {
+        let (n, peer) = match sock.recv_from(&mut buf).await {
+            Ok(recv) => recv,
+            Err(err) => {
+                debug!("udp, error: {}", err);
+                continue;
+            }
+        };
+
+        let data = &buf[..n];
+        let probe = Probe::classify(data);
+        info!("udp, peer: {}, probe: {}, bytes: {}", peer, probe.name(), n);
+
+        let response = match probe.respond(data) {
+            Some(response) if pending.load(Ordering::Relaxed) < MAX_PENDING => response,
+            _ => continue,
+        };
+
+        pending.fetch_add(1, Ordering::Relaxed);
+        let sock = sock.clone();
+        let pending = pending.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if let Err(err) = sock.send_to(&response, peer).await {
+                warn!("udp, peer: {}, error: {}", peer, err);
+            }
+            pending.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+#[cfg(test)]
+quickcheck::quickcheck! {
+    fn prop_no_amplification(data: Vec<u8>) -> bool {
+        let probe = Probe::classify(&data);
+        probe.respond(&data).is_none_or(|r| r.len() <= data.len())
+    }
+}
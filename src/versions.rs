@@ -0,0 +1,146 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// How many versions to report
+pub const TOP: usize = 10;
+
+/// Longest identification line to wait for, per RFC 4253
+const LINE_MAX: usize = 255;
+
+/// Most distinct versions to keep counts for
+const TRACKED: usize = 1024;
+
+/// Longest version to keep, since clients can send whatever they like
+const VERSION_MAX: usize = 64;
+
+/// Counts of SSH client versions seen, keeping only the most common.
+#[derive(Debug, Default)]
+pub struct VersionTable {
+    counts: HashMap<String, u64>,
+}
+
+impl VersionTable {
+    pub fn record(&mut self, version: String) {
+        if !self.counts.contains_key(&version) && self.counts.len() >= TRACKED {
+            // Make room by forgetting the rarest
+            let rarest = self
+                .counts
+                .iter()
+                .min_by_key(|(_, count)| **count)
+                .map(|(version, _)| version.clone());
+            if let Some(rarest) = rarest {
+                self.counts.remove(&rarest);
+            }
+        }
+        *self.counts.entry(version).or_default() += 1;
+    }
+
+    /// The `k` most common versions, most common first
+    pub fn top(&self, k: usize) -> Vec<(String, u64)> {
+        let mut top: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(version, count)| (version.clone(), *count))
+            .collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(k);
+        top
+    }
+}
+
+/// Per-connection watch for the client's identification string.
+pub struct VersionWatch {
+    table: Rc<RefCell<VersionTable>>,
+    line: Vec<u8>,
+    done: bool,
+}
+
+impl VersionWatch {
+    pub fn new(table: Rc<RefCell<VersionTable>>) -> Self {
+        Self {
+            table,
+            line: vec![],
+            done: false,
+        }
+    }
+
+    pub fn memory(&self) -> usize {
+        self.line.capacity()
+    }
+
+    /// Feed client data, returning the client's version once its first line
+    /// is complete, if that line was an SSH identification string
+    pub fn scan(&mut self, data: &[u8]) -> Option<String> {
+        if self.done {
+            return None;
+        }
+
+        let data = &data[..data.len().min(LINE_MAX - self.line.len())];
+        match data.iter().position(|b| *b == b'\n') {
+            Some(end) => self.line.extend_from_slice(&data[..end]),
+            None => {
+                self.line.extend_from_slice(data);
+                self.done = self.line.len() >= LINE_MAX;
+                return None;
+            }
+        }
+        self.done = true;
+
+        let version = parse(&self.line)?;
+        self.table.borrow_mut().record(version.clone());
+        Some(version)
+    }
+}
+
+/// The protocol and software version from an identification line, without
+/// comments, and made safe to use as a log field or metric label
+fn parse(line: &[u8]) -> Option<String> {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    if !line.starts_with(b"SSH-") {
+        return None;
+    }
+
+    let version = line.split(|b| *b == b' ').next()?;
+    Some(
+        version
+            .iter()
+            .take(VERSION_MAX)
+            .map(|&b| match b {
+                b',' | b'=' | b'"' | b'\\' => '_',
+                b'!'..=b'~' => b as char,
+                _ => '_',
+            })
+            .collect(),
+    )
+}
+
+#[test]
+fn test_version_watch() {
+    let table = Rc::new(RefCell::new(VersionTable::default()));
+
+    let mut watch = VersionWatch::new(table.clone());
+    assert_eq!(watch.scan(b"SSH-2.0-OpenSSH_8.9p1 Ubu"), None);
+    assert_eq!(
+        watch.scan(b"ntu-3\r\n\0\0\0"),
+        Some("SSH-2.0-OpenSSH_8.9p1".to_string())
+    );
+    assert_eq!(watch.scan(b"SSH-2.0-again\r\n"), None);
+
+    let mut watch = VersionWatch::new(table.clone());
+    assert_eq!(
+        watch.scan(b"SSH-2.0-Go,\"x\"\r\n"),
+        Some("SSH-2.0-Go__x_".to_string())
+    );
+    let mut watch = VersionWatch::new(table.clone());
+    assert_eq!(watch.scan(b"GET / HTTP/1.1\r\n"), None);
+    VersionWatch::new(table.clone()).scan(b"SSH-2.0-OpenSSH_8.9p1\r\n");
+
+    assert_eq!(
+        table.borrow().top(TOP),
+        vec![
+            ("SSH-2.0-OpenSSH_8.9p1".to_string(), 2),
+            ("SSH-2.0-Go__x_".to_string(), 1)
+        ]
+    );
+}
@@ -0,0 +1,170 @@
+//! Noticing when files read at startup change, so banners and bans dropped in
+//! by an external feed apply without a signal.
+//!
+//! Files are polled rather than watched with inotify or kqueue, which keeps
+//! this portable and works the same for files replaced by rename.  A change is
+//! only reported once a poll finds the file just as the poll before it did, so
+//! one caught half-written is left until its writer is done.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often to look for changes
+pub const INTERVAL: Duration = Duration::from_secs(1);
+
+/// Name, modification time and size of each file at a path: just the one for
+/// a file, each visible file for a directory, or none if it can't be read
+type Stamp = Vec<(OsString, Option<SystemTime>, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    let mut stamp = vec![];
+    match std::fs::read_dir(path) {
+        Ok(entries) => {
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                if name.to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                if let Some(meta) = entry.path().metadata().ok().filter(|meta| meta.is_file()) {
+                    stamp.push((name, meta.modified().ok(), meta.len()));
+                }
+            }
+            stamp.sort();
+        }
+        Err(_) => {
+            if let Ok(meta) = path.metadata() {
+                stamp.push((OsString::new(), meta.modified().ok(), meta.len()));
+            }
+        }
+    }
+    stamp
+}
+
+/// A file or directory polled for changes
+#[derive(Debug)]
+pub struct Watched {
+    path: PathBuf,
+    /// As last reported
+    applied: Stamp,
+    /// As last polled
+    seen: Stamp,
+}
+
+impl Watched {
+    pub fn new(path: PathBuf) -> Self {
+        let applied = stamp(&path);
+        Self {
+            path,
+            seen: applied.clone(),
+            applied,
+        }
+    }
+
+    /// The path, if it's changed since last reported and stayed that way
+    /// since the poll before
+    pub fn changed(&mut self) -> Option<&Path> {
+        let now = stamp(&self.path);
+        let settled = now == self.seen;
+        self.seen = now;
+        if settled && self.seen != self.applied {
+            self.applied = self.seen.clone();
+            Some(&self.path)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("tarssh-watch-{}-{}", name, std::process::id()))
+}
+
+#[cfg(test)]
+fn touch(path: &Path, text: &str, secs: u64) {
+    std::fs::write(path, text).unwrap();
+    let file = std::fs::File::options().write(true).open(path).unwrap();
+    file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+        .unwrap();
+}
+
+#[test]
+fn test_watch_file() {
+    let path = temp_path("file");
+    touch(&path, "one", 1000);
+    let mut watched = Watched::new(path.clone());
+    assert_eq!(watched.changed(), None);
+
+    // Reported only once it's settled, and then only the once
+    touch(&path, "two", 1001);
+    assert_eq!(watched.changed(), None);
+    assert_eq!(watched.changed(), Some(path.as_path()));
+    assert_eq!(watched.changed(), None);
+
+    // Same size, later time
+    touch(&path, "six", 1002);
+    assert_eq!(watched.changed(), None);
+    assert_eq!(watched.changed(), Some(path.as_path()));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_watch_unsettled() {
+    let path = temp_path("unsettled");
+    touch(&path, "one", 1000);
+    let mut watched = Watched::new(path.clone());
+
+    // Still being written at each poll
+    for secs in 1001..1005 {
+        touch(&path, &"x".repeat(secs as usize - 1000), secs);
+        assert_eq!(watched.changed(), None);
+    }
+    assert_eq!(watched.changed(), Some(path.as_path()));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_watch_missing() {
+    let path = temp_path("missing");
+    touch(&path, "one", 1000);
+    let mut watched = Watched::new(path.clone());
+
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(watched.changed(), None);
+    assert_eq!(watched.changed(), Some(path.as_path()));
+
+    // Back as it was before it went
+    touch(&path, "one", 1000);
+    assert_eq!(watched.changed(), None);
+    assert_eq!(watched.changed(), Some(path.as_path()));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_watch_dir() {
+    let dir = temp_path("dir");
+    std::fs::create_dir_all(&dir).unwrap();
+    touch(&dir.join("a"), "one", 1000);
+    let mut watched = Watched::new(dir.clone());
+    assert_eq!(watched.changed(), None);
+
+    touch(&dir.join("b"), "two", 1000);
+    assert_eq!(watched.changed(), None);
+    assert_eq!(watched.changed(), Some(dir.as_path()));
+
+    // Edited in place, leaving the directory itself alone
+    touch(&dir.join("a"), "three", 1001);
+    assert_eq!(watched.changed(), None);
+    assert_eq!(watched.changed(), Some(dir.as_path()));
+
+    // Hidden files, such as an editor's swap file, don't count
+    touch(&dir.join(".a.swp"), "four", 1002);
+    assert_eq!(watched.changed(), None);
+    assert_eq!(watched.changed(), None);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}